@@ -24,6 +24,8 @@ class DecoderController {
         height = 0,
         surfaceBound = false
     )
+    @Volatile
+    private var screenshotPending = false
 
     fun setSurface(surface: Surface?) {
         this.surface = surface
@@ -47,6 +49,22 @@ class DecoderController {
         // TODO: feed MediaCodec once decoder pipeline is wired.
     }
 
+    /**
+     * Host asked for a copy of the rendered frame. Only the request is recorded:
+     * nothing renders frames yet, so no screenshot is saved. The renderer should
+     * call [takeScreenshotRequest] once it exists.
+     */
+    fun requestScreenshot() {
+        screenshotPending = true
+        Diagnostics.logInfo("screenshot_requested ${status.width}x${status.height}")
+    }
+
+    fun takeScreenshotRequest(): Boolean {
+        val pending = screenshotPending
+        screenshotPending = false
+        return pending
+    }
+
     fun getStatus(): DecoderStatus = status
 
     private fun codecIdToMime(codecId: Int): String {
@@ -11,10 +11,18 @@ sealed class Packet {
         val codecId: Int? = null,
         val codecProfile: Int = 0,
         val codecLevel: Int = 0,
-        val codecFlags: Int = 0
+        val codecFlags: Int = 0,
+        val color: ColorDescription? = null
     ) : Packet()
+    data class ColorDescription(
+        val primaries: Int,
+        val transfer: Int,
+        val matrix: Int,
+        val fullRange: Boolean
+    )
     data class Frame(val data: ByteArray, val timestamp100ns: Long? = null) : Packet()
     data class FrameDone(val encoderId: Int) : Packet()
+    object TakeScreenshot : Packet()
     data class TouchPoint(
         val pointerId: Int,
         val down: Boolean,
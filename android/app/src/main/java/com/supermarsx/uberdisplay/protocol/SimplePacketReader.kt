@@ -13,6 +13,7 @@ class SimplePacketReader : PacketReader {
             ProtocolDataTypes.ERROR -> parseError(bytes)
             ProtocolDataTypes.FRAME_DONE -> parseFrameDone(bytes)
             ProtocolDataTypes.CAPABILITIES -> parseCapabilities(bytes)
+            ProtocolDataTypes.TAKE_SCREENSHOT -> Packet.TakeScreenshot
             else -> null
         }
     }
@@ -61,6 +62,16 @@ class SimplePacketReader : PacketReader {
             codecFlags = buffer.get().toInt() and 0xFF
         }
 
+        var color: Packet.ColorDescription? = null
+        if (codecId != null && buffer.remaining() >= 4) {
+            color = Packet.ColorDescription(
+                primaries = buffer.get().toInt() and 0xFF,
+                transfer = buffer.get().toInt() and 0xFF,
+                matrix = buffer.get().toInt() and 0xFF,
+                fullRange = buffer.get().toInt() != 0
+            )
+        }
+
         return Packet.Configure(
             width = width,
             height = height,
@@ -70,7 +81,8 @@ class SimplePacketReader : PacketReader {
             codecId = codecId,
             codecProfile = codecProfile,
             codecLevel = codecLevel,
-            codecFlags = codecFlags
+            codecFlags = codecFlags,
+            color = color
         )
     }
 
@@ -27,6 +27,9 @@ class StreamChunkParser {
                     AppServices.decoderController.onFrame(packet.data)
                     sendFrameDone()
                 }
+                if (packet is Packet.TakeScreenshot) {
+                    AppServices.decoderController.requestScreenshot()
+                }
             }
         }
     }
@@ -51,6 +51,29 @@ class SimplePacketReaderTest {
         assertEquals(2, configure.codecId)
     }
 
+    @Test
+    fun parsesConfigurePacketWithColorDescription() {
+        val buffer = ByteBuffer.allocate(1 + 20 + 8).order(ByteOrder.LITTLE_ENDIAN)
+        buffer.put(ProtocolDataTypes.CONFIGURE.toByte())
+        buffer.putInt(1920)
+        buffer.putInt(1080)
+        buffer.putInt(1920)
+        buffer.putInt(1080)
+        buffer.putInt(3)
+        buffer.put(1)
+        buffer.put(100)
+        buffer.put(40)
+        buffer.put(4)
+        buffer.put(5)
+        buffer.put(6)
+        buffer.put(5)
+        buffer.put(1)
+
+        val packet = SimplePacketReader().read(buffer.array())
+        val configure = packet as Packet.Configure
+        assertEquals(Packet.ColorDescription(5, 6, 5, true), configure.color)
+    }
+
     @Test
     fun parsesStatePacket() {
         val bytes = byteArrayOf(ProtocolDataTypes.STATE.toByte(), 5)
@@ -92,4 +115,11 @@ class SimplePacketReaderTest {
         assertEquals(1, caps.codecMask)
         assertEquals(2, caps.flags)
     }
+
+    @Test
+    fun parsesTakeScreenshotPacket() {
+        val bytes = byteArrayOf(ProtocolDataTypes.TAKE_SCREENSHOT.toByte())
+        val packet = SimplePacketReader().read(bytes)
+        assertEquals(Packet.TakeScreenshot, packet)
+    }
 }
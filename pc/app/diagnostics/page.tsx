@@ -57,6 +57,42 @@ type SessionStats = {
   dxgiLastBytes: number;
   capturePath: string;
   captureScale: string;
+  changedAreaPercent: number;
+  unchangedFrames: number;
+  nalCounts: {
+    vps: number;
+    sps: number;
+    pps: number;
+    idr: number;
+    nonIdr: number;
+    sei: number;
+    other: number;
+  };
+  encodedWidth: number;
+  encodedHeight: number;
+  encodedProfile: number;
+  encodedLevel: number;
+  undecodableFramesSkipped: number;
+};
+
+type RecordingStatus = {
+  active: boolean;
+  path: string | null;
+  format: "raw" | "mp4" | null;
+  bytes: number;
+  frames: number;
+  durationMs: number;
+  stopReason: string | null;
+};
+
+const idleRecording: RecordingStatus = {
+  active: false,
+  path: null,
+  format: null,
+  bytes: 0,
+  frames: 0,
+  durationMs: 0,
+  stopReason: null,
 };
 
 const fallbackLogs: HostLogEntry[] = [
@@ -78,6 +114,14 @@ const fallbackStats: SessionStats = {
   dxgiLastBytes: 0,
   capturePath: "Unknown",
   captureScale: "Unknown",
+  changedAreaPercent: 0,
+  unchangedFrames: 0,
+  nalCounts: { vps: 0, sps: 0, pps: 0, idr: 0, nonIdr: 0, sei: 0, other: 0 },
+  encodedWidth: 0,
+  encodedHeight: 0,
+  encodedProfile: 0,
+  encodedLevel: 0,
+  undecodableFramesSkipped: 0,
 };
 
 export default function DiagnosticsPage() {
@@ -86,6 +130,10 @@ export default function DiagnosticsPage() {
   const [logs, setLogs] = useState<HostLogEntry[]>(fallbackLogs);
   const [sessionStats, setSessionStats] = useState<SessionStats>(fallbackStats);
   const [displays, setDisplays] = useState<DisplayInfo[]>([]);
+  const [recording, setRecording] = useState<RecordingStatus>(idleRecording);
+  const [recordingFormat, setRecordingFormat] = useState<"raw" | "mp4">("mp4");
+  const [recordingMaxMb, setRecordingMaxMb] = useState(500);
+  const [recordingMaxMinutes, setRecordingMaxMinutes] = useState(10);
 
   useEffect(() => {
     let cancelled = false;
@@ -147,11 +195,29 @@ export default function DiagnosticsPage() {
       }
     };
 
+    const loadRecording = async () => {
+      try {
+        const { invoke } = await import("@tauri-apps/api/tauri");
+        const data = await invoke<RecordingStatus>("recording_status");
+        if (!cancelled) {
+          setRecording(data);
+        }
+      } catch (_error) {
+        if (!cancelled) {
+          setRecording(idleRecording);
+        }
+      }
+    };
+
     loadStatus();
     loadLogs();
     loadDisplays();
     loadSessionStats();
-    statsTimer = setInterval(loadSessionStats, 2000);
+    loadRecording();
+    statsTimer = setInterval(() => {
+      loadSessionStats();
+      loadRecording();
+    }, 2000);
     return () => {
       cancelled = true;
       if (statsTimer) {
@@ -186,6 +252,58 @@ export default function DiagnosticsPage() {
     }
   };
 
+  const handleTakeScreenshot = async () => {
+    try {
+      const { invoke } = await import("@tauri-apps/api/tauri");
+      const path = await invoke<string>("take_screenshot");
+      pushToast(`Screenshot saved to ${path}`, "success");
+    } catch (err) {
+      pushToast("Unable to take screenshot.", "error");
+      console.error(err);
+    }
+  };
+
+  const handleClientScreenshot = async () => {
+    try {
+      const { invoke } = await import("@tauri-apps/api/tauri");
+      await invoke("request_client_screenshot");
+      pushToast("Screenshot requested from client.", "success");
+    } catch (err) {
+      pushToast("Unable to reach client for screenshot.", "error");
+      console.error(err);
+    }
+  };
+
+  const handleStartRecording = async () => {
+    try {
+      const { invoke } = await import("@tauri-apps/api/tauri");
+      const path = await invoke<string>("start_recording", {
+        format: recordingFormat,
+        limits: {
+          maxBytes: recordingMaxMb > 0 ? recordingMaxMb * 1024 * 1024 : null,
+          maxDurationMs: recordingMaxMinutes > 0 ? recordingMaxMinutes * 60 * 1000 : null,
+        },
+      });
+      setRecording({ ...idleRecording, active: true, path, format: recordingFormat });
+      pushToast(`Recording to ${path}`, "success");
+    } catch (err) {
+      pushToast(`Unable to start recording: ${err}`, "error");
+      console.error(err);
+    }
+  };
+
+  const handleStopRecording = async () => {
+    try {
+      const { invoke } = await import("@tauri-apps/api/tauri");
+      const data = await invoke<RecordingStatus>("stop_recording");
+      setRecording(data);
+      pushToast(`Recording saved to ${data.path ?? "app data"}`, "success");
+    } catch (err) {
+      pushToast("Unable to stop recording.", "error");
+      console.error(err);
+    }
+  };
+
   const handleLogDetails = async (message: string) => {
     try {
       const { invoke } = await import("@tauri-apps/api/tauri");
@@ -305,6 +423,37 @@ export default function DiagnosticsPage() {
               <div className="metric-label">Capture Scale</div>
               <div className="metric-value">{sessionStats.captureScale}</div>
             </div>
+            <div>
+              <div className="metric-label">Changed Area</div>
+              <div className="metric-value">{sessionStats.changedAreaPercent.toFixed(1)}%</div>
+            </div>
+            <div>
+              <div className="metric-label">Unchanged Frames Skipped</div>
+              <div className="metric-value">{sessionStats.unchangedFrames}</div>
+            </div>
+            <div>
+              <div className="metric-label">Encoded Stream</div>
+              <div className="metric-value">
+                {sessionStats.encodedWidth}x{sessionStats.encodedHeight} (profile {sessionStats.encodedProfile}, level{" "}
+                {sessionStats.encodedLevel})
+              </div>
+            </div>
+            <div>
+              <div className="metric-label">NAL Units (SPS/PPS/VPS)</div>
+              <div className="metric-value">
+                {sessionStats.nalCounts.sps} / {sessionStats.nalCounts.pps} / {sessionStats.nalCounts.vps}
+              </div>
+            </div>
+            <div>
+              <div className="metric-label">NAL Units (IDR/Non-IDR/SEI)</div>
+              <div className="metric-value">
+                {sessionStats.nalCounts.idr} / {sessionStats.nalCounts.nonIdr} / {sessionStats.nalCounts.sei}
+              </div>
+            </div>
+            <div>
+              <div className="metric-label">Skipped Before Keyframe</div>
+              <div className="metric-value">{sessionStats.undecodableFramesSkipped}</div>
+            </div>
           </div>
         </section>
 
@@ -334,9 +483,81 @@ export default function DiagnosticsPage() {
             <button className="secondary-button" type="button" onClick={handleClearLogView}>Clear View</button>
             <button className="secondary-button" type="button" onClick={handleExportLogs}>Export Logs</button>
             <button className="secondary-button" type="button" onClick={handleExportDiagnostics}>Export Diagnostics</button>
+            <button className="secondary-button" type="button" onClick={handleTakeScreenshot}>Take Screenshot</button>
+            <button className="secondary-button" type="button" onClick={handleClientScreenshot}>Client Screenshot</button>
             <Link className="ghost-button" href="/">Return</Link>
           </div>
         </section>
+        <section className="card settings-card">
+          <div className="card-header">
+            <div className="card-title">Recording</div>
+            <div className="card-subtitle">Save the frames sent to the device</div>
+          </div>
+          <div className="form-grid prefs-grid">
+            <label className="form-field">
+              <span className="form-label">Format</span>
+              <select
+                className="form-input"
+                value={recordingFormat}
+                disabled={recording.active}
+                onChange={(event) => setRecordingFormat(event.target.value as "raw" | "mp4")}
+              >
+                <option value="mp4">Fragmented MP4</option>
+                <option value="raw">Raw H.264 / H.265</option>
+              </select>
+            </label>
+            <label className="form-field">
+              <span className="form-label">Size Cap (MB)</span>
+              <input
+                className="form-input"
+                type="number"
+                min={0}
+                value={recordingMaxMb}
+                disabled={recording.active}
+                onChange={(event) => setRecordingMaxMb(Number(event.target.value))}
+              />
+              <span className="form-note">0 disables the cap.</span>
+            </label>
+            <label className="form-field">
+              <span className="form-label">Duration Cap (min)</span>
+              <input
+                className="form-input"
+                type="number"
+                min={0}
+                value={recordingMaxMinutes}
+                disabled={recording.active}
+                onChange={(event) => setRecordingMaxMinutes(Number(event.target.value))}
+              />
+              <span className="form-note">0 disables the cap.</span>
+            </label>
+          </div>
+          <div className="status-metrics">
+            <div>
+              <div className="metric-label">State</div>
+              <div className="metric-value">{recording.active ? "Recording" : recording.stopReason ?? "Idle"}</div>
+            </div>
+            <div>
+              <div className="metric-label">Frames</div>
+              <div className="metric-value">{recording.frames}</div>
+            </div>
+            <div>
+              <div className="metric-label">Size</div>
+              <div className="metric-value">{(recording.bytes / (1024 * 1024)).toFixed(1)} MB</div>
+            </div>
+            <div>
+              <div className="metric-label">Duration</div>
+              <div className="metric-value">{(recording.durationMs / 1000).toFixed(1)} s</div>
+            </div>
+          </div>
+          <div className="divider" />
+          <div className="connect-actions">
+            {recording.active ? (
+              <button className="primary-button" type="button" onClick={handleStopRecording}>Stop Recording</button>
+            ) : (
+              <button className="primary-button" type="button" onClick={handleStartRecording}>Start Recording</button>
+            )}
+          </div>
+        </section>
         <section className="card settings-card">
         <div className="card-header">
           <div className="card-title">Displays</div>
@@ -7,7 +7,14 @@ import Toast, { type ToastState } from "./components/Toast";
 type AppStatus = {
   protocolVersion: number;
   driver: { installed: boolean; active: boolean };
-  transport: { tcpListening: boolean; tcpConnections: number; aoapAttached: boolean };
+  transport: {
+    tcpListening: boolean;
+    tcpConnections: number;
+    listenAddress?: string | null;
+    quicAddress?: string | null;
+    traffic?: { packetsSent: number; bytesSent: number; packetsReceived: number; bytesReceived: number };
+    aoapAttached: boolean;
+  };
   settings: { codec: string; quality: number; refreshCapHz: number; keyframeInterval: number; inputMode: string };
   session: { lifecycle: string };
   devices: Array<{
@@ -22,14 +29,60 @@ type AppStatus = {
       pen: boolean;
       keyboard: boolean;
     };
+    peerFingerprint?: string | null;
+    address?: string | null;
+    port?: number | null;
   }>;
 };
 
+type PairingQr = {
+  payload: string;
+  image: string;
+  expiresAt: number;
+};
+
+type PendingApproval = {
+  id: string;
+  address: string;
+  port: number;
+  fingerprint?: string | null;
+  code?: string | null;
+  requestedAt: number;
+};
+
+type ConnectCandidate = {
+  id: string;
+  name: string;
+  transport: string;
+  address?: string | null;
+  port?: number | null;
+};
+
+type DiscoveredDevice = ConnectCandidate & {
+  protocolVersion: number;
+  fingerprint?: string | null;
+  source: string;
+  lastSeen: number;
+  pairedId?: string | null;
+  pairedName?: string | null;
+  pairedAddress?: string | null;
+};
+
 type CodecSelection = {
   codecId: number;
   codecName: string;
   hostMask: number;
   clientMask: number;
+  codecProfile: number;
+  codecLevel: number;
+  codecFlags: number;
+  candidates: {
+    codecId: number;
+    codecName: string;
+    eligible: boolean;
+    selected: boolean;
+    reason: string;
+  }[];
 };
 
 type SessionStats = {
@@ -62,7 +115,7 @@ type DeviceForm = {
 const fallbackStatus: AppStatus = {
   protocolVersion: 4,
   driver: { installed: false, active: false },
-  transport: { tcpListening: true, tcpConnections: 0, aoapAttached: false },
+  transport: { tcpListening: false, tcpConnections: 0, listenAddress: null, quicAddress: null, aoapAttached: false },
   settings: { codec: "H.264 High", quality: 80, refreshCapHz: 120, keyframeInterval: 60, inputMode: "Touch + Pen" },
   session: { lifecycle: "idle" },
   devices: [],
@@ -111,6 +164,11 @@ export default function HomePage() {
   const [editingDeviceId, setEditingDeviceId] = useState<string | null>(null);
   const [codecSelection, setCodecSelection] = useState<CodecSelection | null>(null);
   const [sessionStats, setSessionStats] = useState<SessionStats>(fallbackStats);
+  const [approvals, setApprovals] = useState<PendingApproval[]>([]);
+  const [usbCandidates, setUsbCandidates] = useState<ConnectCandidate[]>([]);
+  const [discovered, setDiscovered] = useState<DiscoveredDevice[]>([]);
+  const [qrPayload, setQrPayload] = useState("");
+  const [hostQr, setHostQr] = useState<PairingQr | null>(null);
   const [tcpForm, setTcpForm] = useState({
     host: "",
     port: 1445,
@@ -163,9 +221,61 @@ export default function HomePage() {
       }
     };
 
+    const loadApprovals = async () => {
+      try {
+        const { invoke } = await import("@tauri-apps/api/tauri");
+        const data = await invoke<PendingApproval[]>("list_pending_approvals");
+        if (!cancelled) {
+          setApprovals(data ?? []);
+        }
+      } catch (_error) {
+        if (!cancelled) {
+          setApprovals([]);
+        }
+      }
+    };
+
+    const loadUsbCandidates = async () => {
+      try {
+        const { invoke } = await import("@tauri-apps/api/tauri");
+        const data = await invoke<ConnectCandidate[]>("adb_candidates");
+        if (!cancelled) {
+          setUsbCandidates(data ?? []);
+        }
+      } catch (_error) {
+        if (!cancelled) {
+          setUsbCandidates([]);
+        }
+      }
+    };
+
+    const loadDiscovered = async () => {
+      try {
+        const { invoke } = await import("@tauri-apps/api/tauri");
+        const data = await invoke<DiscoveredDevice[]>("discovered_devices");
+        if (!cancelled) {
+          setDiscovered(data ?? []);
+        }
+      } catch (_error) {
+        if (!cancelled) {
+          setDiscovered([]);
+        }
+      }
+    };
+
     loadStatus();
     loadSessionStats();
-    statsTimer = setInterval(loadSessionStats, 2500);
+    loadApprovals();
+    loadUsbCandidates();
+    loadDiscovered();
+    // Clients dialing the listener can queue approvals at any time, and phones
+    // come and go on USB and the LAN.
+    statsTimer = setInterval(() => {
+      loadSessionStats();
+      loadApprovals();
+      loadUsbCandidates();
+      loadDiscovered();
+    }, 2500);
     return () => {
       cancelled = true;
       if (statsTimer) {
@@ -226,6 +336,35 @@ export default function HomePage() {
     }
   };
 
+  const handlePairFromQr = async () => {
+    if (!qrPayload.trim()) {
+      pushToast("Pairing code is required.", "error");
+      return;
+    }
+    try {
+      const list = await invokeTauri<AppStatus["devices"]>("pair_from_qr_payload", {
+        payload: qrPayload.trim(),
+      });
+      setDevices(list ?? []);
+      setQrPayload("");
+      setPairingOpen(false);
+      pushToast("Device paired from code.", "success");
+    } catch (err) {
+      pushToast(`Unable to pair: ${String(err)}`, "error");
+      console.error(err);
+    }
+  };
+
+  const handleShowHostQr = async () => {
+    try {
+      const qr = await invokeTauri<PairingQr>("host_pairing_qr", { format: "png" });
+      setHostQr(qr);
+    } catch (err) {
+      pushToast("Unable to create a pairing QR.", "error");
+      console.error(err);
+    }
+  };
+
   const handleEditOpen = (device: AppStatus["devices"][number]) => {
     setForm({
       name: device.name,
@@ -294,6 +433,10 @@ export default function HomePage() {
     try {
       const list = await invokeTauri<AppStatus["devices"]>("connect_device", { deviceId });
       setDevices(list ?? []);
+      const device = list?.find((item) => item.id === deviceId);
+      if (device?.address) {
+        setTcpForm((prev) => ({ ...prev, host: device.address ?? prev.host, port: device.port ?? prev.port }));
+      }
       pushToast("Connection requested.", "success");
     } catch (err) {
       pushToast("Unable to connect device.", "error");
@@ -338,11 +481,70 @@ export default function HomePage() {
       setCodecSelection(selection);
       pushToast(`TCP connected. Selected codec: ${selection.codecName}.`, "success");
     } catch (err) {
-      pushToast("Unable to connect via TCP.", "error");
+      const message = String(err);
+      const pending = await invokeTauri<PendingApproval[]>("list_pending_approvals").catch(() => []);
+      setApprovals(pending ?? []);
+      if ((pending ?? []).length > 0 && !message.startsWith("SECURITY")) {
+        pushToast("New device: approve the connection below.", "info");
+      } else if (message.startsWith("SECURITY")) {
+        pushToast("Device identity changed. Connection refused.", "error");
+      } else {
+        pushToast("Unable to connect via TCP.", "error");
+      }
+      console.error(err);
+    }
+  };
+
+  const handleApproveConnection = async (approval: PendingApproval) => {
+    try {
+      const list = await invokeTauri<AppStatus["devices"]>("approve_pending_connection", {
+        id: approval.id,
+      });
+      setDevices(list ?? []);
+      setApprovals((prev) => prev.filter((item) => item.id !== approval.id));
+      pushToast("Device approved. Connect again to start.", "success");
+    } catch (err) {
+      pushToast("Unable to approve device.", "error");
+      console.error(err);
+    }
+  };
+
+  const handleRejectConnection = async (approval: PendingApproval) => {
+    try {
+      await invokeTauri("reject_pending_connection", { id: approval.id });
+    } catch (err) {
+      console.error(err);
+    }
+    setApprovals((prev) => prev.filter((item) => item.id !== approval.id));
+  };
+
+  const handleUseCandidate = async (candidate: ConnectCandidate) => {
+    try {
+      const prepared = await invokeTauri<ConnectCandidate>("adb_prepare", {
+        serial: candidate.id.replace(/^adb:/, ""),
+      });
+      setUsbCandidates((prev) => prev.map((item) => (item.id === prepared.id ? prepared : item)));
+      setTcpForm((prev) => ({
+        ...prev,
+        host: prepared.address ?? prev.host,
+        port: prepared.port ?? prev.port,
+      }));
+      pushToast(`${prepared.name} is ready. Connect + Configure to start.`, "success");
+    } catch (err) {
+      pushToast(`Unable to set up ${candidate.name} over ADB.`, "error");
       console.error(err);
     }
   };
 
+  const handleUseDiscovered = (device: DiscoveredDevice) => {
+    setTcpForm((prev) => ({
+      ...prev,
+      host: device.address ?? prev.host,
+      port: device.port ?? prev.port,
+    }));
+    pushToast(`${device.name} selected. Connect + Configure to start.`, "success");
+  };
+
   const handleTcpDisconnect = async () => {
     try {
       await invokeTauri("tcp_disconnect");
@@ -360,8 +562,10 @@ export default function HomePage() {
     : "Driver Missing";
   const usbChip = status.transport.aoapAttached ? "USB Attached" : "USB Idle";
   const wifiChip = status.transport.tcpListening
-    ? `Wi-Fi Ready (${status.transport.tcpConnections})`
-    : "Wi-Fi Offline";
+    ? `Wi-Fi Listening on ${status.transport.listenAddress} (${status.transport.tcpConnections})`
+    : status.transport.tcpConnections > 0
+      ? `Wi-Fi Connected (${status.transport.tcpConnections})`
+      : "Wi-Fi Offline";
   const sessionLifecycle = status.session?.lifecycle ?? "idle";
   const sessionLabel = sessionLifecycle.charAt(0).toUpperCase() + sessionLifecycle.slice(1);
   const formatLastSeen = (value?: string | null) => {
@@ -437,7 +641,11 @@ export default function HomePage() {
               Encoder Standby
             </div>
             <div className="chip">
-              <span className={`chip-dot ${status.transport.tcpListening ? "ok" : "warn"}`} />
+              <span
+                className={`chip-dot ${
+                  status.transport.tcpListening || status.transport.tcpConnections > 0 ? "ok" : "warn"
+                }`}
+              />
               {wifiChip}
             </div>
           </div>
@@ -647,11 +855,66 @@ export default function HomePage() {
               </div>
             </form>
           )}
+          {pairingOpen && !editingDeviceId && (
+            <div className="pair-form">
+              <div className="form-grid">
+                <label className="form-field">
+                  <span className="form-label">Pairing Code</span>
+                  <input
+                    className="form-input"
+                    value={qrPayload}
+                    onChange={(event) => setQrPayload(event.target.value)}
+                    placeholder="uberdisplay:..."
+                  />
+                  <span className="form-note">Paste the code scanned from the device&apos;s QR.</span>
+                </label>
+              </div>
+              <div className="form-actions">
+                <button className="secondary-button" type="button" onClick={handleShowHostQr}>
+                  Show This PC&apos;s QR
+                </button>
+                <button className="primary-button" type="button" onClick={handlePairFromQr}>
+                  Pair From Code
+                </button>
+              </div>
+              {hostQr && (
+                <div className="form-note">
+                  <img src={hostQr.image} alt="Pairing QR code" width={256} height={256} />
+                  <div>Expires at {new Date(hostQr.expiresAt * 1000).toLocaleTimeString()}.</div>
+                </div>
+              )}
+            </div>
+          )}
           <div className="divider" />
           <div className="card-header">
             <div className="card-title">TCP Session</div>
             <div className="card-subtitle">Manual connect + configure</div>
           </div>
+          {usbCandidates.map((candidate) => (
+            <div className="form-note" key={candidate.id}>
+              <strong>{candidate.name}</strong> on {candidate.transport}
+              {candidate.port ? ` — forwarded to ${candidate.address}:${candidate.port}` : ""}
+              <div className="form-actions">
+                <button className="secondary-button" type="button" onClick={() => handleUseCandidate(candidate)}>
+                  Use
+                </button>
+              </div>
+            </div>
+          ))}
+          {discovered.map((device) => (
+            <div className="form-note" key={device.id}>
+              <strong>{device.name}</strong> at {device.address}:{device.port} via {device.source}
+              {device.pairedId
+                ? ` — paired as ${device.pairedName}${device.pairedAddress ? `, last at ${device.pairedAddress}` : ""}`
+                : ""}
+              {` — seen ${Math.max(0, Math.round(Date.now() / 1000 - device.lastSeen))}s ago`}
+              <div className="form-actions">
+                <button className="secondary-button" type="button" onClick={() => handleUseDiscovered(device)}>
+                  Use
+                </button>
+              </div>
+            </div>
+          ))}
           <details className="accordion">
             <summary>Open TCP Session</summary>
             <div className="accordion-body">
@@ -828,9 +1091,39 @@ export default function HomePage() {
               </form>
             </div>
           </details>
+          {approvals.map((approval) => (
+            <div className="form-note" key={approval.id}>
+              Connection request from{" "}
+              <strong>{approval.port ? `${approval.address}:${approval.port}` : approval.address}</strong>
+              {approval.code && approval.fingerprint ? (
+                <>
+                  {" "}with pairing code <strong>{approval.code}</strong> for device{" "}
+                  {approval.fingerprint.slice(0, 16)}. Approve only if the device shows the same code.
+                </>
+              ) : (
+                ". Approve only if you recognize this device."
+              )}
+              <div className="form-actions">
+                <button className="secondary-button" type="button" onClick={() => handleRejectConnection(approval)}>
+                  Reject
+                </button>
+                <button className="primary-button" type="button" onClick={() => handleApproveConnection(approval)}>
+                  Approve
+                </button>
+              </div>
+            </div>
+          ))}
           {codecSelection && (
             <div className="form-note">
-              Negotiated codec: {codecSelection.codecName} (host {codecSelection.hostMask}, client {codecSelection.clientMask})
+              Negotiated codec: {codecSelection.codecName} (host {codecSelection.hostMask}, client {codecSelection.clientMask}
+              , profile {codecSelection.codecProfile}, level {codecSelection.codecLevel}, flags {codecSelection.codecFlags})
+              <ul>
+                {codecSelection.candidates.map((candidate) => (
+                  <li key={candidate.codecId}>
+                    {candidate.selected ? "✓" : candidate.eligible ? "•" : "✗"} {candidate.codecName}: {candidate.reason}
+                  </li>
+                ))}
+              </ul>
             </div>
           )}
           <div className="divider" />
@@ -7,8 +7,45 @@ import Toast, { type ToastState } from "../components/Toast";
 type AppStatus = {
   protocolVersion: number;
   driver: { installed: boolean; active: boolean };
-  transport: { tcpListening: boolean; tcpConnections: number; aoapAttached: boolean };
-  settings: { codec: string; quality: number; refreshCapHz: number; keyframeInterval: number; inputMode: string };
+  transport: {
+    tcpListening: boolean;
+    tcpConnections: number;
+    listenAddress?: string | null;
+    quicAddress?: string | null;
+    traffic?: { packetsSent: number; bytesSent: number; packetsReceived: number; bytesReceived: number };
+    aoapAttached: boolean;
+  };
+  settings: {
+    codec: string;
+    quality: number;
+    refreshCapHz: number;
+    keyframeInterval: number;
+    inputMode: string;
+    enableEvc: boolean;
+    enableLcevc: boolean;
+    colorMatrix: string;
+    fullRangeColor: boolean;
+    scaleFilter: string;
+    scaleFit: string;
+    cryptoMode: string;
+    cipher: string;
+    psk: string;
+    rekeyAfterMb: number;
+    rekeyAfterMinutes: number;
+    authMode: string;
+    allowedSubnets: string[];
+    listenEnabled: boolean;
+    listenAddress: string;
+    listenPort: number;
+    maxSessions: number;
+    udpMedia: boolean;
+    fecGroup: number;
+    quicEnabled: boolean;
+    quicPort: number;
+    aoapEnabled: boolean;
+    adbEnabled: boolean;
+    discoveryEnabled: boolean;
+  };
   devices: Array<{
     id: string;
     name: string;
@@ -21,11 +58,46 @@ type AppStatus = {
 const fallbackStatus: AppStatus = {
   protocolVersion: 4,
   driver: { installed: false, active: false },
-  transport: { tcpListening: false, tcpConnections: 0, aoapAttached: false },
-  settings: { codec: "H.264 High", quality: 80, refreshCapHz: 120, keyframeInterval: 60, inputMode: "Touch + Pen" },
+  transport: { tcpListening: false, tcpConnections: 0, listenAddress: null, quicAddress: null, aoapAttached: false },
+  settings: {
+    codec: "H.264 High",
+    quality: 80,
+    refreshCapHz: 120,
+    keyframeInterval: 60,
+    inputMode: "Touch + Pen",
+    enableEvc: false,
+    enableLcevc: false,
+    colorMatrix: "BT.709",
+    fullRangeColor: false,
+    scaleFilter: "Bicubic",
+    scaleFit: "Stretch",
+    cryptoMode: "PSK",
+    cipher: "Auto",
+    psk: "",
+    rekeyAfterMb: 1024,
+    rekeyAfterMinutes: 30,
+    authMode: "Prompt for unknown",
+    allowedSubnets: [],
+    listenEnabled: false,
+    listenAddress: "0.0.0.0",
+    listenPort: 1445,
+    maxSessions: 1,
+    udpMedia: false,
+    fecGroup: 0,
+    quicEnabled: false,
+    quicPort: 1446,
+    aoapEnabled: false,
+    adbEnabled: false,
+    discoveryEnabled: false,
+  },
   devices: [],
 };
 
+type CaptureWindow = {
+  handle: number;
+  title: string;
+};
+
 type DisplayInfo = {
   id: string;
   name: string;
@@ -45,6 +117,10 @@ export default function PreferencesPage() {
   const [displays, setDisplays] = useState<DisplayInfo[]>([]);
   const [virtualDisplays, setVirtualDisplays] = useState<DisplayInfo[]>([]);
   const [displayTarget, setDisplayTarget] = useState("auto");
+  const [captureKind, setCaptureKind] = useState<"display" | "region" | "window">("display");
+  const [captureRegion, setCaptureRegion] = useState({ x: 0, y: 0, width: 1280, height: 720 });
+  const [captureWindows, setCaptureWindows] = useState<CaptureWindow[]>([]);
+  const [captureWindow, setCaptureWindow] = useState("");
   const [virtualDisplayLabel, setVirtualDisplayLabel] = useState("UberDisplay");
   const [virtualDisplayCount, setVirtualDisplayCount] = useState(1);
   const [driverStatus, setDriverStatus] = useState<{
@@ -66,6 +142,7 @@ export default function PreferencesPage() {
     hardwareCursor: false,
   });
   const [driverGpuName, setDriverGpuName] = useState("");
+  const [hostFingerprint, setHostFingerprint] = useState("");
   const [linuxConfig, setLinuxConfig] = useState({
     baseDisplay: 99,
     width: 2560,
@@ -122,6 +199,20 @@ export default function PreferencesPage() {
       }
     };
 
+    const loadHostFingerprint = async () => {
+      try {
+        const { invoke } = await import("@tauri-apps/api/tauri");
+        const value = await invoke<string>("host_identity_fingerprint");
+        if (!cancelled) {
+          setHostFingerprint(value);
+        }
+      } catch (_error) {
+        if (!cancelled) {
+          setHostFingerprint("");
+        }
+      }
+    };
+
     const loadDisplays = async () => {
       try {
         const { invoke } = await import("@tauri-apps/api/tauri");
@@ -155,6 +246,7 @@ export default function PreferencesPage() {
     loadPlatform();
     loadStatus();
     loadDisplays();
+    loadHostFingerprint();
     loadVirtualDisplays();
     return () => {
       cancelled = true;
@@ -246,12 +338,38 @@ export default function PreferencesPage() {
     }
   };
 
+  const handleRefreshCaptureWindows = async () => {
+    try {
+      const { invoke } = await import("@tauri-apps/api/tauri");
+      const windows = await invoke<CaptureWindow[]>("list_capture_windows");
+      setCaptureWindows(windows);
+    } catch (err) {
+      pushToast("Unable to list windows.", "error");
+      console.error(err);
+    }
+  };
+
   const handleDisplayTargetSave = async () => {
     try {
       const { invoke } = await import("@tauri-apps/api/tauri");
-      const payload = displayTarget === "auto" ? null : displayTarget;
-      await invoke("set_session_display_target", { displayId: payload });
-      pushToast("Display target updated.", "success");
+      const displayId = displayTarget === "auto" ? null : displayTarget;
+      if (captureKind === "window") {
+        const selected = captureWindows.find((window) => String(window.handle) === captureWindow);
+        if (!selected) {
+          pushToast("Pick a window to capture.", "error");
+          return;
+        }
+        await invoke("set_session_capture_target", {
+          target: { kind: "window", handle: selected.handle, title: selected.title },
+        });
+      } else if (captureKind === "region") {
+        await invoke("set_session_capture_target", {
+          target: { kind: "region", displayId, ...captureRegion },
+        });
+      } else {
+        await invoke("set_session_display_target", { displayId });
+      }
+      pushToast("Capture target updated.", "success");
     } catch (err) {
       pushToast("Unable to update display target.", "error");
       console.error(err);
@@ -388,6 +506,30 @@ export default function PreferencesPage() {
         refreshCapHz: Number(form.refreshCapHz),
         keyframeInterval: Number(form.keyframeInterval),
         inputMode: form.inputMode,
+        enableEvc: form.enableEvc,
+        enableLcevc: form.enableLcevc,
+        colorMatrix: form.colorMatrix,
+        fullRangeColor: form.fullRangeColor,
+        scaleFilter: form.scaleFilter,
+        scaleFit: form.scaleFit,
+        cryptoMode: form.cryptoMode,
+        cipher: form.cipher,
+        psk: form.psk,
+        rekeyAfterMb: Number(form.rekeyAfterMb),
+        rekeyAfterMinutes: Number(form.rekeyAfterMinutes),
+        authMode: form.authMode,
+        allowedSubnets: form.allowedSubnets.map((subnet) => subnet.trim()).filter(Boolean),
+        listenEnabled: form.listenEnabled,
+        listenAddress: form.listenAddress.trim(),
+        listenPort: Number(form.listenPort),
+        maxSessions: Number(form.maxSessions),
+        udpMedia: form.udpMedia,
+        fecGroup: Number(form.fecGroup),
+        quicEnabled: form.quicEnabled,
+        quicPort: Number(form.quicPort),
+        aoapEnabled: form.aoapEnabled,
+        adbEnabled: form.adbEnabled,
+        discoveryEnabled: form.discoveryEnabled,
       };
       const saved = await invoke<AppStatus["settings"]>("update_settings", { settings: payload });
       setStatus((prev) => ({ ...prev, settings: saved }));
@@ -399,6 +541,18 @@ export default function PreferencesPage() {
     }
   };
 
+  const handleGeneratePsk = async () => {
+    try {
+      const { invoke } = await import("@tauri-apps/api/tauri");
+      const psk = await invoke<string>("generate_psk");
+      setForm({ ...form, psk });
+      pushToast("Generated a new key. Save, then enter it on the device.", "info");
+    } catch (err) {
+      pushToast("Unable to generate a key.", "error");
+      console.error(err);
+    }
+  };
+
   const handleReset = async () => {
     try {
       const { invoke } = await import("@tauri-apps/api/tauri");
@@ -583,6 +737,284 @@ export default function PreferencesPage() {
                 <span className="form-note">Shown on the host and sent to clients.</span>
               </label>
             </div>
+            <div className="form-toggle-row">
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.enableEvc}
+                  onChange={(event) => setForm({ ...form, enableEvc: event.target.checked })}
+                />
+                Allow EVC
+              </label>
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.enableLcevc}
+                  onChange={(event) => setForm({ ...form, enableLcevc: event.target.checked })}
+                />
+                Allow MPEG-5 LCEVC
+              </label>
+            </div>
+            <span className="form-note">Opt-in codecs are only negotiated when allowed here and supported on both sides.</span>
+            <div className="form-grid prefs-grid">
+              <label className="form-field">
+                <span className="form-label">Color Matrix</span>
+                <select
+                  className="form-input"
+                  value={form.colorMatrix}
+                  onChange={(event) => setForm({ ...form, colorMatrix: event.target.value })}
+                >
+                  <option value="BT.709">BT.709 (HD)</option>
+                  <option value="BT.601">BT.601 (SD)</option>
+                  <option value="BT.2020">BT.2020</option>
+                </select>
+                <span className="form-note">Used for RGB to YUV conversion and signalled in Configure.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Scaling Filter</span>
+                <select
+                  className="form-input"
+                  value={form.scaleFilter}
+                  onChange={(event) => setForm({ ...form, scaleFilter: event.target.value })}
+                >
+                  <option value="Bilinear">Bilinear (fastest)</option>
+                  <option value="Bicubic">Bicubic</option>
+                  <option value="Lanczos">Lanczos (sharpest text)</option>
+                </select>
+                <span className="form-note">Applied when the display and stream resolutions differ.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Aspect Ratio</span>
+                <select
+                  className="form-input"
+                  value={form.scaleFit}
+                  onChange={(event) => setForm({ ...form, scaleFit: event.target.value })}
+                >
+                  <option value="Stretch">Stretch to fill</option>
+                  <option value="Letterbox">Letterbox</option>
+                  <option value="Crop">Crop</option>
+                </select>
+                <span className="form-note">Letterbox pads with black bars; crop trims the edges.</span>
+              </label>
+            </div>
+            <div className="form-toggle-row">
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.fullRangeColor}
+                  onChange={(event) => setForm({ ...form, fullRangeColor: event.target.checked })}
+                />
+                Full-range color (0-255)
+              </label>
+            </div>
+            <div className="form-grid prefs-grid">
+              <label className="form-field">
+                <span className="form-label">Encryption</span>
+                <select
+                  className="form-input"
+                  value={form.cryptoMode}
+                  onChange={(event) => setForm({ ...form, cryptoMode: event.target.value })}
+                >
+                  <option value="PSK">Pre-shared key</option>
+                  <option value="Public key">Public key (pairing code)</option>
+                  <option value="Off (debug)">Off (debug only)</option>
+                </select>
+                <span className="form-note">
+                  {form.cryptoMode === "Off (debug)"
+                    ? "Warning: video, input and control traffic is sent unencrypted."
+                    : form.cryptoMode === "Public key"
+                      ? `New devices are paired by comparing a code. This PC: ${hostFingerprint.slice(0, 16) || "unknown"}`
+                      : "Sessions fail unless the device uses the same key."}
+                </span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Cipher</span>
+                <select
+                  className="form-input"
+                  value={form.cipher}
+                  onChange={(event) => setForm({ ...form, cipher: event.target.value })}
+                >
+                  <option value="Auto">Auto</option>
+                  <option value="AES-256-GCM">AES-256-GCM</option>
+                  <option value="ChaCha20-Poly1305">ChaCha20-Poly1305</option>
+                </select>
+                <span className="form-note">Auto prefers AES-GCM when this PC accelerates AES.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Pre-shared Key</span>
+                <input
+                  className="form-input"
+                  type="password"
+                  value={form.psk}
+                  onChange={(event) => setForm({ ...form, psk: event.target.value })}
+                />
+                <button className="secondary-button" type="button" onClick={handleGeneratePsk}>
+                  Generate
+                </button>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Rotate Key After (MiB)</span>
+                <input
+                  className="form-input"
+                  type="number"
+                  min={0}
+                  value={form.rekeyAfterMb}
+                  onChange={(event) => setForm({ ...form, rekeyAfterMb: Number(event.target.value) })}
+                />
+              </label>
+              <label className="form-field">
+                <span className="form-label">Rotate Key After (minutes)</span>
+                <input
+                  className="form-input"
+                  type="number"
+                  min={0}
+                  value={form.rekeyAfterMinutes}
+                  onChange={(event) =>
+                    setForm({ ...form, rekeyAfterMinutes: Number(event.target.value) })
+                  }
+                />
+                <span className="form-note">Whichever comes first; 0 uses the default.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Connection Authorization</span>
+                <select
+                  className="form-input"
+                  value={form.authMode}
+                  onChange={(event) => setForm({ ...form, authMode: event.target.value })}
+                >
+                  <option value="Paired devices only">Paired devices only</option>
+                  <option value="Prompt for unknown">Prompt for unknown devices</option>
+                  <option value="Open (debug)">Open (debug only)</option>
+                </select>
+                <span className="form-note">
+                  {form.authMode === "Open (debug)"
+                    ? "Warning: any device on the allowed subnets can connect."
+                    : "Five failed pairing attempts block an address for 10 minutes."}
+                </span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Allowed Subnets</span>
+                <input
+                  className="form-input"
+                  type="text"
+                  placeholder="192.168.1.0/24, fd00::/8"
+                  value={form.allowedSubnets.join(",")}
+                  onChange={(event) =>
+                    setForm({ ...form, allowedSubnets: event.target.value.split(",") })
+                  }
+                />
+                <span className="form-note">Comma separated; empty allows any address.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">Listen Address</span>
+                <input
+                  className="form-input"
+                  type="text"
+                  placeholder="0.0.0.0"
+                  value={form.listenAddress}
+                  onChange={(event) => setForm({ ...form, listenAddress: event.target.value })}
+                />
+              </label>
+              <label className="form-field">
+                <span className="form-label">Listen Port</span>
+                <input
+                  className="form-input"
+                  type="number"
+                  min={1}
+                  max={65535}
+                  value={form.listenPort}
+                  onChange={(event) => setForm({ ...form, listenPort: Number(event.target.value) })}
+                />
+              </label>
+              <label className="form-field">
+                <span className="form-label">Simultaneous Clients</span>
+                <input
+                  className="form-input"
+                  type="number"
+                  min={1}
+                  max={8}
+                  value={form.maxSessions}
+                  onChange={(event) => setForm({ ...form, maxSessions: Number(event.target.value) })}
+                />
+                <span className="form-note">Extra clients mirror the first; others are turned away.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">FEC Group</span>
+                <input
+                  className="form-input"
+                  type="number"
+                  min={0}
+                  max={32}
+                  value={form.fecGroup}
+                  onChange={(event) => setForm({ ...form, fecGroup: Number(event.target.value) })}
+                />
+                <span className="form-note">Fragments per parity fragment for video over UDP; 0 turns FEC off.</span>
+              </label>
+              <label className="form-field">
+                <span className="form-label">QUIC Port</span>
+                <input
+                  className="form-input"
+                  type="number"
+                  min={1}
+                  max={65535}
+                  value={form.quicPort}
+                  onChange={(event) => setForm({ ...form, quicPort: Number(event.target.value) })}
+                />
+              </label>
+            </div>
+            <div className="form-toggle-row">
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.listenEnabled}
+                  onChange={(event) => setForm({ ...form, listenEnabled: event.target.checked })}
+                />
+                Accept connections from clients (listener)
+                {status.transport.listenAddress ? ` — listening on ${status.transport.listenAddress}` : ""}
+              </label>
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.udpMedia}
+                  onChange={(event) => setForm({ ...form, udpMedia: event.target.checked })}
+                />
+                Send video over UDP to clients that support it (encrypted sessions only)
+              </label>
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.quicEnabled}
+                  onChange={(event) => setForm({ ...form, quicEnabled: event.target.checked })}
+                />
+                Move sessions to QUIC when the client supports it (public-key mode only)
+                {status.transport.quicAddress ? ` — on ${status.transport.quicAddress}` : ""}
+              </label>
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.aoapEnabled}
+                  onChange={(event) => setForm({ ...form, aoapEnabled: event.target.checked })}
+                />
+                Stream to phones plugged in over USB (accessory mode, Linux)
+                {status.transport.aoapAttached ? " — attached" : ""}
+              </label>
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.adbEnabled}
+                  onChange={(event) => setForm({ ...form, adbEnabled: event.target.checked })}
+                />
+                List phones from the local adb server as USB devices
+              </label>
+              <label className="form-toggle">
+                <input
+                  type="checkbox"
+                  checked={form.discoveryEnabled}
+                  onChange={(event) => setForm({ ...form, discoveryEnabled: event.target.checked })}
+                />
+                Find phones on the local network (mDNS and broadcast)
+              </label>
+            </div>
           </form>
         </section>
 
@@ -800,25 +1232,84 @@ export default function PreferencesPage() {
         <section className="card settings-card">
           <div className="card-header">
             <div className="card-title">Display Targets</div>
-            <div className="card-subtitle">Assign sessions to a display output</div>
+            <div className="card-subtitle">Assign sessions to a display, region or window</div>
           </div>
           <div className="form-grid prefs-grid">
             <label className="form-field">
-              <span className="form-label">Target Display</span>
+              <span className="form-label">Capture</span>
               <select
                 className="form-input"
-                value={displayTarget}
-                onChange={(event) => setDisplayTarget(event.target.value)}
+                value={captureKind}
+                onChange={(event) => {
+                  const kind = event.target.value as "display" | "region" | "window";
+                  setCaptureKind(kind);
+                  if (kind === "window") {
+                    handleRefreshCaptureWindows();
+                  }
+                }}
               >
-                <option value="auto">Auto (Primary)</option>
-                {displays.map((display) => (
-                  <option key={display.id} value={display.id}>
-                    {display.name || display.id} ({display.width}x{display.height}@{display.refreshHz}Hz)
-                  </option>
-                ))}
+                <option value="display">Whole display</option>
+                <option value="region">Region</option>
+                <option value="window">Window</option>
               </select>
+              <span className="form-note">Touch and pen input follow the captured area.</span>
             </label>
+            {captureKind !== "window" && (
+              <label className="form-field">
+                <span className="form-label">Target Display</span>
+                <select
+                  className="form-input"
+                  value={displayTarget}
+                  onChange={(event) => setDisplayTarget(event.target.value)}
+                >
+                  <option value="auto">Auto (Primary)</option>
+                  {displays.map((display) => (
+                    <option key={display.id} value={display.id}>
+                      {display.name || display.id} ({display.width}x{display.height}@{display.refreshHz}Hz)
+                    </option>
+                  ))}
+                </select>
+              </label>
+            )}
+            {captureKind === "region" &&
+              (["x", "y", "width", "height"] as const).map((field) => (
+                <label className="form-field" key={field}>
+                  <span className="form-label">
+                    {field === "x" ? "Left" : field === "y" ? "Top" : field === "width" ? "Width" : "Height"}
+                  </span>
+                  <input
+                    className="form-input"
+                    type="number"
+                    min={field === "width" || field === "height" ? 1 : 0}
+                    value={captureRegion[field]}
+                    onChange={(event) =>
+                      setCaptureRegion((prev) => ({ ...prev, [field]: Number(event.target.value) }))
+                    }
+                  />
+                </label>
+              ))}
+            {captureKind === "window" && (
+              <label className="form-field">
+                <span className="form-label">Window</span>
+                <select
+                  className="form-input"
+                  value={captureWindow}
+                  onChange={(event) => setCaptureWindow(event.target.value)}
+                >
+                  <option value="">Select a window</option>
+                  {captureWindows.map((window) => (
+                    <option key={window.handle} value={String(window.handle)}>
+                      {window.title}
+                    </option>
+                  ))}
+                </select>
+                <span className="form-note">Found again by title if the window is recreated.</span>
+              </label>
+            )}
             <div className="form-actions align-left">
+              {captureKind === "window" && (
+                <button className="ghost-button" type="button" onClick={handleRefreshCaptureWindows}>Refresh</button>
+              )}
               <button className="secondary-button" type="button" onClick={handleDisplayTargetSave}>Apply</button>
             </div>
           </div>
@@ -0,0 +1,375 @@
+//! ADB-assisted USB setup. Talks to the local adb server's host protocol
+//! directly (no `adb` binary needed) to list attached phones, forward a local
+//! port to the client's listener, and launch the client app. The UI then dials
+//! the forwarded port like any network client.
+//!
+//! Requests are a 4-digit hex length and the request text; the server answers
+//! `OKAY`, or `FAIL` with a length-prefixed message.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::app_state::{ConnectCandidate, HostSettings};
+
+pub const DEFAULT_SERVER_PORT: u16 = 5037;
+/// Where the Android client listens for the host.
+const CLIENT_PORT: u16 = 1445;
+const CLIENT_ACTIVITY: &str = "com.supermarsx.uberdisplay/.MainActivity";
+pub const TRANSPORT_NAME: &str = "USB (ADB)";
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Local port forwarded for each device serial, so a device keeps its port
+/// while the host runs.
+static FORWARDS: OnceLock<Mutex<HashMap<String, u16>>> = OnceLock::new();
+
+fn forwards_store() -> &'static Mutex<HashMap<String, u16>> {
+    FORWARDS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// A device the adb server lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdbDevice {
+    pub serial: String,
+    /// `device` once authorized; `unauthorized`, `offline` and so on otherwise.
+    pub state: String,
+    pub model: Option<String>,
+}
+
+impl AdbDevice {
+    pub fn is_ready(&self) -> bool {
+        self.state == "device"
+    }
+
+    fn name(&self) -> String {
+        self.model
+            .as_deref()
+            .map(|model| model.replace('_', " "))
+            .unwrap_or_else(|| self.serial.clone())
+    }
+}
+
+pub struct AdbClient {
+    server: SocketAddr,
+}
+
+impl AdbClient {
+    pub fn local() -> Self {
+        Self::new(SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_SERVER_PORT)))
+    }
+
+    pub fn new(server: SocketAddr) -> Self {
+        Self { server }
+    }
+
+    pub fn devices(&self) -> Result<Vec<AdbDevice>, String> {
+        let mut stream = self.request("host:devices-l")?;
+        let listing = read_string(&mut stream)?;
+        Ok(parse_devices(&listing))
+    }
+
+    /// Forwards `local` on this machine to `remote` on the device.
+    pub fn forward(&self, serial: &str, local: u16, remote: u16) -> Result<(), String> {
+        let mut stream = self.request(&format!(
+            "host-serial:{serial}:forward:tcp:{local};tcp:{remote}"
+        ))?;
+        // The first status is for picking the device, the second for the forward.
+        read_status(&mut stream)
+    }
+
+    /// Runs a shell command on the device and returns its output.
+    pub fn shell(&self, serial: &str, command: &str) -> Result<String, String> {
+        let mut stream = self.request(&format!("host:transport:{serial}"))?;
+        send_request(&mut stream, &format!("shell:{command}"))?;
+        read_status(&mut stream)?;
+        let mut output = String::new();
+        stream
+            .read_to_string(&mut output)
+            .map_err(|err| format!("adb shell output: {err}"))?;
+        Ok(output)
+    }
+
+    /// Starts the UberDisplay client on the device.
+    pub fn launch(&self, serial: &str) -> Result<(), String> {
+        let output = self.shell(serial, &format!("am start -n {CLIENT_ACTIVITY}"))?;
+        if output.contains("Error") {
+            return Err(format!("Cannot start the client: {}", output.trim()));
+        }
+        Ok(())
+    }
+
+    /// Opens a connection and sends `request`, which the server must accept.
+    fn request(&self, request: &str) -> Result<TcpStream, String> {
+        let mut stream = TcpStream::connect_timeout(&self.server, IO_TIMEOUT)
+            .map_err(|err| format!("Cannot reach the adb server at {}: {err}", self.server))?;
+        stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        send_request(&mut stream, request)?;
+        read_status(&mut stream)?;
+        Ok(stream)
+    }
+}
+
+fn send_request(stream: &mut TcpStream, request: &str) -> Result<(), String> {
+    stream
+        .write_all(format!("{:04x}{request}", request.len()).as_bytes())
+        .map_err(|err| format!("adb request: {err}"))
+}
+
+fn read_status(stream: &mut TcpStream) -> Result<(), String> {
+    let mut status = [0u8; 4];
+    stream
+        .read_exact(&mut status)
+        .map_err(|err| format!("adb reply: {err}"))?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => Err(format!("adb: {}", read_string(stream)?)),
+        other => Err(format!(
+            "adb: unexpected reply {:?}",
+            String::from_utf8_lossy(other)
+        )),
+    }
+}
+
+fn read_string(stream: &mut TcpStream) -> Result<String, String> {
+    let mut length = [0u8; 4];
+    stream
+        .read_exact(&mut length)
+        .map_err(|err| format!("adb reply: {err}"))?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| "adb: malformed reply length".to_string())?;
+    let mut text = vec![0u8; length];
+    stream
+        .read_exact(&mut text)
+        .map_err(|err| format!("adb reply: {err}"))?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Parses `host:devices-l` output: a serial, a state, then `key:value` fields.
+pub fn parse_devices(listing: &str) -> Vec<AdbDevice> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let serial = fields.next()?.to_string();
+            let state = fields.next()?.to_string();
+            let model = fields
+                .find_map(|field| field.strip_prefix("model:"))
+                .map(str::to_string);
+            Some(AdbDevice {
+                serial,
+                state,
+                model,
+            })
+        })
+        .collect()
+}
+
+/// Ready devices as connect candidates, with their forward when they have one.
+pub fn candidates(settings: &HostSettings) -> Result<Vec<ConnectCandidate>, String> {
+    if !settings.adb_enabled {
+        return Ok(Vec::new());
+    }
+    list_candidates(&AdbClient::local())
+}
+
+fn list_candidates(client: &AdbClient) -> Result<Vec<ConnectCandidate>, String> {
+    let forwards = forwards_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?
+        .clone();
+    Ok(client
+        .devices()?
+        .into_iter()
+        .filter(AdbDevice::is_ready)
+        .map(|device| {
+            let port = forwards.get(&device.serial).copied();
+            candidate(&device, port)
+        })
+        .collect())
+}
+
+/// Forwards a local port to the client on `serial` and launches the app, so the
+/// host can dial the returned address.
+pub fn prepare(settings: &HostSettings, serial: &str) -> Result<ConnectCandidate, String> {
+    if !settings.adb_enabled {
+        return Err("ADB setup is turned off".to_string());
+    }
+    prepare_device(&AdbClient::local(), serial)
+}
+
+fn prepare_device(client: &AdbClient, serial: &str) -> Result<ConnectCandidate, String> {
+    let device = client
+        .devices()?
+        .into_iter()
+        .find(|device| device.serial == serial)
+        .ok_or_else(|| format!("{serial} is not attached"))?;
+    if !device.is_ready() {
+        return Err(format!("{} is {}", device.name(), device.state));
+    }
+    let known = forwards_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?
+        .get(serial)
+        .copied();
+    let port = match known {
+        Some(port) => port,
+        None => free_local_port()?,
+    };
+    // Forwarding again is harmless and restores one the adb server dropped.
+    client.forward(serial, port, CLIENT_PORT)?;
+    forwards_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?
+        .insert(serial.to_string(), port);
+    client.launch(serial)?;
+    Ok(candidate(&device, Some(port)))
+}
+
+fn candidate(device: &AdbDevice, port: Option<u16>) -> ConnectCandidate {
+    ConnectCandidate {
+        id: format!("adb:{}", device.serial),
+        name: device.name(),
+        transport: TRANSPORT_NAME.to_string(),
+        address: port.map(|_| Ipv4Addr::LOCALHOST.to_string()),
+        port,
+    }
+}
+
+fn free_local_port() -> Result<u16, String> {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|address| address.port())
+        .map_err(|err| format!("No free local port: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    const LISTING: &str =
+        "R58M12345 device usb:1-2 product:beyond1 model:SM_G973F device:beyond1 transport_id:3\n\
+                           emulator-5554 unauthorized transport_id:4\n";
+
+    enum Step {
+        /// Reads one request and reports it.
+        Read,
+        Write(Vec<u8>),
+    }
+
+    fn okay() -> Step {
+        Step::Write(b"OKAY".to_vec())
+    }
+
+    fn text(text: &str) -> Step {
+        Step::Write(format!("{:04x}{text}", text.len()).into_bytes())
+    }
+
+    /// An adb server that plays one script per connection, then closes it, and
+    /// reports the requests it saw.
+    fn fake_server(connections: Vec<Vec<Step>>) -> (AdbClient, Receiver<String>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let (seen, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for script in connections {
+                let (mut stream, _) = listener.accept().unwrap();
+                for step in script {
+                    match step {
+                        Step::Read => {
+                            let mut length = [0u8; 4];
+                            stream.read_exact(&mut length).unwrap();
+                            let length =
+                                usize::from_str_radix(std::str::from_utf8(&length).unwrap(), 16)
+                                    .unwrap();
+                            let mut request = vec![0u8; length];
+                            stream.read_exact(&mut request).unwrap();
+                            seen.send(String::from_utf8(request).unwrap()).unwrap();
+                        }
+                        Step::Write(bytes) => stream.write_all(&bytes).unwrap(),
+                    }
+                }
+            }
+        });
+        (AdbClient::new(address), requests)
+    }
+
+    fn listing() -> Vec<Step> {
+        vec![Step::Read, okay(), text(LISTING)]
+    }
+
+    #[test]
+    fn parses_the_long_device_listing() {
+        let devices = parse_devices(LISTING);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].serial, "R58M12345");
+        assert!(devices[0].is_ready());
+        assert_eq!(devices[0].name(), "SM G973F");
+        assert!(!devices[1].is_ready());
+        assert_eq!(devices[1].name(), "emulator-5554");
+    }
+
+    #[test]
+    fn prepares_a_device_and_lists_it_with_its_forward() {
+        let (client, requests) = fake_server(vec![
+            listing(),
+            vec![Step::Read, okay(), okay()],
+            vec![
+                Step::Read,
+                okay(),
+                Step::Read,
+                okay(),
+                Step::Write(
+                    b"Starting: Intent { cmp=com.supermarsx.uberdisplay/.MainActivity }\n".to_vec(),
+                ),
+            ],
+            listing(),
+        ]);
+
+        let prepared = prepare_device(&client, "R58M12345").unwrap();
+        assert_eq!(requests.recv().unwrap(), "host:devices-l");
+        let port = prepared.port.unwrap();
+        assert_eq!(
+            requests.recv().unwrap(),
+            format!("host-serial:R58M12345:forward:tcp:{port};tcp:1445")
+        );
+        assert_eq!(requests.recv().unwrap(), "host:transport:R58M12345");
+        assert_eq!(
+            requests.recv().unwrap(),
+            "shell:am start -n com.supermarsx.uberdisplay/.MainActivity"
+        );
+        assert_eq!(prepared.address.as_deref(), Some("127.0.0.1"));
+
+        let candidates = list_candidates(&client).unwrap();
+        assert_eq!(candidates, vec![prepared]);
+        assert_eq!(candidates[0].id, "adb:R58M12345");
+        assert_eq!(candidates[0].name, "SM G973F");
+        assert_eq!(candidates[0].transport, TRANSPORT_NAME);
+    }
+
+    #[test]
+    fn server_failures_come_back_as_errors() {
+        let mut fail = b"FAIL".to_vec();
+        fail.extend(b"000edevice offline");
+        let (client, _requests) = fake_server(vec![
+            listing(),
+            listing(),
+            vec![Step::Read, Step::Write(fail)],
+        ]);
+        assert_eq!(
+            prepare_device(&client, "emulator-5554").unwrap_err(),
+            "emulator-5554 is unauthorized"
+        );
+        assert_eq!(
+            prepare_device(&client, "R58M12345").unwrap_err(),
+            "adb: device offline"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Streams to phones plugged in over USB, using the Android Open Accessory
+//! protocol (`protocol::aoap`). While enabled, a watcher looks for a phone on the
+//! bus, switches it to accessory mode and runs the usual handshake over the
+//! accessory's bulk pair; the session then joins the others like a client that
+//! dialed in. Only Linux builds talk to USB, through libusb.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::app_state::HostSettings;
+use crate::host_log;
+use crate::host_transport::TransportHandle;
+
+/// How often the watcher looks at the bus while no accessory session runs.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+/// Pause after a failed session, so a phone that keeps refusing is not hammered.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+static WATCHER: OnceLock<Mutex<Option<Watcher>>> = OnceLock::new();
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+fn watcher_store() -> &'static Mutex<Option<Watcher>> {
+    WATCHER.get_or_init(|| Mutex::new(None))
+}
+
+struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+/// Starts or stops the USB watcher to match `settings`. A running accessory
+/// session is left alone.
+pub fn apply(
+    app_handle: &tauri::AppHandle,
+    transport: &TransportHandle,
+    settings: &HostSettings,
+) -> Result<(), String> {
+    let mut lock = watcher_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    if lock.is_some() == settings.aoap_enabled {
+        return Ok(());
+    }
+    if let Some(watcher) = lock.take() {
+        watcher.stop.store(true, Ordering::SeqCst);
+        let _ = watcher.thread.join();
+        ATTACHED.store(false, Ordering::SeqCst);
+        let _ = host_log::append_log(app_handle, "Stopped watching for USB accessories");
+        return Ok(());
+    }
+    if !cfg!(target_os = "linux") {
+        return Err("USB accessory mode is only available on Linux".to_string());
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let app_handle = app_handle.clone();
+        let transport = transport.clone();
+        let stop = stop.clone();
+        thread::spawn(move || watch(&app_handle, &transport, &stop))
+    };
+    *lock = Some(Watcher { stop, thread });
+    let _ = host_log::append_log(app_handle, "Watching for USB accessories");
+    Ok(())
+}
+
+/// Whether a session is running over a USB accessory.
+pub fn attached() -> bool {
+    ATTACHED.load(Ordering::SeqCst)
+}
+
+#[cfg(target_os = "linux")]
+fn watch(app_handle: &tauri::AppHandle, transport: &TransportHandle, stop: &AtomicBool) {
+    use crate::host_listener;
+    use crate::protocol::aoap::{self, AccessoryStrings, UsbStream};
+    use crate::settings_registry;
+
+    let strings = AccessoryStrings::default();
+    let mut refused = Vec::new();
+    let mut session = None;
+    let mut last_error = None;
+    while !stop.load(Ordering::SeqCst) {
+        if session.is_some_and(|id| transport.has_session(id)) {
+            thread::sleep(SCAN_INTERVAL);
+            continue;
+        }
+        if session.take().is_some() {
+            ATTACHED.store(false, Ordering::SeqCst);
+            log(app_handle, "session ended".to_string());
+        }
+        let settings = settings_registry::load_settings(app_handle);
+        if transport.session_count() >= settings.max_sessions() {
+            thread::sleep(SCAN_INTERVAL);
+            continue;
+        }
+        let result = aoap::connect(&libusb::Backend, &strings, &mut refused)
+            .map_err(|err| err.to_string())
+            .and_then(|device| match device {
+                Some(device) => {
+                    let stream = UsbStream::new(device);
+                    host_listener::open_session(
+                        app_handle, transport, &settings, stream, &"USB", None,
+                    )
+                    .map(Some)
+                }
+                None => Ok(None),
+            });
+        match result {
+            Ok(Some(id)) => {
+                session = Some(id);
+                last_error = None;
+                ATTACHED.store(true, Ordering::SeqCst);
+            }
+            Ok(None) => thread::sleep(SCAN_INTERVAL),
+            Err(err) => {
+                // The same failure on every retry is logged once.
+                if last_error.as_ref() != Some(&err) {
+                    log(app_handle, format!("accessory session failed: {err}"));
+                    last_error = Some(err);
+                }
+                thread::sleep(RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_app_handle: &tauri::AppHandle, _transport: &TransportHandle, _stop: &AtomicBool) {}
+
+#[cfg(target_os = "linux")]
+fn log(app_handle: &tauri::AppHandle, message: String) {
+    let _ = host_log::append_log(app_handle, format!("USB: {message}"));
+}
+
+/// `UsbBackend` over libusb.
+#[cfg(target_os = "linux")]
+mod libusb {
+    use std::time::Duration;
+
+    use rusb::{Direction, GlobalContext, Recipient, RequestType, TransferType, UsbContext};
+
+    use crate::protocol::aoap::{AoapError, UsbBackend, UsbDevice, UsbDeviceInfo, CONTROL_TIMEOUT};
+
+    const ACCESSORY_INTERFACE: u8 = 0;
+
+    pub struct Backend;
+
+    pub struct Device {
+        handle: rusb::DeviceHandle<GlobalContext>,
+        /// Bulk IN and OUT endpoint addresses; accessories only.
+        endpoints: Option<(u8, u8)>,
+    }
+
+    impl UsbBackend for Backend {
+        type Device = Device;
+
+        fn devices(&self) -> Result<Vec<UsbDeviceInfo>, AoapError> {
+            let devices = GlobalContext::default().devices().map_err(usb_error)?;
+            Ok(devices
+                .iter()
+                .filter_map(|device| {
+                    let descriptor = device.device_descriptor().ok()?;
+                    Some(UsbDeviceInfo {
+                        bus: device.bus_number(),
+                        address: device.address(),
+                        vendor_id: descriptor.vendor_id(),
+                        product_id: descriptor.product_id(),
+                        class: descriptor.class_code(),
+                    })
+                })
+                .collect())
+        }
+
+        fn open(&self, info: &UsbDeviceInfo) -> Result<Device, AoapError> {
+            let devices = GlobalContext::default().devices().map_err(usb_error)?;
+            let device = devices
+                .iter()
+                .find(|device| device.bus_number() == info.bus && device.address() == info.address)
+                .ok_or(AoapError::Disconnected)?;
+            let handle = device.open().map_err(usb_error)?;
+            if !info.is_accessory() {
+                return Ok(Device {
+                    handle,
+                    endpoints: None,
+                });
+            }
+
+            let config = device.active_config_descriptor().map_err(usb_error)?;
+            let mut bulk_in = None;
+            let mut bulk_out = None;
+            let interface = config
+                .interfaces()
+                .find(|interface| interface.number() == ACCESSORY_INTERFACE)
+                .ok_or(AoapError::NoAccessory)?;
+            for endpoint in interface
+                .descriptors()
+                .flat_map(|descriptor| descriptor.endpoint_descriptors().collect::<Vec<_>>())
+                .filter(|endpoint| endpoint.transfer_type() == TransferType::Bulk)
+            {
+                match endpoint.direction() {
+                    Direction::In => bulk_in = bulk_in.or(Some(endpoint.address())),
+                    Direction::Out => bulk_out = bulk_out.or(Some(endpoint.address())),
+                }
+            }
+            let (Some(bulk_in), Some(bulk_out)) = (bulk_in, bulk_out) else {
+                return Err(AoapError::NoAccessory);
+            };
+            // Not every platform can detach kernel drivers; claiming decides.
+            let _ = handle.set_auto_detach_kernel_driver(true);
+            handle
+                .claim_interface(ACCESSORY_INTERFACE)
+                .map_err(usb_error)?;
+            Ok(Device {
+                handle,
+                endpoints: Some((bulk_in, bulk_out)),
+            })
+        }
+    }
+
+    impl Device {
+        fn endpoints(&self) -> Result<(u8, u8), AoapError> {
+            self.endpoints.ok_or(AoapError::NoAccessory)
+        }
+    }
+
+    impl UsbDevice for Device {
+        fn control_in(
+            &self,
+            request: u8,
+            value: u16,
+            index: u16,
+            buf: &mut [u8],
+        ) -> Result<usize, AoapError> {
+            let request_type =
+                rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+            self.handle
+                .read_control(request_type, request, value, index, buf, CONTROL_TIMEOUT)
+                .map_err(usb_error)
+        }
+
+        fn control_out(
+            &self,
+            request: u8,
+            value: u16,
+            index: u16,
+            data: &[u8],
+        ) -> Result<usize, AoapError> {
+            let request_type =
+                rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+            self.handle
+                .write_control(request_type, request, value, index, data, CONTROL_TIMEOUT)
+                .map_err(usb_error)
+        }
+
+        fn bulk_read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, AoapError> {
+            let (bulk_in, _) = self.endpoints()?;
+            self.handle
+                .read_bulk(bulk_in, buf, timeout)
+                .map_err(usb_error)
+        }
+
+        fn bulk_write(&self, data: &[u8], timeout: Duration) -> Result<usize, AoapError> {
+            let (_, bulk_out) = self.endpoints()?;
+            self.handle
+                .write_bulk(bulk_out, data, timeout)
+                .map_err(usb_error)
+        }
+    }
+
+    fn usb_error(err: rusb::Error) -> AoapError {
+        match err {
+            rusb::Error::Timeout => AoapError::Timeout,
+            rusb::Error::NoDevice => AoapError::Disconnected,
+            rusb::Error::Pipe | rusb::Error::NotSupported => AoapError::NotSupported,
+            other => AoapError::Usb(other.to_string()),
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use serde::Serialize;
 
+use crate::bitstream::NalCounts;
+use crate::protocol::transport::TransportStats;
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DriverStatus {
@@ -33,6 +36,12 @@ pub struct DisplayMode {
 pub struct TransportStatus {
     pub tcp_listening: bool,
     pub tcp_connections: u32,
+    /// Where the host's listener is bound, when it is running.
+    pub listen_address: Option<String>,
+    /// Where the QUIC endpoint is bound, when it is running.
+    pub quic_address: Option<String>,
+    /// What the running sessions have sent and received so far.
+    pub traffic: TransportStats,
     pub aoap_attached: bool,
 }
 
@@ -45,6 +54,152 @@ pub struct HostSettings {
     #[serde(default)]
     pub keyframe_interval: u16,
     pub input_mode: String,
+    #[serde(default)]
+    pub enable_evc: bool,
+    #[serde(default)]
+    pub enable_lcevc: bool,
+    /// `BT.601`, `BT.709` or `BT.2020`; anything else falls back to BT.709.
+    #[serde(default)]
+    pub color_matrix: String,
+    #[serde(default)]
+    pub full_range_color: bool,
+    /// `Bilinear`, `Bicubic` or `Lanczos`, used when the display and stream sizes differ.
+    #[serde(default)]
+    pub scale_filter: String,
+    /// `Stretch`, `Letterbox` or `Crop`.
+    #[serde(default)]
+    pub scale_fit: String,
+    /// `PSK`, or `Off (debug)` to send everything unencrypted.
+    #[serde(default)]
+    pub crypto_mode: String,
+    /// `Auto`, `AES-256-GCM` or `ChaCha20-Poly1305`.
+    #[serde(default)]
+    pub cipher: String,
+    /// Pre-shared key for `PSK` mode, as entered on both devices.
+    #[serde(default)]
+    pub psk: String,
+    /// Rotate the session key after this many MiB sent; 0 uses the default.
+    #[serde(default)]
+    pub rekey_after_mb: u32,
+    /// Rotate the session key after this many minutes; 0 uses the default.
+    #[serde(default)]
+    pub rekey_after_minutes: u32,
+    /// `Paired devices only`, `Prompt for unknown` or `Open (debug)`.
+    #[serde(default)]
+    pub auth_mode: String,
+    /// CIDR networks peers must be in; empty allows any address.
+    #[serde(default)]
+    pub allowed_subnets: Vec<String>,
+    /// Accept connections from clients as well as dialing out.
+    #[serde(default)]
+    pub listen_enabled: bool,
+    /// Bind address for the listener; empty means all IPv4 interfaces.
+    #[serde(default)]
+    pub listen_address: String,
+    /// Listener port; 0 uses the default.
+    #[serde(default)]
+    pub listen_port: u16,
+    /// Clients streamed to at once (mirrored); 0 means one.
+    #[serde(default)]
+    pub max_sessions: u8,
+    /// Send video over UDP to clients that support it; control stays on TCP.
+    #[serde(default)]
+    pub udp_media: bool,
+    /// Data datagrams per parity datagram on the UDP path; 0 turns FEC off.
+    #[serde(default)]
+    pub fec_group: u8,
+    /// Move public-key sessions to QUIC when the client supports it.
+    #[serde(default)]
+    pub quic_enabled: bool,
+    /// UDP port for QUIC; 0 uses the default.
+    #[serde(default)]
+    pub quic_port: u16,
+    /// Switch phones plugged in over USB to accessory mode and stream to them
+    /// over the cable (Linux).
+    #[serde(default)]
+    pub aoap_enabled: bool,
+    /// List phones known to the local adb server as connect candidates.
+    #[serde(default)]
+    pub adb_enabled: bool,
+    /// Look for clients on the LAN (mDNS and broadcast beacons).
+    #[serde(default)]
+    pub discovery_enabled: bool,
+}
+
+impl HostSettings {
+    pub fn color_space(&self) -> crate::color::ColorSpace {
+        crate::color::ColorSpace::from_settings(&self.color_matrix, self.full_range_color)
+    }
+
+    pub fn scale_mode(&self) -> crate::scaler::ScaleMode {
+        crate::scaler::ScaleMode::from_settings(&self.scale_filter, &self.scale_fit)
+    }
+
+    /// Anything but an explicit `Off (debug)` means encryption. Public-key mode needs
+    /// the stored identity and pins, so `pairing::channel_security` builds that one.
+    pub fn channel_security(&self) -> crate::protocol::secure::ChannelSecurity {
+        use crate::protocol::secure::{ChannelSecurity, Cipher, CipherChoice};
+        if self.crypto_mode == "Off (debug)" {
+            return ChannelSecurity::Plaintext;
+        }
+        ChannelSecurity::Psk {
+            key: self.psk.trim().as_bytes().to_vec(),
+            cipher: Cipher::from_name(&self.cipher)
+                .map(CipherChoice::Fixed)
+                .unwrap_or(CipherChoice::Auto),
+        }
+    }
+
+    /// Whichever limit is reached first triggers a rekey.
+    pub fn rekey_policy(&self) -> crate::protocol::secure::RekeyPolicy {
+        let defaults = crate::protocol::secure::RekeyPolicy::default();
+        crate::protocol::secure::RekeyPolicy {
+            max_bytes: match self.rekey_after_mb {
+                0 => defaults.max_bytes,
+                mb => Some(u64::from(mb) << 20),
+            },
+            max_age: match self.rekey_after_minutes {
+                0 => defaults.max_age,
+                minutes => Some(std::time::Duration::from_secs(u64::from(minutes) * 60)),
+            },
+        }
+    }
+
+    pub fn listen_port(&self) -> u16 {
+        match self.listen_port {
+            0 => crate::host_listener::DEFAULT_PORT,
+            port => port,
+        }
+    }
+
+    pub fn quic_port(&self) -> u16 {
+        match self.quic_port {
+            0 => crate::protocol::quic::DEFAULT_PORT,
+            port => port,
+        }
+    }
+
+    pub fn listen_address(&self) -> &str {
+        match self.listen_address.trim() {
+            "" => "0.0.0.0",
+            address => address,
+        }
+    }
+
+    pub fn max_sessions(&self) -> usize {
+        usize::from(self.max_sessions.max(1))
+    }
+
+    pub fn codec_opt_in_mask(&self) -> u32 {
+        let mut mask = 0;
+        if self.enable_evc {
+            mask |= crate::codec::CODEC_MASK_EVC;
+        }
+        if self.enable_lcevc {
+            mask |= crate::codec::CODEC_MASK_LCEVC;
+        }
+        mask
+    }
 }
 
 impl Default for HostSettings {
@@ -55,10 +210,67 @@ impl Default for HostSettings {
             refresh_cap_hz: 120,
             keyframe_interval: 60,
             input_mode: "Touch + Pen".to_string(),
+            enable_evc: false,
+            enable_lcevc: false,
+            color_matrix: "BT.709".to_string(),
+            full_range_color: false,
+            scale_filter: "Bicubic".to_string(),
+            scale_fit: "Stretch".to_string(),
+            crypto_mode: "PSK".to_string(),
+            cipher: "Auto".to_string(),
+            psk: String::new(),
+            rekey_after_mb: 1024,
+            rekey_after_minutes: 30,
+            auth_mode: crate::authorization::MODE_PROMPT.to_string(),
+            allowed_subnets: Vec::new(),
+            listen_enabled: false,
+            listen_address: "0.0.0.0".to_string(),
+            listen_port: crate::host_listener::DEFAULT_PORT,
+            max_sessions: 1,
+            udp_media: false,
+            fec_group: 0,
+            quic_enabled: false,
+            quic_port: crate::protocol::quic::DEFAULT_PORT,
+            aoap_enabled: false,
+            adb_enabled: false,
+            discovery_enabled: false,
         }
     }
 }
 
+/// A device the host could connect to right now, as the UI lists it.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectCandidate {
+    pub id: String,
+    pub name: String,
+    pub transport: String,
+    /// Where to dial, once known; ADB devices get one when they are prepared.
+    pub address: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// A client found on the LAN as it announced itself, with the paired device whose
+/// key it carries.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredDevice {
+    #[serde(flatten)]
+    pub candidate: ConnectCandidate,
+    pub protocol_version: u16,
+    /// Hex SHA-256 of the identity key the device announced.
+    pub fingerprint: Option<String>,
+    /// `mDNS` or `Broadcast`, whichever was heard last.
+    pub source: String,
+    /// Unix seconds.
+    pub last_seen: u64,
+    /// The paired device whose pinned fingerprint this one announced.
+    pub paired_id: Option<String>,
+    pub paired_name: Option<String>,
+    /// Where the paired device was last reached over an authenticated session.
+    pub paired_address: Option<String>,
+}
+
 #[derive(Debug, Serialize, serde::Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PairedDevice {
@@ -69,6 +281,14 @@ pub struct PairedDevice {
     pub last_seen: Option<String>,
     #[serde(default)]
     pub input_permissions: InputPermissions,
+    /// Hex SHA-256 of the device's identity key, pinned on first pairing.
+    #[serde(default)]
+    pub peer_fingerprint: Option<String>,
+    /// Where the device listens, when known from its pairing code or discovery.
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
 }
 
 #[derive(Debug, Serialize, serde::Deserialize, Clone)]
@@ -109,6 +329,20 @@ pub struct CodecSelection {
     pub codec_name: String,
     pub host_mask: u32,
     pub client_mask: u32,
+    pub codec_profile: u8,
+    pub codec_level: u8,
+    pub codec_flags: u8,
+    pub candidates: Vec<CodecCandidate>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CodecCandidate {
+    pub codec_id: u8,
+    pub codec_name: String,
+    pub eligible: bool,
+    pub selected: bool,
+    pub reason: String,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -142,6 +376,16 @@ pub struct SessionStats {
     pub dxgi_last_bytes: u32,
     pub capture_path: String,
     pub capture_scale: String,
+    /// Average share of each frame that changed over the last second, in percent.
+    pub changed_area_percent: f32,
+    /// Frames not encoded because the picture was static.
+    pub unchanged_frames: u64,
+    pub nal_counts: NalCounts,
+    pub encoded_width: u32,
+    pub encoded_height: u32,
+    pub encoded_profile: u8,
+    pub encoded_level: u8,
+    pub undecodable_frames_skipped: u64,
 }
 
 impl Default for SessionStats {
@@ -159,6 +403,14 @@ impl Default for SessionStats {
             dxgi_last_bytes: 0,
             capture_path: "Unknown".to_string(),
             capture_scale: "Unknown".to_string(),
+            changed_area_percent: 0.0,
+            unchanged_frames: 0,
+            nal_counts: NalCounts::default(),
+            encoded_width: 0,
+            encoded_height: 0,
+            encoded_profile: 0,
+            encoded_level: 0,
+            undecodable_frames_skipped: 0,
         }
     }
 }
@@ -166,14 +418,17 @@ impl Default for SessionStats {
 impl Default for AppStatus {
     fn default() -> Self {
         Self {
-            protocol_version: 4,
+            protocol_version: crate::protocol::handshake::PROTOCOL_VERSION,
             driver: DriverStatus {
                 installed: false,
                 active: false,
             },
             transport: TransportStatus {
-                tcp_listening: true,
+                tcp_listening: false,
                 tcp_connections: 0,
+                listen_address: None,
+                quic_address: None,
+                traffic: TransportStats::default(),
                 aoap_attached: false,
             },
             settings: HostSettings::default(),
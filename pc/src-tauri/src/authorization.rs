@@ -0,0 +1,618 @@
+//! Decides which peers the host streams to and takes input from.
+//!
+//! Every connection is checked against the source-subnet allowlist and the failed
+//! pairing rate limit, then against the mode: paired devices only, prompt for
+//! unknown peers (queued for approval in the UI), or open for debugging. Every
+//! decision is written to the host log.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::app_state::{HostSettings, PairedDevice};
+use crate::device_registry;
+use crate::host_log;
+use crate::host_transport::HostTransport;
+use crate::pairing;
+
+pub const MODE_PAIRED_ONLY: &str = "Paired devices only";
+pub const MODE_PROMPT: &str = "Prompt for unknown";
+pub const MODE_OPEN: &str = "Open (debug)";
+
+const MAX_FAILED_ATTEMPTS: usize = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Unanswered approvals are dropped, and count as failed attempts, after this.
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+static AUTH_STATE: OnceLock<Mutex<AuthState>> = OnceLock::new();
+
+fn auth_state_store() -> &'static Mutex<AuthState> {
+    AUTH_STATE.get_or_init(|| {
+        Mutex::new(AuthState {
+            limiter: FailureLimiter::new(MAX_FAILED_ATTEMPTS, FAILURE_WINDOW),
+            pending: Vec::new(),
+            next_id: 1,
+        })
+    })
+}
+
+struct AuthState {
+    limiter: FailureLimiter,
+    pending: Vec<QueuedApproval>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMode {
+    PairedOnly,
+    Prompt,
+    Open,
+}
+
+impl AuthMode {
+    /// Unknown names fall back to prompting.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            MODE_PAIRED_ONLY => AuthMode::PairedOnly,
+            MODE_OPEN => AuthMode::Open,
+            _ => AuthMode::Prompt,
+        }
+    }
+}
+
+/// An IPv4 or IPv6 network in CIDR notation; a bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+            None => (text, None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { network, prefix })
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], address: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    let rest = prefix % 8;
+    if network[..full] != address[..full] {
+        return false;
+    }
+    rest == 0 || {
+        let mask = 0xffu8 << (8 - rest);
+        network[full] & mask == address[full] & mask
+    }
+}
+
+/// Failed pairing attempts per address within a sliding window.
+pub struct FailureLimiter {
+    max: usize,
+    window: Duration,
+    failures: HashMap<IpAddr, Vec<Instant>>,
+}
+
+impl FailureLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            failures: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, address: IpAddr, now: Instant) {
+        self.failures.entry(address).or_default().push(now);
+    }
+
+    /// How much longer `address` stays blocked, if it is.
+    pub fn blocked_for(&mut self, address: IpAddr, now: Instant) -> Option<Duration> {
+        let window = self.window;
+        let failures = self.failures.get_mut(&address)?;
+        failures.retain(|at| now.duration_since(*at) < window);
+        if failures.len() < self.max {
+            return None;
+        }
+        let oldest = failures[failures.len() - self.max];
+        Some(window.saturating_sub(now.duration_since(oldest)))
+    }
+}
+
+/// What the policy allows for one connection attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow(&'static str),
+    Deny(String),
+    Prompt,
+}
+
+/// The policy part of a decision, without side effects.
+#[derive(Debug, Clone)]
+pub struct AuthPolicy {
+    pub mode: AuthMode,
+    pub subnets: Vec<Subnet>,
+    /// The handshake authenticates identities (public-key mode), so unknown peers
+    /// are vetted by pairing code rather than by address.
+    pub identity_checked: bool,
+}
+
+impl AuthPolicy {
+    pub fn from_settings(settings: &HostSettings) -> Self {
+        Self {
+            mode: AuthMode::from_name(&settings.auth_mode),
+            subnets: settings
+                .allowed_subnets
+                .iter()
+                .filter_map(|subnet| Subnet::parse(subnet))
+                .collect(),
+            identity_checked: settings.crypto_mode == pairing::PUBLIC_KEY_MODE,
+        }
+    }
+
+    pub fn evaluate(
+        &self,
+        limiter: &mut FailureLimiter,
+        address: IpAddr,
+        known: bool,
+        now: Instant,
+    ) -> Verdict {
+        if !self.subnets.is_empty() && !self.subnets.iter().any(|subnet| subnet.contains(address)) {
+            return Verdict::Deny(format!("{address} is outside the allowed subnets"));
+        }
+        if self.mode == AuthMode::Open {
+            return Verdict::Allow("open mode");
+        }
+        if let Some(remaining) = limiter.blocked_for(address, now) {
+            return Verdict::Deny(format!(
+                "too many failed pairing attempts from {address}; try again in {} s",
+                remaining.as_secs().max(1)
+            ));
+        }
+        if known {
+            return Verdict::Allow("paired device");
+        }
+        if self.identity_checked {
+            return Verdict::Allow("identity checked during handshake");
+        }
+        match self.mode {
+            AuthMode::PairedOnly => Verdict::Deny(format!("{address} is not a paired device")),
+            _ => Verdict::Prompt,
+        }
+    }
+}
+
+/// A connection waiting for the user, shown in the UI.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingApproval {
+    pub id: String,
+    pub address: String,
+    pub port: u16,
+    /// Set for public-key peers, with the code to compare on both devices.
+    pub fingerprint: Option<String>,
+    pub code: Option<String>,
+    pub requested_at: u64,
+}
+
+struct QueuedApproval {
+    approval: PendingApproval,
+    peer: IpAddr,
+    queued: Instant,
+}
+
+/// Checks a connection to `host:port` before anything is sent.
+pub fn authorize_connect(
+    app_handle: &tauri::AppHandle,
+    settings: &HostSettings,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let peer = resolve(host, port)?;
+    authorize(app_handle, settings, host, port, peer)
+}
+
+/// Checks a client that dialed the host's listener. Its source port is not one it
+/// can be reached on, so approvals record the address alone (port 0).
+pub fn authorize_incoming(
+    app_handle: &tauri::AppHandle,
+    settings: &HostSettings,
+    peer: SocketAddr,
+) -> Result<(), String> {
+    let host = peer.ip().to_canonical().to_string();
+    authorize(app_handle, settings, &host, 0, peer.ip().to_canonical())
+}
+
+fn authorize(
+    app_handle: &tauri::AppHandle,
+    settings: &HostSettings,
+    host: &str,
+    port: u16,
+    peer: IpAddr,
+) -> Result<(), String> {
+    let target = endpoint(host, port);
+    let devices = device_registry::load_devices(app_handle);
+    let known = known_device(&devices, host, peer);
+    let policy = AuthPolicy::from_settings(settings);
+
+    let mut state = auth_state_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    expire_approvals(app_handle, &mut state);
+    let verdict = policy.evaluate(&mut state.limiter, peer, known.is_some(), Instant::now());
+    match verdict {
+        Verdict::Allow(reason) => {
+            let name = known
+                .map(|device| format!(" ({})", device.name))
+                .unwrap_or_default();
+            audit(app_handle, format!("allowed {target}{name}: {reason}"));
+            Ok(())
+        }
+        Verdict::Deny(reason) => {
+            audit(app_handle, format!("denied {target}: {reason}"));
+            Err(format!("Connection not allowed: {reason}"))
+        }
+        Verdict::Prompt => {
+            let id = queue(&mut state, peer, host, port, None, None);
+            audit(app_handle, format!("queued {target} for approval ({id})"));
+            Err(format!(
+                "{host} is not a paired device; approve it in the pending list, then connect again"
+            ))
+        }
+    }
+}
+
+/// Records what a failed handshake means for authorization: a new public-key peer
+/// is queued for approval, a changed identity counts as a failed attempt.
+pub fn handshake_failed(
+    app_handle: &tauri::AppHandle,
+    transport: &HostTransport,
+    host: &str,
+    port: u16,
+    error: &str,
+) {
+    let Ok(peer) = resolve(host, port) else {
+        return;
+    };
+    let target = endpoint(host, port);
+    let Ok(mut state) = auth_state_store().lock() else {
+        return;
+    };
+    if let Some(pending) = transport.take_pending_pairing() {
+        let id = queue(
+            &mut state,
+            peer,
+            host,
+            port,
+            Some(pending.fingerprint),
+            Some(pending.code),
+        );
+        audit(
+            app_handle,
+            format!("queued {target} for pairing approval ({id})"),
+        );
+    } else if error.starts_with("SECURITY") {
+        state.limiter.record(peer, Instant::now());
+        audit(app_handle, format!("denied {target}: identity key changed"));
+    }
+}
+
+/// Records a session whose first record failed authentication: the client keyed
+/// the channel with a different PSK. Like a changed identity, it counts as a
+/// failed attempt; the reader that notices has no app handle, so the lockout is
+/// what shows up in the host log.
+pub fn psk_mismatch(peer: IpAddr) {
+    if let Ok(mut state) = auth_state_store().lock() {
+        state.limiter.record(peer.to_canonical(), Instant::now());
+    }
+}
+
+/// How much longer `peer` is locked out of pairing, if it is.
+#[cfg(test)]
+pub(crate) fn blocked_for(peer: IpAddr) -> Option<Duration> {
+    auth_state_store()
+        .lock()
+        .ok()?
+        .limiter
+        .blocked_for(peer.to_canonical(), Instant::now())
+}
+
+pub fn pending_approvals() -> Vec<PendingApproval> {
+    auth_state_store()
+        .lock()
+        .map(|state| {
+            state
+                .pending
+                .iter()
+                .map(|queued| queued.approval.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Accepts a queued connection: pins its fingerprint, or adds its address as a
+/// paired device.
+pub fn approve(app_handle: &tauri::AppHandle, id: &str) -> Result<Vec<PairedDevice>, String> {
+    let approval = take_approval(id)?.approval;
+    let devices = match &approval.fingerprint {
+        Some(fingerprint) => pairing::pin_fingerprint(
+            app_handle,
+            fingerprint,
+            "Wi-Fi",
+            Some((&approval.address, approval.port)),
+        )?,
+        None => {
+            let mut devices = device_registry::load_devices(app_handle);
+            devices.push(PairedDevice {
+                id: format!("addr-{}", endpoint(&approval.address, approval.port)),
+                name: approval.address.clone(),
+                transport: "Wi-Fi".to_string(),
+                status: "Paired".to_string(),
+                last_seen: None,
+                input_permissions: Default::default(),
+                peer_fingerprint: None,
+                address: Some(approval.address.clone()),
+                port: Some(approval.port).filter(|port| *port != 0),
+            });
+            device_registry::save_devices(app_handle, &devices)?;
+            devices
+        }
+    };
+    audit(
+        app_handle,
+        format!(
+            "approved {} ({id})",
+            endpoint(&approval.address, approval.port)
+        ),
+    );
+    Ok(devices)
+}
+
+/// Refuses a queued connection; it counts as a failed pairing attempt.
+pub fn reject(app_handle: &tauri::AppHandle, id: &str) -> Result<(), String> {
+    let queued = take_approval(id)?;
+    if let Ok(mut state) = auth_state_store().lock() {
+        state.limiter.record(queued.peer, Instant::now());
+    }
+    audit(
+        app_handle,
+        format!(
+            "rejected {} ({id})",
+            endpoint(&queued.approval.address, queued.approval.port)
+        ),
+    );
+    Ok(())
+}
+
+fn take_approval(id: &str) -> Result<QueuedApproval, String> {
+    let mut state = auth_state_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    let index = state
+        .pending
+        .iter()
+        .position(|queued| queued.approval.id == id)
+        .ok_or_else(|| "No such pending connection".to_string())?;
+    Ok(state.pending.remove(index))
+}
+
+fn queue(
+    state: &mut AuthState,
+    peer: IpAddr,
+    host: &str,
+    port: u16,
+    fingerprint: Option<String>,
+    code: Option<String>,
+) -> String {
+    // A retry replaces the earlier entry for the same peer.
+    state
+        .pending
+        .retain(|queued| !(queued.peer == peer && queued.approval.port == port));
+    let id = format!("req-{}", state.next_id);
+    state.next_id += 1;
+    state.pending.push(QueuedApproval {
+        approval: PendingApproval {
+            id: id.clone(),
+            address: host.to_string(),
+            port,
+            fingerprint,
+            code,
+            requested_at: unix_now(),
+        },
+        peer,
+        queued: Instant::now(),
+    });
+    id
+}
+
+fn expire_approvals(app_handle: &tauri::AppHandle, state: &mut AuthState) {
+    let now = Instant::now();
+    let (expired, kept): (Vec<_>, Vec<_>) = state
+        .pending
+        .drain(..)
+        .partition(|queued| now.duration_since(queued.queued) >= APPROVAL_TIMEOUT);
+    state.pending = kept;
+    for queued in expired {
+        state.limiter.record(queued.peer, now);
+        audit(
+            app_handle,
+            format!(
+                "expired {} ({})",
+                endpoint(&queued.approval.address, queued.approval.port),
+                queued.approval.id
+            ),
+        );
+    }
+}
+
+fn known_device<'a>(
+    devices: &'a [PairedDevice],
+    host: &str,
+    peer: IpAddr,
+) -> Option<&'a PairedDevice> {
+    devices.iter().find(|device| {
+        device.address.as_deref().is_some_and(|address| {
+            address.eq_ignore_ascii_case(host)
+                || address
+                    .parse::<IpAddr>()
+                    .is_ok_and(|address| address == peer)
+        })
+    })
+}
+
+fn resolve(host: &str, port: u16) -> Result<IpAddr, String> {
+    (host, port)
+        .to_socket_addrs()
+        .map_err(|err| err.to_string())?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| "No address resolved".to_string())
+}
+
+/// `host:port`, or just `host` for clients that dialed in.
+fn endpoint(host: &str, port: u16) -> String {
+    match port {
+        0 => host.to_string(),
+        port => format!("{host}:{port}"),
+    }
+}
+
+fn audit(app_handle: &tauri::AppHandle, message: String) {
+    let _ = host_log::append_log(app_handle, format!("Authorization: {message}"));
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_subnets() {
+        let lan = Subnet::parse("192.168.1.0/24").unwrap();
+        assert!(lan.contains(address("192.168.1.77")));
+        assert!(lan.contains(address("::ffff:192.168.1.77")));
+        assert!(!lan.contains(address("192.168.2.1")));
+        assert!(!lan.contains(address("fe80::1")));
+
+        let odd = Subnet::parse("10.0.0.0/9").unwrap();
+        assert!(odd.contains(address("10.127.255.255")));
+        assert!(!odd.contains(address("10.128.0.0")));
+
+        let ula = Subnet::parse("fd00::/8").unwrap();
+        assert!(ula.contains(address("fd12:3456::1")));
+        assert!(Subnet::parse("10.0.0.5")
+            .unwrap()
+            .contains(address("10.0.0.5")));
+        assert!(Subnet::parse("0.0.0.0/0")
+            .unwrap()
+            .contains(address("8.8.8.8")));
+        assert_eq!(Subnet::parse("10.0.0.0/33"), None);
+        assert_eq!(Subnet::parse("lan"), None);
+    }
+
+    #[test]
+    fn blocks_after_repeated_failures_until_the_window_passes() {
+        let mut limiter = FailureLimiter::new(3, Duration::from_secs(60));
+        let peer = address("192.168.1.9");
+        let start = Instant::now();
+        for second in 0..2 {
+            limiter.record(peer, start + Duration::from_secs(second));
+        }
+        assert_eq!(
+            limiter.blocked_for(peer, start + Duration::from_secs(2)),
+            None
+        );
+        limiter.record(peer, start + Duration::from_secs(2));
+        assert_eq!(
+            limiter.blocked_for(peer, start + Duration::from_secs(10)),
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(limiter.blocked_for(address("192.168.1.10"), start), None);
+        assert_eq!(
+            limiter.blocked_for(peer, start + Duration::from_secs(61)),
+            None
+        );
+    }
+
+    #[test]
+    fn evaluates_modes() {
+        let now = Instant::now();
+        let peer = address("192.168.1.9");
+        let mut limiter = FailureLimiter::new(1, Duration::from_secs(60));
+        let policy = |mode, subnets: &[&str], identity_checked| AuthPolicy {
+            mode,
+            subnets: subnets
+                .iter()
+                .filter_map(|text| Subnet::parse(text))
+                .collect(),
+            identity_checked,
+        };
+
+        let paired_only = policy(AuthMode::PairedOnly, &[], false);
+        assert_eq!(
+            paired_only.evaluate(&mut limiter, peer, true, now),
+            Verdict::Allow("paired device")
+        );
+        assert!(matches!(
+            paired_only.evaluate(&mut limiter, peer, false, now),
+            Verdict::Deny(_)
+        ));
+        let prompt = policy(AuthMode::Prompt, &[], false);
+        assert_eq!(
+            prompt.evaluate(&mut limiter, peer, false, now),
+            Verdict::Prompt
+        );
+        assert_eq!(
+            policy(AuthMode::Prompt, &[], true).evaluate(&mut limiter, peer, false, now),
+            Verdict::Allow("identity checked during handshake")
+        );
+
+        let lan_only = policy(AuthMode::Open, &["10.0.0.0/8"], false);
+        assert!(matches!(
+            lan_only.evaluate(&mut limiter, peer, true, now),
+            Verdict::Deny(reason) if reason.contains("subnets")
+        ));
+
+        limiter.record(peer, now);
+        assert!(matches!(
+            prompt.evaluate(&mut limiter, peer, true, now),
+            Verdict::Deny(reason) if reason.contains("failed pairing")
+        ));
+        assert_eq!(
+            policy(AuthMode::Open, &[], false).evaluate(&mut limiter, peer, false, now),
+            Verdict::Allow("open mode")
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Inspects protocol traces written by the host and replays them.
+//!
+//! ```text
+//! trace print <file> [--hex]
+//! trace replay <file> [--listen <addr>] [--speed <factor>] [--settle-ms <ms>]
+//! ```
+//!
+//! `replay` stands in for the client: it listens like the Android app does, waits
+//! for the host to connect, sends the recorded client packets on the recorded
+//! schedule and checks that the host answers with the same control packets. It
+//! does not encrypt, so the host has to be in debug plaintext mode.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use uberdisplay_pc::protocol::framing::{write_stream_chunks, StreamDecoder};
+use uberdisplay_pc::protocol::trace::{
+    data_type_name, format_record, Direction, ReplayReport, TraceReader, TraceRecord,
+};
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:1445";
+const HANDSHAKE_PREFIX: &[u8] = b"KELOCUBE_MIRR_";
+const HANDSHAKE_LEN: usize = 18;
+const HEX_BYTES: usize = 32;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("print") => print(&args[1..]),
+        Some("replay") => replay(&args[1..]),
+        _ => Err(usage()),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn usage() -> String {
+    "usage:\n  trace print <file> [--hex]\n  trace replay <file> [--listen <addr>] [--speed <factor>] [--settle-ms <ms>]"
+        .to_string()
+}
+
+fn read_trace(path: &str) -> Result<(bool, Vec<TraceRecord>), String> {
+    let file = File::open(path).map_err(|err| format!("{path}: {err}"))?;
+    let reader = TraceReader::new(BufReader::new(file)).map_err(|err| format!("{path}: {err}"))?;
+    let include_payloads = reader.include_payloads;
+    let records = reader.records().map_err(|err| format!("{path}: {err}"))?;
+    Ok((include_payloads, records))
+}
+
+/// Value following `flag`, if present.
+fn option<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+fn print(args: &[String]) -> Result<bool, String> {
+    let path = args.first().ok_or_else(usage)?;
+    let hex = args.iter().any(|arg| arg == "--hex");
+    let (include_payloads, records) = read_trace(path)?;
+    println!(
+        "{path}: {} records, payloads {}",
+        records.len(),
+        if include_payloads {
+            "included"
+        } else {
+            "omitted"
+        }
+    );
+    for record in &records {
+        println!("{}", format_record(record));
+        if let (true, Some(payload)) = (hex, &record.payload) {
+            let shown: Vec<String> = payload
+                .iter()
+                .take(HEX_BYTES)
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            let more = if payload.len() > HEX_BYTES {
+                " ..."
+            } else {
+                ""
+            };
+            println!("                 {}{more}", shown.join(" "));
+        }
+    }
+
+    let mut totals = [(0usize, 0u64); 256];
+    for record in &records {
+        let entry = &mut totals[record.data_type as usize];
+        entry.0 += 1;
+        entry.1 += record.length as u64;
+    }
+    println!();
+    for (data_type, (count, bytes)) in totals.iter().enumerate() {
+        if *count > 0 {
+            println!(
+                "{:<14} ({data_type:>2})  {count:>8} packets  {bytes:>12} bytes",
+                data_type_name(data_type as u8)
+            );
+        }
+    }
+    Ok(true)
+}
+
+fn replay(args: &[String]) -> Result<bool, String> {
+    let path = args.first().ok_or_else(usage)?;
+    let listen = option(args, "--listen").unwrap_or(DEFAULT_LISTEN);
+    let speed: f64 = option(args, "--speed")
+        .map(|value| value.parse().map_err(|_| format!("invalid speed {value}")))
+        .transpose()?
+        .unwrap_or(1.0);
+    if speed <= 0.0 {
+        return Err("speed must be positive".to_string());
+    }
+    let settle = Duration::from_millis(
+        option(args, "--settle-ms")
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid settle time {value}"))
+            })
+            .transpose()?
+            .unwrap_or(2000),
+    );
+
+    let (include_payloads, expected) = read_trace(path)?;
+    if !include_payloads {
+        return Err(format!(
+            "{path} was recorded without payloads; record with payloads to replay it"
+        ));
+    }
+    let base_us = expected
+        .first()
+        .map(|record| record.timestamp_us)
+        .unwrap_or(0);
+
+    let listener = TcpListener::bind(listen).map_err(|err| format!("{listen}: {err}"))?;
+    println!("Waiting for the host to connect on {listen} ...");
+    let (mut stream, peer) = listener.accept().map_err(|err| err.to_string())?;
+    stream.set_nodelay(true).map_err(|err| err.to_string())?;
+    let mut handshake = [0u8; HANDSHAKE_LEN];
+    stream
+        .read_exact(&mut handshake)
+        .map_err(|err| format!("handshake: {err}"))?;
+    if !handshake.starts_with(HANDSHAKE_PREFIX) {
+        return Err(format!(
+            "unexpected handshake from {peer}: {handshake:02x?}"
+        ));
+    }
+    println!(
+        "Host {peer} connected ({})",
+        String::from_utf8_lossy(&handshake[..HANDSHAKE_LEN - 1])
+    );
+
+    let started = Instant::now();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let reader = {
+        let stream = stream.try_clone().map_err(|err| err.to_string())?;
+        let received = Arc::clone(&received);
+        thread::spawn(move || collect_host_packets(stream, started, &received))
+    };
+
+    for record in expected
+        .iter()
+        .filter(|record| record.direction == Direction::ClientToHost)
+    {
+        let Some(packet) = &record.payload else {
+            continue;
+        };
+        let due = Duration::from_secs_f64(
+            record.timestamp_us.saturating_sub(base_us) as f64 / 1_000_000.0 / speed,
+        );
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+        let mut framed = Vec::with_capacity(4 + packet.len());
+        framed.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        framed.extend_from_slice(packet);
+        let mut chunked = Vec::with_capacity(framed.len() + 3);
+        write_stream_chunks(record.stream_id, &framed, &mut chunked);
+        stream
+            .write_all(&chunked)
+            .map_err(|err| format!("sending {}: {err}", data_type_name(record.data_type)))?;
+        println!(
+            "{}",
+            format_record(&TraceRecord {
+                timestamp_us: started.elapsed().as_micros() as u64,
+                ..record.clone()
+            })
+        );
+    }
+
+    thread::sleep(settle);
+    let _ = stream.shutdown(Shutdown::Both);
+    let _ = reader.join();
+
+    let actual = received
+        .lock()
+        .map(|guard| guard.clone())
+        .unwrap_or_default();
+    let report = ReplayReport::compare(&expected, &actual);
+    println!();
+    println!(
+        "Frames: recorded {}, replayed {}",
+        report.expected_frames, report.actual_frames
+    );
+    match report.divergence {
+        None => println!("Control packets match"),
+        Some((index, expected, actual)) => {
+            let name = |data_type: Option<u8>| {
+                data_type
+                    .map(|data_type| format!("{} ({data_type})", data_type_name(data_type)))
+                    .unwrap_or_else(|| "nothing".to_string())
+            };
+            println!(
+                "Control packet {index} differs: recorded {}, host sent {}",
+                name(expected),
+                name(actual)
+            );
+        }
+    }
+    Ok(report.matches())
+}
+
+fn collect_host_packets(
+    mut stream: TcpStream,
+    started: Instant,
+    received: &Mutex<Vec<TraceRecord>>,
+) {
+    let mut decoder = StreamDecoder::default();
+    let mut buffer = [0u8; 16 * 1024];
+    loop {
+        let read = match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        for (stream_id, packet) in decoder.push(&buffer[..read]) {
+            let record = TraceRecord {
+                timestamp_us: started.elapsed().as_micros() as u64,
+                direction: Direction::HostToClient,
+                stream_id,
+                data_type: packet.first().copied().unwrap_or(u8::MAX),
+                length: packet.len() as u32,
+                payload: None,
+            };
+            println!("{}", format_record(&record));
+            if let Ok(mut guard) = received.lock() {
+                guard.push(record);
+            }
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use serde::Serialize;
+
+use crate::codec::CodecId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalKind {
+    Vps,
+    Sps,
+    Pps,
+    /// H.264 IDR slice, or any HEVC IRAP picture (IDR/CRA/BLA).
+    Idr,
+    NonIdr,
+    Sei,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NalUnit<'a> {
+    pub kind: NalKind,
+    pub nal_type: u8,
+    /// NAL header and payload, without the start code.
+    pub payload: &'a [u8],
+    /// The unit including its start code, as it appears in the stream.
+    pub raw: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpsInfo {
+    pub width: u32,
+    pub height: u32,
+    pub profile: u8,
+    pub level: u8,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NalCounts {
+    pub vps: u64,
+    pub sps: u64,
+    pub pps: u64,
+    pub idr: u64,
+    pub non_idr: u64,
+    pub sei: u64,
+    pub other: u64,
+}
+
+impl NalCounts {
+    pub fn record(&mut self, kind: NalKind) {
+        let slot = match kind {
+            NalKind::Vps => &mut self.vps,
+            NalKind::Sps => &mut self.sps,
+            NalKind::Pps => &mut self.pps,
+            NalKind::Idr => &mut self.idr,
+            NalKind::NonIdr => &mut self.non_idr,
+            NalKind::Sei => &mut self.sei,
+            NalKind::Other => &mut self.other,
+        };
+        *slot = slot.saturating_add(1);
+    }
+
+    pub fn add(&mut self, other: &NalCounts) {
+        self.vps = self.vps.saturating_add(other.vps);
+        self.sps = self.sps.saturating_add(other.sps);
+        self.pps = self.pps.saturating_add(other.pps);
+        self.idr = self.idr.saturating_add(other.idr);
+        self.non_idr = self.non_idr.saturating_add(other.non_idr);
+        self.sei = self.sei.saturating_add(other.sei);
+        self.other = self.other.saturating_add(other.other);
+    }
+}
+
+/// Summary of one encoded access unit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameInspection {
+    pub counts: NalCounts,
+    pub sps: Option<SpsInfo>,
+}
+
+impl FrameInspection {
+    pub fn is_keyframe(&self) -> bool {
+        self.counts.idr > 0
+    }
+
+    pub fn has_parameter_sets(&self, codec_id: CodecId) -> bool {
+        let vps_ok = codec_id != CodecId::H265 || self.counts.vps > 0;
+        vps_ok && self.counts.sps > 0 && self.counts.pps > 0
+    }
+
+    /// A client that has just been configured can start decoding at this frame.
+    pub fn is_decodable_start(&self, codec_id: CodecId) -> bool {
+        self.is_keyframe() && self.has_parameter_sets(codec_id)
+    }
+}
+
+/// Splits an Annex-B buffer on 3- and 4-byte start codes.
+pub fn split_annexb(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut starts = Vec::new();
+    let mut index = 0;
+    while index + 3 <= data.len() {
+        if data[index] == 0 && data[index + 1] == 0 && data[index + 2] == 1 {
+            let begin = if index > 0 && data[index - 1] == 0 {
+                index - 1
+            } else {
+                index
+            };
+            starts.push((begin, index + 3));
+            index += 3;
+        } else {
+            index += 1;
+        }
+    }
+    let mut units = Vec::with_capacity(starts.len());
+    for (position, (begin, payload)) in starts.iter().enumerate() {
+        let end = starts
+            .get(position + 1)
+            .map(|(next, _)| *next)
+            .unwrap_or(data.len());
+        if *payload < end {
+            units.push((&data[*begin..end], &data[*payload..end]));
+        }
+    }
+    units
+}
+
+pub fn nal_units(codec_id: CodecId, data: &[u8]) -> Vec<NalUnit<'_>> {
+    split_annexb(data)
+        .into_iter()
+        .map(|(raw, payload)| {
+            let (nal_type, kind) = classify(codec_id, payload[0]);
+            NalUnit {
+                kind,
+                nal_type,
+                payload,
+                raw,
+            }
+        })
+        .collect()
+}
+
+pub fn classify(codec_id: CodecId, header: u8) -> (u8, NalKind) {
+    match codec_id {
+        CodecId::H265 => {
+            let nal_type = (header >> 1) & 0x3f;
+            let kind = match nal_type {
+                0..=9 => NalKind::NonIdr,
+                16..=21 => NalKind::Idr,
+                32 => NalKind::Vps,
+                33 => NalKind::Sps,
+                34 => NalKind::Pps,
+                39 | 40 => NalKind::Sei,
+                _ => NalKind::Other,
+            };
+            (nal_type, kind)
+        }
+        _ => {
+            let nal_type = header & 0x1f;
+            let kind = match nal_type {
+                1 => NalKind::NonIdr,
+                5 => NalKind::Idr,
+                6 => NalKind::Sei,
+                7 => NalKind::Sps,
+                8 => NalKind::Pps,
+                _ => NalKind::Other,
+            };
+            (nal_type, kind)
+        }
+    }
+}
+
+/// Inspects an access unit. Codecs other than H.264/HEVC yield an empty summary.
+pub fn inspect(codec_id: CodecId, data: &[u8]) -> FrameInspection {
+    let mut inspection = FrameInspection::default();
+    if !matches!(codec_id, CodecId::H264 | CodecId::H265) {
+        return inspection;
+    }
+    for unit in nal_units(codec_id, data) {
+        inspection.counts.record(unit.kind);
+        if unit.kind == NalKind::Sps && inspection.sps.is_none() {
+            inspection.sps = parse_sps(codec_id, unit.payload);
+        }
+    }
+    inspection
+}
+
+/// Parses an SPS NAL (header included, start code excluded).
+pub fn parse_sps(codec_id: CodecId, payload: &[u8]) -> Option<SpsInfo> {
+    match codec_id {
+        CodecId::H264 => parse_h264_sps(&unescape(payload.get(1..)?)),
+        CodecId::H265 => parse_hevc_sps(&unescape(payload.get(2..)?)),
+        _ => None,
+    }
+}
+
+/// The 12-byte `general_profile_tier_level` of an HEVC SPS NAL (header included),
+/// as copied into `hvcC`.
+pub fn hevc_general_ptl(payload: &[u8]) -> Option<[u8; 12]> {
+    let rbsp = unescape(payload.get(2..)?);
+    rbsp.get(1..13)?.try_into().ok()
+}
+
+/// Strips emulation prevention bytes (`00 00 03` -> `00 00`).
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        output.push(byte);
+    }
+    output
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - (self.position % 8))) & 1;
+        self.position += 1;
+        Some(bit as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            value = (value << 1) | self.bit()?;
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.position += count;
+        (self.position <= self.data.len() * 8).then_some(())
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let value = self.ue()?;
+        Some(if value % 2 == 1 {
+            value.div_ceil(2) as i32
+        } else {
+            -((value / 2) as i32)
+        })
+    }
+}
+
+fn chroma_subsampling(chroma_format_idc: u32) -> (u32, u32) {
+    match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    }
+}
+
+fn parse_h264_sps(rbsp: &[u8]) -> Option<SpsInfo> {
+    let mut reader = BitReader::new(rbsp);
+    let profile = reader.bits(8)? as u8;
+    reader.skip(8)?;
+    let level = reader.bits(8)? as u8;
+    reader.ue()?;
+
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = reader.ue()?;
+        if chroma_format_idc == 3 {
+            reader.skip(1)?;
+        }
+        reader.ue()?;
+        reader.ue()?;
+        reader.skip(1)?;
+        if reader.bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for index in 0..lists {
+                if reader.bit()? == 1 {
+                    skip_scaling_list(&mut reader, if index < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    reader.ue()?;
+    match reader.ue()? {
+        0 => {
+            reader.ue()?;
+        }
+        1 => {
+            reader.skip(1)?;
+            reader.se()?;
+            reader.se()?;
+            for _ in 0..reader.ue()? {
+                reader.se()?;
+            }
+        }
+        _ => {}
+    }
+    reader.ue()?;
+    reader.skip(1)?;
+    let width_mbs = reader.ue()? + 1;
+    let height_map_units = reader.ue()? + 1;
+    let frame_mbs_only = reader.bit()?;
+    if frame_mbs_only == 0 {
+        reader.skip(1)?;
+    }
+    reader.skip(1)?;
+
+    let mut width = width_mbs * 16;
+    let mut height = (2 - frame_mbs_only) * height_map_units * 16;
+    if reader.bit()? == 1 {
+        let (left, right, top, bottom) = (reader.ue()?, reader.ue()?, reader.ue()?, reader.ue()?);
+        let (sub_width, sub_height) = chroma_subsampling(chroma_format_idc);
+        let crop_x = if chroma_format_idc == 0 { 1 } else { sub_width };
+        let crop_y = (if chroma_format_idc == 0 {
+            1
+        } else {
+            sub_height
+        }) * (2 - frame_mbs_only);
+        width = width.checked_sub(crop_x * (left + right))?;
+        height = height.checked_sub(crop_y * (top + bottom))?;
+    }
+
+    Some(SpsInfo {
+        width,
+        height,
+        profile,
+        level,
+    })
+}
+
+fn skip_scaling_list(reader: &mut BitReader<'_>, size: usize) -> Option<()> {
+    let mut last_scale = 8i32;
+    let mut next_scale = 8i32;
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + reader.se()? + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Some(())
+}
+
+fn parse_hevc_sps(rbsp: &[u8]) -> Option<SpsInfo> {
+    let mut reader = BitReader::new(rbsp);
+    reader.skip(4)?;
+    let max_sub_layers_minus1 = reader.bits(3)? as usize;
+    reader.skip(1)?;
+
+    reader.skip(3)?;
+    let profile = reader.bits(5)? as u8;
+    reader.skip(32 + 48)?;
+    let level = reader.bits(8)? as u8;
+    let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_flags.push((reader.bit()?, reader.bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        reader.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present == 1 {
+            reader.skip(88)?;
+        }
+        if level_present == 1 {
+            reader.skip(8)?;
+        }
+    }
+
+    reader.ue()?;
+    let chroma_format_idc = reader.ue()?;
+    if chroma_format_idc == 3 {
+        reader.skip(1)?;
+    }
+    let mut width = reader.ue()?;
+    let mut height = reader.ue()?;
+    if reader.bit()? == 1 {
+        let (left, right, top, bottom) = (reader.ue()?, reader.ue()?, reader.ue()?, reader.ue()?);
+        let (sub_width, sub_height) = chroma_subsampling(chroma_format_idc);
+        width = width.checked_sub(sub_width * (left + right))?;
+        height = height.checked_sub(sub_height * (top + bottom))?;
+    }
+
+    Some(SpsInfo {
+        width,
+        height,
+        profile,
+        level,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// H.264 SPS for 1920x1080 High@4.0: 1088 coded lines with 8 cropped at the bottom.
+    const H264_SPS_1080P: [u8; 12] = [
+        0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x40,
+    ];
+
+    /// HEVC SPS for 1280x720 Main@3.1, with emulation prevention bytes in the PTL.
+    const HEVC_SPS_720P: [u8; 25] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x5d, 0xa0, 0x02, 0x80, 0x80, 0x2d, 0x16, 0x58,
+    ];
+
+    #[test]
+    fn parses_h264_sps_with_cropping() {
+        let info = parse_sps(CodecId::H264, &H264_SPS_1080P).unwrap();
+        assert_eq!(
+            info,
+            SpsInfo {
+                width: 1920,
+                height: 1080,
+                profile: 100,
+                level: 40,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_hevc_sps() {
+        let info = parse_sps(CodecId::H265, &HEVC_SPS_720P).unwrap();
+        assert_eq!(
+            info,
+            SpsInfo {
+                width: 1280,
+                height: 720,
+                profile: 1,
+                level: 93,
+            }
+        );
+    }
+
+    #[test]
+    fn extracts_hevc_profile_tier_level() {
+        assert_eq!(
+            hevc_general_ptl(&HEVC_SPS_720P),
+            Some([0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 0x5d])
+        );
+        assert_eq!(hevc_general_ptl(&HEVC_SPS_720P[..8]), None);
+    }
+
+    #[test]
+    fn classifies_h264_access_unit() {
+        let mut stream = vec![0u8, 0, 0, 1];
+        stream.extend_from_slice(&H264_SPS_1080P);
+        stream.extend_from_slice(&[
+            0, 0, 1, 0x68, 0xeb, 0xe3, 0xcb, 0, 0, 1, 0x06, 0x05, 0, 0, 1, 0x65, 0x88, 0x84,
+        ]);
+        let inspection = inspect(CodecId::H264, &stream);
+        assert_eq!(inspection.counts.sps, 1);
+        assert_eq!(inspection.counts.pps, 1);
+        assert_eq!(inspection.counts.sei, 1);
+        assert_eq!(inspection.counts.idr, 1);
+        assert!(inspection.is_decodable_start(CodecId::H264));
+        assert_eq!(inspection.sps.map(|sps| sps.width), Some(1920));
+
+        let delta = inspect(CodecId::H264, &[0, 0, 0, 1, 0x41, 0x9a]);
+        assert_eq!(delta.counts.non_idr, 1);
+        assert!(!delta.is_keyframe());
+        assert!(!delta.is_decodable_start(CodecId::H264));
+    }
+
+    #[test]
+    fn classifies_hevc_units_and_requires_vps() {
+        let (nal_type, kind) = classify(CodecId::H265, 0x26);
+        assert_eq!((nal_type, kind), (19, NalKind::Idr));
+        assert_eq!(classify(CodecId::H265, 0x40).1, NalKind::Vps);
+        assert_eq!(classify(CodecId::H265, 0x02).1, NalKind::NonIdr);
+        assert_eq!(classify(CodecId::H265, 0x4e).1, NalKind::Sei);
+
+        let mut stream = vec![0u8, 0, 0, 1];
+        stream.extend_from_slice(&HEVC_SPS_720P);
+        stream.extend_from_slice(&[0, 0, 1, 0x44, 0x01, 0xc1, 0, 0, 1, 0x26, 0x01, 0xaf]);
+        let inspection = inspect(CodecId::H265, &stream);
+        assert!(inspection.is_keyframe());
+        assert!(!inspection.is_decodable_start(CodecId::H265));
+    }
+
+    #[test]
+    fn removes_emulation_prevention_bytes() {
+        assert_eq!(
+            unescape(&[0, 0, 3, 1, 0, 0, 3, 0, 3]),
+            vec![0, 0, 1, 0, 0, 0, 3]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::time::{Duration, Instant};
+
+use super::{CaptureStats, DamageRect};
+
+/// Longest gap between encoded frames while the picture is static, so clients keep
+/// seeing traffic and a lost frame is repaired without waiting for the next change.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Edge of the square tiles hashed for backends without change tracking.
+pub const TILE_SIZE: i32 = 32;
+
+/// Period over which the changed-area percentage is averaged.
+const REPORT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Decides which frames are worth encoding. Dirty rects come from the backend when
+/// it tracks changes, otherwise from a per-tile hash of the frame.
+pub struct DamageTracker {
+    keepalive: Duration,
+    tile_hashes: Vec<u64>,
+    tile_frame: (i32, i32),
+    last_encode: Option<Instant>,
+    window_start: Option<Instant>,
+    window_area: f64,
+    window_frames: u32,
+    changed_area_percent: f32,
+    unchanged_frames: u64,
+}
+
+impl DamageTracker {
+    pub fn new(keepalive: Duration) -> Self {
+        Self {
+            keepalive,
+            tile_hashes: Vec::new(),
+            tile_frame: (0, 0),
+            last_encode: None,
+            window_start: None,
+            window_area: 0.0,
+            window_frames: 0,
+            changed_area_percent: 0.0,
+            unchanged_frames: 0,
+        }
+    }
+
+    /// Records the damage of a `width`x`height` frame captured at `now` and returns
+    /// whether it should be encoded: something changed or a keepalive is due.
+    pub fn observe(&mut self, rects: &[DamageRect], width: i32, height: i32, now: Instant) -> bool {
+        let rects = clip_rects(rects, width, height);
+        let frame_area = width.max(1) as u64 * height.max(1) as u64;
+        let changed_area = (union_area(&rects) as f64 / frame_area as f64).min(1.0) as f32;
+        let keepalive_due = self
+            .last_encode
+            .map(|at| now.saturating_duration_since(at) >= self.keepalive)
+            .unwrap_or(true);
+        let encode = !rects.is_empty() || keepalive_due;
+        if encode {
+            self.last_encode = Some(now);
+        } else {
+            self.unchanged_frames = self.unchanged_frames.saturating_add(1);
+        }
+
+        let window_start = *self.window_start.get_or_insert(now);
+        self.window_area += changed_area as f64;
+        self.window_frames = self.window_frames.saturating_add(1);
+        if now.saturating_duration_since(window_start) >= REPORT_WINDOW {
+            self.changed_area_percent =
+                (self.window_area * 100.0 / self.window_frames as f64) as f32;
+            self.window_start = Some(now);
+            self.window_area = 0.0;
+            self.window_frames = 0;
+        }
+
+        encode
+    }
+
+    /// Tiles of `bgra` that differ from the previous call. Everything counts as
+    /// changed on the first frame and after a size change.
+    pub fn hash_diff(&mut self, bgra: &[u8], width: i32, height: i32) -> Vec<DamageRect> {
+        let hashes = tile_hashes(bgra, width, height);
+        let rects = if self.tile_frame != (width, height) || self.tile_hashes.len() != hashes.len()
+        {
+            vec![DamageRect::full(width, height)]
+        } else {
+            let columns = tiles(width);
+            hashes
+                .iter()
+                .zip(&self.tile_hashes)
+                .enumerate()
+                .filter(|(_, (current, previous))| current != previous)
+                .map(|(index, _)| {
+                    let x = (index % columns) as i32 * TILE_SIZE;
+                    let y = (index / columns) as i32 * TILE_SIZE;
+                    DamageRect {
+                        x,
+                        y,
+                        width: TILE_SIZE.min(width - x),
+                        height: TILE_SIZE.min(height - y),
+                    }
+                })
+                .collect()
+        };
+        self.tile_hashes = hashes;
+        self.tile_frame = (width, height);
+        rects
+    }
+
+    /// Copies the tracker's counters into source stats before they are published.
+    pub fn annotate(&self, stats: &mut CaptureStats) {
+        stats.changed_area_percent = (self.changed_area_percent * 10.0).round() / 10.0;
+        stats.unchanged_frames = self.unchanged_frames;
+    }
+}
+
+impl Default for DamageTracker {
+    fn default() -> Self {
+        Self::new(KEEPALIVE_INTERVAL)
+    }
+}
+
+fn tiles(length: i32) -> usize {
+    ((length.max(0) + TILE_SIZE - 1) / TILE_SIZE) as usize
+}
+
+/// FNV-1a over each tile's rows, eight bytes at a time.
+fn tile_hashes(bgra: &[u8], width: i32, height: i32) -> Vec<u64> {
+    let (columns, rows) = (tiles(width), tiles(height));
+    let stride = width.max(0) as usize * 4;
+    let mut hashes = vec![0xcbf2_9ce4_8422_2325u64; columns * rows];
+    for y in 0..height.max(0) as usize {
+        let Some(line) = bgra.get(y * stride..(y + 1) * stride) else {
+            break;
+        };
+        let row_hashes = &mut hashes[(y / TILE_SIZE as usize) * columns..][..columns];
+        for (hash, span) in row_hashes
+            .iter_mut()
+            .zip(line.chunks(TILE_SIZE as usize * 4))
+        {
+            for word in span.chunks(8) {
+                let mut bytes = [0u8; 8];
+                bytes[..word.len()].copy_from_slice(word);
+                *hash ^= u64::from_le_bytes(bytes);
+                *hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+    }
+    hashes
+}
+
+/// `rects` cut to the `width`x`height` frame, dropping those outside it.
+fn clip_rects(rects: &[DamageRect], width: i32, height: i32) -> Vec<DamageRect> {
+    rects
+        .iter()
+        .filter_map(|rect| {
+            let x0 = rect.x.max(0);
+            let y0 = rect.y.max(0);
+            let x1 = (rect.x + rect.width).min(width);
+            let y1 = (rect.y + rect.height).min(height);
+            (x0 < x1 && y0 < y1).then_some(DamageRect {
+                x: x0,
+                y: y0,
+                width: x1 - x0,
+                height: y1 - y0,
+            })
+        })
+        .collect()
+}
+
+/// Area covered by `rects`, counting overlaps once.
+fn union_area(rects: &[DamageRect]) -> u64 {
+    let mut edges: Vec<i32> = rects
+        .iter()
+        .flat_map(|rect| [rect.x, rect.x + rect.width])
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+    let mut area = 0u64;
+    for span in edges.windows(2) {
+        let (x0, x1) = (span[0], span[1]);
+        let mut rows: Vec<(i32, i32)> = rects
+            .iter()
+            .filter(|rect| rect.x <= x0 && rect.x + rect.width >= x1)
+            .map(|rect| (rect.y, rect.y + rect.height))
+            .collect();
+        rows.sort_unstable();
+        let mut covered = 0i64;
+        let mut end = i32::MIN;
+        for (y0, y1) in rows {
+            let start = y0.max(end);
+            if y1 > start {
+                covered += (y1 - start) as i64;
+            }
+            end = end.max(y1);
+        }
+        area += covered as u64 * (x1 - x0) as u64;
+    }
+    area
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> DamageRect {
+        DamageRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn clips_rects_to_frame() {
+        let clipped = clip_rects(
+            &[
+                rect(0, 0, 10, 10),
+                rect(40, 40, 30, 30),
+                rect(-5, 90, 10, 20),
+                rect(70, 0, 10, 10),
+            ],
+            64,
+            100,
+        );
+        assert_eq!(
+            clipped,
+            vec![rect(0, 0, 10, 10), rect(40, 40, 24, 30), rect(0, 90, 5, 10)]
+        );
+        assert_eq!(union_area(&[rect(0, 0, 10, 10), rect(5, 5, 10, 10)]), 175);
+    }
+
+    #[test]
+    fn skips_static_frames_until_keepalive() {
+        let start = Instant::now();
+        let mut tracker = DamageTracker::new(Duration::from_millis(500));
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        assert!(
+            tracker.observe(&[], 100, 100, at(0)),
+            "the first frame is always encoded"
+        );
+        assert!(!tracker.observe(&[], 100, 100, at(100)));
+        assert!(tracker.observe(&[rect(10, 10, 10, 10)], 100, 100, at(200)));
+        assert!(!tracker.observe(&[], 100, 100, at(600)));
+        assert!(
+            tracker.observe(&[], 100, 100, at(700)),
+            "keepalive after 500 ms without an encode"
+        );
+
+        let mut stats = CaptureStats::default();
+        tracker.observe(&[], 100, 100, at(1000));
+        tracker.annotate(&mut stats);
+        assert_eq!(stats.unchanged_frames, 3);
+        // One 1% frame out of six in the first window.
+        assert_eq!(stats.changed_area_percent, 0.2);
+    }
+
+    #[test]
+    fn counts_only_the_changed_pixels_of_touching_rects() {
+        let start = Instant::now();
+        let mut tracker = DamageTracker::new(Duration::from_millis(500));
+        // Corner to corner: 200 pixels, not the 400 of their bounding box.
+        let diagonal = [rect(0, 0, 10, 10), rect(10, 10, 10, 10)];
+        tracker.observe(&diagonal, 100, 100, start);
+        tracker.observe(&diagonal, 100, 100, start + REPORT_WINDOW);
+
+        let mut stats = CaptureStats::default();
+        tracker.annotate(&mut stats);
+        assert_eq!(stats.changed_area_percent, 2.0);
+    }
+
+    #[test]
+    fn hashes_tiles_for_backends_without_damage() {
+        let (width, height) = (70, 40);
+        let mut frame = vec![0u8; (width * height * 4) as usize];
+        let mut tracker = DamageTracker::default();
+        assert_eq!(
+            tracker.hash_diff(&frame, width, height),
+            vec![DamageRect::full(width, height)]
+        );
+        assert!(tracker.hash_diff(&frame, width, height).is_empty());
+
+        // One pixel in the bottom-right partial tile.
+        let pixel = ((35 * width + 68) * 4) as usize;
+        frame[pixel] = 0xff;
+        assert_eq!(
+            tracker.hash_diff(&frame, width, height),
+            vec![rect(64, 32, 6, 8)]
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use std::time::Duration;
+
+use windows::core::Interface;
+use windows::Win32::Foundation::RECT;
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BIND_FLAG,
+    D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_MAPPED_SUBRESOURCE,
+    D3D11_MAP_READ, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+    D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC};
+use windows::Win32::Graphics::Dxgi::{
+    IDXGIAdapter, IDXGIDevice, IDXGIOutput, IDXGIOutput1, IDXGIOutputDuplication, IDXGIResource,
+    DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_WAIT_TIMEOUT, DXGI_OUTDUPL_DESC, DXGI_OUTDUPL_FRAME_INFO,
+    DXGI_OUTDUPL_MOVE_RECT, DXGI_OUTPUT_DESC,
+};
+
+use super::target::{clip, crop_bgra, crop_damage};
+use super::window::WindowTracker;
+use super::{CaptureStats, CaptureTarget, CapturedFrame, DamageRect, FrameSource};
+use crate::scaler::Region;
+
+struct DxgiDuplication {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    duplication: IDXGIOutputDuplication,
+    staging: ID3D11Texture2D,
+    /// GPU copy of the latest desktop image for the zero-copy path, so the
+    /// duplication frame can be released at once and static desktops re-encoded.
+    retained: Option<ID3D11Texture2D>,
+    width: i32,
+    height: i32,
+    /// GDI device name of the duplicated output.
+    display_id: String,
+    /// Top-left of the output in virtual-screen coordinates.
+    origin: (i32, i32),
+}
+
+/// Desktop Duplication capture of one output at its native size, cropped to a
+/// region or window when the target asks for one. The duplication is recreated on
+/// the next acquire after access loss or any other failure, and when a tracked
+/// window moves to another output.
+pub struct DxgiSource {
+    target: CaptureTarget,
+    window: Option<WindowTracker>,
+    duplication: Option<DxgiDuplication>,
+    /// Part of the output the target covers, in output pixels.
+    crop: Region,
+    damage: Vec<DamageRect>,
+    /// Returned again, without damage, when the desktop did not change.
+    last_frame: Option<CapturedFrame>,
+    stats: CaptureStats,
+}
+
+pub struct DxgiFrame {
+    pub texture: ID3D11Texture2D,
+    /// `false` when the desktop did not change and this is the previous image.
+    pub fresh: bool,
+}
+
+impl FrameSource for DxgiSource {
+    fn open(target: &CaptureTarget, _width: i32, _height: i32) -> Result<Self, String> {
+        let window = match target {
+            CaptureTarget::Window { handle, title } => {
+                Some(WindowTracker::new(*handle, title.clone()))
+            }
+            _ => None,
+        };
+        let mut source = Self {
+            target: target.clone(),
+            window,
+            duplication: None,
+            crop: Region::default(),
+            damage: Vec::new(),
+            last_frame: None,
+            stats: CaptureStats {
+                capture_path: "DXGI".to_string(),
+                capture_scale: "1:1".to_string(),
+                ..CaptureStats::default()
+            },
+        };
+        source.ensure_duplication()?;
+        Ok(source)
+    }
+
+    fn acquire(&mut self, timeout: Duration) -> Result<CapturedFrame, String> {
+        if let Err(err) = self.ensure_duplication() {
+            return Err(self.record_failure(err));
+        }
+        let Some(duplication) = self.duplication.as_mut() else {
+            return Err("DXGI capture not initialized".to_string());
+        };
+        match acquire_frame(duplication, timeout, &mut self.stats) {
+            Ok((frame, damage)) => {
+                let full = Region {
+                    x: 0,
+                    y: 0,
+                    width: frame.width,
+                    height: frame.height,
+                };
+                let (frame, damage) = if self.crop == full {
+                    (frame, damage)
+                } else {
+                    (
+                        CapturedFrame {
+                            bgra: crop_bgra(&frame.bgra, frame.width, self.crop),
+                            width: self.crop.width,
+                            height: self.crop.height,
+                        },
+                        crop_damage(&damage, self.crop),
+                    )
+                };
+                self.damage = damage;
+                self.stats.frames = self.stats.frames.saturating_add(1);
+                self.stats.last_frame_bytes = frame.bgra.len() as u32;
+                self.last_frame = Some(frame.clone());
+                Ok(frame)
+            }
+            Err(err) if err == TIMEOUT_ERROR => match self.last_frame.as_ref() {
+                // Nothing was presented; hand back the previous image so the
+                // encoder sees an unchanged frame instead of falling back to GDI.
+                Some(frame)
+                    if (frame.width, frame.height) == (self.crop.width, self.crop.height) =>
+                {
+                    self.damage.clear();
+                    Ok(frame.clone())
+                }
+                _ => Err(err),
+            },
+            Err(err) => Err(self.record_failure(err)),
+        }
+    }
+
+    fn stats(&self) -> CaptureStats {
+        self.stats.clone()
+    }
+
+    fn damage(&self) -> &[DamageRect] {
+        &self.damage
+    }
+
+    fn bounds(&self) -> Region {
+        let (x, y) = self
+            .duplication
+            .as_ref()
+            .map(|duplication| duplication.origin)
+            .unwrap_or_default();
+        Region {
+            x: x + self.crop.x,
+            y: y + self.crop.y,
+            ..self.crop
+        }
+    }
+}
+
+impl DxgiSource {
+    /// Native size of the duplicated output, or `None` until a duplication is open.
+    pub fn size(&self) -> Option<(i32, i32)> {
+        self.duplication
+            .as_ref()
+            .map(|duplication| (duplication.width, duplication.height))
+    }
+
+    /// Acquires the next desktop texture without copying it to the CPU. When the
+    /// desktop did not change the previous texture comes back with `fresh` unset.
+    pub fn acquire_surface(&mut self, timeout: Duration) -> Result<DxgiFrame, String> {
+        if let Err(err) = self.ensure_duplication() {
+            return Err(self.record_failure(err));
+        }
+        let Some(duplication) = self.duplication.as_mut() else {
+            return Err("DXGI capture not initialized".to_string());
+        };
+        match acquire_surface(duplication, timeout, &mut self.stats) {
+            Ok((frame, damage)) => {
+                self.damage = damage;
+                if frame.fresh {
+                    self.stats.frames = self.stats.frames.saturating_add(1);
+                }
+                Ok(frame)
+            }
+            Err(err) => Err(self.record_failure(err)),
+        }
+    }
+
+    /// Opens the duplication for the target's output and works out the crop. Window
+    /// targets are resolved on every call so the crop follows the window.
+    fn ensure_duplication(&mut self) -> Result<(), String> {
+        let placement = match self.window.as_mut() {
+            Some(tracker) => Some(tracker.resolve()?),
+            None => None,
+        };
+        let display_id = match placement.as_ref() {
+            Some(placement) => Some(placement.display_id.as_str()),
+            None => self.target.display_id(),
+        };
+        let stale = match (self.duplication.as_ref(), display_id) {
+            (Some(duplication), Some(display_id)) => {
+                !duplication.display_id.eq_ignore_ascii_case(display_id)
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        if stale {
+            self.duplication = None;
+            self.duplication = Some(init_duplication(display_id)?);
+        }
+        let Some(duplication) = self.duplication.as_ref() else {
+            return Err("DXGI capture not initialized".to_string());
+        };
+        let (width, height) = (duplication.width, duplication.height);
+        self.crop = match (&self.target, placement) {
+            (_, Some(placement)) => clip(
+                Region {
+                    x: placement.rect.x - duplication.origin.0,
+                    y: placement.rect.y - duplication.origin.1,
+                    ..placement.rect
+                },
+                width,
+                height,
+            )
+            .ok_or_else(|| "Capture window is off screen".to_string())?,
+            (
+                CaptureTarget::Region {
+                    x,
+                    y,
+                    width: region_width,
+                    height: region_height,
+                    ..
+                },
+                None,
+            ) => clip(
+                Region {
+                    x: *x,
+                    y: *y,
+                    width: *region_width,
+                    height: *region_height,
+                },
+                width,
+                height,
+            )
+            .ok_or_else(|| "Capture region is outside the display".to_string())?,
+            _ => Region {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        };
+        Ok(())
+    }
+
+    /// Timeouts just mean the desktop did not change; anything else drops the
+    /// duplication so it is recreated on the next acquire.
+    fn record_failure(&mut self, err: String) -> String {
+        if err != TIMEOUT_ERROR {
+            self.stats.failures = self.stats.failures.saturating_add(1);
+            self.duplication = None;
+        }
+        err
+    }
+}
+
+const TIMEOUT_ERROR: &str = "DXGI capture timeout";
+
+fn init_duplication(target_id: Option<&str>) -> Result<DxgiDuplication, String> {
+    let mut device: Option<ID3D11Device> = None;
+    let mut context: Option<ID3D11DeviceContext> = None;
+    unsafe {
+        D3D11CreateDevice(
+            None,
+            D3D_DRIVER_TYPE_HARDWARE,
+            None,
+            D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+            None,
+            D3D11_SDK_VERSION,
+            Some(&mut device),
+            None,
+            Some(&mut context),
+        )
+        .map_err(|err| format!("D3D11CreateDevice failed: 0x{:08x}", err.code().0))?;
+    }
+    let device = device.ok_or_else(|| "D3D11 device unavailable".to_string())?;
+    let context = context.ok_or_else(|| "D3D11 context unavailable".to_string())?;
+
+    let dxgi_device: IDXGIDevice = device
+        .cast()
+        .map_err(|err| format!("DXGI device cast failed: 0x{:08x}", err.code().0))?;
+    let adapter: IDXGIAdapter = unsafe { dxgi_device.GetAdapter() }
+        .map_err(|err| format!("DXGI GetAdapter failed: 0x{:08x}", err.code().0))?;
+    let (output1, display_id, desktop) = select_output(&adapter, target_id)?;
+    let duplication = unsafe { output1.DuplicateOutput(&device) }
+        .map_err(|err| format!("DXGI DuplicateOutput failed: 0x{:08x}", err.code().0))?;
+    let mut dupl_desc = DXGI_OUTDUPL_DESC::default();
+    unsafe { duplication.GetDesc(&mut dupl_desc) };
+    let width = dupl_desc.ModeDesc.Width as i32;
+    let height = dupl_desc.ModeDesc.Height as i32;
+
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: width as u32,
+        Height: height as u32,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: D3D11_USAGE_STAGING,
+        BindFlags: D3D11_BIND_FLAG(0).0 as u32,
+        CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+        MiscFlags: 0,
+    };
+    let mut staging: Option<ID3D11Texture2D> = None;
+    unsafe {
+        device
+            .CreateTexture2D(&desc, None, Some(&mut staging))
+            .map_err(|err| format!("CreateTexture2D failed: 0x{:08x}", err.code().0))?;
+    }
+    let staging = staging.ok_or_else(|| "Staging texture unavailable".to_string())?;
+
+    Ok(DxgiDuplication {
+        device,
+        context,
+        duplication,
+        staging,
+        retained: None,
+        width,
+        height,
+        display_id,
+        origin: (desktop.left, desktop.top),
+    })
+}
+
+fn acquire_next(
+    duplication: &IDXGIOutputDuplication,
+    timeout: Duration,
+    stats: &mut CaptureStats,
+) -> Result<(ID3D11Texture2D, DXGI_OUTDUPL_FRAME_INFO), String> {
+    let mut frame_info = DXGI_OUTDUPL_FRAME_INFO::default();
+    let mut resource: Option<IDXGIResource> = None;
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+    let result =
+        unsafe { duplication.AcquireNextFrame(timeout_ms, &mut frame_info, &mut resource) };
+    if let Err(err) = result {
+        let code = err.code();
+        if code == DXGI_ERROR_WAIT_TIMEOUT {
+            stats.timeouts = stats.timeouts.saturating_add(1);
+            return Err(TIMEOUT_ERROR.to_string());
+        }
+        if code == DXGI_ERROR_ACCESS_LOST {
+            stats.access_lost = stats.access_lost.saturating_add(1);
+            return Err("DXGI capture access lost".to_string());
+        }
+        return Err(format!("DXGI AcquireNextFrame failed: 0x{:08x}", code.0));
+    }
+
+    let texture = resource
+        .ok_or_else(|| "DXGI resource missing".to_string())
+        .and_then(|resource| {
+            resource
+                .cast::<ID3D11Texture2D>()
+                .map_err(|err| format!("DXGI resource cast failed: 0x{:08x}", err.code().0))
+        });
+    match texture {
+        Ok(texture) => Ok((texture, frame_info)),
+        Err(err) => {
+            unsafe {
+                let _ = duplication.ReleaseFrame();
+            }
+            Err(err)
+        }
+    }
+}
+
+fn acquire_frame(
+    capture: &mut DxgiDuplication,
+    timeout: Duration,
+    stats: &mut CaptureStats,
+) -> Result<(CapturedFrame, Vec<DamageRect>), String> {
+    let (width, height) = (capture.width, capture.height);
+    let (texture, frame_info) = acquire_next(&capture.duplication, timeout, stats)?;
+    let damage = frame_damage(&capture.duplication, &frame_info, width, height);
+    unsafe {
+        capture.context.CopyResource(&capture.staging, &texture);
+    }
+
+    let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+    let mapped_ok = unsafe {
+        capture
+            .context
+            .Map(&capture.staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))
+    };
+    if let Err(err) = mapped_ok {
+        unsafe {
+            let _ = capture.duplication.ReleaseFrame();
+        }
+        return Err(format!("DXGI Map failed: 0x{:08x}", err.code().0));
+    }
+
+    let row_pitch = mapped.RowPitch as usize;
+    let src = mapped.pData as *const u8;
+    let row_bytes = width as usize * 4;
+    let mut buffer = vec![0u8; row_bytes * height as usize];
+    for (y, dst_row) in buffer.chunks_exact_mut(row_bytes).enumerate() {
+        let src_row = unsafe { src.add(y * row_pitch) };
+        unsafe {
+            std::ptr::copy_nonoverlapping(src_row, dst_row.as_mut_ptr(), row_bytes);
+        }
+    }
+    unsafe {
+        capture.context.Unmap(&capture.staging, 0);
+        let _ = capture.duplication.ReleaseFrame();
+    }
+    Ok((
+        CapturedFrame {
+            bgra: buffer,
+            width,
+            height,
+        },
+        damage,
+    ))
+}
+
+fn acquire_surface(
+    capture: &mut DxgiDuplication,
+    timeout: Duration,
+    stats: &mut CaptureStats,
+) -> Result<(DxgiFrame, Vec<DamageRect>), String> {
+    let (texture, frame_info) = match acquire_next(&capture.duplication, timeout, stats) {
+        Ok(next) => next,
+        Err(err) if err == TIMEOUT_ERROR => {
+            return match capture.retained.clone() {
+                Some(texture) => Ok((
+                    DxgiFrame {
+                        texture,
+                        fresh: false,
+                    },
+                    Vec::new(),
+                )),
+                None => Err(err),
+            };
+        }
+        Err(err) => return Err(err),
+    };
+    let damage = frame_damage(
+        &capture.duplication,
+        &frame_info,
+        capture.width,
+        capture.height,
+    );
+    if capture.retained.is_none() {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        desc.Usage = D3D11_USAGE_DEFAULT;
+        desc.BindFlags = 0;
+        desc.CPUAccessFlags = 0;
+        desc.MiscFlags = 0;
+        let mut retained: Option<ID3D11Texture2D> = None;
+        let created = unsafe {
+            capture
+                .device
+                .CreateTexture2D(&desc, None, Some(&mut retained))
+        };
+        if let Err(err) = created {
+            unsafe {
+                let _ = capture.duplication.ReleaseFrame();
+            }
+            return Err(format!("CreateTexture2D failed: 0x{:08x}", err.code().0));
+        }
+        capture.retained = retained;
+    }
+    let retained = capture.retained.clone();
+    unsafe {
+        if let Some(retained) = retained.as_ref() {
+            capture.context.CopyResource(retained, &texture);
+        }
+        let _ = capture.duplication.ReleaseFrame();
+    }
+    let texture = retained.ok_or_else(|| "Retained texture unavailable".to_string())?;
+    Ok((
+        DxgiFrame {
+            texture,
+            fresh: true,
+        },
+        damage,
+    ))
+}
+
+/// Dirty and move-destination rects reported for the acquired frame. Falls back to
+/// the whole frame when the metadata cannot be read.
+fn frame_damage(
+    duplication: &IDXGIOutputDuplication,
+    frame_info: &DXGI_OUTDUPL_FRAME_INFO,
+    width: i32,
+    height: i32,
+) -> Vec<DamageRect> {
+    let full = vec![DamageRect::full(width, height)];
+    if frame_info.LastPresentTime == 0 {
+        // Only the pointer moved; the desktop image is unchanged.
+        return Vec::new();
+    }
+    let buffer_size = frame_info.TotalMetadataBufferSize;
+    if buffer_size == 0 {
+        return full;
+    }
+
+    let mut rects = Vec::new();
+    let move_size = std::mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>();
+    let mut moves = vec![DXGI_OUTDUPL_MOVE_RECT::default(); buffer_size as usize / move_size + 1];
+    let mut required = 0u32;
+    let moved = unsafe {
+        duplication.GetFrameMoveRects(
+            (moves.len() * move_size) as u32,
+            moves.as_mut_ptr(),
+            &mut required,
+        )
+    };
+    if moved.is_err() {
+        return full;
+    }
+    let move_count = (required as usize / move_size).min(moves.len());
+    rects.extend(
+        moves[..move_count]
+            .iter()
+            .map(|entry| rect_to_damage(&entry.DestinationRect)),
+    );
+
+    let mut dirty = vec![RECT::default(); buffer_size as usize / std::mem::size_of::<RECT>() + 1];
+    let dirtied = unsafe {
+        duplication.GetFrameDirtyRects(
+            (dirty.len() * std::mem::size_of::<RECT>()) as u32,
+            dirty.as_mut_ptr(),
+            &mut required,
+        )
+    };
+    if dirtied.is_err() {
+        return full;
+    }
+    let dirty_count = (required as usize / std::mem::size_of::<RECT>()).min(dirty.len());
+    rects.extend(dirty[..dirty_count].iter().map(rect_to_damage));
+    rects
+}
+
+fn rect_to_damage(rect: &RECT) -> DamageRect {
+    DamageRect {
+        x: rect.left,
+        y: rect.top,
+        width: rect.right - rect.left,
+        height: rect.bottom - rect.top,
+    }
+}
+
+/// The output named `target_id` (the first one for `None`), with its device name and
+/// desktop rect.
+fn select_output(
+    adapter: &IDXGIAdapter,
+    target_id: Option<&str>,
+) -> Result<(IDXGIOutput1, String, RECT), String> {
+    let mut index = 0u32;
+    loop {
+        let output: IDXGIOutput = match unsafe { adapter.EnumOutputs(index) } {
+            Ok(output) => output,
+            Err(_) => break,
+        };
+        let output1: IDXGIOutput1 = output
+            .cast()
+            .map_err(|err| format!("DXGI output cast failed: 0x{:08x}", err.code().0))?;
+        let mut desc = DXGI_OUTPUT_DESC::default();
+        unsafe {
+            output
+                .GetDesc(&mut desc)
+                .map_err(|err| format!("DXGI GetDesc failed: 0x{:08x}", err.code().0))?;
+        }
+        let name = utf16_to_string(&desc.DeviceName);
+        if target_id
+            .map(|target| target.eq_ignore_ascii_case(&name))
+            .unwrap_or(true)
+        {
+            return Ok((output1, name, desc.DesktopCoordinates));
+        }
+        index = index.saturating_add(1);
+    }
+    Err("DXGI output not found".to_string())
+}
+
+fn utf16_to_string(buffer: &[u16]) -> String {
+    let len = buffer
+        .iter()
+        .position(|&ch| ch == 0)
+        .unwrap_or(buffer.len());
+    String::from_utf16_lossy(&buffer[..len])
+}
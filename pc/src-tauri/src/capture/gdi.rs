@@ -0,0 +1,305 @@
+use std::mem::size_of;
+use std::time::Duration;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Graphics::Gdi::{
+    BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, CreateDCW, DeleteDC, DeleteObject, GetDC,
+    GetDIBits, ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS,
+    SRCCOPY,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetSystemMetrics, SM_CXSCREEN, SM_CXVIRTUALSCREEN, SM_CYSCREEN, SM_CYVIRTUALSCREEN,
+    SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN,
+};
+
+use super::target::clip;
+use super::window::WindowTracker;
+use super::{CaptureStats, CaptureTarget, CapturedFrame, DamageRect, FrameSource};
+use crate::scaler::Region;
+
+/// GDI BitBlt capture at the target's native size; slow but works on every
+/// desktop, including sessions where DXGI duplication is unavailable.
+pub struct GdiSource {
+    target: CaptureTarget,
+    window: Option<WindowTracker>,
+    damage: Vec<DamageRect>,
+    bounds: Region,
+    stats: CaptureStats,
+}
+
+impl FrameSource for GdiSource {
+    fn open(target: &CaptureTarget, _width: i32, _height: i32) -> Result<Self, String> {
+        let window = match target {
+            CaptureTarget::Window { handle, title } => {
+                Some(WindowTracker::new(*handle, title.clone()))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            target: target.clone(),
+            window,
+            damage: Vec::new(),
+            bounds: Region::default(),
+            stats: CaptureStats {
+                capture_path: "GDI".to_string(),
+                capture_scale: "1:1".to_string(),
+                ..CaptureStats::default()
+            },
+        })
+    }
+
+    /// GDI reads are synchronous, so the timeout is not used.
+    fn acquire(&mut self, _timeout: Duration) -> Result<CapturedFrame, String> {
+        let frame = match self
+            .resolve()
+            .and_then(|(display_id, area)| capture_bgra_gdi(display_id.as_deref(), area))
+        {
+            Ok(frame) => frame,
+            Err(err) => {
+                self.stats.failures = self.stats.failures.saturating_add(1);
+                return Err(err);
+            }
+        };
+        // GDI has no change tracking; report the whole frame.
+        self.damage = vec![DamageRect::full(frame.width, frame.height)];
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        self.stats.last_frame_bytes = frame.bgra.len() as u32;
+        Ok(frame)
+    }
+
+    fn stats(&self) -> CaptureStats {
+        self.stats.clone()
+    }
+
+    fn damage(&self) -> &[DamageRect] {
+        &self.damage
+    }
+
+    fn precise_damage(&self) -> bool {
+        false
+    }
+
+    fn bounds(&self) -> Region {
+        self.bounds
+    }
+}
+
+impl GdiSource {
+    /// Display DC to read from and the area to copy in that DC's coordinates. Also
+    /// records where the area sits on the desktop.
+    fn resolve(&mut self) -> Result<(Option<String>, Region), String> {
+        if let Some(tracker) = self.window.as_mut() {
+            // The screen DC spans the virtual screen with the primary display at (0, 0).
+            let placement = tracker.resolve()?;
+            let virtual_screen = unsafe {
+                Region {
+                    x: GetSystemMetrics(SM_XVIRTUALSCREEN),
+                    y: GetSystemMetrics(SM_YVIRTUALSCREEN),
+                    width: GetSystemMetrics(SM_CXVIRTUALSCREEN),
+                    height: GetSystemMetrics(SM_CYVIRTUALSCREEN),
+                }
+            };
+            let moved = Region {
+                x: placement.rect.x - virtual_screen.x,
+                y: placement.rect.y - virtual_screen.y,
+                ..placement.rect
+            };
+            let visible = clip(moved, virtual_screen.width, virtual_screen.height)
+                .ok_or_else(|| "Capture window is off screen".to_string())?;
+            let area = Region {
+                x: visible.x + virtual_screen.x,
+                y: visible.y + virtual_screen.y,
+                ..visible
+            };
+            self.bounds = area;
+            return Ok((None, area));
+        }
+        let display_id = self.target.display_id().map(|value| value.to_string());
+        let display = match display_id.as_deref() {
+            Some(name) => query_display_rect(name),
+            None => primary_display_rect(),
+        };
+        let area = match &self.target {
+            CaptureTarget::Region {
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => clip(
+                Region {
+                    x: *x,
+                    y: *y,
+                    width: *width,
+                    height: *height,
+                },
+                display.width,
+                display.height,
+            )
+            .ok_or_else(|| "Capture region is outside the display".to_string())?,
+            _ => Region {
+                x: 0,
+                y: 0,
+                width: display.width,
+                height: display.height,
+            },
+        };
+        self.bounds = Region {
+            x: display.x + area.x,
+            y: display.y + area.y,
+            ..area
+        };
+        Ok((display_id, area))
+    }
+}
+
+/// Copies `area` of the display DC (`None` is the screen DC).
+fn capture_bgra_gdi(target_id: Option<&str>, area: Region) -> Result<CapturedFrame, String> {
+    let hwnd = HWND(0);
+    let screen_dc = if let Some(name) = target_id {
+        let driver_wide = to_wide("DISPLAY");
+        let name_wide = to_wide(name);
+        unsafe {
+            CreateDCW(
+                PCWSTR::from_raw(driver_wide.as_ptr()),
+                PCWSTR::from_raw(name_wide.as_ptr()),
+                PCWSTR::null(),
+                None,
+            )
+        }
+    } else {
+        unsafe { GetDC(hwnd) }
+    };
+    if screen_dc.0 == 0 {
+        return Err("GetDC failed".to_string());
+    }
+    let (width, height) = (area.width, area.height);
+    if width <= 0 || height <= 0 {
+        unsafe {
+            if target_id.is_some() {
+                DeleteDC(screen_dc);
+            } else {
+                ReleaseDC(hwnd, screen_dc);
+            }
+        }
+        return Err(format!("Invalid display size {width}x{height}"));
+    }
+    let mem_dc = unsafe { CreateCompatibleDC(screen_dc) };
+    if mem_dc.0 == 0 {
+        unsafe {
+            ReleaseDC(hwnd, screen_dc);
+        }
+        return Err("CreateCompatibleDC failed".to_string());
+    }
+    let bitmap = unsafe { CreateCompatibleBitmap(screen_dc, width, height) };
+    if bitmap.0 == 0 {
+        unsafe {
+            DeleteDC(mem_dc);
+            ReleaseDC(hwnd, screen_dc);
+        }
+        return Err("CreateCompatibleBitmap failed".to_string());
+    }
+
+    let old = unsafe { SelectObject(mem_dc, bitmap) };
+    let blit_ok = unsafe {
+        BitBlt(
+            mem_dc, 0, 0, width, height, screen_dc, area.x, area.y, SRCCOPY,
+        )
+    }
+    .is_ok();
+
+    let mut info = BITMAPINFO::default();
+    info.bmiHeader = BITMAPINFOHEADER {
+        biSize: size_of::<BITMAPINFOHEADER>() as u32,
+        biWidth: width,
+        biHeight: -height,
+        biPlanes: 1,
+        biBitCount: 32,
+        biCompression: BI_RGB.0,
+        biSizeImage: 0,
+        biXPelsPerMeter: 0,
+        biYPelsPerMeter: 0,
+        biClrUsed: 0,
+        biClrImportant: 0,
+    };
+
+    let buffer_len = (width as usize) * (height as usize) * 4;
+    let mut buffer = vec![0u8; buffer_len];
+    let rows = unsafe {
+        GetDIBits(
+            mem_dc,
+            bitmap,
+            0,
+            height as u32,
+            Some(buffer.as_mut_ptr() as *mut _),
+            &mut info,
+            DIB_RGB_COLORS,
+        )
+    };
+
+    unsafe {
+        SelectObject(mem_dc, old);
+        DeleteObject(bitmap);
+        DeleteDC(mem_dc);
+        if target_id.is_some() {
+            DeleteDC(screen_dc);
+        } else {
+            ReleaseDC(hwnd, screen_dc);
+        }
+    }
+
+    if blit_ok && rows > 0 {
+        Ok(CapturedFrame {
+            bgra: buffer,
+            width,
+            height,
+        })
+    } else {
+        Err("GetDIBits failed".to_string())
+    }
+}
+
+fn primary_display_rect() -> Region {
+    Region {
+        x: 0,
+        y: 0,
+        width: unsafe { GetSystemMetrics(SM_CXSCREEN) },
+        height: unsafe { GetSystemMetrics(SM_CYSCREEN) },
+    }
+}
+
+/// Current mode of a display in virtual-screen coordinates.
+fn query_display_rect(display_id: &str) -> Region {
+    use windows::Win32::Graphics::Gdi::{
+        EnumDisplaySettingsExW, DEVMODEW, ENUM_CURRENT_SETTINGS, ENUM_DISPLAY_SETTINGS_FLAGS,
+    };
+    let name_wide = to_wide(display_id);
+    let mut devmode = DEVMODEW::default();
+    devmode.dmSize = std::mem::size_of::<DEVMODEW>() as u16;
+    let ok = unsafe {
+        EnumDisplaySettingsExW(
+            PCWSTR::from_raw(name_wide.as_ptr()),
+            ENUM_CURRENT_SETTINGS,
+            &mut devmode,
+            ENUM_DISPLAY_SETTINGS_FLAGS(0),
+        )
+    };
+    if ok.as_bool() {
+        let position = unsafe { devmode.Anonymous1.Anonymous2.dmPosition };
+        Region {
+            x: position.x,
+            y: position.y,
+            width: devmode.dmPelsWidth as i32,
+            height: devmode.dmPelsHeight as i32,
+        }
+    } else {
+        primary_display_rect()
+    }
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+    let mut wide: Vec<u16> = value.encode_utf16().collect();
+    wide.push(0);
+    wide
+}
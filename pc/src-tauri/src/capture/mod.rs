@@ -0,0 +1,597 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::color::{self, ColorSpace, PixelFormat};
+use crate::scaler::{Region, ScaleMode, Scaler};
+
+mod damage;
+#[cfg(windows)]
+mod dxgi;
+#[cfg(windows)]
+mod gdi;
+mod target;
+pub mod test_pattern;
+#[cfg(windows)]
+mod window;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use damage::DamageTracker;
+#[cfg(windows)]
+pub use dxgi::{DxgiFrame, DxgiSource};
+#[cfg(windows)]
+pub use gdi::GdiSource;
+pub use target::{CaptureTarget, CaptureWindow, InputMapping};
+pub use test_pattern::TestPatternSource;
+#[cfg(target_os = "linux")]
+pub use x11::X11Source;
+
+/// Display target that selects the synthetic test pattern on every platform.
+pub const TEST_PATTERN_TARGET: &str = "test-pattern";
+
+/// How long encoders wait for a new desktop frame before reusing the pipeline tick.
+pub const ACQUIRE_TIMEOUT: Duration = Duration::from_millis(16);
+
+/// A changed region of a captured frame, in frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DamageRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl DamageRect {
+    pub fn full(width: i32, height: i32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Maps the rect between frame sizes, rounding outwards so no change is lost.
+    pub fn scale(self, src_w: i32, src_h: i32, dst_w: i32, dst_h: i32) -> Self {
+        if src_w <= 0 || src_h <= 0 {
+            return self;
+        }
+        let x0 = (self.x as i64 * dst_w as i64) / src_w as i64;
+        let y0 = (self.y as i64 * dst_h as i64) / src_h as i64;
+        let x1 = ((self.x + self.width) as i64 * dst_w as i64 + src_w as i64 - 1) / src_w as i64;
+        let y1 = ((self.y + self.height) as i64 * dst_h as i64 + src_h as i64 - 1) / src_h as i64;
+        Self {
+            x: x0 as i32,
+            y: y0 as i32,
+            width: (x1 - x0) as i32,
+            height: (y1 - y0) as i32,
+        }
+    }
+}
+
+/// One captured frame, BGRA at the size the source was opened with or, for desktop
+/// sources, the display's native size.
+#[derive(Clone)]
+pub struct CapturedFrame {
+    pub bgra: Vec<u8>,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStats {
+    pub frames: u64,
+    pub timeouts: u32,
+    pub access_lost: u32,
+    pub failures: u32,
+    pub last_frame_bytes: u32,
+    pub capture_path: String,
+    pub capture_scale: String,
+    /// Average share of the frame that changed over the last second, in percent.
+    pub changed_area_percent: f32,
+    /// Frames left unencoded because nothing changed.
+    pub unchanged_frames: u64,
+}
+
+/// A capture backend bound to one capture target.
+pub trait FrameSource: Send {
+    /// Opens `target` (display ids are backend specific). `width`x`height` is the
+    /// encode size; desktop sources capture the target at native size and leave
+    /// scaling to `CaptureHandle`.
+    fn open(target: &CaptureTarget, width: i32, height: i32) -> Result<Self, String>
+    where
+        Self: Sized;
+    /// Returns the next frame, waiting up to `timeout` for the display to change.
+    fn acquire(&mut self, timeout: Duration) -> Result<CapturedFrame, String>;
+    fn stats(&self) -> CaptureStats;
+    /// Regions that changed in the most recently acquired frame.
+    fn damage(&self) -> &[DamageRect];
+    /// Whether `damage` tracks real changes. Backends that always report the whole
+    /// frame return `false` and their frames are compared tile by tile instead.
+    fn precise_damage(&self) -> bool {
+        true
+    }
+    /// Where the most recently acquired frame sits in desktop (virtual screen)
+    /// coordinates, for mapping input back onto the target.
+    fn bounds(&self) -> Region;
+}
+
+/// Opens the backend for `target`: the test pattern when asked for, otherwise the
+/// platform's desktop capture.
+pub fn open_source(
+    target: &CaptureTarget,
+    width: i32,
+    height: i32,
+) -> Result<Box<dyn FrameSource>, String> {
+    if target.is_test_pattern() {
+        return Ok(Box::new(TestPatternSource::open(target, width, height)?));
+    }
+    open_desktop_source(target, width, height)
+}
+
+#[cfg(windows)]
+fn open_desktop_source(
+    target: &CaptureTarget,
+    width: i32,
+    height: i32,
+) -> Result<Box<dyn FrameSource>, String> {
+    Ok(Box::new(DesktopSource::open(target, width, height)?))
+}
+
+#[cfg(target_os = "linux")]
+fn open_desktop_source(
+    target: &CaptureTarget,
+    width: i32,
+    height: i32,
+) -> Result<Box<dyn FrameSource>, String> {
+    Ok(Box::new(X11Source::open(target, width, height)?))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn open_desktop_source(
+    _target: &CaptureTarget,
+    _width: i32,
+    _height: i32,
+) -> Result<Box<dyn FrameSource>, String> {
+    Err("Capture not supported on this platform".to_string())
+}
+
+/// DXGI duplication with a per-frame GDI fallback, matching what the encoders
+/// have always done on Windows.
+#[cfg(windows)]
+struct DesktopSource {
+    target: CaptureTarget,
+    width: i32,
+    height: i32,
+    dxgi: Option<DxgiSource>,
+    gdi: GdiSource,
+    using_gdi: bool,
+}
+
+#[cfg(windows)]
+impl FrameSource for DesktopSource {
+    fn open(target: &CaptureTarget, width: i32, height: i32) -> Result<Self, String> {
+        Ok(Self {
+            target: target.clone(),
+            width,
+            height,
+            dxgi: DxgiSource::open(target, width, height).ok(),
+            gdi: GdiSource::open(target, width, height)?,
+            using_gdi: false,
+        })
+    }
+
+    fn acquire(&mut self, timeout: Duration) -> Result<CapturedFrame, String> {
+        if self.dxgi.is_none() {
+            self.dxgi = DxgiSource::open(&self.target, self.width, self.height).ok();
+        }
+        if let Some(dxgi) = self.dxgi.as_mut() {
+            if let Ok(frame) = dxgi.acquire(timeout) {
+                self.using_gdi = false;
+                return Ok(frame);
+            }
+        }
+        self.using_gdi = true;
+        self.gdi.acquire(timeout)
+    }
+
+    fn stats(&self) -> CaptureStats {
+        let gdi = self.gdi.stats();
+        let Some(dxgi) = self.dxgi.as_ref().map(|source| source.stats()) else {
+            return gdi;
+        };
+        let active = if self.using_gdi { &gdi } else { &dxgi };
+        CaptureStats {
+            frames: dxgi.frames.saturating_add(gdi.frames),
+            timeouts: dxgi.timeouts,
+            access_lost: dxgi.access_lost,
+            failures: dxgi.failures.saturating_add(gdi.failures),
+            last_frame_bytes: active.last_frame_bytes,
+            capture_path: active.capture_path.clone(),
+            capture_scale: active.capture_scale.clone(),
+            // `CaptureHandle` fills in the damage counters.
+            ..CaptureStats::default()
+        }
+    }
+
+    fn damage(&self) -> &[DamageRect] {
+        match self.dxgi.as_ref() {
+            Some(dxgi) if !self.using_gdi => dxgi.damage(),
+            _ => self.gdi.damage(),
+        }
+    }
+
+    fn precise_damage(&self) -> bool {
+        !self.using_gdi && self.dxgi.is_some()
+    }
+
+    fn bounds(&self) -> Region {
+        match self.dxgi.as_ref() {
+            Some(dxgi) if !self.using_gdi => dxgi.bounds(),
+            _ => self.gdi.bounds(),
+        }
+    }
+}
+
+/// Lazily opened source for an encoder: scales frames to the encode size, converts
+/// them to the encoder's YUV layout, tracks which frames changed and publishes the
+/// source stats and input mapping for the session.
+pub struct CaptureHandle {
+    target: CaptureTarget,
+    width: i32,
+    height: i32,
+    color: ColorSpace,
+    scale: ScaleMode,
+    scaler: Option<Scaler>,
+    source: Option<Box<dyn FrameSource>>,
+    tracker: DamageTracker,
+    unchanged: bool,
+}
+
+impl CaptureHandle {
+    pub fn new(
+        target: CaptureTarget,
+        width: i32,
+        height: i32,
+        color: ColorSpace,
+        scale: ScaleMode,
+    ) -> Self {
+        Self {
+            target,
+            width: width.max(2) & !1,
+            height: height.max(2) & !1,
+            color,
+            scale,
+            scaler: None,
+            source: None,
+            tracker: DamageTracker::default(),
+            unchanged: false,
+        }
+    }
+
+    /// The last acquired frame matches what was last encoded and no keepalive is
+    /// due, so encoders can skip it.
+    pub fn unchanged(&self) -> bool {
+        self.unchanged
+    }
+
+    pub fn acquire(&mut self, format: PixelFormat) -> Result<Vec<u8>, String> {
+        let bgra = self.acquire_bgra()?;
+        Ok(color::convert(
+            &bgra,
+            self.width as usize,
+            self.height as usize,
+            self.color,
+            format,
+        ))
+    }
+
+    /// Next frame as BGRA at the encode size.
+    pub fn acquire_bgra(&mut self) -> Result<Vec<u8>, String> {
+        self.unchanged = false;
+        if self.source.is_none() {
+            self.source = Some(open_source(&self.target, self.width, self.height)?);
+        }
+        let source = self
+            .source
+            .as_mut()
+            .ok_or_else(|| "Capture source not initialized".to_string())?;
+        let result = source.acquire(ACQUIRE_TIMEOUT);
+        let mut stats = source.stats();
+        let frame = match result {
+            Ok(frame) => frame,
+            Err(err) => {
+                publish_stats(stats);
+                return Err(err);
+            }
+        };
+        let bounds = source.bounds();
+        let precise_damage = source.precise_damage();
+        let bgra = if frame.width == self.width && frame.height == self.height {
+            self.scaler = None;
+            stats.capture_scale = "1:1".to_string();
+            publish_input_mapping(InputMapping::direct(bounds));
+            frame.bgra
+        } else {
+            let scaler = match self.scaler.take() {
+                Some(scaler) if scaler.source_size() == (frame.width, frame.height) => scaler,
+                _ => Scaler::new(
+                    frame.width,
+                    frame.height,
+                    self.width,
+                    self.height,
+                    self.scale,
+                ),
+            };
+            let bgra = scaler.scale(&frame.bgra);
+            stats.capture_scale = scaler.describe();
+            publish_input_mapping(InputMapping {
+                stream_width: self.width,
+                stream_height: self.height,
+                layout: scaler.layout(),
+                desktop: bounds,
+            });
+            self.scaler = Some(scaler);
+            bgra
+        };
+        let damage = if precise_damage {
+            self.damage()
+        } else {
+            self.tracker.hash_diff(&bgra, self.width, self.height)
+        };
+        self.unchanged = !self
+            .tracker
+            .observe(&damage, self.width, self.height, Instant::now());
+        self.tracker.annotate(&mut stats);
+        publish_stats(stats);
+        Ok(bgra)
+    }
+
+    /// Damage of the last acquired frame in encode-size pixels, widened by the
+    /// scaler's filter reach.
+    pub fn damage(&self) -> Vec<DamageRect> {
+        let Some(source) = self.source.as_ref() else {
+            return Vec::new();
+        };
+        let Some(scaler) = self.scaler.as_ref() else {
+            return source.damage().to_vec();
+        };
+        let layout = scaler.layout();
+        let (from, to) = (layout.source, layout.target);
+        let (reach_x, reach_y) = scaler.reach();
+        source
+            .damage()
+            .iter()
+            .filter_map(|rect| {
+                let x0 = (rect.x - reach_x).max(from.x);
+                let y0 = (rect.y - reach_y).max(from.y);
+                let x1 = (rect.x + rect.width + reach_x).min(from.x + from.width);
+                let y1 = (rect.y + rect.height + reach_y).min(from.y + from.height);
+                if x0 >= x1 || y0 >= y1 {
+                    return None;
+                }
+                let mapped = DamageRect {
+                    x: x0 - from.x,
+                    y: y0 - from.y,
+                    width: x1 - x0,
+                    height: y1 - y0,
+                }
+                .scale(from.width, from.height, to.width, to.height);
+                Some(DamageRect {
+                    x: mapped.x + to.x,
+                    y: mapped.y + to.y,
+                    ..mapped
+                })
+            })
+            .collect()
+    }
+}
+
+static CAPTURE_STATS: OnceLock<Mutex<Option<CaptureStats>>> = OnceLock::new();
+
+fn capture_stats_store() -> &'static Mutex<Option<CaptureStats>> {
+    CAPTURE_STATS.get_or_init(|| Mutex::new(None))
+}
+
+pub fn publish_stats(stats: CaptureStats) {
+    if let Ok(mut guard) = capture_stats_store().lock() {
+        *guard = Some(stats);
+    }
+}
+
+/// Stats of the source most recently used by an encoder.
+pub fn stats_snapshot() -> Option<CaptureStats> {
+    capture_stats_store().lock().ok()?.clone()
+}
+
+static INPUT_MAPPING: OnceLock<Mutex<Option<InputMapping>>> = OnceLock::new();
+
+fn input_mapping_store() -> &'static Mutex<Option<InputMapping>> {
+    INPUT_MAPPING.get_or_init(|| Mutex::new(None))
+}
+
+pub fn publish_input_mapping(mapping: InputMapping) {
+    if let Ok(mut guard) = input_mapping_store().lock() {
+        *guard = Some(mapping);
+    }
+}
+
+/// Maps a normalized `Touch`/`Pen` point onto the desktop through the current
+/// capture target. `None` before the first frame or outside the captured picture.
+pub fn map_input(x: i16, y: i16) -> Option<(i32, i32)> {
+    input_mapping_store()
+        .lock()
+        .ok()?
+        .as_ref()?
+        .map_normalized(x, y)
+}
+
+/// Windows that can be selected as a `CaptureTarget::Window`.
+#[cfg(windows)]
+pub fn list_windows() -> Vec<CaptureWindow> {
+    window::list_windows()
+}
+
+#[cfg(target_os = "linux")]
+pub fn list_windows() -> Vec<CaptureWindow> {
+    std::env::var("DISPLAY")
+        .ok()
+        .map(|display| x11::list_windows(&display))
+        .unwrap_or_default()
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn list_windows() -> Vec<CaptureWindow> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scaler::{ScaleFilter, ScaleFit};
+
+    #[test]
+    fn scales_damage_outwards() {
+        let rect = DamageRect {
+            x: 1,
+            y: 1,
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(
+            rect.scale(4, 4, 2, 2),
+            DamageRect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1
+            }
+        );
+        assert_eq!(
+            DamageRect::full(4, 4).scale(4, 4, 8, 6),
+            DamageRect::full(8, 6)
+        );
+    }
+
+    #[test]
+    fn converts_test_pattern_through_the_handle() {
+        let mut handle = CaptureHandle::new(
+            CaptureTarget::display(Some(TEST_PATTERN_TARGET.to_string())),
+            64,
+            48,
+            ColorSpace::default(),
+            ScaleMode::default(),
+        );
+        let nv12 = handle.acquire(PixelFormat::Nv12).unwrap();
+        assert_eq!(nv12.len(), 64 * 48 * 3 / 2);
+        // The first colour bar is studio white, the last one studio black.
+        let row = 20 * 64;
+        assert_eq!(nv12[row + 2], 218);
+        assert_eq!(nv12[row + 62], 30);
+
+        let stats = stats_snapshot().unwrap();
+        assert_eq!(stats.capture_path, "Test pattern");
+        assert!(stats.frames >= 1);
+    }
+
+    #[test]
+    fn scales_native_size_frames_to_the_encode_size() {
+        let mut handle = CaptureHandle::new(
+            CaptureTarget::default(),
+            64,
+            48,
+            ColorSpace::default(),
+            ScaleMode::default(),
+        );
+        handle.source = Some(Box::new(
+            TestPatternSource::open(&CaptureTarget::default(), 128, 96).unwrap(),
+        ));
+        let bgra = handle.acquire_bgra().unwrap();
+        assert_eq!(bgra.len(), 64 * 48 * 4);
+        // The first bar is white at both sizes.
+        assert_eq!(
+            &bgra[(20 * 64 + 2) * 4..(20 * 64 + 3) * 4],
+            &[235, 235, 235, 0xff]
+        );
+        assert_eq!(handle.scaler.as_ref().unwrap().source_size(), (128, 96));
+        assert_eq!(handle.damage(), vec![DamageRect::full(64, 48)]);
+    }
+
+    #[test]
+    fn maps_damage_into_the_letterboxed_target() {
+        let mode = ScaleMode {
+            filter: ScaleFilter::Bilinear,
+            fit: ScaleFit::Letterbox,
+        };
+        let mut handle = CaptureHandle::new(
+            CaptureTarget::default(),
+            64,
+            64,
+            ColorSpace::default(),
+            mode,
+        );
+        handle.source = Some(Box::new(
+            TestPatternSource::open(&CaptureTarget::default(), 128, 64).unwrap(),
+        ));
+        handle.acquire_bgra().unwrap();
+        handle.acquire_bgra().unwrap();
+        // The band in the bottom quarter of the 128x64 source lands at rows 40..48
+        // of the 64x32 picture centred vertically, plus a row of filter reach.
+        let band = handle
+            .damage()
+            .into_iter()
+            .find(|rect| rect.width == 64)
+            .unwrap();
+        assert_eq!(band.y, 40 - 1);
+        assert_eq!(band.y + band.height, 48);
+    }
+
+    /// A frozen desktop whose backend has no change tracking, like GDI.
+    struct StaticSource;
+
+    impl FrameSource for StaticSource {
+        fn open(_target: &CaptureTarget, _width: i32, _height: i32) -> Result<Self, String> {
+            Ok(Self)
+        }
+
+        fn acquire(&mut self, _timeout: Duration) -> Result<CapturedFrame, String> {
+            Ok(CapturedFrame {
+                bgra: vec![0x40; 32 * 32 * 4],
+                width: 32,
+                height: 32,
+            })
+        }
+
+        fn stats(&self) -> CaptureStats {
+            CaptureStats::default()
+        }
+
+        fn damage(&self) -> &[DamageRect] {
+            &[]
+        }
+
+        fn precise_damage(&self) -> bool {
+            false
+        }
+
+        fn bounds(&self) -> Region {
+            Region::default()
+        }
+    }
+
+    #[test]
+    fn flags_static_frames_from_backends_without_damage() {
+        let mut handle = CaptureHandle::new(
+            CaptureTarget::default(),
+            32,
+            32,
+            ColorSpace::default(),
+            ScaleMode::default(),
+        );
+        handle.source = Some(Box::new(StaticSource));
+        handle.acquire_bgra().unwrap();
+        assert!(!handle.unchanged(), "the first frame is always encoded");
+        handle.acquire_bgra().unwrap();
+        assert!(handle.unchanged());
+    }
+}
@@ -0,0 +1,318 @@
+use serde::{Deserialize, Serialize};
+
+use super::{DamageRect, TEST_PATTERN_TARGET};
+use crate::scaler::{Region, ScaleLayout};
+
+/// What a session captures. Backends crop their frames to the target, and input is
+/// mapped back through the same target so touches land where they were aimed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum CaptureTarget {
+    /// A whole display; `None` is the primary display.
+    Display { display_id: Option<String> },
+    /// A fixed rectangle in the display's own pixels.
+    Region {
+        display_id: Option<String>,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    /// One application window, followed as it moves. Found by handle (HWND or X11
+    /// window id) first and by case-insensitive title substring once the handle is gone.
+    Window {
+        handle: Option<u64>,
+        title: Option<String>,
+    },
+}
+
+impl Default for CaptureTarget {
+    fn default() -> Self {
+        CaptureTarget::Display { display_id: None }
+    }
+}
+
+impl CaptureTarget {
+    pub fn display(display_id: Option<String>) -> Self {
+        CaptureTarget::Display { display_id }
+    }
+
+    /// Display the target lives on; windows are resolved per frame instead.
+    pub fn display_id(&self) -> Option<&str> {
+        match self {
+            CaptureTarget::Display { display_id } | CaptureTarget::Region { display_id, .. } => {
+                display_id.as_deref()
+            }
+            CaptureTarget::Window { .. } => None,
+        }
+    }
+
+    pub fn is_test_pattern(&self) -> bool {
+        self.display_id() == Some(TEST_PATTERN_TARGET)
+    }
+
+    /// Whether frames are the display as-is, so zero-copy paths can be used.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn is_whole_display(&self) -> bool {
+        matches!(self, CaptureTarget::Display { .. })
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            CaptureTarget::Display { display_id } => {
+                display_id.clone().unwrap_or_else(|| "Auto".to_string())
+            }
+            CaptureTarget::Region {
+                display_id,
+                x,
+                y,
+                width,
+                height,
+            } => format!(
+                "{} {width}x{height}+{x}+{y}",
+                display_id.as_deref().unwrap_or("Auto")
+            ),
+            CaptureTarget::Window { handle, title } => match (handle, title) {
+                (Some(handle), Some(title)) => format!("Window {handle:#x} \"{title}\""),
+                (Some(handle), None) => format!("Window {handle:#x}"),
+                (None, Some(title)) => format!("Window \"{title}\""),
+                (None, None) => "Window".to_string(),
+            },
+        }
+    }
+}
+
+/// A window that can be picked as a capture target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureWindow {
+    pub handle: u64,
+    pub title: String,
+}
+
+/// Whether a window title matches the title a target was saved with.
+pub fn title_matches(title: &str, wanted: &str) -> bool {
+    !wanted.is_empty() && title.to_lowercase().contains(&wanted.to_lowercase())
+}
+
+/// Clips `rect` to a `width`x`height` frame; `None` when nothing is left.
+pub fn clip(rect: Region, width: i32, height: i32) -> Option<Region> {
+    let x0 = rect.x.max(0);
+    let y0 = rect.y.max(0);
+    let x1 = (rect.x + rect.width).min(width);
+    let y1 = (rect.y + rect.height).min(height);
+    if x0 >= x1 || y0 >= y1 {
+        return None;
+    }
+    Some(Region {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+    })
+}
+
+/// Copies `rect` (already clipped) out of a `width`-wide BGRA frame.
+pub fn crop_bgra(bgra: &[u8], width: i32, rect: Region) -> Vec<u8> {
+    let stride = width as usize * 4;
+    let row_bytes = rect.width as usize * 4;
+    let mut output = Vec::with_capacity(row_bytes * rect.height as usize);
+    for row in rect.y as usize..(rect.y + rect.height) as usize {
+        let start = row * stride + rect.x as usize * 4;
+        output.extend_from_slice(&bgra[start..start + row_bytes]);
+    }
+    output
+}
+
+/// Moves damage reported for a whole frame into the coordinates of `crop`,
+/// dropping what falls outside it.
+pub fn crop_damage(damage: &[DamageRect], crop: Region) -> Vec<DamageRect> {
+    damage
+        .iter()
+        .filter_map(|rect| {
+            let moved = Region {
+                x: rect.x - crop.x,
+                y: rect.y - crop.y,
+                width: rect.width,
+                height: rect.height,
+            };
+            clip(moved, crop.width, crop.height).map(|inside| DamageRect {
+                x: inside.x,
+                y: inside.y,
+                width: inside.width,
+                height: inside.height,
+            })
+        })
+        .collect()
+}
+
+/// Full scale of the normalized `i16` coordinates in `Touch` and `Pen` packets.
+pub const INPUT_SCALE: i32 = 32767;
+
+/// How stream pixels relate to desktop pixels for the most recent frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputMapping {
+    pub stream_width: i32,
+    pub stream_height: i32,
+    /// Which part of the captured frame is shown where in the stream.
+    pub layout: ScaleLayout,
+    /// The captured frame in desktop (virtual screen) coordinates.
+    pub desktop: Region,
+}
+
+impl InputMapping {
+    /// Mapping for a frame streamed 1:1.
+    pub fn direct(desktop: Region) -> Self {
+        let frame = Region {
+            x: 0,
+            y: 0,
+            width: desktop.width,
+            height: desktop.height,
+        };
+        Self {
+            stream_width: desktop.width,
+            stream_height: desktop.height,
+            layout: ScaleLayout {
+                source: frame,
+                target: frame,
+            },
+            desktop,
+        }
+    }
+
+    /// Maps a normalized client point to desktop pixels. Points on letterbox bars
+    /// map to `None` so they are not injected into whatever sits next to the target.
+    pub fn map_normalized(&self, x: i16, y: i16) -> Option<(i32, i32)> {
+        let stream_x = x.max(0) as i64 * self.stream_width as i64 / INPUT_SCALE as i64;
+        let stream_y = y.max(0) as i64 * self.stream_height as i64 / INPUT_SCALE as i64;
+        let (source, target) = (self.layout.source, self.layout.target);
+        let dx = stream_x - target.x as i64;
+        let dy = stream_y - target.y as i64;
+        if dx < 0 || dy < 0 || dx > target.width as i64 || dy > target.height as i64 {
+            return None;
+        }
+        let frame_x = source.x as i64 + dx * source.width as i64 / target.width.max(1) as i64;
+        let frame_y = source.y as i64 + dy * source.height as i64 / target.height.max(1) as i64;
+        let frame_x = frame_x.min((source.x + source.width - 1) as i64) as i32;
+        let frame_y = frame_y.min((source.y + source.height - 1) as i64) as i32;
+        Some((self.desktop.x + frame_x, self.desktop.y + frame_y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scaler::{layout, ScaleFit};
+
+    #[test]
+    fn serializes_as_tagged_enum() {
+        let target = CaptureTarget::Region {
+            display_id: Some(r"\\.\DISPLAY2".to_string()),
+            x: 10,
+            y: 20,
+            width: 640,
+            height: 480,
+        };
+        let json = serde_json::to_value(&target).unwrap();
+        assert_eq!(json["kind"], "region");
+        assert_eq!(json["displayId"], r"\\.\DISPLAY2");
+        assert_eq!(
+            serde_json::from_value::<CaptureTarget>(json).unwrap(),
+            target
+        );
+        let window: CaptureTarget =
+            serde_json::from_str(r#"{"kind":"window","title":"Notepad"}"#).unwrap();
+        assert_eq!(
+            window,
+            CaptureTarget::Window {
+                handle: None,
+                title: Some("Notepad".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn clips_and_crops_regions() {
+        let rect = Region {
+            x: -2,
+            y: 1,
+            width: 4,
+            height: 10,
+        };
+        let clipped = clip(rect, 4, 3).unwrap();
+        assert_eq!(
+            clipped,
+            Region {
+                x: 0,
+                y: 1,
+                width: 2,
+                height: 2
+            }
+        );
+        assert_eq!(clip(Region { x: 5, ..rect }, 4, 3), None);
+
+        let damage = [DamageRect {
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 2,
+        }];
+        assert_eq!(
+            crop_damage(&damage, clipped),
+            vec![DamageRect {
+                x: 1,
+                y: 0,
+                width: 1,
+                height: 1
+            }]
+        );
+
+        let frame: Vec<u8> = (0..4 * 3).flat_map(|i| [i as u8; 4]).collect();
+        assert_eq!(
+            crop_bgra(&frame, 4, clipped),
+            [[4u8; 4], [5; 4], [8; 4], [9; 4]].concat()
+        );
+    }
+
+    #[test]
+    fn maps_input_through_letterbox_to_desktop() {
+        // A 400x300 window at (1000, 200) shown letterboxed in a 800x400 stream.
+        let mapping = InputMapping {
+            stream_width: 800,
+            stream_height: 400,
+            layout: layout(400, 300, 800, 400, ScaleFit::Letterbox),
+            desktop: Region {
+                x: 1000,
+                y: 200,
+                width: 400,
+                height: 300,
+            },
+        };
+        assert_eq!(mapping.layout.target.x, 133);
+        // Centre of the stream is the centre of the window.
+        assert_eq!(mapping.map_normalized(16384, 16384), Some((1200, 350)));
+        // Bottom-right corner stays inside the window.
+        assert_eq!(mapping.map_normalized(27278, 32767), Some((1399, 499)));
+        // The left bar is outside the picture.
+        assert_eq!(mapping.map_normalized(1000, 16384), None);
+
+        let direct = InputMapping::direct(Region {
+            x: -1920,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        });
+        assert_eq!(direct.map_normalized(0, 0), Some((-1920, 0)));
+    }
+
+    #[test]
+    fn matches_titles_case_insensitively() {
+        assert!(title_matches("Untitled - Notepad", "notepad"));
+        assert!(!title_matches("Untitled - Notepad", ""));
+        assert!(!title_matches("Terminal", "notepad"));
+    }
+}
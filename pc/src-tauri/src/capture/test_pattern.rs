@@ -0,0 +1,272 @@
+use std::time::Duration;
+
+use super::{CaptureStats, CaptureTarget, CapturedFrame, DamageRect, FrameSource};
+use crate::scaler::Region;
+
+/// Nominal rate used for the burned-in timestamp; frames are produced on demand.
+pub const TEST_PATTERN_FPS: u64 = 60;
+
+const COLOR_BARS: [[u8; 3]; 8] = [
+    [235, 235, 235],
+    [235, 235, 16],
+    [16, 235, 235],
+    [16, 235, 16],
+    [235, 16, 235],
+    [235, 16, 16],
+    [16, 16, 235],
+    [16, 16, 16],
+];
+
+/// 3x5 glyphs for `0-9`, `:` and `.`, one row per nibble (MSB is the left column).
+const GLYPHS: [[u8; 5]; 12] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b000, 0b010, 0b000, 0b010, 0b000],
+    [0b000, 0b000, 0b000, 0b000, 0b010],
+];
+
+/// Deterministic synthetic source: static colour bars, a band of bars sweeping
+/// across the bottom quarter and a burned-in `mm:ss.mmm frame` timestamp.
+/// Lets the capture-to-encode pipeline run without a display.
+pub struct TestPatternSource {
+    width: i32,
+    height: i32,
+    frame_index: u64,
+    damage: Vec<DamageRect>,
+    stats: CaptureStats,
+}
+
+impl FrameSource for TestPatternSource {
+    /// The target is ignored; every test-pattern source renders the same sequence.
+    fn open(_target: &CaptureTarget, width: i32, height: i32) -> Result<Self, String> {
+        if width <= 0 || height <= 0 {
+            return Err(format!("Invalid test pattern size {width}x{height}"));
+        }
+        Ok(Self {
+            width: width.max(2) & !1,
+            height: height.max(2) & !1,
+            frame_index: 0,
+            damage: Vec::new(),
+            stats: CaptureStats {
+                capture_path: "Test pattern".to_string(),
+                capture_scale: "1:1".to_string(),
+                ..CaptureStats::default()
+            },
+        })
+    }
+
+    /// Frames are generated immediately, so the timeout is not used.
+    fn acquire(&mut self, _timeout: Duration) -> Result<CapturedFrame, String> {
+        let bgra = render(self.width, self.height, self.frame_index);
+        self.damage = if self.frame_index == 0 {
+            vec![DamageRect::full(self.width, self.height)]
+        } else {
+            changed_regions(self.width, self.height, self.frame_index)
+        };
+        self.frame_index = self.frame_index.wrapping_add(1);
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        self.stats.last_frame_bytes = bgra.len() as u32;
+        Ok(CapturedFrame {
+            bgra,
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    fn stats(&self) -> CaptureStats {
+        self.stats.clone()
+    }
+
+    fn damage(&self) -> &[DamageRect] {
+        &self.damage
+    }
+
+    fn bounds(&self) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Renders frame `frame_index` of the pattern as BGRA.
+pub fn render(width: i32, height: i32, frame_index: u64) -> Vec<u8> {
+    let w = width.max(0) as usize;
+    let h = height.max(0) as usize;
+    let mut bgra = vec![0u8; w * h * 4];
+    let band_top = band_top(height) as usize;
+
+    for y in 0..band_top {
+        for x in 0..w {
+            let [r, g, b] = COLOR_BARS[x * COLOR_BARS.len() / w.max(1)];
+            put_pixel(&mut bgra, w, x, y, [r, g, b]);
+        }
+    }
+
+    let period = sweep_period(width) as usize;
+    let offset = (frame_index.wrapping_mul(sweep_step(width) as u64) % period as u64) as usize;
+    for y in band_top..h {
+        for x in 0..w {
+            let phase = (x + period - offset) % period;
+            let shade = if phase < period / 4 { 235 } else { 64 };
+            put_pixel(&mut bgra, w, x, y, [shade, shade, shade]);
+        }
+    }
+
+    draw_timestamp(&mut bgra, w, h, frame_index);
+    bgra
+}
+
+fn changed_regions(width: i32, height: i32, frame_index: u64) -> Vec<DamageRect> {
+    let top = band_top(height);
+    let mut timestamp = timestamp_rect(width, height, frame_index);
+    timestamp.width = timestamp
+        .width
+        .max(timestamp_rect(width, height, frame_index.wrapping_sub(1)).width);
+    vec![
+        timestamp,
+        DamageRect {
+            x: 0,
+            y: top,
+            width,
+            height: height - top,
+        },
+    ]
+}
+
+fn band_top(height: i32) -> i32 {
+    height - height / 4
+}
+
+fn sweep_period(width: i32) -> i32 {
+    (width / 4).max(4)
+}
+
+fn sweep_step(width: i32) -> i32 {
+    (width / 120).max(1)
+}
+
+fn glyph_scale(height: i32) -> usize {
+    (height / 120).max(1) as usize
+}
+
+fn timestamp_text(frame_index: u64) -> String {
+    let millis = frame_index.saturating_mul(1000) / TEST_PATTERN_FPS;
+    format!(
+        "{:02}:{:02}.{:03} {}",
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
+        frame_index
+    )
+}
+
+fn timestamp_rect(width: i32, height: i32, frame_index: u64) -> DamageRect {
+    let scale = glyph_scale(height) as i32;
+    let chars = timestamp_text(frame_index).chars().count() as i32;
+    DamageRect {
+        x: 0,
+        y: 0,
+        width: ((chars * 4 + 3) * scale).min(width),
+        height: (7 * scale).min(height),
+    }
+}
+
+fn draw_timestamp(bgra: &mut [u8], w: usize, h: usize, frame_index: u64) {
+    let scale = glyph_scale(h as i32);
+    let rect = timestamp_rect(w as i32, h as i32, frame_index);
+    for y in 0..rect.height as usize {
+        for x in 0..rect.width as usize {
+            put_pixel(bgra, w, x, y, [0, 0, 0]);
+        }
+    }
+    for (index, ch) in timestamp_text(frame_index).chars().enumerate() {
+        let glyph = match ch {
+            '0'..='9' => GLYPHS[ch as usize - '0' as usize],
+            ':' => GLYPHS[10],
+            '.' => GLYPHS[11],
+            _ => continue,
+        };
+        let left = (index * 4 + 2) * scale;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = left + col * scale + dx;
+                        let y = (row + 1) * scale + dy;
+                        if x < rect.width as usize && y < h {
+                            put_pixel(bgra, w, x, y, [255, 255, 255]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn put_pixel(bgra: &mut [u8], w: usize, x: usize, y: usize, [r, g, b]: [u8; 3]) {
+    let idx = (y * w + x) * 4;
+    bgra[idx..idx + 4].copy_from_slice(&[b, g, r, 0xff]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_deterministically() {
+        assert_eq!(render(64, 48, 7), render(64, 48, 7));
+        assert_ne!(render(64, 48, 7), render(64, 48, 8));
+    }
+
+    #[test]
+    fn only_the_band_and_timestamp_change() {
+        let mut source = TestPatternSource::open(&CaptureTarget::default(), 320, 240).unwrap();
+        let first = source.acquire(Duration::ZERO).unwrap();
+        assert_eq!(source.damage(), &[DamageRect::full(320, 240)]);
+        let second = source.acquire(Duration::ZERO).unwrap();
+
+        let changed: Vec<(usize, usize)> = first
+            .bgra
+            .chunks_exact(4)
+            .zip(second.bgra.chunks_exact(4))
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(index, _)| (index % 320, index / 320))
+            .collect();
+        assert!(!changed.is_empty());
+        let damage = source.damage();
+        assert!(changed.iter().all(|&(x, y)| damage.iter().any(|rect| {
+            (x as i32) >= rect.x
+                && (x as i32) < rect.x + rect.width
+                && (y as i32) >= rect.y
+                && (y as i32) < rect.y + rect.height
+        })));
+        assert_eq!(source.stats().frames, 2);
+        assert_eq!(source.stats().capture_path, "Test pattern");
+    }
+
+    #[test]
+    fn burns_in_timestamp() {
+        assert_eq!(timestamp_text(0), "00:00.000 0");
+        assert_eq!(timestamp_text(90), "00:01.500 90");
+        let frame = render(320, 240, 90);
+        // Top-left padding stays black; the first glyph ('0') lights its top row.
+        assert_eq!(&frame[..4], &[0, 0, 0, 0xff]);
+        let idx = (2 * 320 + 4) * 4;
+        assert_eq!(&frame[idx..idx + 4], &[255, 255, 255, 0xff]);
+    }
+}
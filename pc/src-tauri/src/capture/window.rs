@@ -0,0 +1,124 @@
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, RECT};
+use windows::Win32::Graphics::Gdi::{
+    GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITORINFOEXW, MONITOR_DEFAULTTONEAREST,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetWindowRect, GetWindowTextW, IsIconic, IsWindow, IsWindowVisible,
+};
+
+use super::target::{title_matches, CaptureWindow};
+use crate::scaler::Region;
+
+/// Where a tracked window currently is.
+pub struct WindowPlacement {
+    /// GDI device name of the monitor holding most of the window, e.g. `\\.\DISPLAY1`.
+    pub display_id: String,
+    /// That monitor in virtual-screen coordinates.
+    pub monitor: Region,
+    /// The window in virtual-screen coordinates.
+    pub rect: Region,
+}
+
+/// Follows one window by handle, falling back to a title match when the window is
+/// recreated (for example after an application restart).
+pub struct WindowTracker {
+    title: Option<String>,
+    current: Option<HWND>,
+}
+
+impl WindowTracker {
+    pub fn new(handle: Option<u64>, title: Option<String>) -> Self {
+        Self {
+            title,
+            current: handle.map(|value| HWND(value as isize)),
+        }
+    }
+
+    pub fn resolve(&mut self) -> Result<WindowPlacement, String> {
+        let alive = self
+            .current
+            .map(|hwnd| unsafe { IsWindow(hwnd) }.as_bool())
+            .unwrap_or(false);
+        if !alive {
+            self.current = self.title.as_deref().and_then(|title| {
+                list_windows()
+                    .into_iter()
+                    .find(|window| title_matches(&window.title, title))
+                    .map(|window| HWND(window.handle as isize))
+            });
+        }
+        let hwnd = self
+            .current
+            .ok_or_else(|| "Capture window not found".to_string())?;
+        placement(hwnd)
+    }
+}
+
+fn placement(hwnd: HWND) -> Result<WindowPlacement, String> {
+    if unsafe { IsIconic(hwnd) }.as_bool() {
+        return Err("Capture window is minimized".to_string());
+    }
+    let mut rect = RECT::default();
+    unsafe { GetWindowRect(hwnd, &mut rect) }
+        .map_err(|err| format!("GetWindowRect failed: 0x{:08x}", err.code().0))?;
+
+    let monitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) };
+    let mut info = MONITORINFOEXW::default();
+    info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+    let ok = unsafe {
+        GetMonitorInfoW(
+            monitor,
+            &mut info as *mut MONITORINFOEXW as *mut MONITORINFO,
+        )
+    };
+    if !ok.as_bool() {
+        return Err("GetMonitorInfoW failed".to_string());
+    }
+    let len = info
+        .szDevice
+        .iter()
+        .position(|&ch| ch == 0)
+        .unwrap_or(info.szDevice.len());
+    Ok(WindowPlacement {
+        display_id: String::from_utf16_lossy(&info.szDevice[..len]),
+        monitor: to_region(info.monitorInfo.rcMonitor),
+        rect: to_region(rect),
+    })
+}
+
+fn to_region(rect: RECT) -> Region {
+    Region {
+        x: rect.left,
+        y: rect.top,
+        width: rect.right - rect.left,
+        height: rect.bottom - rect.top,
+    }
+}
+
+/// Visible top-level windows with a title, in z-order.
+pub fn list_windows() -> Vec<CaptureWindow> {
+    let mut windows: Vec<CaptureWindow> = Vec::new();
+    unsafe {
+        let _ = EnumWindows(
+            Some(collect_window),
+            LPARAM(&mut windows as *mut Vec<CaptureWindow> as isize),
+        );
+    }
+    windows
+}
+
+unsafe extern "system" fn collect_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = &mut *(lparam.0 as *mut Vec<CaptureWindow>);
+    if IsWindowVisible(hwnd).as_bool() {
+        let mut buffer = [0u16; 512];
+        let len = GetWindowTextW(hwnd, &mut buffer).max(0) as usize;
+        let title = String::from_utf16_lossy(&buffer[..len]);
+        if !title.trim().is_empty() {
+            windows.push(CaptureWindow {
+                handle: hwnd.0 as u64,
+                title,
+            });
+        }
+    }
+    BOOL(1)
+}
@@ -0,0 +1,579 @@
+use std::time::Duration;
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::damage::{self, ConnectionExt as DamageExt, ReportLevel};
+use x11rb::protocol::shm::{self, ConnectionExt as ShmExt};
+use x11rb::protocol::xfixes::{self, ConnectionExt as XfixesExt};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, ImageFormat, MapState, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::target::{clip, crop_bgra, crop_damage, title_matches};
+use super::{CaptureStats, CaptureTarget, CaptureWindow, CapturedFrame, DamageRect, FrameSource};
+use crate::scaler::Region;
+
+struct ShmSegment {
+    seg: shm::Seg,
+    shmid: i32,
+    addr: *mut u8,
+    len: usize,
+}
+
+// The segment is only touched through the owning source, which is used from one thread at a time.
+unsafe impl Send for ShmSegment {}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        unsafe {
+            libc::shmdt(self.addr as *const libc::c_void);
+            libc::shmctl(self.shmid, libc::IPC_RMID, std::ptr::null_mut());
+        }
+    }
+}
+
+struct X11Connection {
+    conn: RustConnection,
+    root: Window,
+    source_width: u16,
+    source_height: u16,
+    shm: Option<ShmSegment>,
+    damage: Option<damage::Damage>,
+}
+
+/// Captures the root window of an X11 display such as an Xvfb `:99` at its native
+/// size, cropped to a region or window when the target asks for one.
+pub struct X11Source {
+    display: String,
+    target: CaptureTarget,
+    connection: Option<X11Connection>,
+    window: Option<Window>,
+    previous: Option<Vec<u8>>,
+    damage: Vec<DamageRect>,
+    bounds: Region,
+    stats: CaptureStats,
+}
+
+impl FrameSource for X11Source {
+    /// The target's display id is an X11 display name; `None` (and window targets)
+    /// fall back to `$DISPLAY`.
+    fn open(target: &CaptureTarget, _width: i32, _height: i32) -> Result<Self, String> {
+        let display = match target.display_id() {
+            Some(name) => name.to_string(),
+            None => std::env::var("DISPLAY").map_err(|_| "DISPLAY is not set".to_string())?,
+        };
+        let connection = X11Connection::open(&display)?;
+        let window = match target {
+            CaptureTarget::Window { handle, .. } => handle.map(|value| value as Window),
+            _ => None,
+        };
+        Ok(Self {
+            display,
+            target: target.clone(),
+            connection: Some(connection),
+            window,
+            previous: None,
+            damage: Vec::new(),
+            bounds: Region::default(),
+            stats: CaptureStats::default(),
+        })
+    }
+
+    /// X11 reads are synchronous, so the timeout is not used.
+    fn acquire(&mut self, _timeout: Duration) -> Result<CapturedFrame, String> {
+        match self.grab() {
+            Ok(frame) => Ok(frame),
+            Err(err) => {
+                // Reconnect on the next acquire; the server may have gone away.
+                self.connection = None;
+                self.previous = None;
+                self.stats.failures = self.stats.failures.saturating_add(1);
+                Err(err)
+            }
+        }
+    }
+
+    fn stats(&self) -> CaptureStats {
+        self.stats.clone()
+    }
+
+    fn damage(&self) -> &[DamageRect] {
+        &self.damage
+    }
+
+    fn bounds(&self) -> Region {
+        self.bounds
+    }
+}
+
+impl X11Source {
+    fn grab(&mut self) -> Result<CapturedFrame, String> {
+        if self.connection.is_none() {
+            self.connection = Some(X11Connection::open(&self.display)?);
+        }
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or_else(|| "X11 capture not initialized".to_string())?;
+        let source = connection.read_root()?;
+        let src_w = connection.source_width as usize;
+        let src_h = connection.source_height as usize;
+        let source_damage = match (connection.damage, self.previous.as_deref()) {
+            (Some(damage), Some(_)) => connection.fetch_damage(damage)?,
+            (None, Some(previous)) => diff_rows(previous, &source, src_w, src_h),
+            (damage, None) => {
+                if let Some(damage) = damage {
+                    // Reset the server-side accumulator so later fetches are relative to this frame.
+                    connection.fetch_damage(damage)?;
+                }
+                vec![DamageRect::full(src_w as i32, src_h as i32)]
+            }
+        };
+        let full = Region {
+            x: 0,
+            y: 0,
+            width: src_w as i32,
+            height: src_h as i32,
+        };
+        let crop = match &self.target {
+            CaptureTarget::Display { .. } => full,
+            CaptureTarget::Region {
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => clip(
+                Region {
+                    x: *x,
+                    y: *y,
+                    width: *width,
+                    height: *height,
+                },
+                full.width,
+                full.height,
+            )
+            .ok_or_else(|| "Capture region is outside the display".to_string())?,
+            CaptureTarget::Window { title, .. } => {
+                let rect = connection.locate_window(&mut self.window, title.as_deref())?;
+                clip(rect, full.width, full.height)
+                    .ok_or_else(|| "Capture window is off screen".to_string())?
+            }
+        };
+        self.previous = Some(source.clone());
+        let (source, source_damage) = if crop == full {
+            (source, source_damage)
+        } else {
+            (
+                crop_bgra(&source, full.width, crop),
+                crop_damage(&source_damage, crop),
+            )
+        };
+        self.damage = source_damage;
+        self.bounds = crop;
+
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        self.stats.last_frame_bytes = source.len() as u32;
+        self.stats.capture_path = if connection.shm.is_some() {
+            "X11 SHM"
+        } else {
+            "X11 GetImage"
+        }
+        .to_string();
+        self.stats.capture_scale = "1:1".to_string();
+
+        Ok(CapturedFrame {
+            bgra: source,
+            width: crop.width,
+            height: crop.height,
+        })
+    }
+}
+
+impl X11Connection {
+    fn open(display: &str) -> Result<Self, String> {
+        let (conn, screen_num) = x11rb::connect(Some(display))
+            .map_err(|err| format!("X11 connect to {display} failed: {err}"))?;
+        let screen = &conn.setup().roots[screen_num];
+        let root = screen.root;
+        let source_width = screen.width_in_pixels;
+        let source_height = screen.height_in_pixels;
+        if screen.root_depth != 24 && screen.root_depth != 32 {
+            return Err(format!("Unsupported X11 root depth {}", screen.root_depth));
+        }
+        let shm = attach_shm(&conn, source_width, source_height);
+        let damage = create_damage(&conn, root);
+        Ok(Self {
+            conn,
+            root,
+            source_width,
+            source_height,
+            shm,
+            damage,
+        })
+    }
+
+    fn read_root(&mut self) -> Result<Vec<u8>, String> {
+        let (w, h) = (self.source_width, self.source_height);
+        let expected = w as usize * h as usize * 4;
+        let mut data = if let Some(segment) = self.shm.as_ref() {
+            let reply = self
+                .conn
+                .shm_get_image(
+                    self.root,
+                    0,
+                    0,
+                    w,
+                    h,
+                    !0,
+                    ImageFormat::Z_PIXMAP.into(),
+                    segment.seg,
+                    0,
+                )
+                .map_err(|err| format!("XShmGetImage failed: {err}"))?
+                .reply()
+                .map_err(|err| format!("XShmGetImage failed: {err}"))?;
+            let len = (reply.size as usize).min(segment.len);
+            unsafe { std::slice::from_raw_parts(segment.addr, len) }.to_vec()
+        } else {
+            self.conn
+                .get_image(ImageFormat::Z_PIXMAP, self.root, 0, 0, w, h, !0)
+                .map_err(|err| format!("XGetImage failed: {err}"))?
+                .reply()
+                .map_err(|err| format!("XGetImage failed: {err}"))?
+                .data
+        };
+        if data.len() < expected {
+            return Err(format!("X11 image too short: {} < {expected}", data.len()));
+        }
+        data.truncate(expected);
+        // Depth-24 visuals leave the padding byte undefined.
+        for pixel in data.chunks_exact_mut(4) {
+            pixel[3] = 0xff;
+        }
+        Ok(data)
+    }
+
+    /// Root-relative rect of the tracked window. A missing or destroyed window is
+    /// looked up again by title.
+    fn locate_window(
+        &self,
+        window: &mut Option<Window>,
+        title: Option<&str>,
+    ) -> Result<Region, String> {
+        if let Some(current) = *window {
+            if let Some(rect) = self.window_rect(current) {
+                return Ok(rect);
+            }
+        }
+        *window = title.and_then(|title| {
+            top_level_windows(&self.conn, self.root)
+                .into_iter()
+                .find(|candidate| title_matches(&candidate.title, title))
+                .map(|candidate| candidate.handle as Window)
+        });
+        window
+            .and_then(|current| self.window_rect(current))
+            .ok_or_else(|| "Capture window not found".to_string())
+    }
+
+    fn window_rect(&self, window: Window) -> Option<Region> {
+        let geometry = self.conn.get_geometry(window).ok()?.reply().ok()?;
+        let origin = self
+            .conn
+            .translate_coordinates(window, self.root, 0, 0)
+            .ok()?
+            .reply()
+            .ok()?;
+        Some(Region {
+            x: origin.dst_x as i32,
+            y: origin.dst_y as i32,
+            width: geometry.width as i32,
+            height: geometry.height as i32,
+        })
+    }
+
+    fn fetch_damage(&self, damage: damage::Damage) -> Result<Vec<DamageRect>, String> {
+        let region = self
+            .conn
+            .generate_id()
+            .map_err(|err| format!("X11 id allocation failed: {err}"))?;
+        let request = || -> Result<Vec<DamageRect>, x11rb::errors::ReplyError> {
+            self.conn.xfixes_create_region(region, &[])?;
+            self.conn.damage_subtract(damage, x11rb::NONE, region)?;
+            let reply = self.conn.xfixes_fetch_region(region)?.reply()?;
+            self.conn.xfixes_destroy_region(region)?;
+            Ok(reply
+                .rectangles
+                .iter()
+                .map(|rect| DamageRect {
+                    x: rect.x as i32,
+                    y: rect.y as i32,
+                    width: rect.width as i32,
+                    height: rect.height as i32,
+                })
+                .collect())
+        };
+        let rects = request().map_err(|err| format!("X11 damage fetch failed: {err}"))?;
+        while let Ok(Some(_)) = self.conn.poll_for_event() {}
+        Ok(rects)
+    }
+}
+
+/// Viewable windows with a title on `display`, for picking a window target.
+pub fn list_windows(display: &str) -> Vec<CaptureWindow> {
+    match x11rb::connect(Some(display)) {
+        Ok((conn, screen_num)) => {
+            let root = conn.setup().roots[screen_num].root;
+            top_level_windows(&conn, root)
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Titled, viewable children of the root. Reparenting window managers wrap clients
+/// in untitled frames, so the frames' children are checked too.
+fn top_level_windows(conn: &RustConnection, root: Window) -> Vec<CaptureWindow> {
+    let mut windows = Vec::new();
+    let Some(children) = query_children(conn, root) else {
+        return windows;
+    };
+    for child in children {
+        let viewable = conn
+            .get_window_attributes(child)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|attributes| attributes.map_state == MapState::VIEWABLE)
+            .unwrap_or(false);
+        if !viewable {
+            continue;
+        }
+        if let Some(title) = window_title(conn, child) {
+            windows.push(CaptureWindow {
+                handle: child as u64,
+                title,
+            });
+            continue;
+        }
+        for client in query_children(conn, child).unwrap_or_default() {
+            if let Some(title) = window_title(conn, client) {
+                windows.push(CaptureWindow {
+                    handle: client as u64,
+                    title,
+                });
+            }
+        }
+    }
+    windows
+}
+
+fn query_children(conn: &RustConnection, window: Window) -> Option<Vec<Window>> {
+    Some(conn.query_tree(window).ok()?.reply().ok()?.children)
+}
+
+/// `_NET_WM_NAME` when set, `WM_NAME` otherwise; `None` for untitled windows.
+fn window_title(conn: &RustConnection, window: Window) -> Option<String> {
+    let net_wm_name = conn
+        .intern_atom(true, b"_NET_WM_NAME")
+        .ok()
+        .and_then(|cookie| cookie.reply().ok())
+        .map(|reply| reply.atom)
+        .filter(|&atom| atom != x11rb::NONE);
+    let atoms = net_wm_name
+        .into_iter()
+        .chain(std::iter::once(AtomEnum::WM_NAME.into()));
+    for atom in atoms {
+        let value = conn
+            .get_property(false, window, atom, AtomEnum::ANY, 0, 1024)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|reply| reply.value)
+            .unwrap_or_default();
+        let title = String::from_utf8_lossy(&value).trim().to_string();
+        if !title.is_empty() {
+            return Some(title);
+        }
+    }
+    None
+}
+
+fn attach_shm(conn: &RustConnection, width: u16, height: u16) -> Option<ShmSegment> {
+    conn.extension_information(shm::X11_EXTENSION_NAME).ok()??;
+    conn.shm_query_version().ok()?.reply().ok()?;
+    let len = width as usize * height as usize * 4;
+    let shmid = unsafe { libc::shmget(libc::IPC_PRIVATE, len, libc::IPC_CREAT | 0o600) };
+    if shmid < 0 {
+        return None;
+    }
+    let addr = unsafe { libc::shmat(shmid, std::ptr::null(), 0) };
+    if addr as isize == -1 {
+        unsafe { libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut()) };
+        return None;
+    }
+    let segment = ShmSegment {
+        seg: conn.generate_id().ok()?,
+        shmid,
+        addr: addr as *mut u8,
+        len,
+    };
+    // A remote server cannot see our segment; the checked attach fails and we fall back.
+    conn.shm_attach(segment.seg, shmid as u32, false)
+        .ok()?
+        .check()
+        .ok()?;
+    Some(segment)
+}
+
+fn create_damage(conn: &RustConnection, root: Window) -> Option<damage::Damage> {
+    conn.extension_information(damage::X11_EXTENSION_NAME)
+        .ok()??;
+    conn.extension_information(xfixes::X11_EXTENSION_NAME)
+        .ok()??;
+    conn.xfixes_query_version(5, 0).ok()?.reply().ok()?;
+    conn.damage_query_version(1, 1).ok()?.reply().ok()?;
+    let damage = conn.generate_id().ok()?;
+    conn.damage_create(damage, root, ReportLevel::NON_EMPTY)
+        .ok()?
+        .check()
+        .ok()?;
+    Some(damage)
+}
+
+/// Fallback damage when the DAMAGE extension is missing: one full-width rect per
+/// run of changed rows.
+pub fn diff_rows(previous: &[u8], current: &[u8], width: usize, height: usize) -> Vec<DamageRect> {
+    let stride = width * 4;
+    let mut rects = Vec::new();
+    let mut run_start: Option<usize> = None;
+    for row in 0..=height {
+        let changed = row < height
+            && previous.get(row * stride..(row + 1) * stride)
+                != current.get(row * stride..(row + 1) * stride);
+        match (changed, run_start) {
+            (true, None) => run_start = Some(row),
+            (false, Some(start)) => {
+                rects.push(DamageRect {
+                    x: 0,
+                    y: start as i32,
+                    width: width as i32,
+                    height: (row - start) as i32,
+                });
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use x11rb::protocol::xproto::{CreateGCAux, Rectangle};
+
+    #[test]
+    fn diffs_changed_row_runs() {
+        let previous = vec![0u8; 4 * 4 * 4];
+        let mut current = previous.clone();
+        current[16 + 1] = 9;
+        current[3 * 16] = 9;
+        assert_eq!(
+            diff_rows(&previous, &current, 4, 4),
+            vec![
+                DamageRect {
+                    x: 0,
+                    y: 1,
+                    width: 4,
+                    height: 1
+                },
+                DamageRect {
+                    x: 0,
+                    y: 3,
+                    width: 4,
+                    height: 1
+                },
+            ]
+        );
+        assert!(diff_rows(&previous, &previous, 4, 4).is_empty());
+    }
+
+    /// Runs against a private Xvfb server; skipped when Xvfb is not installed.
+    #[test]
+    fn captures_xvfb_root_and_reports_damage() {
+        let display_num = 180 + std::process::id() % 60;
+        let display = format!(":{display_num}");
+        let Ok(mut child) = Command::new("Xvfb")
+            .args([
+                display.as_str(),
+                "-screen",
+                "0",
+                "320x240x24",
+                "-nolisten",
+                "tcp",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            eprintln!("Xvfb not available; skipping");
+            return;
+        };
+        let socket = format!("/tmp/.X11-unix/X{display_num}");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !Path::new(&socket).exists() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        let mut source = X11Source::open(&CaptureTarget::display(Some(display.clone())), 160, 120)
+            .expect("open display");
+        let first = source.acquire(Duration::ZERO).expect("first capture");
+        assert_eq!((first.width, first.height), (320, 240));
+        assert_eq!(source.damage(), &[DamageRect::full(320, 240)]);
+
+        let (conn, screen_num) = x11rb::connect(Some(&display)).unwrap();
+        let root = conn.setup().roots[screen_num].root;
+        let gc = conn.generate_id().unwrap();
+        conn.create_gc(gc, root, &CreateGCAux::new().foreground(0x00ff_0000))
+            .unwrap();
+        conn.poly_fill_rectangle(
+            root,
+            gc,
+            &[Rectangle {
+                x: 10,
+                y: 20,
+                width: 30,
+                height: 40,
+            }],
+        )
+        .unwrap();
+        conn.get_input_focus().unwrap().reply().unwrap();
+
+        let second = source.acquire(Duration::ZERO).expect("second capture");
+        assert_eq!(second.bgra.len(), 320 * 240 * 4);
+        assert!(!source.damage().is_empty());
+        assert_eq!(source.stats().frames, 2);
+        let pixel = (30 * 320 + 20) * 4;
+        assert_eq!(&second.bgra[pixel..pixel + 4], &[0x00, 0x00, 0xff, 0xff]);
+
+        let region = CaptureTarget::Region {
+            display_id: Some(display.clone()),
+            x: 10,
+            y: 20,
+            width: 30,
+            height: 40,
+        };
+        let mut cropped = X11Source::open(&region, 30, 40).expect("open region");
+        let frame = cropped.acquire(Duration::ZERO).expect("region capture");
+        assert_eq!((frame.width, frame.height), (30, 40));
+        assert!(frame
+            .bgra
+            .chunks_exact(4)
+            .all(|pixel| pixel == [0x00, 0x00, 0xff, 0xff]));
+        assert_eq!(cropped.bounds().x, 10);
+
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
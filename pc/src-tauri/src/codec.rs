@@ -1,4 +1,8 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use serde::Serialize;
+
+use crate::app_state::CodecCandidate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum CodecId {
     H264 = 1,
     H265 = 2,
@@ -54,40 +58,187 @@ pub fn codec_mask(codec_id: CodecId) -> u32 {
     }
 }
 
+/// Codecs at least one probed encoder backend can produce.
 pub fn host_codec_mask() -> u32 {
-    #[cfg(windows)]
-    {
-        CODEC_MASK_H265
-            | CODEC_MASK_AV1
-            | CODEC_MASK_H264
-            | CODEC_MASK_VP9
-            | CODEC_MASK_EVC
-            | CODEC_MASK_LCEVC
+    crate::encoder::capabilities().codec_mask()
+}
+
+/// Codecs that are only negotiated when the user opts in through `HostSettings`.
+pub const CODEC_MASK_OPT_IN: u32 = CODEC_MASK_EVC | CODEC_MASK_LCEVC;
+
+const CODEC_PRIORITY: [CodecId; 7] = [
+    CodecId::H265,
+    CodecId::Av1,
+    CodecId::H264,
+    CodecId::Vp9,
+    CodecId::H266,
+    CodecId::Evc,
+    CodecId::Lcevc,
+];
+
+/// Inputs to codec selection. `opt_in_mask` lists the opt-in codecs the user enabled.
+#[derive(Debug, Clone, Copy)]
+pub struct CodecPolicy {
+    pub host_mask: u32,
+    pub client_mask: u32,
+    pub preferred: Option<CodecId>,
+    pub opt_in_mask: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CodecDecision {
+    pub selected: Option<CodecId>,
+    /// Every known codec, eligible ones first in the order they were considered.
+    pub candidates: Vec<CodecCandidate>,
+}
+
+impl CodecPolicy {
+    pub fn evaluate(&self) -> CodecDecision {
+        let mut order = Vec::with_capacity(CODEC_PRIORITY.len());
+        if let Some(preferred) = self.preferred {
+            order.push(preferred);
+        }
+        order.extend(
+            CODEC_PRIORITY
+                .iter()
+                .copied()
+                .filter(|codec| Some(*codec) != self.preferred),
+        );
+
+        let mut eligible = Vec::new();
+        let mut rejected = Vec::new();
+        for codec in order {
+            match self.rejection(codec) {
+                Some(reason) => rejected.push(candidate(codec, false, false, reason)),
+                None => eligible.push(codec),
+            }
+        }
+
+        let selected = eligible.first().copied();
+        let mut candidates = Vec::with_capacity(eligible.len() + rejected.len());
+        for codec in eligible {
+            let reason = if Some(codec) != selected {
+                format!(
+                    "Available, ranked below {}",
+                    codec_name(selected.unwrap_or(codec))
+                )
+            } else if Some(codec) == self.preferred {
+                "Preferred codec in settings".to_string()
+            } else if let Some(preferred) = self.preferred {
+                format!(
+                    "Highest-priority shared codec ({} unavailable)",
+                    codec_name(preferred)
+                )
+            } else {
+                "Highest-priority shared codec".to_string()
+            };
+            candidates.push(candidate(codec, true, Some(codec) == selected, reason));
+        }
+        candidates.extend(rejected);
+
+        CodecDecision {
+            selected,
+            candidates,
+        }
     }
-    #[cfg(not(windows))]
-    {
-        CODEC_MASK_H264
+
+    fn rejection(&self, codec: CodecId) -> Option<String> {
+        let mask = codec_mask(codec);
+        if self.host_mask & mask == 0 {
+            Some("No encoder for this codec on the host".to_string())
+        } else if self.client_mask & mask == 0 {
+            Some("Client does not advertise this codec".to_string())
+        } else if CODEC_MASK_OPT_IN & mask != 0 && self.opt_in_mask & mask == 0 {
+            Some("Opt-in codec; enable it in preferences".to_string())
+        } else {
+            None
+        }
     }
 }
 
-pub fn select_codec(
-    host_mask: u32,
-    client_mask: u32,
-    preferred: Option<CodecId>,
-) -> Option<CodecId> {
-    let available = host_mask & client_mask;
-    if let Some(codec) = preferred {
-        if available & codec_mask(codec) != 0 {
-            return Some(codec);
-        }
+fn candidate(codec: CodecId, eligible: bool, selected: bool, reason: String) -> CodecCandidate {
+    CodecCandidate {
+        codec_id: codec as u8,
+        codec_name: codec_name(codec).to_string(),
+        eligible,
+        selected,
+        reason,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let priority = [CodecId::H265, CodecId::Av1, CodecId::H264, CodecId::Vp9];
-    for codec in priority {
-        if available & codec_mask(codec) != 0 {
-            return Some(codec);
+    const ALL: u32 = CODEC_MASK_H264
+        | CODEC_MASK_H265
+        | CODEC_MASK_AV1
+        | CODEC_MASK_VP9
+        | CODEC_MASK_H266
+        | CODEC_MASK_EVC
+        | CODEC_MASK_LCEVC;
+
+    fn policy(
+        host_mask: u32,
+        client_mask: u32,
+        preferred: Option<CodecId>,
+        opt_in_mask: u32,
+    ) -> CodecDecision {
+        CodecPolicy {
+            host_mask,
+            client_mask,
+            preferred,
+            opt_in_mask,
         }
+        .evaluate()
+    }
+
+    #[test]
+    fn falls_back_by_priority_and_explains_rejections() {
+        let decision = policy(CODEC_MASK_H264, ALL, Some(CodecId::H265), 0);
+        assert_eq!(decision.selected, Some(CodecId::H264));
+        assert_eq!(decision.candidates.len(), 7);
+        assert_eq!(decision.candidates[0].codec_id, CodecId::H264 as u8);
+        assert!(decision.candidates[0].selected);
+        assert!(decision.candidates[0]
+            .reason
+            .contains("H.265 HEVC unavailable"));
+        let hevc = decision
+            .candidates
+            .iter()
+            .find(|candidate| candidate.codec_id == CodecId::H265 as u8)
+            .unwrap();
+        assert!(!hevc.eligible);
+        assert_eq!(hevc.reason, "No encoder for this codec on the host");
     }
 
-    None
+    #[test]
+    fn requires_opt_in_for_evc_and_lcevc() {
+        let mask = CODEC_MASK_H264 | CODEC_MASK_EVC;
+        let decision = policy(mask, mask, Some(CodecId::Evc), 0);
+        assert_eq!(decision.selected, Some(CodecId::H264));
+        let evc = decision
+            .candidates
+            .iter()
+            .find(|candidate| candidate.codec_id == CodecId::Evc as u8)
+            .unwrap();
+        assert_eq!(evc.reason, "Opt-in codec; enable it in preferences");
+
+        let decision = policy(mask, mask, Some(CodecId::Evc), CODEC_MASK_EVC);
+        assert_eq!(decision.selected, Some(CodecId::Evc));
+        assert_eq!(
+            decision.candidates[1].reason,
+            "Available, ranked below EVC (xevd/xeve)"
+        );
+    }
+
+    #[test]
+    fn selects_nothing_without_overlap() {
+        let decision = policy(CODEC_MASK_H264, CODEC_MASK_VP9, None, 0);
+        assert_eq!(decision.selected, None);
+        assert!(decision
+            .candidates
+            .iter()
+            .all(|candidate| !candidate.eligible));
+    }
 }
@@ -0,0 +1,436 @@
+use crate::codec::CodecId;
+use crate::protocol::packets::{CodecLimit, DecoderLimits};
+
+/// `codecFlags` bits carried in the `Configure` v2 extension.
+pub const CODEC_FLAG_10BIT: u8 = 1 << 0;
+pub const CODEC_FLAG_444: u8 = 1 << 1;
+pub const CODEC_FLAG_LOW_DELAY: u8 = 1 << 2;
+
+/// Profile ids as sent on the wire: `profile_idc` for H.264 and
+/// `general_profile_idc` for HEVC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecProfile {
+    H264Baseline = 66,
+    H264Main = 77,
+    H264High = 100,
+    HevcMain = 1,
+    HevcMain10 = 2,
+}
+
+/// Profile, level and flags sent in `Configure` v2. Zero means "unspecified".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CodecParameters {
+    pub profile: u8,
+    pub level: u8,
+    pub flags: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub flags: u8,
+}
+
+struct Level {
+    idc: u8,
+    max_picture: u64,
+    max_rate: u64,
+}
+
+/// H.264 Table A-1: `level_idc`, MaxFS (macroblocks), MaxMBPS (macroblocks/s).
+const H264_LEVELS: &[Level] = &[
+    Level {
+        idc: 10,
+        max_picture: 99,
+        max_rate: 1_485,
+    },
+    Level {
+        idc: 11,
+        max_picture: 396,
+        max_rate: 3_000,
+    },
+    Level {
+        idc: 12,
+        max_picture: 396,
+        max_rate: 6_000,
+    },
+    Level {
+        idc: 13,
+        max_picture: 396,
+        max_rate: 11_880,
+    },
+    Level {
+        idc: 20,
+        max_picture: 396,
+        max_rate: 11_880,
+    },
+    Level {
+        idc: 21,
+        max_picture: 792,
+        max_rate: 19_800,
+    },
+    Level {
+        idc: 22,
+        max_picture: 1_620,
+        max_rate: 20_250,
+    },
+    Level {
+        idc: 30,
+        max_picture: 1_620,
+        max_rate: 40_500,
+    },
+    Level {
+        idc: 31,
+        max_picture: 3_600,
+        max_rate: 108_000,
+    },
+    Level {
+        idc: 32,
+        max_picture: 5_120,
+        max_rate: 216_000,
+    },
+    Level {
+        idc: 40,
+        max_picture: 8_192,
+        max_rate: 245_760,
+    },
+    Level {
+        idc: 41,
+        max_picture: 8_192,
+        max_rate: 245_760,
+    },
+    Level {
+        idc: 42,
+        max_picture: 8_704,
+        max_rate: 522_240,
+    },
+    Level {
+        idc: 50,
+        max_picture: 22_080,
+        max_rate: 589_824,
+    },
+    Level {
+        idc: 51,
+        max_picture: 36_864,
+        max_rate: 983_040,
+    },
+    Level {
+        idc: 52,
+        max_picture: 36_864,
+        max_rate: 2_073_600,
+    },
+    Level {
+        idc: 60,
+        max_picture: 139_264,
+        max_rate: 4_177_920,
+    },
+    Level {
+        idc: 61,
+        max_picture: 139_264,
+        max_rate: 8_355_840,
+    },
+    Level {
+        idc: 62,
+        max_picture: 139_264,
+        max_rate: 16_711_680,
+    },
+];
+
+/// HEVC Table A.8: `general_level_idc` (level * 30), MaxLumaPs, MaxLumaSr.
+const HEVC_LEVELS: &[Level] = &[
+    Level {
+        idc: 30,
+        max_picture: 36_864,
+        max_rate: 552_960,
+    },
+    Level {
+        idc: 60,
+        max_picture: 122_880,
+        max_rate: 3_686_400,
+    },
+    Level {
+        idc: 63,
+        max_picture: 245_760,
+        max_rate: 7_372_800,
+    },
+    Level {
+        idc: 90,
+        max_picture: 552_960,
+        max_rate: 16_588_800,
+    },
+    Level {
+        idc: 93,
+        max_picture: 983_040,
+        max_rate: 33_177_600,
+    },
+    Level {
+        idc: 120,
+        max_picture: 2_228_224,
+        max_rate: 66_846_720,
+    },
+    Level {
+        idc: 123,
+        max_picture: 2_228_224,
+        max_rate: 133_693_440,
+    },
+    Level {
+        idc: 150,
+        max_picture: 8_912_896,
+        max_rate: 267_386_880,
+    },
+    Level {
+        idc: 153,
+        max_picture: 8_912_896,
+        max_rate: 534_773_760,
+    },
+    Level {
+        idc: 156,
+        max_picture: 8_912_896,
+        max_rate: 1_069_547_520,
+    },
+    Level {
+        idc: 180,
+        max_picture: 35_651_584,
+        max_rate: 1_069_547_520,
+    },
+    Level {
+        idc: 183,
+        max_picture: 35_651_584,
+        max_rate: 2_139_095_040,
+    },
+    Level {
+        idc: 186,
+        max_picture: 35_651_584,
+        max_rate: 4_278_190_080,
+    },
+];
+
+/// Picks profile, level and flags for `codec_id`. The host asks for `format.flags`;
+/// only the ones the client also advertises survive. The signalled level is the
+/// highest one the client accepts (or the lowest that fits when it sends no limits),
+/// and negotiation fails if the stream does not fit within the client's cap.
+pub fn negotiate(
+    codec_id: CodecId,
+    format: StreamFormat,
+    client: Option<&DecoderLimits>,
+) -> Result<CodecParameters, String> {
+    if let Some(limits) = client {
+        check_decoder_bounds(format, limits)?;
+    }
+    let codec_limit = client.and_then(|limits| limit_for(limits, codec_id));
+    let flags = match codec_limit {
+        Some(limit) => format.flags & limit.codec_flags,
+        None => format.flags & CODEC_FLAG_LOW_DELAY,
+    };
+
+    let (profile, levels) = match codec_id {
+        CodecId::H264 => (h264_profile(codec_limit), H264_LEVELS),
+        CodecId::H265 => (hevc_profile(flags, codec_limit), HEVC_LEVELS),
+        _ => {
+            return Ok(CodecParameters {
+                profile: 0,
+                level: 0,
+                flags,
+            })
+        }
+    };
+    let flags = match profile {
+        CodecProfile::HevcMain10 => flags,
+        _ => flags & !(CODEC_FLAG_10BIT | CODEC_FLAG_444),
+    };
+
+    let required = required_level(codec_id, levels, format).ok_or_else(|| {
+        format!(
+            "{}x{}@{} exceeds the highest {} level",
+            format.width,
+            format.height,
+            format.fps,
+            crate::codec::codec_name(codec_id)
+        )
+    })?;
+    let level = match codec_limit
+        .map(|limit| limit.max_level)
+        .filter(|level| *level > 0)
+    {
+        Some(client_max) => {
+            let cap = levels
+                .iter()
+                .rev()
+                .find(|level| level.idc <= client_max)
+                .map(|level| level.idc)
+                .unwrap_or(0);
+            if cap < required {
+                return Err(format!(
+                    "{}x{}@{} needs level {} but the client decodes up to {}",
+                    format.width, format.height, format.fps, required, client_max
+                ));
+            }
+            cap
+        }
+        None => required,
+    };
+
+    Ok(CodecParameters {
+        profile: profile as u8,
+        level,
+        flags,
+    })
+}
+
+fn check_decoder_bounds(format: StreamFormat, limits: &DecoderLimits) -> Result<(), String> {
+    let too_wide = limits.max_width > 0 && format.width > limits.max_width as u32;
+    let too_tall = limits.max_height > 0 && format.height > limits.max_height as u32;
+    if too_wide || too_tall {
+        return Err(format!(
+            "{}x{} exceeds client decoder limit {}x{}",
+            format.width, format.height, limits.max_width, limits.max_height
+        ));
+    }
+    if limits.max_fps > 0 && format.fps > limits.max_fps as u32 {
+        return Err(format!(
+            "{} fps exceeds client decoder limit {}",
+            format.fps, limits.max_fps
+        ));
+    }
+    Ok(())
+}
+
+fn limit_for(limits: &DecoderLimits, codec_id: CodecId) -> Option<CodecLimit> {
+    limits
+        .codecs
+        .iter()
+        .find(|limit| limit.codec_id == codec_id as u8)
+        .copied()
+}
+
+fn h264_profile(limit: Option<CodecLimit>) -> CodecProfile {
+    match limit
+        .map(|limit| limit.max_profile)
+        .filter(|profile| *profile > 0)
+    {
+        Some(max) if max < CodecProfile::H264Main as u8 => CodecProfile::H264Baseline,
+        Some(max) if max < CodecProfile::H264High as u8 => CodecProfile::H264Main,
+        _ => CodecProfile::H264High,
+    }
+}
+
+fn hevc_profile(flags: u8, limit: Option<CodecLimit>) -> CodecProfile {
+    let allows_main10 = limit
+        .map(|limit| limit.max_profile == 0 || limit.max_profile >= CodecProfile::HevcMain10 as u8)
+        .unwrap_or(false);
+    if flags & CODEC_FLAG_10BIT != 0 && allows_main10 {
+        CodecProfile::HevcMain10
+    } else {
+        CodecProfile::HevcMain
+    }
+}
+
+/// Lowest level whose picture size, per-dimension bound and sample rate cover `format`.
+fn required_level(codec_id: CodecId, levels: &[Level], format: StreamFormat) -> Option<u8> {
+    let (picture, longest_side) = match codec_id {
+        CodecId::H264 => {
+            let mbs_w = format.width.div_ceil(16) as u64;
+            let mbs_h = format.height.div_ceil(16) as u64;
+            (mbs_w * mbs_h, mbs_w.max(mbs_h))
+        }
+        _ => (
+            format.width as u64 * format.height as u64,
+            format.width.max(format.height) as u64,
+        ),
+    };
+    let rate = picture * format.fps.max(1) as u64;
+    levels
+        .iter()
+        .find(|level| {
+            picture <= level.max_picture
+                && longest_side * longest_side <= level.max_picture * 8
+                && rate <= level.max_rate
+        })
+        .map(|level| level.idc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(width: u32, height: u32, fps: u32) -> StreamFormat {
+        StreamFormat {
+            width,
+            height,
+            fps,
+            flags: CODEC_FLAG_LOW_DELAY,
+        }
+    }
+
+    fn client(codec_id: CodecId, max_profile: u8, max_level: u8, codec_flags: u8) -> DecoderLimits {
+        DecoderLimits {
+            max_width: 0,
+            max_height: 0,
+            max_fps: 0,
+            codecs: vec![CodecLimit {
+                codec_id: codec_id as u8,
+                max_profile,
+                max_level,
+                codec_flags,
+            }],
+        }
+    }
+
+    #[test]
+    fn picks_lowest_fitting_level_without_client_limits() {
+        let params = negotiate(CodecId::H264, format(1920, 1080, 60), None).unwrap();
+        assert_eq!(params.profile, CodecProfile::H264High as u8);
+        assert_eq!(params.level, 42);
+        assert_eq!(params.flags, CODEC_FLAG_LOW_DELAY);
+
+        let params = negotiate(CodecId::H264, format(1280, 720, 30), None).unwrap();
+        assert_eq!(params.level, 31);
+
+        let params = negotiate(CodecId::H265, format(3840, 2160, 60), None).unwrap();
+        assert_eq!(params.profile, CodecProfile::HevcMain as u8);
+        assert_eq!(params.level, 153);
+    }
+
+    #[test]
+    fn signals_client_maximum_level_when_stream_fits() {
+        let limits = client(CodecId::H264, 77, 51, CODEC_FLAG_LOW_DELAY);
+        let params = negotiate(CodecId::H264, format(1920, 1080, 30), Some(&limits)).unwrap();
+        assert_eq!(params.profile, CodecProfile::H264Main as u8);
+        assert_eq!(params.level, 51);
+
+        let limits = client(CodecId::H264, 66, 31, 0);
+        assert!(negotiate(CodecId::H264, format(1920, 1080, 60), Some(&limits)).is_err());
+    }
+
+    #[test]
+    fn intersects_flags_and_selects_main10() {
+        let mut wanted = format(2560, 1440, 60);
+        wanted.flags = CODEC_FLAG_10BIT | CODEC_FLAG_444 | CODEC_FLAG_LOW_DELAY;
+        let limits = client(CodecId::H265, 2, 0, CODEC_FLAG_10BIT | CODEC_FLAG_LOW_DELAY);
+        let params = negotiate(CodecId::H265, wanted, Some(&limits)).unwrap();
+        assert_eq!(params.profile, CodecProfile::HevcMain10 as u8);
+        assert_eq!(params.flags, CODEC_FLAG_10BIT | CODEC_FLAG_LOW_DELAY);
+
+        let params = negotiate(CodecId::H264, wanted, None).unwrap();
+        assert_eq!(params.flags, CODEC_FLAG_LOW_DELAY);
+    }
+
+    #[test]
+    fn rejects_streams_beyond_decoder_bounds() {
+        let mut limits = client(CodecId::H264, 0, 0, 0);
+        limits.max_width = 1920;
+        limits.max_height = 1080;
+        limits.max_fps = 60;
+        assert!(negotiate(CodecId::H264, format(2560, 1440, 60), Some(&limits)).is_err());
+        assert!(negotiate(CodecId::H264, format(1920, 1080, 120), Some(&limits)).is_err());
+        assert!(negotiate(CodecId::H264, format(1920, 1080, 60), Some(&limits)).is_ok());
+    }
+
+    #[test]
+    fn leaves_other_codecs_unspecified() {
+        let params = negotiate(CodecId::Av1, format(1920, 1080, 60), None).unwrap();
+        assert_eq!((params.profile, params.level), (0, 0));
+    }
+}
@@ -0,0 +1,558 @@
+use std::thread;
+
+use serde::Serialize;
+
+use crate::protocol::packets::ColorDescription;
+
+/// YCbCr matrix used for BGRA -> YUV conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ColorRange {
+    /// Studio swing: Y 16-235, chroma 16-240 (scaled for 10-bit).
+    Limited,
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ColorSpace {
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        Self {
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Limited,
+        }
+    }
+}
+
+impl ColorSpace {
+    pub fn from_settings(matrix: &str, full_range: bool) -> Self {
+        let matrix = match matrix.to_ascii_lowercase().replace(['.', ' '], "").as_str() {
+            "bt601" => ColorMatrix::Bt601,
+            "bt2020" => ColorMatrix::Bt2020,
+            _ => ColorMatrix::Bt709,
+        };
+        Self {
+            matrix,
+            range: if full_range {
+                ColorRange::Full
+            } else {
+                ColorRange::Limited
+            },
+        }
+    }
+
+    /// ITU-T H.273 code points for VUI and the `Configure` colour extension.
+    pub fn description(self) -> ColorDescription {
+        // Screen content is sRGB-ish; BT.601 uses the 625-line (PAL) primaries and
+        // BT.2020 the SDR 10-bit transfer so decoders do not guess HDR.
+        let (primaries, transfer, matrix) = match self.matrix {
+            ColorMatrix::Bt601 => (5, 6, 5),
+            ColorMatrix::Bt709 => (1, 1, 1),
+            ColorMatrix::Bt2020 => (9, 14, 9),
+        };
+        ColorDescription {
+            primaries,
+            transfer,
+            matrix,
+            full_range: self.range == ColorRange::Full,
+        }
+    }
+
+    /// Luma weights (Kr, Kb); Kg is `1 - Kr - Kb`.
+    fn weights(self) -> (f64, f64) {
+        match self.matrix {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+            ColorMatrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8-bit Y plane followed by interleaved UV.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Nv12,
+    /// 8-bit Y, U and V planes.
+    I420,
+    /// 16-bit little-endian samples with the 10-bit value in the high bits, NV12 layout.
+    #[allow(dead_code)]
+    P010,
+}
+
+impl PixelFormat {
+    fn sample_bytes(self) -> usize {
+        match self {
+            PixelFormat::P010 => 2,
+            _ => 1,
+        }
+    }
+
+    fn bit_depth(self) -> u32 {
+        match self {
+            PixelFormat::P010 => 10,
+            _ => 8,
+        }
+    }
+
+    pub fn frame_len(self, width: usize, height: usize) -> usize {
+        let chroma = width.div_ceil(2) * height.div_ceil(2);
+        (width * height + chroma * 2) * self.sample_bytes()
+    }
+}
+
+const SHIFT: u32 = 16;
+
+/// Q16 fixed-point coefficients for 8-bit RGB input at the output bit depth.
+#[derive(Debug, Clone, Copy)]
+struct Coefficients {
+    y: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+    y_offset: i32,
+    c_offset: i32,
+    max: i32,
+}
+
+impl Coefficients {
+    fn new(space: ColorSpace, bit_depth: u32) -> Self {
+        let (kr, kb) = space.weights();
+        let kg = 1.0 - kr - kb;
+        let max = (1i32 << bit_depth) - 1;
+        let scale = (1u32 << (bit_depth - 8)) as f64;
+        let (y_offset, y_range, c_range) = match space.range {
+            ColorRange::Limited => (16.0 * scale, 219.0 * scale, 224.0 * scale),
+            ColorRange::Full => (0.0, max as f64, max as f64),
+        };
+        let fixed = |value: f64| (value * (1 << SHIFT) as f64 / 255.0).round() as i32;
+        let cb = 0.5 / (1.0 - kb);
+        let cr = 0.5 / (1.0 - kr);
+        Self {
+            y: [
+                fixed(kr * y_range),
+                fixed(kg * y_range),
+                fixed(kb * y_range),
+            ],
+            u: [
+                fixed(-kr * cb * c_range),
+                fixed(-kg * cb * c_range),
+                fixed((1.0 - kb) * cb * c_range),
+            ],
+            v: [
+                fixed((1.0 - kr) * cr * c_range),
+                fixed(-kg * cr * c_range),
+                fixed(-kb * cr * c_range),
+            ],
+            y_offset: y_offset as i32,
+            c_offset: 1 << (bit_depth - 1),
+            max,
+        }
+    }
+
+    #[cfg(test)]
+    fn luma(&self, r: i32, g: i32, b: i32) -> u16 {
+        let value = (self.y[0] * r + self.y[1] * g + self.y[2] * b + (1 << (SHIFT - 1))) >> SHIFT;
+        (value + self.y_offset).clamp(0, self.max) as u16
+    }
+
+    /// Chroma from the sum of up to four pixels (`count` of them).
+    #[cfg(test)]
+    fn chroma(&self, coeffs: [i32; 3], r: i32, g: i32, b: i32, count: i32) -> u16 {
+        let divisor = (count as i64) << SHIFT;
+        let sum =
+            coeffs[0] as i64 * r as i64 + coeffs[1] as i64 * g as i64 + coeffs[2] as i64 * b as i64;
+        let value = (sum + divisor / 2).div_euclid(divisor) as i32;
+        (value + self.c_offset).clamp(0, self.max) as u16
+    }
+}
+
+/// Per-channel product tables; sums of table entries equal the scalar products.
+struct Tables {
+    coeffs: Coefficients,
+    y: [[i32; 256]; 3],
+    u: [[i32; 256]; 3],
+    v: [[i32; 256]; 3],
+}
+
+impl Tables {
+    fn new(coeffs: Coefficients) -> Self {
+        let table = |weights: [i32; 3]| {
+            let mut out = [[0i32; 256]; 3];
+            for (channel, weight) in weights.iter().enumerate() {
+                for (value, slot) in out[channel].iter_mut().enumerate() {
+                    *slot = weight * value as i32;
+                }
+            }
+            out
+        };
+        Self {
+            coeffs,
+            y: table(coeffs.y),
+            u: table(coeffs.u),
+            v: table(coeffs.v),
+        }
+    }
+
+    fn luma(&self, pixel: &[u8]) -> u16 {
+        let (b, g, r) = (pixel[0] as usize, pixel[1] as usize, pixel[2] as usize);
+        let value = (self.y[0][r] + self.y[1][g] + self.y[2][b] + (1 << (SHIFT - 1))) >> SHIFT;
+        (value + self.coeffs.y_offset).clamp(0, self.coeffs.max) as u16
+    }
+
+    fn chroma(&self, table: &[[i32; 256]; 3], pixels: &[&[u8]]) -> u16 {
+        let mut sum = 0i64;
+        for pixel in pixels {
+            sum += (table[0][pixel[2] as usize]
+                + table[1][pixel[1] as usize]
+                + table[2][pixel[0] as usize]) as i64;
+        }
+        let divisor = (pixels.len() as i64) << SHIFT;
+        let value = (sum + divisor / 2).div_euclid(divisor) as i32;
+        (value + self.coeffs.c_offset).clamp(0, self.coeffs.max) as u16
+    }
+}
+
+/// Output planes for a band of rows; `v` is `None` for interleaved chroma.
+struct Planes<'a> {
+    y: &'a mut [u8],
+    u: &'a mut [u8],
+    v: Option<&'a mut [u8]>,
+}
+
+fn store(plane: &mut [u8], index: usize, value: u16, format: PixelFormat) {
+    match format {
+        PixelFormat::P010 => {
+            plane[index * 2..index * 2 + 2].copy_from_slice(&(value << 6).to_le_bytes());
+        }
+        _ => plane[index] = value as u8,
+    }
+}
+
+/// Converts a BGRA frame to `format` in `space`, splitting the frame into row bands
+/// converted on worker threads with table lookups.
+pub fn convert(
+    bgra: &[u8],
+    width: usize,
+    height: usize,
+    space: ColorSpace,
+    format: PixelFormat,
+) -> Vec<u8> {
+    let mut output = vec![0u8; format.frame_len(width, height)];
+    if width == 0 || height == 0 || bgra.len() < width * height * 4 {
+        return output;
+    }
+    let tables = Tables::new(Coefficients::new(space, format.bit_depth()));
+    let chroma_width = width.div_ceil(2);
+    let sample = format.sample_bytes();
+    let workers = thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .min(height / MIN_BAND_ROWS)
+        .max(1);
+    // Bands hold an even number of rows so each owns whole chroma rows.
+    let band_rows = (height.div_ceil(workers) + 1) & !1;
+
+    let (y_plane, chroma) = output.split_at_mut(width * height * sample);
+    let chroma_row_bytes = match format {
+        PixelFormat::I420 => chroma_width * sample,
+        _ => chroma_width * 2 * sample,
+    };
+    let (u_plane, mut v_plane) = match format {
+        PixelFormat::I420 => {
+            let (u, v) = chroma.split_at_mut(chroma.len() / 2);
+            (u, Some(v))
+        }
+        _ => (chroma, None),
+    };
+
+    let mut bands = Vec::new();
+    let mut y_rest = y_plane;
+    let mut u_rest = u_plane;
+    let mut top = 0;
+    while top < height {
+        let rows = band_rows.min(height - top);
+        let (y, y_next) = y_rest.split_at_mut(rows * width * sample);
+        let chroma_len = (rows.div_ceil(2) * chroma_row_bytes).min(u_rest.len());
+        let (u, u_next) = u_rest.split_at_mut(chroma_len);
+        let v = match v_plane.take() {
+            Some(plane) => {
+                let (v, v_next) = plane.split_at_mut(chroma_len);
+                v_plane = Some(v_next);
+                Some(v)
+            }
+            None => None,
+        };
+        bands.push((top, rows, Planes { y, u, v }));
+        y_rest = y_next;
+        u_rest = u_next;
+        top += rows;
+    }
+
+    thread::scope(|scope| {
+        for (top, rows, planes) in bands {
+            let tables = &tables;
+            let source = &bgra[top * width * 4..(top + rows) * width * 4];
+            scope.spawn(move || convert_band(source, width, rows, tables, planes, format));
+        }
+    });
+    output
+}
+
+const MIN_BAND_ROWS: usize = 64;
+
+fn convert_band(
+    bgra: &[u8],
+    width: usize,
+    rows: usize,
+    tables: &Tables,
+    mut planes: Planes<'_>,
+    format: PixelFormat,
+) {
+    let stride = width * 4;
+    let chroma_width = width.div_ceil(2);
+    for pair in 0..rows.div_ceil(2) {
+        let row0 = &bgra[pair * 2 * stride..(pair * 2 + 1) * stride];
+        let row1 = if pair * 2 + 1 < rows {
+            Some(&bgra[(pair * 2 + 1) * stride..(pair * 2 + 2) * stride])
+        } else {
+            None
+        };
+        for (x, pixel) in row0.chunks_exact(4).enumerate() {
+            store(planes.y, pair * 2 * width + x, tables.luma(pixel), format);
+        }
+        if let Some(row1) = row1 {
+            for (x, pixel) in row1.chunks_exact(4).enumerate() {
+                store(
+                    planes.y,
+                    (pair * 2 + 1) * width + x,
+                    tables.luma(pixel),
+                    format,
+                );
+            }
+        }
+        for cx in 0..chroma_width {
+            let mut pixels: [&[u8]; 4] = [&[]; 4];
+            let mut count = 0;
+            for row in std::iter::once(row0).chain(row1) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    pixels[count] = &row[x * 4..x * 4 + 4];
+                    count += 1;
+                }
+            }
+            let u = tables.chroma(&tables.u, &pixels[..count]);
+            let v = tables.chroma(&tables.v, &pixels[..count]);
+            let index = pair * chroma_width + cx;
+            match planes.v.as_deref_mut() {
+                Some(v_plane) => {
+                    store(planes.u, index, u, format);
+                    store(v_plane, index, v, format);
+                }
+                None => {
+                    store(planes.u, index * 2, u, format);
+                    store(planes.u, index * 2 + 1, v, format);
+                }
+            }
+        }
+    }
+}
+
+/// Straightforward per-pixel reference `convert` is checked against.
+#[cfg(test)]
+pub fn convert_scalar(
+    bgra: &[u8],
+    width: usize,
+    height: usize,
+    space: ColorSpace,
+    format: PixelFormat,
+) -> Vec<u8> {
+    let mut output = vec![0u8; format.frame_len(width, height)];
+    if width == 0 || height == 0 || bgra.len() < width * height * 4 {
+        return output;
+    }
+    let coeffs = Coefficients::new(space, format.bit_depth());
+    let sample = format.sample_bytes();
+    let chroma_width = width.div_ceil(2);
+    let chroma_samples = chroma_width * height.div_ceil(2);
+    let (y_plane, chroma) = output.split_at_mut(width * height * sample);
+    let pixel = |x: usize, y: usize| {
+        let idx = (y * width + x) * 4;
+        (bgra[idx + 2] as i32, bgra[idx + 1] as i32, bgra[idx] as i32)
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = pixel(x, y);
+            store(y_plane, y * width + x, coeffs.luma(r, g, b), format);
+        }
+    }
+    for cy in 0..height.div_ceil(2) {
+        for cx in 0..chroma_width {
+            let (mut r, mut g, mut b, mut count) = (0, 0, 0, 0);
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    let (pr, pg, pb) = pixel(x, y);
+                    r += pr;
+                    g += pg;
+                    b += pb;
+                    count += 1;
+                }
+            }
+            let u = coeffs.chroma(coeffs.u, r, g, b, count);
+            let v = coeffs.chroma(coeffs.v, r, g, b, count);
+            let index = cy * chroma_width + cx;
+            match format {
+                PixelFormat::I420 => {
+                    store(chroma, index, u, format);
+                    store(chroma, chroma_samples + index, v, format);
+                }
+                _ => {
+                    store(chroma, index * 2, u, format);
+                    store(chroma, index * 2 + 1, v, format);
+                }
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_SPACES: [ColorSpace; 6] = [
+        ColorSpace {
+            matrix: ColorMatrix::Bt601,
+            range: ColorRange::Limited,
+        },
+        ColorSpace {
+            matrix: ColorMatrix::Bt601,
+            range: ColorRange::Full,
+        },
+        ColorSpace {
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Limited,
+        },
+        ColorSpace {
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Full,
+        },
+        ColorSpace {
+            matrix: ColorMatrix::Bt2020,
+            range: ColorRange::Limited,
+        },
+        ColorSpace {
+            matrix: ColorMatrix::Bt2020,
+            range: ColorRange::Full,
+        },
+    ];
+
+    fn noise(width: usize, height: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..width * height * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn solid(width: usize, height: usize, [r, g, b]: [u8; 3]) -> Vec<u8> {
+        [b, g, r, 0xff].repeat(width * height)
+    }
+
+    #[test]
+    fn parallel_matches_scalar_reference() {
+        for (width, height) in [(2, 2), (6, 4), (64, 130), (97, 301)] {
+            let frame = noise(width, height);
+            for space in ALL_SPACES {
+                for format in [PixelFormat::Nv12, PixelFormat::I420, PixelFormat::P010] {
+                    assert_eq!(
+                        convert(&frame, width, height, space, format),
+                        convert_scalar(&frame, width, height, space, format),
+                        "{width}x{height} {space:?} {format:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn maps_black_and_white_to_range_limits() {
+        let limited = ColorSpace::default();
+        let full = ColorSpace {
+            range: ColorRange::Full,
+            ..limited
+        };
+        let white = solid(2, 2, [255, 255, 255]);
+        let black = solid(2, 2, [0, 0, 0]);
+        assert_eq!(
+            convert(&white, 2, 2, limited, PixelFormat::Nv12),
+            vec![235, 235, 235, 235, 128, 128]
+        );
+        assert_eq!(
+            convert(&black, 2, 2, limited, PixelFormat::Nv12),
+            vec![16, 16, 16, 16, 128, 128]
+        );
+        assert_eq!(convert(&white, 2, 2, full, PixelFormat::Nv12)[0], 255);
+        assert_eq!(convert(&black, 2, 2, full, PixelFormat::Nv12)[0], 0);
+
+        let p010 = convert(&white, 2, 2, limited, PixelFormat::P010);
+        assert_eq!(u16::from_le_bytes([p010[0], p010[1]]), 940 << 6);
+        assert_eq!(u16::from_le_bytes([p010[8], p010[9]]), 512 << 6);
+    }
+
+    #[test]
+    fn uses_matrix_specific_chroma() {
+        let red = solid(2, 2, [255, 0, 0]);
+        let bt601 = ColorSpace {
+            matrix: ColorMatrix::Bt601,
+            range: ColorRange::Limited,
+        };
+        let bt709 = ColorSpace::default();
+        // BT.601 red: Y 81, Cb 90, Cr 240; BT.709 red: Y 63, Cb 102, Cr 240.
+        assert_eq!(
+            convert(&red, 2, 2, bt601, PixelFormat::I420),
+            vec![81, 81, 81, 81, 90, 240]
+        );
+        assert_eq!(
+            convert(&red, 2, 2, bt709, PixelFormat::I420),
+            vec![63, 63, 63, 63, 102, 240]
+        );
+    }
+
+    #[test]
+    fn describes_color_with_h273_code_points() {
+        let description = ColorSpace::from_settings("BT.709", true).description();
+        assert_eq!(
+            description,
+            ColorDescription {
+                primaries: 1,
+                transfer: 1,
+                matrix: 1,
+                full_range: true
+            }
+        );
+        assert_eq!(
+            ColorSpace::from_settings("bt601", false)
+                .description()
+                .matrix,
+            5
+        );
+        assert_eq!(
+            ColorSpace::from_settings("unknown", false).matrix,
+            ColorMatrix::Bt709
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Finds clients on the LAN so nobody has to type an IP (§7.13). While enabled,
+//! one thread asks for `_uberdisplay._tcp` over mDNS every few seconds and
+//! another listens for broadcast beacons. Every device heard is kept with the
+//! time it was last heard; the UI polls the list, which shows each device next
+//! to the paired entry it matches.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::app_state::{ConnectCandidate, DiscoveredDevice, HostSettings, PairedDevice};
+use crate::device_registry;
+use crate::host_log;
+use crate::protocol::discovery::{
+    build_query, decode_beacon, parse_response, Advertisement, BEACON_PORT, MDNS_ADDR,
+};
+use crate::protocol::key_exchange::format_fingerprint;
+
+const QUERY_INTERVAL: Duration = Duration::from_secs(5);
+/// Socket read timeout, which is how often the threads check whether to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// A device not heard from for this long is dropped from the list.
+const EXPIRY_SECS: u64 = 30;
+const TRANSPORT_NAME: &str = "Wi-Fi";
+const SOURCE_MDNS: &str = "mDNS";
+const SOURCE_BEACON: &str = "Broadcast";
+
+static SERVICE: OnceLock<Mutex<Option<Service>>> = OnceLock::new();
+static SEEN: OnceLock<Seen> = OnceLock::new();
+
+fn service_store() -> &'static Mutex<Option<Service>> {
+    SERVICE.get_or_init(|| Mutex::new(None))
+}
+
+fn seen_store() -> &'static Seen {
+    SEEN.get_or_init(Seen::default)
+}
+
+struct Service {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// Devices heard, by id.
+#[derive(Default)]
+struct Seen(Mutex<HashMap<String, Sighting>>);
+
+#[derive(Debug, Clone)]
+struct Sighting {
+    advertisement: Advertisement,
+    source: &'static str,
+    last_seen: u64,
+}
+
+impl Seen {
+    fn record(&self, advertisement: Advertisement, source: &'static str) {
+        if let Ok(mut guard) = self.0.lock() {
+            guard.insert(
+                advertisement.id.clone(),
+                Sighting {
+                    advertisement,
+                    source,
+                    last_seen: unix_now(),
+                },
+            );
+        }
+    }
+
+    /// Current sightings, forgetting the ones that expired.
+    fn current(&self, now: u64) -> Vec<Sighting> {
+        let Ok(mut guard) = self.0.lock() else {
+            return Vec::new();
+        };
+        guard.retain(|_, sighting| now.saturating_sub(sighting.last_seen) < EXPIRY_SECS);
+        guard.values().cloned().collect()
+    }
+
+    fn clear(&self) {
+        if let Ok(mut guard) = self.0.lock() {
+            guard.clear();
+        }
+    }
+}
+
+/// Starts or stops discovery to match `settings`.
+pub fn apply(app_handle: &tauri::AppHandle, settings: &HostSettings) -> Result<(), String> {
+    let mut lock = service_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    if lock.is_some() == settings.discovery_enabled {
+        return Ok(());
+    }
+    if let Some(service) = lock.take() {
+        service.stop.store(true, Ordering::SeqCst);
+        for thread in service.threads {
+            let _ = thread.join();
+        }
+        seen_store().clear();
+        let _ = host_log::append_log(app_handle, "Stopped looking for devices on the LAN");
+        return Ok(());
+    }
+
+    let beacons = open_socket((Ipv4Addr::UNSPECIFIED, BEACON_PORT).into()).map_err(|err| {
+        format!("Cannot listen for discovery beacons on port {BEACON_PORT}: {err}")
+    })?;
+    let queries = open_socket((Ipv4Addr::UNSPECIFIED, 0).into())
+        .map_err(|err| format!("Cannot open the mDNS socket: {err}"))?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let threads = vec![
+        {
+            let stop = stop.clone();
+            thread::spawn(move || listen(&beacons, seen_store(), &stop))
+        },
+        {
+            let stop = stop.clone();
+            thread::spawn(move || browse(&queries, MDNS_ADDR, seen_store(), &stop))
+        },
+    ];
+    *lock = Some(Service { stop, threads });
+    let _ = host_log::append_log(app_handle, "Looking for devices on the LAN");
+    Ok(())
+}
+
+/// Devices heard recently, each next to the paired device whose pinned key it
+/// announced. Only reads the registry: a sighting is unauthenticated, so a
+/// paired device's address changes only after a handshake proves its key.
+pub fn discovered(app_handle: &tauri::AppHandle) -> Result<Vec<DiscoveredDevice>, String> {
+    let sightings = seen_store().current(unix_now());
+    if sightings.is_empty() {
+        return Ok(Vec::new());
+    }
+    let devices = device_registry::load_devices(app_handle);
+    Ok(merge(sightings, &devices))
+}
+
+fn merge(sightings: Vec<Sighting>, devices: &[PairedDevice]) -> Vec<DiscoveredDevice> {
+    let mut discovered: Vec<DiscoveredDevice> = sightings
+        .into_iter()
+        .map(|sighting| {
+            let advertisement = sighting.advertisement;
+            let fingerprint = advertisement.fingerprint.as_ref().map(format_fingerprint);
+            let paired = fingerprint.as_ref().and_then(|fingerprint| {
+                devices.iter().find(|device| {
+                    device
+                        .peer_fingerprint
+                        .as_deref()
+                        .is_some_and(|pinned| pinned.eq_ignore_ascii_case(fingerprint))
+                })
+            });
+            DiscoveredDevice {
+                candidate: ConnectCandidate {
+                    id: advertisement.id,
+                    name: advertisement.name,
+                    transport: TRANSPORT_NAME.to_string(),
+                    address: advertisement.address.map(|address| address.to_string()),
+                    port: Some(advertisement.port),
+                },
+                protocol_version: advertisement.protocol_version,
+                fingerprint,
+                source: sighting.source.to_string(),
+                last_seen: sighting.last_seen,
+                paired_id: paired.map(|device| device.id.clone()),
+                paired_name: paired.map(|device| device.name.clone()),
+                paired_address: paired.and_then(|device| device.address.clone()),
+            }
+        })
+        .collect();
+    discovered.sort_by(|a, b| a.candidate.name.cmp(&b.candidate.name));
+    discovered
+}
+
+fn open_socket(address: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(address)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+/// Records beacons until `stop`; a beacon's address is where it came from.
+fn listen(socket: &UdpSocket, seen: &Seen, stop: &AtomicBool) {
+    let mut buf = [0u8; 1024];
+    while !stop.load(Ordering::SeqCst) {
+        let Ok((len, from)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        if let Ok(mut advertisement) = decode_beacon(&buf[..len]) {
+            advertisement.address = Some(from.ip());
+            seen.record(advertisement, SOURCE_BEACON);
+        }
+    }
+}
+
+/// Queries `target` every `QUERY_INTERVAL` and records the answers until `stop`.
+fn browse(socket: &UdpSocket, target: SocketAddr, seen: &Seen, stop: &AtomicBool) {
+    let query = build_query(0);
+    let mut last_query: Option<Instant> = None;
+    let mut buf = [0u8; 9000];
+    while !stop.load(Ordering::SeqCst) {
+        if last_query.is_none_or(|sent| sent.elapsed() >= QUERY_INTERVAL) {
+            // No route (no network yet) is retried with the next query.
+            let _ = socket.send_to(&query, target);
+            last_query = Some(Instant::now());
+        }
+        let Ok((len, from)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        for mut advertisement in parse_response(&buf[..len]).unwrap_or_default() {
+            advertisement.address.get_or_insert(from.ip());
+            seen.record(advertisement, SOURCE_MDNS);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::discovery::{build_response, encode_beacon, is_service_query};
+    use std::net::IpAddr;
+
+    fn tablet() -> Advertisement {
+        Advertisement {
+            id: "a1b2c3".to_string(),
+            name: "Tablet".to_string(),
+            port: 1445,
+            protocol_version: 4,
+            fingerprint: Some([7; 32]),
+            address: None,
+        }
+    }
+
+    fn local_socket() -> UdpSocket {
+        open_socket((Ipv4Addr::LOCALHOST, 0).into()).unwrap()
+    }
+
+    /// Runs `body` with a thread doing `work` until `body` returns.
+    fn while_running(work: impl FnOnce(&AtomicBool) + Send + 'static, body: impl FnOnce()) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let stop = stop.clone();
+            thread::spawn(move || work(&stop))
+        };
+        body();
+        stop.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+    }
+
+    fn wait_for(seen: &Seen) -> Sighting {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            if let Some(sighting) = seen.current(unix_now()).pop() {
+                return sighting;
+            }
+            assert!(Instant::now() < deadline, "nothing discovered");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn browses_an_mdns_responder() {
+        // The responder answers like a phone would, without an address record.
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        responder
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let target = responder.local_addr().unwrap();
+        let seen = Arc::new(Seen::default());
+        let browser = local_socket();
+        let browsing = seen.clone();
+        while_running(
+            move |stop| browse(&browser, target, &browsing, stop),
+            || {
+                let mut buf = [0u8; 512];
+                let (len, from) = responder.recv_from(&mut buf).unwrap();
+                assert!(is_service_query(&buf[..len]));
+                responder
+                    .send_to(&build_response(0, &tablet()), from)
+                    .unwrap();
+                let sighting = wait_for(&seen);
+                assert_eq!(sighting.source, SOURCE_MDNS);
+                assert_eq!(sighting.advertisement.id, "a1b2c3");
+                assert_eq!(
+                    sighting.advertisement.address,
+                    Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn hears_broadcast_beacons() {
+        let listener = local_socket();
+        let target = listener.local_addr().unwrap();
+        let seen = Arc::new(Seen::default());
+        let listening = seen.clone();
+        while_running(
+            move |stop| listen(&listener, &listening, stop),
+            || {
+                let phone = local_socket();
+                phone.send_to(b"not a beacon", target).unwrap();
+                phone.send_to(&encode_beacon(&tablet()), target).unwrap();
+                let sighting = wait_for(&seen);
+                assert_eq!(sighting.source, SOURCE_BEACON);
+                assert_eq!(sighting.advertisement.name, "Tablet");
+                assert_eq!(sighting.advertisement.fingerprint, Some([7; 32]));
+            },
+        );
+
+        // Long silent devices drop off the list.
+        assert!(seen.current(unix_now() + EXPIRY_SECS).is_empty());
+    }
+
+    #[test]
+    fn merges_with_paired_devices() {
+        let sighting = |advertisement: Advertisement| Sighting {
+            advertisement: Advertisement {
+                address: Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 9))),
+                ..advertisement
+            },
+            source: SOURCE_BEACON,
+            last_seen: 100,
+        };
+        let stranger = Advertisement {
+            id: "ffee".to_string(),
+            name: "Another phone".to_string(),
+            fingerprint: Some([1; 32]),
+            ..tablet()
+        };
+        let devices = vec![PairedDevice {
+            id: "paired-1".to_string(),
+            name: "Kitchen tablet".to_string(),
+            transport: TRANSPORT_NAME.to_string(),
+            status: "Paired".to_string(),
+            last_seen: None,
+            input_permissions: Default::default(),
+            peer_fingerprint: Some(format_fingerprint(&[7; 32]).to_uppercase()),
+            address: Some("192.168.1.4".to_string()),
+            port: Some(1445),
+        }];
+        // Reusing a paired id without its key is not enough to match.
+        let impostor = Advertisement {
+            id: "paired-1".to_string(),
+            name: "Impostor".to_string(),
+            fingerprint: None,
+            ..tablet()
+        };
+
+        let discovered = merge(
+            vec![sighting(tablet()), sighting(stranger), sighting(impostor)],
+            &devices,
+        );
+        assert_eq!(discovered.len(), 3);
+        assert_eq!(discovered[0].candidate.name, "Another phone");
+        assert_eq!(discovered[0].paired_id, None);
+        assert_eq!(discovered[1].candidate.name, "Impostor");
+        assert_eq!(discovered[1].paired_id, None);
+        // The sighting is shown as heard, next to the paired entry.
+        assert_eq!(discovered[2].candidate.name, "Tablet");
+        assert_eq!(
+            discovered[2].candidate.address.as_deref(),
+            Some("192.168.1.9")
+        );
+        assert_eq!(discovered[2].paired_id.as_deref(), Some("paired-1"));
+        assert_eq!(discovered[2].paired_name.as_deref(), Some("Kitchen tablet"));
+        assert_eq!(discovered[2].paired_address.as_deref(), Some("192.168.1.4"));
+        // The registry is left alone.
+        assert_eq!(devices[0].address.as_deref(), Some("192.168.1.4"));
+    }
+}
@@ -168,7 +168,6 @@ fn to_wide_bytes(value: &str) -> Vec<u8> {
     bytes
 }
 
-#[cfg(windows)]
 fn decode_response(bytes: &[u8]) -> String {
     if bytes.len() >= 2 && bytes.len() % 2 == 0 {
         let utf16: Vec<u16> = bytes
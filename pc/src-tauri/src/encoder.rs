@@ -1,8 +1,19 @@
 #![allow(dead_code)]
 
-use crate::codec::CodecId;
+use std::sync::OnceLock;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use serde::Serialize;
+
+use crate::capture::CaptureTarget;
+use crate::codec::{self, CodecId};
+use crate::codec_profile::CodecParameters;
+use crate::color::ColorSpace;
+use crate::encoder_probe::SystemProbe;
+use crate::mf_encoder::MfEncoder;
+use crate::scaler::ScaleMode;
+use crate::sw_encoder::SoftwareEncoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum EncoderBackend {
     Nvenc,
     Amf,
@@ -11,30 +22,141 @@ pub enum EncoderBackend {
     Software,
 }
 
+/// Common interface for the per-backend encoders driven by `stream_loop`.
+/// Each call captures the current target and returns one Annex-B access unit.
+pub trait VideoEncoder {
+    fn encode_frame(&mut self) -> (Vec<u8>, Option<u64>);
+    fn take_last_error(&mut self) -> Option<String>;
+    /// Makes the next encoded frame an IDR, for clients recovering from loss.
+    fn request_keyframe(&mut self);
+    /// Retargets the rate control, for the congestion controller.
+    fn set_bitrate(&mut self, bitrate_kbps: u32);
+}
+
+/// What an encoder is opened with: the stream it produces and where it captures from.
 #[derive(Debug, Clone)]
+pub struct EncoderConfig {
+    pub codec_id: CodecId,
+    /// Profile, level and flags the client accepted in `Configure`; zero fields
+    /// leave the choice to the encoder.
+    pub params: CodecParameters,
+    pub width: i32,
+    pub height: i32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+    /// Frames between scheduled IDRs; 0 leaves them to the encoder.
+    pub keyframe_interval: u32,
+    pub capture_target: CaptureTarget,
+    pub color: ColorSpace,
+    pub scale: ScaleMode,
+}
+
+/// One cell of the capability matrix: a codec a backend can encode, with its limits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct EncoderCapability {
     pub codec: CodecId,
-    pub backends: Vec<EncoderBackend>,
+    pub backend: EncoderBackend,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_fps: u32,
 }
 
-pub fn detect_backends() -> Vec<EncoderBackend> {
-    #[cfg(windows)]
-    {
-        // TODO: replace stubs with real detection (DXGI/NVENC/AMF/QSV/MediaFoundation).
-        vec![
-            EncoderBackend::Nvenc,
-            EncoderBackend::Amf,
-            EncoderBackend::Qsv,
-            EncoderBackend::MediaFoundation,
-            EncoderBackend::Software,
-        ]
+/// Source of capability entries. The system probe is used at runtime; tests supply
+/// a fixed table instead.
+pub trait CapabilityProbe {
+    fn probe(&self) -> Vec<EncoderCapability>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityMatrix {
+    entries: Vec<EncoderCapability>,
+}
+
+impl CapabilityMatrix {
+    pub fn detect(probe: &dyn CapabilityProbe) -> Self {
+        let mut entries: Vec<EncoderCapability> = Vec::new();
+        for entry in probe.probe() {
+            if can_encode(entry.codec, entry.backend)
+                && !entries
+                    .iter()
+                    .any(|known| known.codec == entry.codec && known.backend == entry.backend)
+            {
+                entries.push(entry);
+            }
+        }
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[EncoderCapability] {
+        &self.entries
     }
-    #[cfg(not(windows))]
-    {
-        vec![EncoderBackend::Software]
+
+    pub fn codec_mask(&self) -> u32 {
+        self.entries
+            .iter()
+            .fold(0, |mask, entry| mask | codec::codec_mask(entry.codec))
+    }
+
+    /// Backends with at least one codec, in `backend_priority` order.
+    pub fn backends(&self) -> Vec<EncoderBackend> {
+        backend_priority()
+            .into_iter()
+            .filter(|backend| self.entries.iter().any(|entry| entry.backend == *backend))
+            .collect()
+    }
+
+    pub fn backends_for(&self, codec_id: CodecId) -> Vec<EncoderBackend> {
+        backend_priority()
+            .into_iter()
+            .filter(|backend| self.supports(codec_id, *backend))
+            .collect()
+    }
+
+    pub fn supports(&self, codec_id: CodecId, backend: EncoderBackend) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.codec == codec_id && entry.backend == backend)
+    }
+
+    pub fn fits(
+        &self,
+        codec_id: CodecId,
+        backend: EncoderBackend,
+        width: u32,
+        height: u32,
+        fps: u32,
+    ) -> bool {
+        self.entries.iter().any(|entry| {
+            entry.codec == codec_id
+                && entry.backend == backend
+                && width <= entry.max_width
+                && height <= entry.max_height
+                && fps <= entry.max_fps
+        })
     }
 }
 
+/// Codecs `create_encoder` can open on `backend`. Probed entries outside this are
+/// dropped, so the host never advertises a codec it would fail to encode.
+fn can_encode(codec_id: CodecId, backend: EncoderBackend) -> bool {
+    match backend {
+        EncoderBackend::Software => codec_id == CodecId::H264,
+        _ => matches!(codec_id, CodecId::H264 | CodecId::H265),
+    }
+}
+
+static CAPABILITIES: OnceLock<CapabilityMatrix> = OnceLock::new();
+
+/// Host capability matrix, probed once on first use.
+pub fn capabilities() -> &'static CapabilityMatrix {
+    CAPABILITIES.get_or_init(|| CapabilityMatrix::detect(&SystemProbe))
+}
+
+pub fn detect_backends() -> Vec<EncoderBackend> {
+    capabilities().backends()
+}
+
 pub fn backend_priority() -> Vec<EncoderBackend> {
     vec![
         EncoderBackend::Nvenc,
@@ -46,7 +168,21 @@ pub fn backend_priority() -> Vec<EncoderBackend> {
 }
 
 pub fn select_backend(preferred: Option<EncoderBackend>) -> EncoderBackend {
-    let available = detect_backends();
+    pick_backend(detect_backends(), preferred)
+}
+
+/// Like `select_backend`, but only considers backends that can encode `codec_id`.
+pub fn select_backend_for_codec(
+    codec_id: CodecId,
+    preferred: Option<EncoderBackend>,
+) -> EncoderBackend {
+    pick_backend(capabilities().backends_for(codec_id), preferred)
+}
+
+fn pick_backend(
+    available: Vec<EncoderBackend>,
+    preferred: Option<EncoderBackend>,
+) -> EncoderBackend {
     if let Some(choice) = preferred {
         if available.contains(&choice) {
             return choice;
@@ -59,3 +195,101 @@ pub fn select_backend(preferred: Option<EncoderBackend>) -> EncoderBackend {
     }
     EncoderBackend::Software
 }
+
+pub fn create_encoder(
+    backend: EncoderBackend,
+    config: EncoderConfig,
+) -> Result<Box<dyn VideoEncoder>, String> {
+    match backend {
+        EncoderBackend::Software => Ok(Box::new(SoftwareEncoder::new(config)?)),
+        _ => Ok(Box::new(MfEncoder::new(config)?)),
+    }
+}
+
+pub fn estimate_timestamp_100ns(frame_index: u64, fps: u32) -> u64 {
+    let fps = fps.max(1) as u64;
+    frame_index.saturating_mul(10_000_000 / fps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder_probe::capability;
+
+    struct FakeProbe(Vec<EncoderCapability>);
+
+    impl CapabilityProbe for FakeProbe {
+        fn probe(&self) -> Vec<EncoderCapability> {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn derives_codec_mask_from_matrix() {
+        let matrix = CapabilityMatrix::detect(&FakeProbe(vec![
+            capability(CodecId::H264, EncoderBackend::Software),
+            capability(CodecId::H265, EncoderBackend::Nvenc),
+            capability(CodecId::H264, EncoderBackend::Nvenc),
+        ]));
+        assert_eq!(
+            matrix.codec_mask(),
+            codec::CODEC_MASK_H264 | codec::CODEC_MASK_H265
+        );
+        assert_eq!(
+            matrix.backends(),
+            vec![EncoderBackend::Nvenc, EncoderBackend::Software]
+        );
+        assert_eq!(
+            matrix.backends_for(CodecId::H265),
+            vec![EncoderBackend::Nvenc]
+        );
+        assert_eq!(
+            CapabilityMatrix::detect(&FakeProbe(Vec::new())).codec_mask(),
+            0
+        );
+    }
+
+    #[test]
+    fn drops_codecs_no_encoder_can_produce() {
+        let matrix = CapabilityMatrix::detect(&FakeProbe(vec![
+            capability(CodecId::Av1, EncoderBackend::Nvenc),
+            capability(CodecId::Vp9, EncoderBackend::MediaFoundation),
+            capability(CodecId::H265, EncoderBackend::Software),
+            capability(CodecId::H264, EncoderBackend::Qsv),
+        ]));
+        assert_eq!(matrix.codec_mask(), codec::CODEC_MASK_H264);
+        assert_eq!(matrix.backends(), vec![EncoderBackend::Qsv]);
+    }
+
+    #[test]
+    fn drops_duplicate_entries_and_checks_limits() {
+        let matrix = CapabilityMatrix::detect(&FakeProbe(vec![
+            EncoderCapability {
+                codec: CodecId::H264,
+                backend: EncoderBackend::Software,
+                max_width: 1920,
+                max_height: 1080,
+                max_fps: 30,
+            },
+            capability(CodecId::H264, EncoderBackend::Software),
+        ]));
+        assert_eq!(matrix.entries().len(), 1);
+        assert!(matrix.fits(CodecId::H264, EncoderBackend::Software, 1920, 1080, 30));
+        assert!(!matrix.fits(CodecId::H264, EncoderBackend::Software, 1920, 1080, 60));
+        assert!(!matrix.fits(CodecId::H265, EncoderBackend::Software, 640, 480, 30));
+    }
+
+    #[test]
+    fn picks_preferred_backend_when_available() {
+        let available = vec![EncoderBackend::Qsv, EncoderBackend::Software];
+        assert_eq!(
+            pick_backend(available.clone(), Some(EncoderBackend::Software)),
+            EncoderBackend::Software
+        );
+        assert_eq!(
+            pick_backend(available, Some(EncoderBackend::Nvenc)),
+            EncoderBackend::Qsv
+        );
+        assert_eq!(pick_backend(Vec::new(), None), EncoderBackend::Software);
+    }
+}
@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+
+use crate::codec::CodecId;
+use crate::encoder::{CapabilityProbe, EncoderBackend, EncoderCapability};
+
+#[cfg(windows)]
+use windows::core::{GUID, PWSTR};
+#[cfg(windows)]
+use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory1, IDXGIFactory1, DXGI_ADAPTER_DESC1};
+#[cfg(windows)]
+use windows::Win32::Media::MediaFoundation::{
+    IMFActivate, MFMediaType_Video, MFShutdown, MFStartup, MFTEnumEx,
+    MFT_ENUM_HARDWARE_VENDOR_ID_Attribute, MFVideoFormat_H264, MFVideoFormat_HEVC,
+    MFT_CATEGORY_VIDEO_ENCODER, MFT_ENUM_FLAG, MFT_ENUM_FLAG_HARDWARE, MFT_ENUM_FLAG_LOCALMFT,
+    MFT_ENUM_FLAG_SORTANDFILTER, MFT_ENUM_FLAG_SYNCMFT, MFT_REGISTER_TYPE_INFO, MF_VERSION,
+};
+#[cfg(windows)]
+use windows::Win32::System::Com::{
+    CoInitializeEx, CoTaskMemFree, CoUninitialize, COINIT_MULTITHREADED,
+};
+
+const VENDOR_NVIDIA: u32 = 0x10de;
+const VENDOR_AMD: u32 = 0x1002;
+const VENDOR_INTEL: u32 = 0x8086;
+
+/// Probes the running system: Media Foundation encoder MFTs per codec, GPU adapter
+/// vendors for the hardware backends, and the bundled OpenH264 software encoder.
+pub struct SystemProbe;
+
+impl CapabilityProbe for SystemProbe {
+    fn probe(&self) -> Vec<EncoderCapability> {
+        let mut capabilities = probe_media_foundation();
+        capabilities.push(software_capability(CodecId::H264));
+        capabilities
+    }
+}
+
+pub fn backend_for_vendor(vendor_id: u32) -> Option<EncoderBackend> {
+    match vendor_id {
+        VENDOR_NVIDIA => Some(EncoderBackend::Nvenc),
+        VENDOR_AMD => Some(EncoderBackend::Amf),
+        VENDOR_INTEL => Some(EncoderBackend::Qsv),
+        _ => None,
+    }
+}
+
+/// Parses the `VEN_xxxx` string Media Foundation attaches to hardware MFTs.
+pub fn parse_vendor_id(value: &str) -> Option<u32> {
+    let hex = value.trim().strip_prefix("VEN_")?;
+    u32::from_str_radix(hex.get(..4)?, 16).ok()
+}
+
+/// Conservative per-backend limits; none of the backends expose these through
+/// the enumeration APIs without creating a full encoder session.
+pub fn default_limits(codec: CodecId, backend: EncoderBackend) -> (u32, u32, u32) {
+    match (backend, codec) {
+        (EncoderBackend::Software, _) => (3840, 2160, 60),
+        (EncoderBackend::MediaFoundation, CodecId::H264) => (4096, 2304, 60),
+        (EncoderBackend::MediaFoundation, _) => (3840, 2160, 60),
+        (EncoderBackend::Nvenc, CodecId::H264) => (4096, 4096, 240),
+        (EncoderBackend::Nvenc, _) => (8192, 8192, 240),
+        (EncoderBackend::Amf, CodecId::H264) => (4096, 2176, 240),
+        (EncoderBackend::Amf, _) => (7680, 4320, 240),
+        (EncoderBackend::Qsv, CodecId::H264) => (4096, 4096, 240),
+        (EncoderBackend::Qsv, _) => (8192, 8192, 240),
+    }
+}
+
+pub fn capability(codec: CodecId, backend: EncoderBackend) -> EncoderCapability {
+    let (max_width, max_height, max_fps) = default_limits(codec, backend);
+    EncoderCapability {
+        codec,
+        backend,
+        max_width,
+        max_height,
+        max_fps,
+    }
+}
+
+fn software_capability(codec: CodecId) -> EncoderCapability {
+    capability(codec, EncoderBackend::Software)
+}
+
+#[cfg(windows)]
+fn probe_media_foundation() -> Vec<EncoderCapability> {
+    let com_initialized = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }.is_ok();
+    if unsafe { MFStartup(MF_VERSION, 0) }.is_err() {
+        if com_initialized {
+            unsafe { CoUninitialize() };
+        }
+        return Vec::new();
+    }
+
+    let adapter_backends: Vec<EncoderBackend> = adapter_vendor_ids()
+        .into_iter()
+        .filter_map(backend_for_vendor)
+        .collect();
+    let mut capabilities = Vec::new();
+    // `MfEncoder` only sets up H.264 and HEVC output types; AV1 and VP9 MFTs are
+    // not probed until it can drive them.
+    let codecs = [
+        (CodecId::H264, MFVideoFormat_H264),
+        (CodecId::H265, MFVideoFormat_HEVC),
+    ];
+    for (codec, subtype) in codecs {
+        let hardware_flags = MFT_ENUM_FLAG_HARDWARE | MFT_ENUM_FLAG_SORTANDFILTER;
+        for vendor in enumerate_encoder_vendors(subtype, hardware_flags) {
+            let Some(backend) = vendor.and_then(backend_for_vendor) else {
+                continue;
+            };
+            if adapter_backends.contains(&backend)
+                && !capabilities.iter().any(|entry: &EncoderCapability| {
+                    entry.codec == codec && entry.backend == backend
+                })
+            {
+                capabilities.push(capability(codec, backend));
+            }
+        }
+        let software_flags =
+            MFT_ENUM_FLAG_SYNCMFT | MFT_ENUM_FLAG_LOCALMFT | MFT_ENUM_FLAG_SORTANDFILTER;
+        if !enumerate_encoder_vendors(subtype, software_flags).is_empty() {
+            capabilities.push(capability(codec, EncoderBackend::MediaFoundation));
+        }
+    }
+
+    unsafe {
+        let _ = MFShutdown();
+        if com_initialized {
+            CoUninitialize();
+        }
+    }
+    capabilities
+}
+
+#[cfg(not(windows))]
+fn probe_media_foundation() -> Vec<EncoderCapability> {
+    Vec::new()
+}
+
+/// Returns one entry per matching encoder MFT, carrying its hardware vendor id if any.
+#[cfg(windows)]
+fn enumerate_encoder_vendors(subtype: GUID, flags: MFT_ENUM_FLAG) -> Vec<Option<u32>> {
+    let output_type = MFT_REGISTER_TYPE_INFO {
+        guidMajorType: MFMediaType_Video,
+        guidSubtype: subtype,
+    };
+    let mut activate: *mut Option<IMFActivate> = std::ptr::null_mut();
+    let mut count = 0u32;
+    let result = unsafe {
+        MFTEnumEx(
+            MFT_CATEGORY_VIDEO_ENCODER,
+            flags,
+            None,
+            Some(&output_type),
+            &mut activate,
+            &mut count,
+        )
+    };
+    if result.is_err() || count == 0 || activate.is_null() {
+        return Vec::new();
+    }
+
+    let mut vendors = Vec::with_capacity(count as usize);
+    unsafe {
+        let slice = std::slice::from_raw_parts_mut(activate, count as usize);
+        for item in slice.iter_mut() {
+            if let Some(activate_item) = item.take() {
+                vendors.push(read_vendor_id(&activate_item));
+            }
+        }
+        CoTaskMemFree(Some(activate as *const _));
+    }
+    vendors
+}
+
+#[cfg(windows)]
+fn read_vendor_id(activate: &IMFActivate) -> Option<u32> {
+    let mut value = PWSTR::null();
+    let mut length = 0u32;
+    unsafe {
+        activate
+            .GetAllocatedString(
+                &MFT_ENUM_HARDWARE_VENDOR_ID_Attribute,
+                &mut value,
+                &mut length,
+            )
+            .ok()?;
+        let text = value.to_string().ok();
+        CoTaskMemFree(Some(value.0 as *const _));
+        parse_vendor_id(&text?)
+    }
+}
+
+#[cfg(windows)]
+fn adapter_vendor_ids() -> Vec<u32> {
+    let Ok(factory) = (unsafe { CreateDXGIFactory1::<IDXGIFactory1>() }) else {
+        return Vec::new();
+    };
+    let mut vendors = Vec::new();
+    let mut index = 0u32;
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(index) } {
+        let mut desc = DXGI_ADAPTER_DESC1::default();
+        if unsafe { adapter.GetDesc1(&mut desc) }.is_ok() && !vendors.contains(&desc.VendorId) {
+            vendors.push(desc.VendorId);
+        }
+        index = index.saturating_add(1);
+    }
+    vendors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_hardware_vendor_strings_to_backends() {
+        assert_eq!(parse_vendor_id("VEN_10DE"), Some(VENDOR_NVIDIA));
+        assert_eq!(parse_vendor_id("VEN_8086&DEV_1234"), Some(VENDOR_INTEL));
+        assert_eq!(parse_vendor_id("10DE"), None);
+        assert_eq!(backend_for_vendor(VENDOR_AMD), Some(EncoderBackend::Amf));
+        assert_eq!(backend_for_vendor(0x1414), None);
+    }
+}
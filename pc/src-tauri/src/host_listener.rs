@@ -0,0 +1,407 @@
+//! Accepts sessions from clients that dial the host, for PC receivers and for
+//! phones the host cannot reach (behind hotspot NAT).
+//!
+//! Incoming clients go through the same authorization and handshake as the ones
+//! the host dials. By default one client streams at a time and the rest are
+//! turned away with an `Error` packet; with more sessions allowed, later clients
+//! mirror the running stream.
+
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::app_state::{HostSettings, SessionLifecycle};
+use crate::authorization;
+use crate::codec;
+use crate::host_log;
+use crate::host_transport::{HostTransport, TransportHandle};
+use crate::pairing;
+use crate::protocol::key_exchange::format_fingerprint;
+use crate::protocol::packets::{
+    build_error_packet, CapabilitiesPacket, ERROR_CODEC_MISMATCH, ERROR_HOST_BUSY,
+    ERROR_NOT_AUTHORIZED,
+};
+use crate::protocol::secure::ChannelSecurity;
+use crate::protocol::transport::HandshakeStream;
+use crate::session;
+use crate::session_state;
+use crate::settings_registry;
+
+pub const DEFAULT_PORT: u16 = 1445;
+
+/// How often the accept loop checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Clients in authorization and handshake at once; each holds a thread for up
+/// to the negotiation timeout.
+const MAX_HANDSHAKES: usize = 8;
+/// Stream size for clients that dial in, lowered to what their decoder reports.
+const DEFAULT_SIZE: (u16, u16) = (1920, 1080);
+const ENCODER_ID: i32 = 1;
+
+static LISTENER: OnceLock<Mutex<Option<Listener>>> = OnceLock::new();
+
+fn listener_store() -> &'static Mutex<Option<Listener>> {
+    LISTENER.get_or_init(|| Mutex::new(None))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub max_sessions: usize,
+}
+
+impl ListenerConfig {
+    /// `None` when listening is turned off.
+    pub fn from_settings(settings: &HostSettings) -> Option<Self> {
+        settings.listen_enabled.then(|| Self {
+            bind_address: settings.listen_address().to_string(),
+            port: settings.listen_port(),
+            max_sessions: settings.max_sessions(),
+        })
+    }
+}
+
+struct Listener {
+    address: SocketAddr,
+    config: ListenerConfig,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Listener {
+    fn shut_down(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+}
+
+/// Starts, restarts or stops the listener to match `settings`. Running sessions
+/// are left alone.
+pub fn apply(
+    app_handle: &tauri::AppHandle,
+    transport: &TransportHandle,
+    settings: &HostSettings,
+) -> Result<(), String> {
+    let wanted = ListenerConfig::from_settings(settings);
+    let mut lock = listener_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    if lock.as_ref().map(|listener| &listener.config) == wanted.as_ref() {
+        return Ok(());
+    }
+    if let Some(previous) = lock.take() {
+        let address = previous.address;
+        previous.shut_down();
+        let _ = host_log::append_log(app_handle, format!("Stopped listening on {address}"));
+    }
+    let Some(config) = wanted else {
+        return Ok(());
+    };
+    let listener = start(app_handle.clone(), transport.clone(), config)?;
+    let _ = host_log::append_log(
+        app_handle,
+        format!(
+            "Listening for clients on {} (up to {} at once)",
+            listener.address, listener.config.max_sessions
+        ),
+    );
+    *lock = Some(listener);
+    Ok(())
+}
+
+/// The address the listener is bound to, if it is running.
+pub fn status() -> Option<SocketAddr> {
+    listener_store()
+        .lock()
+        .ok()
+        .and_then(|guard| guard.as_ref().map(|listener| listener.address))
+}
+
+fn start(
+    app_handle: tauri::AppHandle,
+    transport: TransportHandle,
+    config: ListenerConfig,
+) -> Result<Listener, String> {
+    let socket = TcpListener::bind((config.bind_address.as_str(), config.port)).map_err(|err| {
+        format!(
+            "Cannot listen on {}:{}: {err}",
+            config.bind_address, config.port
+        )
+    })?;
+    socket
+        .set_nonblocking(true)
+        .map_err(|err| err.to_string())?;
+    let address = socket.local_addr().map_err(|err| err.to_string())?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        let config = config.clone();
+        thread::spawn(move || accept_loop(&app_handle, &transport, &socket, &config, &stop))
+    };
+    Ok(Listener {
+        address,
+        config,
+        stop,
+        thread,
+    })
+}
+
+/// Each client gets its own thread, so a slow handshake holds up nobody else; at
+/// most `MAX_HANDSHAKES` run at once and further clients are turned away.
+fn accept_loop(
+    app_handle: &tauri::AppHandle,
+    transport: &TransportHandle,
+    socket: &TcpListener,
+    config: &ListenerConfig,
+    stop: &AtomicBool,
+) {
+    let handshakes = Arc::new(AtomicUsize::new(0));
+    while !stop.load(Ordering::SeqCst) {
+        match socket.accept() {
+            Ok((stream, peer)) => {
+                if handshakes.fetch_add(1, Ordering::SeqCst) >= MAX_HANDSHAKES {
+                    handshakes.fetch_sub(1, Ordering::SeqCst);
+                    log(
+                        app_handle,
+                        format!("refused {peer}: too many connections in progress"),
+                    );
+                    let _ = stream.set_nonblocking(false);
+                    let _ = transport.refuse(
+                        stream,
+                        ERROR_HOST_BUSY,
+                        "The host is busy accepting other devices; try again",
+                    );
+                    continue;
+                }
+                let app_handle = app_handle.clone();
+                let transport = transport.clone();
+                let config = config.clone();
+                let handshakes = handshakes.clone();
+                thread::spawn(move || {
+                    handle_client(&app_handle, &transport, &config, stream, peer);
+                    handshakes.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+fn handle_client(
+    app_handle: &tauri::AppHandle,
+    transport: &TransportHandle,
+    config: &ListenerConfig,
+    stream: TcpStream,
+    peer: SocketAddr,
+) {
+    // Accepted sockets inherit non-blocking mode on some platforms.
+    if stream.set_nonblocking(false).is_err() || stream.set_nodelay(true).is_err() {
+        return;
+    }
+    let sessions = transport.session_count();
+    if sessions >= config.max_sessions {
+        log(
+            app_handle,
+            format!("refused {peer}: already streaming to {sessions} device(s)"),
+        );
+        let _ = transport.refuse(
+            stream,
+            ERROR_HOST_BUSY,
+            "The host is already streaming to another device",
+        );
+        return;
+    }
+    let settings = settings_registry::load_settings(app_handle);
+    if let Err(err) = authorization::authorize_incoming(app_handle, &settings, peer) {
+        let _ = transport.refuse(stream, ERROR_NOT_AUTHORIZED, &err);
+        return;
+    }
+    let address = peer.ip().to_canonical();
+    if let Err(err) = open_session(
+        app_handle,
+        transport,
+        &settings,
+        stream,
+        &peer,
+        Some(address),
+    ) {
+        log(app_handle, format!("session from {peer} failed: {err}"));
+        authorization::handshake_failed(app_handle, transport, &address.to_string(), 0, &err);
+    }
+}
+
+/// Runs the handshake on a client's stream and sets the session up next to the
+/// others. Also used for USB accessories, which skip the address checks above
+/// and have no `address` to record.
+pub fn open_session<S: HandshakeStream>(
+    app_handle: &tauri::AppHandle,
+    transport: &TransportHandle,
+    settings: &HostSettings,
+    stream: S,
+    peer: &dyn Display,
+    address: Option<IpAddr>,
+) -> Result<u64, String> {
+    let mut security = pairing::channel_security(app_handle, settings)?;
+    // A client dialing in is not the device selected in the UI; only pins count.
+    if let ChannelSecurity::PublicKey { trust, .. } = &mut security {
+        trust.expected = None;
+    }
+    let host_caps = CapabilitiesPacket {
+        codec_mask: codec::host_codec_mask(),
+        flags: 0,
+        decoder_limits: None,
+        channel_nonce: None,
+        public_key: None,
+    };
+    let first = transport.session_count() == 0;
+    if first {
+        session_state::update_lifecycle(SessionLifecycle::Connecting);
+    }
+    let (id, info) = match transport.accept(stream, &host_caps, &security, settings.rekey_policy())
+    {
+        Ok(accepted) => accepted,
+        Err(err) => {
+            if first {
+                session_state::update_lifecycle(SessionLifecycle::Error);
+            }
+            return Err(err);
+        }
+    };
+    if let (Some(address), Some(fingerprint)) = (
+        address,
+        info.as_ref().and_then(|info| info.peer_fingerprint),
+    ) {
+        let host = address.to_string();
+        if let Err(err) = pairing::record_address(app_handle, &fingerprint, &host, 0) {
+            log(
+                app_handle,
+                format!("unable to record {peer}'s address: {err}"),
+            );
+        }
+    }
+    let protection = match info {
+        Some(info) => {
+            let device = info
+                .peer_fingerprint
+                .map(|fingerprint| format!(", device {}", &format_fingerprint(&fingerprint)[..16]))
+                .unwrap_or_default();
+            format!(
+                "encrypted with {}{device}{}",
+                info.cipher.name(),
+                info.path_note()
+            )
+        }
+        None => "unencrypted (debug plaintext mode)".to_string(),
+    };
+    log(app_handle, format!("session from {peer}, {protection}"));
+
+    // Plaintext sessions send `Capabilities` after the handshake, if at all.
+    let client_mask = transport
+        .take_last_client_codec_mask()
+        .unwrap_or(codec::CODEC_MASK_H264);
+    let configured = if first {
+        configure(transport, settings, id, client_mask)
+    } else {
+        join(transport, id, client_mask)
+    };
+    if let Err(err) = configured {
+        transport.close_session(id);
+        if first {
+            session_state::update_lifecycle(SessionLifecycle::Error);
+        }
+        return Err(err);
+    }
+    Ok(id)
+}
+
+/// Negotiates the stream for the only session.
+fn configure(
+    transport: &HostTransport,
+    settings: &HostSettings,
+    id: u64,
+    client_mask: u32,
+) -> Result<(), String> {
+    let limits = transport.last_client_decoder_limits();
+    let (mut width, mut height) = DEFAULT_SIZE;
+    if let Some(limits) = limits.as_ref() {
+        if limits.max_width > 0 && limits.max_height > 0 {
+            width = width.min(limits.max_width);
+            height = height.min(limits.max_height);
+        }
+    }
+    let (width, height) = (i32::from(width), i32::from(height));
+    let result = session::prepare_session(session::SessionConfig {
+        width,
+        height,
+        host_width: width,
+        host_height: height,
+        encoder_id: ENCODER_ID,
+        client_codec_mask: client_mask,
+        preferred_codec: codec::codec_id_from_name(&settings.codec),
+        codec_opt_in_mask: settings.codec_opt_in_mask(),
+        fps: settings.refresh_cap_hz.max(1) as u32,
+        client_limits: limits,
+        color: settings.color_space(),
+    })?;
+    session::activate(&result, width, height, ENCODER_ID);
+    transport.send_framed_packet_to(id, &result.configure_bytes)?;
+    transport.set_current_configure(result.configure_bytes);
+    session_state::update_lifecycle(SessionLifecycle::Configured);
+    Ok(())
+}
+
+/// Hands a later session the running stream's `Configure` and asks the encoder
+/// for a keyframe, which is where it starts showing frames.
+fn join(transport: &HostTransport, id: u64, client_mask: u32) -> Result<(), String> {
+    if let Some(codec_id) = session_state::snapshot().codec_id {
+        if client_mask & codec::codec_mask(codec_id) == 0 {
+            let message = format!(
+                "The running stream uses {}, which this device cannot decode",
+                codec::codec_name(codec_id)
+            );
+            let _ = transport
+                .send_framed_packet_to(id, &build_error_packet(ERROR_CODEC_MISMATCH, &message));
+            return Err(message);
+        }
+    }
+    let configure = transport
+        .current_configure()
+        .ok_or_else(|| "The running session is not configured yet".to_string())?;
+    transport.send_framed_packet_to(id, &configure)?;
+    transport.request_keyframe();
+    Ok(())
+}
+
+fn log(app_handle: &tauri::AppHandle, message: String) {
+    let _ = host_log::append_log(app_handle, format!("Listener: {message}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_follows_settings() {
+        let mut settings = HostSettings::default();
+        assert_eq!(ListenerConfig::from_settings(&settings), None);
+
+        settings.listen_enabled = true;
+        settings.listen_address = String::new();
+        settings.listen_port = 0;
+        settings.max_sessions = 0;
+        assert_eq!(
+            ListenerConfig::from_settings(&settings),
+            Some(ListenerConfig {
+                bind_address: "0.0.0.0".to_string(),
+                port: DEFAULT_PORT,
+                max_sessions: 1,
+            })
+        );
+    }
+}
@@ -1,303 +1,969 @@
-use std::collections::VecDeque;
-use std::io::{Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Mutex, OnceLock};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+
+use crate::protocol::framing::StreamDecoder;
+use crate::protocol::handshake::{build_host_handshake, PROTOCOL_VERSION};
+use serde::Serialize;
 
-use crate::protocol::framing::write_stream_chunks;
-use crate::protocol::handshake::build_host_handshake;
-use crate::protocol::packets::{parse_client_packet, ClientPacket};
-use crate::session_state;
 use crate::app_state::SessionLifecycle;
+use crate::authorization;
+use crate::capture;
+use crate::input_injection;
+use crate::protocol::key_exchange::{
+    check_commitment, fingerprint, format_fingerprint, sas_code, shared_secret, EphemeralKey,
+    Identity,
+};
+use crate::protocol::packets::{
+    build_capabilities_packet, build_error_packet, build_media_path_packet, build_quic_path_packet,
+    parse_client_packet, CapabilitiesPacket, ClientPacket, DecoderLimits, PublicKeyShare,
+    COMMAND_SCREENSHOT,
+};
+use crate::protocol::quic::CAP_FLAG_QUIC;
+use crate::protocol::secure::{
+    establish, hardware_aes, random_nonce, transcript_hash, ChannelError, ChannelSecurity, Cipher,
+    CipherChoice, DatagramCipher, Opener, PeerTrust, RekeyPolicy, Role, Sealer, CAP_FLAG_CIPHERS,
+    CAP_FLAG_PUBLIC_KEY,
+};
+use crate::protocol::trace::{Direction, TraceWriter};
+use crate::protocol::transport::{
+    chunk_packet, HandshakeStream, Transport, TransportError, TransportStats,
+};
+use crate::protocol::udp::CAP_FLAG_UDP_MEDIA;
+use crate::quic_transport;
+use crate::session_state;
+use crate::udp_transport;
 
-static TCP_STREAM: OnceLock<Mutex<Option<TcpStream>>> = OnceLock::new();
-static LAST_CLIENT_CODEC_MASK: OnceLock<Mutex<Option<u32>>> = OnceLock::new();
-static LAST_FRAME_DONE: OnceLock<Mutex<Option<i32>>> = OnceLock::new();
-static CONNECTED: OnceLock<AtomicBool> = OnceLock::new();
-static RECONNECT_ENABLED: OnceLock<AtomicBool> = OnceLock::new();
-static RECONNECTING: OnceLock<AtomicBool> = OnceLock::new();
-static LAST_CONNECT: OnceLock<Mutex<Option<ConnectInfo>>> = OnceLock::new();
-static LAST_CAPS: OnceLock<Mutex<Option<Vec<u8>>>> = OnceLock::new();
-static LAST_CONFIGURE: OnceLock<Mutex<Option<Vec<u8>>>> = OnceLock::new();
-
-fn stream_store() -> &'static Mutex<Option<TcpStream>> {
-    TCP_STREAM.get_or_init(|| Mutex::new(None))
-}
+/// Session ids are unique across handles; the UDP and QUIC paths are keyed by them.
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
 
-fn codec_mask_store() -> &'static Mutex<Option<u32>> {
-    LAST_CLIENT_CODEC_MASK.get_or_init(|| Mutex::new(None))
-}
+/// How long the client gets to send its `Capabilities` before an encrypted session
+/// gives up.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Larger plaintext packets during negotiation are not `Capabilities`.
+const MAX_NEGOTIATION_PACKET: usize = 4096;
 
-fn frame_done_store() -> &'static Mutex<Option<i32>> {
-    LAST_FRAME_DONE.get_or_init(|| Mutex::new(None))
-}
+/// What the Tauri commands, the listener and the stream loop share.
+pub type TransportHandle = Arc<HostTransport>;
 
-fn connected_flag() -> &'static AtomicBool {
-    CONNECTED.get_or_init(|| AtomicBool::new(false))
+/// The host's sessions and what they last reported. Each session runs over its
+/// own `Transport`; the TCP ones get there through `connect` and `accept`.
+#[derive(Default)]
+pub struct HostTransport {
+    sessions: Mutex<Vec<Connection>>,
+    codec_mask: Mutex<Option<u32>>,
+    client_limits: Mutex<Option<DecoderLimits>>,
+    frame_done: Mutex<Option<i32>>,
+    connected: AtomicBool,
+    reconnect_enabled: AtomicBool,
+    reconnecting: AtomicBool,
+    last_connect: Mutex<Option<ConnectInfo>>,
+    last_configure: Mutex<Option<Vec<u8>>>,
+    trace: Mutex<Option<TraceWriter<BufWriter<File>>>>,
+    pending_pairing: Mutex<Option<PendingPairing>>,
+    /// A session joined the running stream and needs a keyframe to start on.
+    keyframe_requested: AtomicBool,
+    /// A stream loop is encoding for these sessions.
+    streaming: AtomicBool,
 }
 
-fn reconnect_enabled_flag() -> &'static AtomicBool {
-    RECONNECT_ENABLED.get_or_init(|| AtomicBool::new(false))
+/// What protects a connected session.
+#[derive(Debug, Clone)]
+pub struct ChannelInfo {
+    pub cipher: Cipher,
+    /// The client's identity fingerprint in public-key sessions.
+    pub peer_fingerprint: Option<[u8; 32]>,
+    /// Both sides offered a datagram path for video (§7.10).
+    pub media_path: bool,
+    /// Both sides offered to move the session to QUIC (§7.11).
+    pub quic_path: bool,
 }
 
-fn reconnecting_flag() -> &'static AtomicBool {
-    RECONNECTING.get_or_init(|| AtomicBool::new(false))
+impl ChannelInfo {
+    /// For log lines: which extra path the session was offered, if any.
+    pub fn path_note(&self) -> &'static str {
+        if self.quic_path {
+            ", QUIC offered"
+        } else if self.media_path {
+            ", video over UDP"
+        } else {
+            ""
+        }
+    }
 }
 
-fn last_connect_store() -> &'static Mutex<Option<ConnectInfo>> {
-    LAST_CONNECT.get_or_init(|| Mutex::new(None))
+/// A client seen for the first time in public-key mode, waiting for the user to
+/// compare `code` on both devices.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingPairing {
+    pub fingerprint: String,
+    pub code: String,
 }
 
-fn last_caps_store() -> &'static Mutex<Option<Vec<u8>>> {
-    LAST_CAPS.get_or_init(|| Mutex::new(None))
+struct Connection {
+    id: u64,
+    transport: Arc<dyn Transport>,
+    /// Dialed by the host, which redials it when it drops; accepted clients redial
+    /// on their own.
+    dialed: bool,
 }
 
-fn last_configure_store() -> &'static Mutex<Option<Vec<u8>>> {
-    LAST_CONFIGURE.get_or_init(|| Mutex::new(None))
+/// A session whose handshake is done, before it joins the others.
+struct Negotiated {
+    transport: Arc<dyn Transport>,
+    info: Option<ChannelInfo>,
+    /// Keys for the datagram path, when both sides offered one.
+    media: Option<(DatagramCipher, DatagramCipher)>,
+    /// The client's fingerprint, when both sides offered QUIC.
+    quic_peer: Option<[u8; 32]>,
 }
 
 #[derive(Debug, Clone)]
 struct ConnectInfo {
     host: String,
     port: u16,
+    caps: CapabilitiesPacket,
+    security: ChannelSecurity,
+    rekey: RekeyPolicy,
 }
 
-pub fn set_last_session(
-    host: String,
-    port: u16,
-    caps_packet: Vec<u8>,
-    configure_packet: Vec<u8>,
-) {
-    if let Ok(mut guard) = last_connect_store().lock() {
-        *guard = Some(ConnectInfo { host, port });
+impl HostTransport {
+    pub fn set_last_session(
+        &self,
+        host: String,
+        port: u16,
+        caps: CapabilitiesPacket,
+        security: ChannelSecurity,
+        rekey: RekeyPolicy,
+        configure_packet: Vec<u8>,
+    ) {
+        if let Ok(mut guard) = self.last_connect.lock() {
+            *guard = Some(ConnectInfo {
+                host,
+                port,
+                caps,
+                security,
+                rekey,
+            });
+        }
+        self.set_current_configure(configure_packet);
     }
-    if let Ok(mut guard) = last_caps_store().lock() {
-        *guard = Some(caps_packet);
+
+    /// Connects to a client and exchanges `Capabilities`. Unless `security` is the
+    /// debug plaintext mode, everything after that exchange is encrypted, with the
+    /// outgoing key rotated as `rekey` says.
+    pub fn connect(
+        self: &Arc<Self>,
+        addr: &str,
+        port: u16,
+        caps: &CapabilitiesPacket,
+        security: &ChannelSecurity,
+        rekey: RekeyPolicy,
+    ) -> Result<Option<ChannelInfo>, String> {
+        let target = format!("{addr}:{port}");
+        let mut addrs = target.to_socket_addrs().map_err(|err| err.to_string())?;
+        let socket_addr = addrs
+            .next()
+            .ok_or_else(|| "No address resolved".to_string())?;
+        let stream = TcpStream::connect(socket_addr).map_err(|err| err.to_string())?;
+        stream.set_nodelay(true).map_err(|err| err.to_string())?;
+
+        let negotiated = self.open_session(stream, caps, security, rekey)?;
+
+        // Dialing out replaces every other session.
+        let mut lock = self
+            .sessions
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        for previous in lock.drain(..) {
+            previous.transport.close();
+            udp_transport::close(previous.id);
+            quic_transport::close(previous.id);
+        }
+        let info = negotiated.info.clone();
+        self.register(&mut lock, negotiated, true);
+        self.reconnect_enabled.store(true, Ordering::SeqCst);
+        Ok(info)
     }
-    if let Ok(mut guard) = last_configure_store().lock() {
-        *guard = Some(configure_packet);
+
+    /// Runs the same handshake as `connect` on a connection the client dialed or
+    /// a USB accessory, adding it next to any running sessions. Returns the
+    /// session id for `send_framed_packet_to`.
+    pub fn accept<S: HandshakeStream>(
+        self: &Arc<Self>,
+        stream: S,
+        caps: &CapabilitiesPacket,
+        security: &ChannelSecurity,
+        rekey: RekeyPolicy,
+    ) -> Result<(u64, Option<ChannelInfo>), String> {
+        let negotiated = self.open_session(stream, caps, security, rekey)?;
+        let info = negotiated.info.clone();
+        let mut lock = self
+            .sessions
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        let id = self.register(&mut lock, negotiated, false);
+        Ok((id, info))
     }
-}
 
-pub fn connect(addr: &str, port: u16) -> Result<(), String> {
-    let target = format!("{addr}:{port}");
-    let mut addrs = target
-        .to_socket_addrs()
-        .map_err(|err| err.to_string())?;
-    let socket_addr = addrs.next().ok_or_else(|| "No address resolved".to_string())?;
-    let mut stream = TcpStream::connect(socket_addr).map_err(|err| err.to_string())?;
-    stream
-        .set_nodelay(true)
-        .map_err(|err| err.to_string())?;
+    /// Adds a session that needs no handshake, such as one end of a loopback pair.
+    #[cfg(test)]
+    pub fn attach(self: &Arc<Self>, transport: Arc<dyn Transport>) -> Result<u64, String> {
+        let mut lock = self
+            .sessions
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        Ok(self.add_session(&mut lock, transport, false))
+    }
 
-    let handshake = build_host_handshake(4).map_err(|err| err.to_string())?;
-    stream.write_all(&handshake).map_err(|err| err.to_string())?;
+    /// Turns a client away before any session exists: the handshake, then an
+    /// `Error` packet in the clear, then the connection is closed.
+    pub fn refuse(&self, mut stream: TcpStream, code: u8, message: &str) -> Result<(), String> {
+        let handshake = build_host_handshake(PROTOCOL_VERSION).map_err(|err| err.to_string())?;
+        let result = stream
+            .write_all(&handshake)
+            .map_err(|err| err.to_string())
+            .and_then(|()| {
+                self.write_plain_packet(&mut stream, &build_error_packet(code, message))
+            });
+        let _ = stream.shutdown(Shutdown::Both);
+        result
+    }
 
-    let reader_stream = stream.try_clone().map_err(|err| err.to_string())?;
-    start_reader(reader_stream);
+    fn open_session<S: HandshakeStream>(
+        &self,
+        mut stream: S,
+        caps: &CapabilitiesPacket,
+        security: &ChannelSecurity,
+        rekey: RekeyPolicy,
+    ) -> Result<Negotiated, String> {
+        let handshake = build_host_handshake(PROTOCOL_VERSION).map_err(|err| err.to_string())?;
+        stream
+            .write_all(&handshake)
+            .map_err(|err| err.to_string())?;
 
-    let mut lock = stream_store().lock().map_err(|_| "Lock poisoned".to_string())?;
-    *lock = Some(stream);
-    connected_flag().store(true, Ordering::SeqCst);
-    reconnect_enabled_flag().store(true, Ordering::SeqCst);
-    Ok(())
-}
+        let (mut sealer, opener, info) = match security {
+            ChannelSecurity::Plaintext => {
+                self.write_plain_packet(&mut stream, &build_capabilities_packet(caps.clone()))?;
+                (None, None, None)
+            }
+            ChannelSecurity::Psk { key, cipher } => {
+                let (sealer, opener, info) = self.negotiate_psk(&mut stream, caps, key, *cipher)?;
+                (Some(sealer), Some(opener), Some(info))
+            }
+            ChannelSecurity::PublicKey {
+                identity,
+                cipher,
+                trust,
+            } => {
+                let (sealer, opener, info) =
+                    self.negotiate_public_key(&mut stream, caps, identity, *cipher, trust)?;
+                (Some(sealer), Some(opener), Some(info))
+            }
+        };
 
-pub fn disconnect() -> Result<(), String> {
-    let mut lock = stream_store().lock().map_err(|_| "Lock poisoned".to_string())?;
-    *lock = None;
-    connected_flag().store(false, Ordering::SeqCst);
-    reconnect_enabled_flag().store(false, Ordering::SeqCst);
-    crate::stream_loop::stop_streaming();
-    crate::session_state::reset_stats();
-    Ok(())
-}
+        if let Some(sealer) = sealer.as_mut() {
+            sealer.set_rekey_policy(rekey);
+        }
+        let media = match (&sealer, &opener, &info) {
+            (Some(sealer), Some(opener), Some(info)) if info.media_path => {
+                Some((sealer.datagram_cipher(), opener.datagram_cipher()))
+            }
+            _ => None,
+        };
+        let quic_peer = info
+            .as_ref()
+            .filter(|info| info.quic_path)
+            .and_then(|info| info.peer_fingerprint);
+        let transport = stream
+            .into_transport(sealer, opener)
+            .map_err(|err| err.to_string())?;
+        Ok(Negotiated {
+            transport,
+            info,
+            media,
+            quic_peer,
+        })
+    }
 
-pub fn is_connected() -> bool {
-    connected_flag().load(Ordering::SeqCst)
-}
+    fn register(
+        self: &Arc<Self>,
+        sessions: &mut Vec<Connection>,
+        negotiated: Negotiated,
+        dialed: bool,
+    ) -> u64 {
+        let transport = negotiated.transport;
+        let id = self.add_session(sessions, transport.clone(), dialed);
+        if let Some((sealer, opener)) = negotiated.media {
+            self.open_media_path(id, transport.as_ref(), sealer, opener);
+        }
+        // Sessions without a network peer, such as USB ones, stay where they are.
+        if let (Some(peer), Some(_)) = (negotiated.quic_peer, transport.peer_addr()) {
+            self.offer_quic_path(id, transport.as_ref(), peer);
+        }
+        id
+    }
 
-pub fn send_framed_packet(packet: &[u8]) -> Result<(), String> {
-    let mut framed = Vec::with_capacity(4 + packet.len());
-    framed.extend_from_slice(&(packet.len() as u32).to_le_bytes());
-    framed.extend_from_slice(packet);
+    fn add_session(
+        self: &Arc<Self>,
+        sessions: &mut Vec<Connection>,
+        transport: Arc<dyn Transport>,
+        dialed: bool,
+    ) -> u64 {
+        let id = next_session_id();
+        self.start_reader(id, transport.clone());
+        sessions.push(Connection {
+            id,
+            transport,
+            dialed,
+        });
+        self.connected.store(true, Ordering::SeqCst);
+        id
+    }
 
-    let mut chunked = Vec::with_capacity(framed.len() + 3);
-    write_stream_chunks(0, &framed, &mut chunked);
+    /// Moves the session's video to a datagram path and tells the client where it
+    /// is. Without one, frames keep going over the stream.
+    fn open_media_path(
+        &self,
+        id: u64,
+        transport: &dyn Transport,
+        sealer: DatagramCipher,
+        opener: DatagramCipher,
+    ) {
+        let Some(peer) = transport.peer_addr() else {
+            return;
+        };
+        let Ok(path) = udp_transport::open(id, peer.ip(), sealer, opener) else {
+            return;
+        };
+        let packet = build_media_path_packet(path);
+        if transport.send(0, &packet).is_ok() {
+            self.trace_packet(Direction::HostToClient, 0, &packet);
+        } else {
+            udp_transport::close(id);
+        }
+    }
 
-    let mut lock = stream_store().lock().map_err(|_| "Lock poisoned".to_string())?;
-    let stream = lock.as_mut().ok_or_else(|| "TCP stream not connected".to_string())?;
-    stream.write_all(&chunked).map_err(|err| err.to_string())
-}
+    /// Invites the client to move the session to QUIC. Until it connects there, and
+    /// whenever that connection drops, the session stays on TCP.
+    fn offer_quic_path(self: &Arc<Self>, id: u64, transport: &dyn Transport, peer: [u8; 32]) {
+        let Ok(path) = quic_transport::offer(self, id, peer) else {
+            return;
+        };
+        let packet = build_quic_path_packet(path);
+        if transport.send(0, &packet).is_ok() {
+            self.trace_packet(Direction::HostToClient, 0, &packet);
+        }
+    }
 
-pub fn take_last_client_codec_mask() -> Option<u32> {
-    codec_mask_store().lock().ok().and_then(|mut guard| guard.take())
-}
+    /// Reads the client's `Capabilities`, answers with the host's carrying the
+    /// chosen cipher, and keys the channel from the PSK and both packets.
+    fn negotiate_psk<S: HandshakeStream>(
+        &self,
+        stream: &mut S,
+        caps: &CapabilitiesPacket,
+        psk: &[u8],
+        choice: CipherChoice,
+    ) -> Result<(Sealer, Opener, ChannelInfo), String> {
+        if psk.is_empty() {
+            return Err(
+                "No pre-shared key is set; enter one in Preferences or enable debug plaintext mode"
+                    .to_string(),
+            );
+        }
+        let (client_caps, client) = self.read_client_caps(stream)?;
+        let cipher = select_cipher(&client, choice)?;
+        let media_flag = media_path_flag(&client);
 
-pub fn take_last_frame_done() -> Option<i32> {
-    frame_done_store().lock().ok().and_then(|mut guard| guard.take())
-}
+        let host_caps = build_capabilities_packet(CapabilitiesPacket {
+            flags: caps.flags | cipher.flag() | media_flag,
+            channel_nonce: Some(random_nonce()?),
+            ..caps.clone()
+        });
+        self.write_plain_packet(stream, &host_caps)?;
+        stream
+            .set_read_timeout(None)
+            .map_err(|err| err.to_string())?;
 
-fn start_reader(mut stream: TcpStream) {
-    thread::spawn(move || {
-        let mut pending = VecDeque::new();
-        let mut stream_buffers: [VecDeque<u8>; 2] = [VecDeque::new(), VecDeque::new()];
-        let mut buffer = [0u8; 4096];
-        loop {
-            let read = match stream.read(&mut buffer) {
-                Ok(0) => break,
-                Ok(n) => n,
-                Err(_) => break,
-            };
-            for byte in &buffer[..read] {
-                pending.push_back(*byte);
-            }
+        let transcript = transcript_hash(&[&client_caps, &host_caps]);
+        let (sealer, opener) = establish(psk, cipher, &transcript, Role::Host);
+        let info = ChannelInfo {
+            cipher,
+            peer_fingerprint: None,
+            media_path: media_flag != 0,
+            quic_path: false,
+        };
+        Ok((sealer, opener, info))
+    }
 
-            while pending.len() >= 3 {
-                let stream_id = *pending.get(0).unwrap();
-                let len_lo = *pending.get(1).unwrap();
-                let len_hi = *pending.get(2).unwrap();
-                let chunk_len = u16::from_le_bytes([len_lo, len_hi]) as usize;
-                if pending.len() < 3 + chunk_len {
-                    break;
-                }
-                pending.pop_front();
-                pending.pop_front();
-                pending.pop_front();
-
-                let stream_index = (stream_id as usize).min(stream_buffers.len() - 1);
-                for _ in 0..chunk_len {
-                    if let Some(byte) = pending.pop_front() {
-                        stream_buffers[stream_index].push_back(byte);
-                    }
-                }
+    /// Like `negotiate_psk`, with both `Capabilities` carrying identity keys and
+    /// the client revealing its committed ephemeral key in `KeyExchange` afterwards.
+    fn negotiate_public_key<S: HandshakeStream>(
+        &self,
+        stream: &mut S,
+        caps: &CapabilitiesPacket,
+        identity: &Identity,
+        choice: CipherChoice,
+        trust: &PeerTrust,
+    ) -> Result<(Sealer, Opener, ChannelInfo), String> {
+        let (client_caps, client) = self.read_client_caps(stream)?;
+        let client_share = client
+            .public_key
+            .filter(|_| client.flags & CAP_FLAG_PUBLIC_KEY != 0)
+            .ok_or_else(|| {
+                "The client has no identity key; pair it with a pre-shared key instead".to_string()
+            })?;
+        let cipher = select_cipher(&client, choice)?;
+        // QUIC carries video itself; the datagram path is only for TCP sessions.
+        let quic_flag = quic_path_flag(&client);
+        let media_flag = if quic_flag == 0 {
+            media_path_flag(&client)
+        } else {
+            0
+        };
+
+        let ephemeral = EphemeralKey::generate()?;
+        let host_caps = build_capabilities_packet(CapabilitiesPacket {
+            flags: caps.flags | cipher.flag() | CAP_FLAG_PUBLIC_KEY | media_flag | quic_flag,
+            channel_nonce: Some(random_nonce()?),
+            public_key: Some(PublicKeyShare {
+                identity_key: identity.public_key(),
+                key_share: ephemeral.public_key(),
+            }),
+            ..caps.clone()
+        });
+        self.write_plain_packet(stream, &host_caps)?;
+
+        let reveal = self.read_plain_packet(stream)?;
+        let client_ephemeral = match parse_client_packet(&reveal) {
+            Ok(ClientPacket::KeyExchange(packet)) => packet.ephemeral_key,
+            _ => return Err("Expected KeyExchange from the client".to_string()),
+        };
+        check_commitment(&client_ephemeral, &client_share.key_share)
+            .map_err(|err| err.to_string())?;
+        stream
+            .set_read_timeout(None)
+            .map_err(|err| err.to_string())?;
+
+        let transcript = transcript_hash(&[&client_caps, &host_caps, &reveal]);
+        let secret = shared_secret(
+            Role::Host,
+            identity,
+            &ephemeral,
+            &client_share.identity_key,
+            &client_ephemeral,
+        )
+        .map_err(|err| err.to_string())?;
+        let peer = fingerprint(&client_share.identity_key);
+        self.check_peer(trust, &peer, &transcript)?;
 
-                parse_stream_buffer(&mut stream_buffers[stream_index]);
+        let (sealer, opener) = establish(&secret, cipher, &transcript, Role::Host);
+        let info = ChannelInfo {
+            cipher,
+            peer_fingerprint: Some(peer),
+            media_path: media_flag != 0,
+            quic_path: quic_flag != 0,
+        };
+        Ok((sealer, opener, info))
+    }
+
+    /// Accepts pinned clients. A selected device presenting another key is refused;
+    /// an unknown client is parked for the user to confirm its pairing code.
+    fn check_peer(
+        &self,
+        trust: &PeerTrust,
+        peer: &[u8; 32],
+        transcript: &[u8; 32],
+    ) -> Result<(), String> {
+        match trust.expected {
+            Some(expected) if expected == *peer => return Ok(()),
+            Some(expected) => {
+                return Err(format!(
+                    "SECURITY WARNING: the device's identity key has changed (pinned {}, presented {}). \
+                     Someone may be intercepting the connection. Remove and pair the device again \
+                     only if you know it was reset.",
+                    &format_fingerprint(&expected)[..16],
+                    &format_fingerprint(peer)[..16]
+                ));
             }
+            None if trust.trusted.contains(peer) => return Ok(()),
+            None if !trust.allow_pairing => {
+                return Err(format!(
+                    "Device {} is not paired and only paired devices may connect",
+                    &format_fingerprint(peer)[..16]
+                ));
+            }
+            None => {}
+        }
+        let pending = PendingPairing {
+            fingerprint: format_fingerprint(peer),
+            code: sas_code(transcript),
+        };
+        let message = format!(
+            "New device: check that it shows pairing code {}, approve it, then connect again",
+            pending.code
+        );
+        if let Ok(mut guard) = self.pending_pairing.lock() {
+            *guard = Some(pending);
         }
+        Err(message)
+    }
+
+    pub fn take_pending_pairing(&self) -> Option<PendingPairing> {
+        self.pending_pairing
+            .lock()
+            .ok()
+            .and_then(|mut guard| guard.take())
+    }
 
-        if let Ok(mut lock) = stream_store().lock() {
-            *lock = None;
+    /// Reads the plaintext `Capabilities` that opens an encrypted session.
+    fn read_client_caps<S: HandshakeStream>(
+        &self,
+        stream: &mut S,
+    ) -> Result<(Vec<u8>, CapabilitiesPacket), String> {
+        stream
+            .set_read_timeout(Some(NEGOTIATION_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        let client_caps = self.read_plain_packet(stream)?;
+        let client = match parse_client_packet(&client_caps) {
+            Ok(ClientPacket::Capabilities(client)) => client,
+            _ => return Err("Expected Capabilities from the client".to_string()),
+        };
+        self.handle_client_packet(&client_caps);
+        if client.flags & CAP_FLAG_CIPHERS == 0 {
+            return Err(
+                "The client does not support encryption; enable debug plaintext mode to connect anyway"
+                    .to_string(),
+            );
         }
-        connected_flag().store(false, Ordering::SeqCst);
-        crate::stream_loop::stop_streaming();
-        crate::session_state::reset_stats();
-        if reconnect_enabled_flag().load(Ordering::SeqCst) {
-            session_state::update_lifecycle(SessionLifecycle::Error);
-            attempt_reconnect();
-        } else {
-            session_state::update_lifecycle(SessionLifecycle::Idle);
+        if client.channel_nonce.is_none() {
+            return Err("The client offered encryption without a channel nonce".to_string());
         }
-    });
-}
+        Ok((client_caps, client))
+    }
 
-fn attempt_reconnect() {
-    if reconnecting_flag()
-        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
-        .is_err()
-    {
-        return;
+    fn write_plain_packet<S: HandshakeStream>(
+        &self,
+        stream: &mut S,
+        packet: &[u8],
+    ) -> Result<(), String> {
+        stream
+            .write_all(&chunk_packet(0, packet))
+            .map_err(|err| err.to_string())?;
+        self.trace_packet(Direction::HostToClient, 0, packet);
+        Ok(())
     }
 
-    thread::spawn(move || {
-        let mut attempt = 0;
-        let backoff_ms = [1500, 3000, 6000];
-        session_state::update_lifecycle(SessionLifecycle::Connecting);
+    /// Reads one framed packet a chunk at a time, so nothing past it is consumed.
+    fn read_plain_packet<S: HandshakeStream>(&self, stream: &mut S) -> Result<Vec<u8>, String> {
+        let mut decoder = StreamDecoder::default();
+        let mut total = 0;
         loop {
-            if connected_flag().load(Ordering::SeqCst) {
-                break;
+            let mut chunk = vec![0u8; 3];
+            stream
+                .read_exact(&mut chunk)
+                .map_err(|err| format!("Waiting for client Capabilities: {err}"))?;
+            let chunk_len = u16::from_le_bytes([chunk[1], chunk[2]]) as usize;
+            total += chunk_len;
+            if total > MAX_NEGOTIATION_PACKET {
+                return Err("Expected Capabilities from the client".to_string());
             }
-            let info = last_connect_store()
-                .lock()
-                .ok()
-                .and_then(|guard| guard.clone());
-            let (host, port) = match info {
-                Some(info) => (info.host, info.port),
-                None => break,
-            };
+            chunk.resize(3 + chunk_len, 0);
+            stream
+                .read_exact(&mut chunk[3..])
+                .map_err(|err| format!("Waiting for client Capabilities: {err}"))?;
+            if let Some((stream_id, packet)) = decoder.push(&chunk).into_iter().next() {
+                self.trace_packet(Direction::ClientToHost, stream_id, &packet);
+                return Ok(packet);
+            }
+        }
+    }
 
-            let connect_result = connect(&host, port);
-            if connect_result.is_ok() {
-                if let (Some(caps), Some(configure)) = (
-                    last_caps_store().lock().ok().and_then(|guard| guard.clone()),
-                    last_configure_store()
-                        .lock()
-                        .ok()
-                        .and_then(|guard| guard.clone()),
-                ) {
-                    let _ = send_framed_packet(&caps);
-                    let _ = send_framed_packet(&configure);
-                }
-                session_state::update_lifecycle(SessionLifecycle::Configured);
-                break;
+    pub fn disconnect(&self) -> Result<(), String> {
+        let mut lock = self
+            .sessions
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        for connection in lock.drain(..) {
+            connection.transport.close();
+        }
+        udp_transport::close_all();
+        quic_transport::close_all();
+        self.connected.store(false, Ordering::SeqCst);
+        self.reconnect_enabled.store(false, Ordering::SeqCst);
+        self.stop_streaming();
+        crate::session_state::reset_stats();
+        Ok(())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().map(|guard| guard.len()).unwrap_or(0)
+    }
+
+    pub fn has_session(&self, id: u64) -> bool {
+        self.session_ids().contains(&id)
+    }
+
+    /// Traffic of the running sessions, added up.
+    pub fn stats(&self) -> TransportStats {
+        let mut total = TransportStats::default();
+        if let Ok(lock) = self.sessions.lock() {
+            for connection in lock.iter() {
+                total.add(&connection.transport.stats());
             }
+        }
+        total
+    }
 
-            if attempt >= backoff_ms.len() {
-                break;
+    /// Drops one session; the others keep streaming.
+    pub fn close_session(&self, id: u64) {
+        if let Ok(lock) = self.sessions.lock() {
+            if let Some(connection) = lock.iter().find(|connection| connection.id == id) {
+                connection.transport.close();
             }
-            let delay = backoff_ms[attempt];
-            attempt += 1;
-            thread::sleep(std::time::Duration::from_millis(delay));
         }
-        if !connected_flag().load(Ordering::SeqCst) {
-            session_state::update_lifecycle(SessionLifecycle::Error);
+    }
+
+    /// The `Configure` sessions are streaming with, for clients joining later.
+    pub fn current_configure(&self) -> Option<Vec<u8>> {
+        self.last_configure
+            .lock()
+            .ok()
+            .and_then(|guard| guard.clone())
+    }
+
+    pub fn set_current_configure(&self, configure_packet: Vec<u8>) {
+        if let Ok(mut guard) = self.last_configure.lock() {
+            *guard = Some(configure_packet);
         }
-        reconnecting_flag().store(false, Ordering::SeqCst);
-    });
-}
+    }
 
-fn parse_stream_buffer(buffer: &mut VecDeque<u8>) {
-    loop {
-        if buffer.len() < 4 {
-            return;
+    /// Sends `packet` to every session. A session that fails the write is dropped;
+    /// the call only fails when no session took the packet.
+    pub fn send_framed_packet(&self, packet: &[u8]) -> Result<(), String> {
+        self.broadcast(packet, &[])
+    }
+
+    /// Like `send_framed_packet`, for `Frame` packets: sessions on QUIC or with a
+    /// datagram path get them there, the rest on their stream.
+    pub fn send_frame_packet(&self, packet: &[u8]) -> Result<(), String> {
+        let sessions = self.session_ids();
+        let mut reached = quic_transport::send_frame(packet);
+        reached.extend(udp_transport::send_frame(packet));
+        reached.retain(|id| sessions.contains(id));
+        if reached.is_empty() {
+            return self.broadcast(packet, &[]);
         }
-        let len_bytes = [
-            *buffer.get(0).unwrap(),
-            *buffer.get(1).unwrap(),
-            *buffer.get(2).unwrap(),
-            *buffer.get(3).unwrap(),
-        ];
-        let packet_len = u32::from_le_bytes(len_bytes) as usize;
-        if buffer.len() < 4 + packet_len {
-            return;
+        self.trace_packet(Direction::HostToClient, 0, packet);
+        if reached.len() < sessions.len() {
+            let _ = self.broadcast_untraced(packet, &reached);
         }
-        for _ in 0..4 {
-            buffer.pop_front();
+        Ok(())
+    }
+
+    fn session_ids(&self) -> Vec<u64> {
+        self.sessions
+            .lock()
+            .map(|guard| guard.iter().map(|connection| connection.id).collect())
+            .unwrap_or_default()
+    }
+
+    fn broadcast(&self, packet: &[u8], skip: &[u64]) -> Result<(), String> {
+        self.broadcast_untraced(packet, skip)?;
+        self.trace_packet(Direction::HostToClient, 0, packet);
+        Ok(())
+    }
+
+    fn broadcast_untraced(&self, packet: &[u8], skip: &[u64]) -> Result<(), String> {
+        let lock = self
+            .sessions
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        if lock.is_empty() {
+            return Err("TCP stream not connected".to_string());
         }
-        let mut payload = Vec::with_capacity(packet_len);
-        for _ in 0..packet_len {
-            if let Some(byte) = buffer.pop_front() {
-                payload.push(byte);
+        let chunked = chunk_packet(0, packet);
+        let mut last_error = None;
+        let mut delivered = false;
+        for connection in lock.iter() {
+            if skip.contains(&connection.id) {
+                continue;
+            }
+            if quic_transport::send_control(connection.id, &chunked) {
+                delivered = true;
+                continue;
+            }
+            match connection.transport.send(0, packet) {
+                Ok(()) => delivered = true,
+                Err(err) => {
+                    connection.transport.close();
+                    last_error = Some(err.to_string());
+                }
             }
         }
-        if let Ok(packet) = parse_client_packet(&payload) {
+        if !delivered {
+            return Err(last_error.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    /// Sends `packet` to one session only.
+    pub fn send_framed_packet_to(&self, id: u64, packet: &[u8]) -> Result<(), String> {
+        let lock = self
+            .sessions
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        let connection = lock
+            .iter()
+            .find(|connection| connection.id == id)
+            .ok_or_else(|| "Session closed".to_string())?;
+        if !quic_transport::send_control(id, &chunk_packet(0, packet)) {
+            connection
+                .transport
+                .send(0, packet)
+                .map_err(|err| err.to_string())?;
+        }
+        self.trace_packet(Direction::HostToClient, 0, packet);
+        Ok(())
+    }
+
+    /// Starts logging every framed packet, both directions, to a trace file at
+    /// `path`. Payloads are only kept when asked for; frames and input can be
+    /// sensitive.
+    pub fn start_trace(&self, path: &Path, include_payloads: bool) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        let writer = TraceWriter::new(BufWriter::new(file), include_payloads)
+            .map_err(|err| err.to_string())?;
+        let mut lock = self.trace.lock().map_err(|_| "Lock poisoned".to_string())?;
+        if let Some(previous) = lock.replace(writer) {
+            let _ = previous.finish();
+        }
+        Ok(())
+    }
+
+    pub fn stop_trace(&self) -> Result<(), String> {
+        let mut lock = self.trace.lock().map_err(|_| "Lock poisoned".to_string())?;
+        match lock.take() {
+            Some(writer) => writer.finish().map(|_| ()).map_err(|err| err.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace
+            .lock()
+            .map(|guard| guard.is_some())
+            .unwrap_or(false)
+    }
+
+    fn trace_packet(&self, direction: Direction, stream_id: u8, packet: &[u8]) {
+        let Ok(mut lock) = self.trace.lock() else {
+            return;
+        };
+        if let Some(writer) = lock.as_mut() {
+            if writer.record(direction, stream_id, packet).is_err() {
+                // A full disk should not take the session down with it.
+                *lock = None;
+            }
+        }
+    }
+
+    pub fn take_last_client_codec_mask(&self) -> Option<u32> {
+        self.codec_mask
+            .lock()
+            .ok()
+            .and_then(|mut guard| guard.take())
+    }
+
+    pub fn last_client_decoder_limits(&self) -> Option<DecoderLimits> {
+        self.client_limits
+            .lock()
+            .ok()
+            .and_then(|guard| guard.clone())
+    }
+
+    pub fn take_last_frame_done(&self) -> Option<i32> {
+        self.frame_done
+            .lock()
+            .ok()
+            .and_then(|mut guard| guard.take())
+    }
+
+    /// Asks the stream loop for a keyframe, for a session that joined mid-stream
+    /// or a recording that just started.
+    pub fn request_keyframe(&self) {
+        self.keyframe_requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn take_keyframe_request(&self) -> bool {
+        self.keyframe_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Marks the stream as running; false when it already was.
+    pub fn begin_streaming(&self) -> bool {
+        !self.streaming.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streaming.load(Ordering::SeqCst)
+    }
+
+    /// Tells the stream loop to finish after its current frame.
+    pub fn stop_streaming(&self) {
+        self.streaming.store(false, Ordering::SeqCst);
+    }
+
+    fn start_reader(self: &Arc<Self>, id: u64, transport: Arc<dyn Transport>) {
+        let hub = Arc::clone(self);
+        thread::spawn(move || {
+            // A read error or a record that fails to open ends the session like a
+            // clean close. When the very first record fails, the client derived
+            // different keys: a wrong PSK, which counts against its address.
+            let mut opened = false;
+            loop {
+                match transport.receive() {
+                    Ok(Some((stream_id, packet))) => {
+                        opened = true;
+                        hub.receive_client_packet(stream_id, &packet);
+                    }
+                    Err(TransportError::Channel(ChannelError::Authentication)) if !opened => {
+                        if let Some(peer) = transport.peer_addr() {
+                            authorization::psk_mismatch(peer.ip());
+                        }
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+
+            // Sessions closed by `disconnect` or replaced by a new dial are already
+            // gone; the rest only end the stream when they were the last one.
+            let Some(dialed) = hub.remove_session(id) else {
+                return;
+            };
+            if hub.session_count() > 0 {
+                return;
+            }
+            hub.connected.store(false, Ordering::SeqCst);
+            hub.stop_streaming();
+            crate::session_state::reset_stats();
+            if dialed && hub.reconnect_enabled.load(Ordering::SeqCst) {
+                session_state::update_lifecycle(SessionLifecycle::Error);
+                hub.attempt_reconnect();
+            } else {
+                session_state::update_lifecycle(SessionLifecycle::Idle);
+            }
+        });
+    }
+
+    /// Removes a session, returning whether the host dialed it.
+    fn remove_session(&self, id: u64) -> Option<bool> {
+        udp_transport::close(id);
+        quic_transport::close(id);
+        let mut lock = self.sessions.lock().ok()?;
+        let index = lock.iter().position(|connection| connection.id == id)?;
+        Some(lock.remove(index).dialed)
+    }
+
+    fn attempt_reconnect(self: &Arc<Self>) {
+        if self
+            .reconnecting
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return;
+        }
+
+        let hub = Arc::clone(self);
+        thread::spawn(move || {
+            let mut attempt = 0;
+            let backoff_ms = [1500, 3000, 6000];
+            session_state::update_lifecycle(SessionLifecycle::Connecting);
+            loop {
+                if hub.connected.load(Ordering::SeqCst) {
+                    break;
+                }
+                let info = hub.last_connect.lock().ok().and_then(|guard| guard.clone());
+                let Some(info) = info else {
+                    break;
+                };
+
+                let connect_result = hub.connect(
+                    &info.host,
+                    info.port,
+                    &info.caps,
+                    &info.security,
+                    info.rekey,
+                );
+                if connect_result.is_ok() {
+                    if let Some(configure) = hub.current_configure() {
+                        let _ = hub.send_framed_packet(&configure);
+                    }
+                    session_state::update_lifecycle(SessionLifecycle::Configured);
+                    break;
+                }
+
+                if attempt >= backoff_ms.len() {
+                    break;
+                }
+                let delay = backoff_ms[attempt];
+                attempt += 1;
+                thread::sleep(std::time::Duration::from_millis(delay));
+            }
+            if !hub.connected.load(Ordering::SeqCst) {
+                session_state::update_lifecycle(SessionLifecycle::Error);
+            }
+            hub.reconnecting.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Handles a packet from any session, whichever transport it came in on.
+    pub fn receive_client_packet(&self, stream_id: u8, packet: &[u8]) {
+        self.trace_packet(Direction::ClientToHost, stream_id, packet);
+        self.handle_client_packet(packet);
+    }
+
+    fn handle_client_packet(&self, payload: &[u8]) {
+        if let Ok(packet) = parse_client_packet(payload) {
             match packet {
                 ClientPacket::Capabilities(caps) => {
-                    if let Ok(mut guard) = codec_mask_store().lock() {
+                    if let Ok(mut guard) = self.codec_mask.lock() {
                         *guard = Some(caps.codec_mask);
                     }
+                    if let Ok(mut guard) = self.client_limits.lock() {
+                        *guard = caps.decoder_limits;
+                    }
                 }
                 ClientPacket::FrameDone(frame) => {
-                    if let Ok(mut guard) = frame_done_store().lock() {
+                    if let Ok(mut guard) = self.frame_done.lock() {
                         *guard = Some(frame.encoder_id);
                     }
                 }
-                ClientPacket::Touch(_)
-                | ClientPacket::Pen(_)
-                | ClientPacket::Keyboard(_) => {
-                    let state = crate::session_state::snapshot();
-                    if !state.input_permissions.enable_input {
-                        continue;
-                    }
-                    let allowed = match packet {
-                        ClientPacket::Touch(_) => state.input_permissions.touch,
-                        ClientPacket::Pen(_) => state.input_permissions.pen,
-                        ClientPacket::Keyboard(_) => state.input_permissions.keyboard,
-                        _ => true,
-                    };
-                    if !allowed {
-                        continue;
+                ClientPacket::Command(command) if command.command_id == COMMAND_SCREENSHOT => {
+                    spawn_screenshot();
+                }
+                ClientPacket::InputKey(key) if key.down && key.action == COMMAND_SCREENSHOT => {
+                    spawn_screenshot();
+                }
+                // Keys have no injection path yet.
+                ClientPacket::Touch(_) | ClientPacket::Pen(_) if input_allowed(&packet) => {
+                    if let Some(event) = input_injection::pointer_event(&packet, capture::map_input)
+                    {
+                        // A desktop that refuses the event drops it like filtered input.
+                        let _ = input_injection::inject(event);
                     }
                 }
                 _ => {}
@@ -305,3 +971,165 @@ fn parse_stream_buffer(buffer: &mut VecDeque<u8>) {
         }
     }
 }
+
+fn next_session_id() -> u64 {
+    NEXT_SESSION_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// The QUIC bit for the host's `Capabilities` in public-key sessions: set when
+/// the client offers it and the host's endpoint is running.
+fn quic_path_flag(client: &CapabilitiesPacket) -> u32 {
+    if client.flags & CAP_FLAG_QUIC != 0 && quic_transport::enabled() {
+        CAP_FLAG_QUIC
+    } else {
+        0
+    }
+}
+
+/// The datagram path bit for the host's `Capabilities`: set when the client
+/// offers one and the host has them turned on.
+fn media_path_flag(client: &CapabilitiesPacket) -> u32 {
+    if client.flags & CAP_FLAG_UDP_MEDIA != 0 && udp_transport::enabled() {
+        CAP_FLAG_UDP_MEDIA
+    } else {
+        0
+    }
+}
+
+fn select_cipher(client: &CapabilitiesPacket, choice: CipherChoice) -> Result<Cipher, String> {
+    choice
+        .select(client.flags, hardware_aes())
+        .ok_or_else(|| "The client does not support the selected cipher".to_string())
+}
+
+/// Whether an input packet passes the session's permissions.
+fn input_allowed(packet: &ClientPacket) -> bool {
+    let state = crate::session_state::snapshot();
+    if !state.input_permissions.enable_input {
+        return false;
+    }
+    match packet {
+        ClientPacket::Touch(_) => state.input_permissions.touch,
+        ClientPacket::Pen(_) => state.input_permissions.pen,
+        ClientPacket::Keyboard(_) => state.input_permissions.keyboard,
+        _ => true,
+    }
+}
+
+/// Runs off the reader thread; opening a capture source can take a while.
+fn spawn_screenshot() {
+    thread::spawn(crate::screenshot::take_client_requested);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::packets::{build_take_screenshot_packet, ERROR_HOST_BUSY};
+    use crate::protocol::transport::{loopback_pair, seal_packet};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    fn wait_for<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            if let Some(value) = poll() {
+                return value;
+            }
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn refused_clients_get_the_handshake_and_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        HostTransport::default()
+            .refuse(stream, ERROR_HOST_BUSY, "busy")
+            .unwrap();
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        let handshake = build_host_handshake(PROTOCOL_VERSION).unwrap();
+        assert_eq!(&received[..handshake.len()], handshake.as_slice());
+        let packets = StreamDecoder::default().push(&received[handshake.len()..]);
+        assert_eq!(
+            packets,
+            vec![(0, build_error_packet(ERROR_HOST_BUSY, "busy"))]
+        );
+    }
+
+    #[test]
+    fn loopback_sessions_report_frames_done_and_share_broadcasts() {
+        let hub: TransportHandle = Arc::default();
+        let (host_end, first) = loopback_pair();
+        let first_id = hub.attach(Arc::new(host_end)).unwrap();
+        let (host_end, second) = loopback_pair();
+        hub.attach(Arc::new(host_end)).unwrap();
+        assert!(hub.is_connected());
+        assert_eq!(hub.session_count(), 2);
+
+        let packet = build_take_screenshot_packet();
+        hub.send_framed_packet(&packet).unwrap();
+        assert_eq!(first.receive().unwrap(), Some((0, packet.clone())));
+        assert_eq!(second.receive().unwrap(), Some((0, packet.clone())));
+        hub.send_framed_packet_to(first_id, b"only").unwrap();
+        assert_eq!(first.receive().unwrap(), Some((0, b"only".to_vec())));
+
+        // FrameDone for encoder 7.
+        second.send(0, &[4, 7, 0, 0, 0]).unwrap();
+        assert_eq!(wait_for(|| hub.take_last_frame_done()), 7);
+        assert_eq!(hub.stats().packets_received, 1);
+        assert!(!hub.take_keyframe_request());
+        hub.request_keyframe();
+        assert!(hub.take_keyframe_request());
+        assert!(!hub.take_keyframe_request());
+
+        // A session closing from the client end leaves the others running.
+        first.close();
+        wait_for(|| (hub.session_count() == 1).then_some(()));
+        assert!(hub.is_connected());
+        assert!(hub.send_framed_packet_to(first_id, b"gone").is_err());
+
+        // The stream runs once per handle and stops with the last session.
+        assert!(hub.begin_streaming());
+        assert!(!hub.begin_streaming());
+        assert!(!HostTransport::default().is_streaming());
+        hub.disconnect().unwrap();
+        assert!(!hub.is_connected());
+        assert!(!hub.is_streaming());
+        assert_eq!(second.receive().unwrap(), None);
+    }
+
+    #[test]
+    fn wrong_psks_lock_the_address_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transcript = transcript_hash(&[b"client caps", b"host caps"]);
+        let hub: TransportHandle = Arc::default();
+        let mut peer = None;
+        for attempt in 0..5 {
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (stream, address) = listener.accept().unwrap();
+            assert_eq!(
+                authorization::blocked_for(address.ip()),
+                None,
+                "attempt {attempt}"
+            );
+            peer = Some(address.ip());
+            let (sealer, opener) =
+                establish(b"host psk", Cipher::Aes256Gcm, &transcript, Role::Host);
+            hub.attach(stream.into_transport(Some(sealer), Some(opener)).unwrap())
+                .unwrap();
+
+            let (mut wrong, _) = establish(b"guess", Cipher::Aes256Gcm, &transcript, Role::Client);
+            let packet = build_take_screenshot_packet();
+            client
+                .write_all(&seal_packet(Some(&mut wrong), 0, &packet))
+                .unwrap();
+            wait_for(|| (hub.session_count() == 0).then_some(()));
+        }
+        assert!(authorization::blocked_for(peer.unwrap()).is_some());
+    }
+}
@@ -0,0 +1,297 @@
+//! Replays client touches and pen strokes on the host desktop. Points are mapped
+//! through the capture target (`capture::map_input`) first, so they land on what
+//! the client is shown. The primary touch and the pen drive the pointer; keys
+//! have no injection path yet.
+
+use std::sync::{Mutex, OnceLock};
+
+use crate::protocol::packets::ClientPacket;
+
+static PRESSED: OnceLock<Mutex<bool>> = OnceLock::new();
+
+fn pressed_store() -> &'static Mutex<bool> {
+    PRESSED.get_or_init(|| Mutex::new(false))
+}
+
+/// One pointer update in desktop (virtual screen) pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerEvent {
+    /// `None` for a release outside the captured picture; the button comes up
+    /// wherever the pointer already is.
+    pub position: Option<(i32, i32)>,
+    pub down: bool,
+}
+
+/// The pointer update a `Touch` or `Pen` packet asks for, with normalized points
+/// mapped by `map`. Contacts outside the captured picture have nowhere to go.
+pub fn pointer_event(
+    packet: &ClientPacket,
+    map: impl Fn(i16, i16) -> Option<(i32, i32)>,
+) -> Option<PointerEvent> {
+    let (x, y, down) = match packet {
+        ClientPacket::Touch(touch) => {
+            let point = touch.points.iter().min_by_key(|point| point.pointer_id)?;
+            (point.x, point.y, point.down)
+        }
+        ClientPacket::Pen(pen) => (pen.x, pen.y, pen.pressure > 0),
+        _ => return None,
+    };
+    let position = map(x, y);
+    if down && position.is_none() {
+        return None;
+    }
+    Some(PointerEvent { position, down })
+}
+
+/// Moves the pointer, pressing or releasing the primary button when the contact
+/// changed since the last event.
+pub fn inject(event: PointerEvent) -> Result<(), String> {
+    let mut pressed = pressed_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    let button = (event.down != *pressed).then_some(event.down);
+    if event.position.is_none() && button.is_none() {
+        return Ok(());
+    }
+    platform::send(event.position, button)?;
+    *pressed = event.down;
+    Ok(())
+}
+
+#[cfg(windows)]
+mod platform {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        SendInput, INPUT, INPUT_0, INPUT_MOUSE, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_LEFTDOWN,
+        MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_VIRTUALDESK, MOUSEINPUT,
+        MOUSE_EVENT_FLAGS,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN,
+        SM_YVIRTUALSCREEN,
+    };
+
+    pub fn send(position: Option<(i32, i32)>, button: Option<bool>) -> Result<(), String> {
+        let mut flags = MOUSE_EVENT_FLAGS(0);
+        let (mut dx, mut dy) = (0, 0);
+        if let Some((x, y)) = position {
+            let (left, top, width, height) = unsafe {
+                (
+                    GetSystemMetrics(SM_XVIRTUALSCREEN),
+                    GetSystemMetrics(SM_YVIRTUALSCREEN),
+                    GetSystemMetrics(SM_CXVIRTUALSCREEN),
+                    GetSystemMetrics(SM_CYVIRTUALSCREEN),
+                )
+            };
+            dx = normalize(x - left, width);
+            dy = normalize(y - top, height);
+            flags |= MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK;
+        }
+        match button {
+            Some(true) => flags |= MOUSEEVENTF_LEFTDOWN,
+            Some(false) => flags |= MOUSEEVENTF_LEFTUP,
+            None => {}
+        }
+        let input = INPUT {
+            r#type: INPUT_MOUSE,
+            Anonymous: INPUT_0 {
+                mi: MOUSEINPUT {
+                    dx,
+                    dy,
+                    mouseData: 0,
+                    dwFlags: flags,
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        };
+        let sent = unsafe { SendInput(&[input], std::mem::size_of::<INPUT>() as i32) };
+        if sent == 1 {
+            Ok(())
+        } else {
+            Err("SendInput was blocked".to_string())
+        }
+    }
+
+    /// Absolute mouse coordinates run from 0 to 65535 across the virtual desktop.
+    fn normalize(offset: i32, extent: i32) -> i32 {
+        let last = (extent - 1).max(1) as i64;
+        (offset.clamp(0, last as i32) as i64 * 65535 / last) as i32
+    }
+}
+
+/// XTest on the X11 display the session captures.
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::sync::{Mutex, OnceLock};
+
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{
+        Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT, MOTION_NOTIFY_EVENT,
+    };
+    use x11rb::protocol::xtest::ConnectionExt as XtestExt;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::CURRENT_TIME;
+
+    use crate::session_state;
+
+    const PRIMARY_BUTTON: u8 = 1;
+
+    struct Display {
+        name: String,
+        conn: RustConnection,
+        root: Window,
+    }
+
+    static DISPLAY: OnceLock<Mutex<Option<Display>>> = OnceLock::new();
+
+    fn display_store() -> &'static Mutex<Option<Display>> {
+        DISPLAY.get_or_init(|| Mutex::new(None))
+    }
+
+    pub fn send(position: Option<(i32, i32)>, button: Option<bool>) -> Result<(), String> {
+        // Same display choice as `X11Source`.
+        let name = match session_state::snapshot().capture_target.display_id() {
+            Some(name) => name.to_string(),
+            None => std::env::var("DISPLAY").map_err(|_| "DISPLAY is not set".to_string())?,
+        };
+        let mut lock = display_store()
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        if lock.as_ref().is_none_or(|display| display.name != name) {
+            let (conn, screen_num) = x11rb::connect(Some(&name))
+                .map_err(|err| format!("X11 connect to {name} failed: {err}"))?;
+            let root = conn.setup().roots[screen_num].root;
+            *lock = Some(Display { name, conn, root });
+        }
+        let Some(display) = lock.as_ref() else {
+            return Ok(());
+        };
+        let result = fake_input(display, position, button);
+        if result.is_err() {
+            // Reconnect on the next event.
+            *lock = None;
+        }
+        result
+    }
+
+    fn fake_input(
+        display: &Display,
+        position: Option<(i32, i32)>,
+        button: Option<bool>,
+    ) -> Result<(), String> {
+        let conn = &display.conn;
+        let error = |err: x11rb::errors::ConnectionError| format!("XTest failed: {err}");
+        if let Some((x, y)) = position {
+            let (x, y) = (
+                x.clamp(0, i16::MAX as i32) as i16,
+                y.clamp(0, i16::MAX as i32) as i16,
+            );
+            conn.xtest_fake_input(MOTION_NOTIFY_EVENT, 0, CURRENT_TIME, display.root, x, y, 0)
+                .map_err(error)?;
+        }
+        if let Some(down) = button {
+            let kind = if down {
+                BUTTON_PRESS_EVENT
+            } else {
+                BUTTON_RELEASE_EVENT
+            };
+            conn.xtest_fake_input(kind, PRIMARY_BUTTON, CURRENT_TIME, display.root, 0, 0, 0)
+                .map_err(error)?;
+        }
+        conn.flush().map_err(error)
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+mod platform {
+    pub fn send(_position: Option<(i32, i32)>, _button: Option<bool>) -> Result<(), String> {
+        Err("Input injection is not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::InputMapping;
+    use crate::protocol::packets::{PenPacket, TouchPacket, TouchPoint};
+    use crate::scaler::{Region, ScaleLayout};
+
+    fn touch(points: &[(u8, bool, i16, i16)]) -> ClientPacket {
+        ClientPacket::Touch(TouchPacket {
+            points: points
+                .iter()
+                .map(|&(pointer_id, down, x, y)| TouchPoint {
+                    pointer_id,
+                    down,
+                    x,
+                    y,
+                    size: 0,
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn maps_points_through_the_capture_target() {
+        // A 200x100 window at (1000, 500), letterboxed into a 200x200 stream.
+        let mapping = InputMapping {
+            stream_width: 200,
+            stream_height: 200,
+            layout: ScaleLayout {
+                source: Region {
+                    x: 0,
+                    y: 0,
+                    width: 200,
+                    height: 100,
+                },
+                target: Region {
+                    x: 0,
+                    y: 50,
+                    width: 200,
+                    height: 100,
+                },
+            },
+            desktop: Region {
+                x: 1000,
+                y: 500,
+                width: 200,
+                height: 100,
+            },
+        };
+        let map = |x, y| mapping.map_normalized(x, y);
+
+        let center = touch(&[(1, true, 32767, 16383), (0, true, 16383, 16383)]);
+        assert_eq!(
+            pointer_event(&center, map),
+            Some(PointerEvent {
+                position: Some((1099, 549)),
+                down: true,
+            })
+        );
+        // The top bar is not part of the window.
+        assert_eq!(pointer_event(&touch(&[(0, true, 16383, 100)]), map), None);
+        assert_eq!(
+            pointer_event(&touch(&[(0, false, 16383, 100)]), map),
+            Some(PointerEvent {
+                position: None,
+                down: false,
+            })
+        );
+
+        let hover = ClientPacket::Pen(PenPacket {
+            flags: 0,
+            x: 0,
+            y: 16383,
+            pressure: 0,
+            rotation: 0,
+            tilt: 0,
+        });
+        assert_eq!(
+            pointer_event(&hover, map),
+            Some(PointerEvent {
+                position: Some((1000, 549)),
+                down: false,
+            })
+        );
+    }
+}
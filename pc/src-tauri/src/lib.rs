@@ -1,2 +1,3 @@
+pub mod protocol;
 pub mod vdd_ops;
 pub mod vdd_protocol;
@@ -1,33 +1,57 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod adb;
+mod aoap_transport;
 mod app_state;
-mod diagnostics_report;
-mod codec;
+mod authorization;
+mod bitstream;
 mod capture;
+mod codec;
+mod codec_profile;
+mod color;
+mod device_registry;
+mod diagnostics_report;
+mod discovery;
 mod display_probe;
-mod driver_manager;
 mod driver_ipc;
-mod linux_vdd;
-mod service_manager;
-mod vdd_protocol;
-mod encoder;
-mod device_registry;
+mod driver_manager;
 mod driver_probe;
+mod encoder;
+mod encoder_probe;
+mod host_listener;
 mod host_log;
 mod host_transport;
+mod input_injection;
+mod linux_vdd;
 mod mf_encoder;
+mod pairing;
+mod protocol;
+mod quic_transport;
+mod recorder;
+mod scaler;
+mod screenshot;
+mod service_manager;
 mod session;
 mod session_state;
+mod settings_registry;
 mod stream_loop;
+mod sw_encoder;
 mod transport_probe;
-mod settings_registry;
-mod protocol;
+mod udp_transport;
+mod vdd_protocol;
+
+use std::sync::Arc;
+
+use host_transport::TransportHandle;
 
 #[tauri::command]
-fn app_status(app_handle: tauri::AppHandle) -> app_state::AppStatus {
+fn app_status(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> app_state::AppStatus {
     let mut status = app_state::AppStatus::default();
     status.driver = driver_probe::probe_driver_status();
-    status.transport = transport_probe::probe_transport_status();
+    status.transport = transport_probe::probe_transport_status(&transport);
     status.devices = device_registry::load_devices(&app_handle);
     status.settings = settings_registry::load_settings(&app_handle);
     status.session = app_state::SessionOverview {
@@ -53,7 +77,13 @@ fn upsert_device(
 ) -> Result<Vec<app_state::PairedDevice>, String> {
     let mut devices = device_registry::load_devices(&app_handle);
     if let Some(existing) = devices.iter_mut().find(|item| item.id == device.id) {
-        *existing = device;
+        // Pins and addresses only change through pairing and authenticated sessions.
+        *existing = app_state::PairedDevice {
+            peer_fingerprint: existing.peer_fingerprint.take(),
+            address: existing.address.take(),
+            port: existing.port.take(),
+            ..device
+        };
     } else {
         devices.push(device);
     }
@@ -86,6 +116,7 @@ fn connect_device(app_handle: tauri::AppHandle, device_id: String) -> Result<Vec
 #[tauri::command]
 fn update_settings(
     app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
     settings: app_state::HostSettings,
 ) -> Result<app_state::HostSettings, String> {
     let codec_id =
@@ -96,33 +127,164 @@ fn update_settings(
     }
     settings_registry::save_settings(&app_handle, &settings)?;
     let _ = host_log::append_log(&app_handle, "Updated host settings");
+    udp_transport::apply(&settings);
+    quic_transport::apply(&app_handle, &settings)?;
+    host_listener::apply(&app_handle, &transport, &settings)?;
+    aoap_transport::apply(&app_handle, &transport, &settings)?;
+    discovery::apply(&app_handle, &settings)?;
     Ok(settings)
 }
 
 #[tauri::command]
-fn reset_settings(app_handle: tauri::AppHandle) -> Result<app_state::HostSettings, String> {
+fn reset_settings(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> Result<app_state::HostSettings, String> {
     let settings = app_state::HostSettings::default();
     settings_registry::save_settings(&app_handle, &settings)?;
     let _ = host_log::append_log(&app_handle, "Reset host settings to defaults");
+    udp_transport::apply(&settings);
+    quic_transport::apply(&app_handle, &settings)?;
+    host_listener::apply(&app_handle, &transport, &settings)?;
+    aoap_transport::apply(&app_handle, &transport, &settings)?;
+    discovery::apply(&app_handle, &settings)?;
     Ok(settings)
 }
 
+#[tauri::command]
+fn adb_candidates(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<app_state::ConnectCandidate>, String> {
+    let settings = settings_registry::load_settings(&app_handle);
+    adb::candidates(&settings)
+}
+
+#[tauri::command]
+fn adb_prepare(
+    app_handle: tauri::AppHandle,
+    serial: String,
+) -> Result<app_state::ConnectCandidate, String> {
+    let settings = settings_registry::load_settings(&app_handle);
+    match adb::prepare(&settings, &serial) {
+        Ok(candidate) => {
+            let _ = host_log::append_log(
+                &app_handle,
+                format!(
+                    "Forwarded 127.0.0.1:{} to {} over ADB",
+                    candidate.port.unwrap_or_default(),
+                    candidate.name
+                ),
+            );
+            Ok(candidate)
+        }
+        Err(err) => {
+            let _ = host_log::append_log(&app_handle, format!("ADB setup failed: {err}"));
+            Err(err)
+        }
+    }
+}
+
+#[tauri::command]
+fn discovered_devices(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<app_state::DiscoveredDevice>, String> {
+    discovery::discovered(&app_handle)
+}
+
+#[tauri::command]
+fn generate_psk() -> Result<String, String> {
+    protocol::secure::generate_psk()
+}
+
+#[tauri::command]
+fn host_identity_fingerprint(app_handle: tauri::AppHandle) -> Result<String, String> {
+    pairing::host_fingerprint(&app_handle)
+}
+
+#[tauri::command]
+fn list_pending_approvals() -> Vec<authorization::PendingApproval> {
+    authorization::pending_approvals()
+}
+
+#[tauri::command]
+fn approve_pending_connection(
+    app_handle: tauri::AppHandle,
+    id: String,
+) -> Result<Vec<app_state::PairedDevice>, String> {
+    authorization::approve(&app_handle, &id)
+}
+
+#[tauri::command]
+fn reject_pending_connection(app_handle: tauri::AppHandle, id: String) -> Result<(), String> {
+    authorization::reject(&app_handle, &id)
+}
+
+#[tauri::command]
+fn host_pairing_qr(
+    app_handle: tauri::AppHandle,
+    format: String,
+) -> Result<pairing::PairingQr, String> {
+    let settings = settings_registry::load_settings(&app_handle);
+    pairing::host_pairing_qr(&app_handle, &settings, &format)
+}
+
+#[tauri::command]
+fn pair_from_qr_payload(
+    app_handle: tauri::AppHandle,
+    payload: String,
+) -> Result<Vec<app_state::PairedDevice>, String> {
+    match pairing::pair_from_qr_payload(&app_handle, &payload) {
+        Ok(devices) => {
+            let _ = host_log::append_log(&app_handle, "Paired device from QR code");
+            Ok(devices)
+        }
+        Err(err) => {
+            let _ = host_log::append_log(&app_handle, format!("QR pairing failed: {err}"));
+            Err(err)
+        }
+    }
+}
+
 #[tauri::command]
 fn negotiate_codec(
     app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
     client_mask: u32,
 ) -> Result<app_state::CodecSelection, String> {
     let settings = settings_registry::load_settings(&app_handle);
     let preferred = codec::codec_id_from_name(&settings.codec);
     let host_mask = codec::host_codec_mask();
-    let selected = codec::select_codec(host_mask, client_mask, preferred)
+    let decision = codec::CodecPolicy {
+        host_mask,
+        client_mask,
+        preferred,
+        opt_in_mask: settings.codec_opt_in_mask(),
+    }
+    .evaluate();
+    let selected = decision
+        .selected
         .ok_or_else(|| "No compatible codec found".to_string())?;
+    // Profile and level follow the stream size, known once a session is configured.
+    let params = match session_state::config_snapshot() {
+        Some(config) => session::codec_parameters(
+            selected,
+            config.width,
+            config.height,
+            settings.refresh_cap_hz.max(1) as u32,
+            transport.last_client_decoder_limits().as_ref(),
+        )?,
+        None => codec_profile::CodecParameters::default(),
+    };
 
     let selection = app_state::CodecSelection {
         codec_id: selected as u8,
         codec_name: codec::codec_name(selected).to_string(),
         host_mask,
         client_mask,
+        codec_profile: params.profile,
+        codec_level: params.level,
+        codec_flags: params.flags,
+        candidates: decision.candidates,
     };
     let _ = host_log::append_log(
         &app_handle,
@@ -383,13 +545,34 @@ fn remove_virtual_display(app_handle: tauri::AppHandle, display_id: String) -> R
 }
 
 #[tauri::command]
-fn set_session_display_target(app_handle: tauri::AppHandle, display_id: Option<String>) -> Result<(), String> {
-    session_state::update_display_target(display_id.clone());
-    let label = display_id.unwrap_or_else(|| "Auto".to_string());
-    let _ = host_log::append_log(&app_handle, format!("Display target set: {label}"));
+fn set_session_display_target(
+    app_handle: tauri::AppHandle,
+    display_id: Option<String>,
+) -> Result<(), String> {
+    set_session_capture_target(app_handle, capture::CaptureTarget::display(display_id))
+}
+
+#[tauri::command]
+fn set_session_capture_target(
+    app_handle: tauri::AppHandle,
+    target: capture::CaptureTarget,
+) -> Result<(), String> {
+    if let capture::CaptureTarget::Region { width, height, .. } = &target {
+        if *width <= 0 || *height <= 0 {
+            return Err(format!("Invalid capture region {width}x{height}"));
+        }
+    }
+    let label = target.label();
+    session_state::update_capture_target(target);
+    let _ = host_log::append_log(&app_handle, format!("Capture target set: {label}"));
     Ok(())
 }
 
+#[tauri::command]
+fn list_capture_windows() -> Vec<capture::CaptureWindow> {
+    capture::list_windows()
+}
+
 #[tauri::command]
 fn export_logs(app_handle: tauri::AppHandle) -> Result<String, String> {
     let path = host_log::export_logs(&app_handle)?;
@@ -405,8 +588,11 @@ fn clear_logs(app_handle: tauri::AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn export_diagnostics(app_handle: tauri::AppHandle) -> Result<String, String> {
-    let status = app_status(app_handle.clone());
+fn export_diagnostics(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> Result<String, String> {
+    let status = app_status(app_handle.clone(), transport);
     let stats = session_state::stats_snapshot();
     let path = diagnostics_report::export_report(&app_handle, status, stats)?;
     let _ = host_log::append_log(&app_handle, "Exported diagnostics report");
@@ -414,24 +600,37 @@ fn export_diagnostics(app_handle: tauri::AppHandle) -> Result<String, String> {
 }
 
 #[tauri::command]
-fn start_session(app_handle: tauri::AppHandle) -> Result<(), String> {
+fn start_session(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> Result<(), String> {
     let state = session_state::snapshot();
     let codec_id = state.codec_id.ok_or_else(|| "No negotiated codec".to_string())?;
     let config = session_state::config_snapshot().ok_or_else(|| "No session config".to_string())?;
-    let display_target_id = state.display_target_id.clone();
+    let capture_target = state.capture_target.clone();
     let settings = settings_registry::load_settings(&app_handle);
     let fps = settings.refresh_cap_hz.max(1) as u32;
     let bitrate_kbps = (settings.quality as u32 * 80).max(500);
     let keyframe_interval = settings.keyframe_interval.max(1) as u32;
+    let backend = state
+        .encoder_backend
+        .unwrap_or_else(|| encoder::select_backend_for_codec(codec_id, None));
     stream_loop::start_streaming(
-        codec_id,
+        Arc::clone(&transport),
+        backend,
         config.encoder_id,
-        config.width,
-        config.height,
-        bitrate_kbps,
-        fps,
-        keyframe_interval,
-        display_target_id,
+        encoder::EncoderConfig {
+            codec_id,
+            params: state.codec_params,
+            width: config.width,
+            height: config.height,
+            fps,
+            bitrate_kbps,
+            keyframe_interval,
+            capture_target,
+            color: settings.color_space(),
+            scale: settings.scale_mode(),
+        },
     )?;
     session_state::update_lifecycle(app_state::SessionLifecycle::Streaming);
     let _ = host_log::append_log(&app_handle, "Start session requested");
@@ -439,8 +638,10 @@ fn start_session(app_handle: tauri::AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn prepare_session(
     app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
     width: i32,
     height: i32,
     host_width: i32,
@@ -458,13 +659,17 @@ fn prepare_session(
         encoder_id,
         client_codec_mask,
         preferred_codec: preferred,
+        codec_opt_in_mask: settings.codec_opt_in_mask(),
+        fps: settings.refresh_cap_hz.max(1) as u32,
+        client_limits: transport.last_client_decoder_limits(),
+        color: settings.color_space(),
     })
     .map_err(|err| {
         session_state::update_lifecycle(app_state::SessionLifecycle::Error);
         err
     })?;
     if let Some(codec_id) = codec::codec_id_from_name(&result.selection.codec_name) {
-        session_state::update_codec(codec_id);
+        session_state::update_codec(codec_id, result.params);
     }
     session_state::update_config(width, height, encoder_id);
     session_state::update_lifecycle(app_state::SessionLifecycle::Configured);
@@ -478,6 +683,7 @@ fn prepare_session(
 #[tauri::command]
 fn tcp_connect_and_configure(
     app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
     host: String,
     port: u16,
     width: i32,
@@ -488,19 +694,66 @@ fn tcp_connect_and_configure(
     client_codec_mask: u32,
 ) -> Result<app_state::CodecSelection, String> {
     session_state::update_lifecycle(app_state::SessionLifecycle::Connecting);
-    if let Err(err) = host_transport::connect(&host, port) {
-        session_state::update_lifecycle(app_state::SessionLifecycle::Error);
-        return Err(err);
-    }
-
+    let settings = settings_registry::load_settings(&app_handle);
+    let security = match authorization::authorize_connect(&app_handle, &settings, &host, port)
+        .and_then(|()| pairing::channel_security(&app_handle, &settings))
+    {
+        Ok(security) => security,
+        Err(err) => {
+            session_state::update_lifecycle(app_state::SessionLifecycle::Error);
+            return Err(err);
+        }
+    };
     let host_caps = protocol::packets::CapabilitiesPacket {
         codec_mask: codec::host_codec_mask(),
         flags: 0,
+        decoder_limits: None,
+        channel_nonce: None,
+        public_key: None,
     };
-    let caps_packet = protocol::packets::build_capabilities_packet(host_caps);
-    host_transport::send_framed_packet(&caps_packet)?;
+    let rekey = settings.rekey_policy();
+    match transport.connect(&host, port, &host_caps, &security, rekey) {
+        Ok(Some(info)) => {
+            if let Some(fingerprint) = &info.peer_fingerprint {
+                if let Err(err) = pairing::record_address(&app_handle, fingerprint, &host, port) {
+                    let _ = host_log::append_log(
+                        &app_handle,
+                        format!("Unable to record device address: {err}"),
+                    );
+                }
+            }
+            let peer = info
+                .peer_fingerprint
+                .map(|fingerprint| {
+                    format!(
+                        " (device {})",
+                        &protocol::key_exchange::format_fingerprint(&fingerprint)[..16]
+                    )
+                })
+                .unwrap_or_default();
+            let _ = host_log::append_log(
+                &app_handle,
+                format!(
+                    "Session encrypted with {}{peer}{}",
+                    info.cipher.name(),
+                    info.path_note()
+                ),
+            );
+        }
+        Ok(None) => {
+            let _ = host_log::append_log(
+                &app_handle,
+                "Warning: session is unencrypted (debug plaintext mode)",
+            );
+        }
+        Err(err) => {
+            session_state::update_lifecycle(app_state::SessionLifecycle::Error);
+            let _ = host_log::append_log(&app_handle, format!("Connect failed: {err}"));
+            authorization::handshake_failed(&app_handle, &transport, &host, port, &err);
+            return Err(err);
+        }
+    }
 
-    let settings = settings_registry::load_settings(&app_handle);
     let preferred = codec::codec_id_from_name(&settings.codec);
     let result = session::prepare_session(session::SessionConfig {
         width,
@@ -510,43 +763,157 @@ fn tcp_connect_and_configure(
         encoder_id,
         client_codec_mask,
         preferred_codec: preferred,
+        codec_opt_in_mask: settings.codec_opt_in_mask(),
+        fps: settings.refresh_cap_hz.max(1) as u32,
+        client_limits: transport.last_client_decoder_limits(),
+        color: settings.color_space(),
     })
     .map_err(|err| {
         session_state::update_lifecycle(app_state::SessionLifecycle::Error);
         err
     })?;
-    if let Some(codec_id) = codec::codec_id_from_name(&result.selection.codec_name) {
-        session_state::update_codec(codec_id);
-    }
-    session_state::update_config(width, height, encoder_id);
-    host_transport::send_framed_packet(&result.configure_bytes)?;
-    host_transport::set_last_session(
+    session::activate(&result, width, height, encoder_id);
+    transport.send_framed_packet(&result.configure_bytes)?;
+    transport.set_last_session(
         host,
         port,
-        caps_packet.clone(),
+        host_caps,
+        security,
+        rekey,
         result.configure_bytes.clone(),
     );
     session_state::update_lifecycle(app_state::SessionLifecycle::Configured);
-    let backend = encoder::select_backend(None);
-    session_state::update_backend(backend);
     Ok(result.selection)
 }
 
 #[tauri::command]
-fn tcp_disconnect() -> Result<(), String> {
-    host_transport::disconnect()?;
+fn tcp_disconnect(transport: tauri::State<'_, TransportHandle>) -> Result<(), String> {
+    transport.disconnect()?;
     session_state::update_lifecycle(app_state::SessionLifecycle::Idle);
     Ok(())
 }
 
 #[tauri::command]
-fn tcp_poll_status() -> (Option<u32>, Option<i32>) {
+fn take_screenshot(app_handle: tauri::AppHandle) -> Result<String, String> {
+    let path = screenshot::take_screenshot(&app_handle)?;
+    let _ = host_log::append_log(&app_handle, format!("Screenshot saved to {path}"));
+    Ok(path)
+}
+
+#[tauri::command]
+fn request_client_screenshot(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> Result<(), String> {
+    transport.send_framed_packet(&protocol::packets::build_take_screenshot_packet())?;
+    let _ = host_log::append_log(&app_handle, "Requested client screenshot");
+    Ok(())
+}
+
+#[tauri::command]
+fn start_recording(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+    format: recorder::RecordingFormat,
+    limits: recorder::RecordingLimits,
+) -> Result<String, String> {
+    let codec_id = session_state::snapshot()
+        .codec_id
+        .ok_or_else(|| "No negotiated codec".to_string())?;
+    let path = recorder::start(
+        &recorder::recordings_dir(&app_handle),
+        format,
+        codec_id,
+        limits,
+    )?;
+    // The recording starts at a keyframe; ask for one rather than wait.
+    transport.request_keyframe();
+    let _ = host_log::append_log(&app_handle, format!("Recording to {path}"));
+    Ok(path)
+}
+
+#[tauri::command]
+fn stop_recording(app_handle: tauri::AppHandle) -> Result<recorder::RecordingStatus, String> {
+    let status = recorder::stop(None)?;
+    if let Some(path) = &status.path {
+        let _ = host_log::append_log(
+            &app_handle,
+            format!("Recording stopped: {path} ({} frames)", status.frames),
+        );
+    }
+    Ok(status)
+}
+
+#[tauri::command]
+fn recording_status() -> recorder::RecordingStatus {
+    recorder::status()
+}
+
+#[tauri::command]
+fn start_protocol_trace(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+    include_payloads: bool,
+) -> Result<String, String> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = app_handle
+        .path_resolver()
+        .app_data_dir()
+        .unwrap_or_else(|| {
+            std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."))
+        })
+        .join("traces")
+        .join(format!(
+            "trace-{timestamp}.{}",
+            protocol::trace::TRACE_EXTENSION
+        ));
+    transport.start_trace(&path, include_payloads)?;
+    let path = path.to_string_lossy().to_string();
+    let detail = if include_payloads {
+        " with payloads"
+    } else {
+        ""
+    };
+    let _ = host_log::append_log(
+        &app_handle,
+        format!("Protocol trace{detail} started: {path}"),
+    );
+    Ok(path)
+}
+
+#[tauri::command]
+fn stop_protocol_trace(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> Result<(), String> {
+    if transport.is_tracing() {
+        transport.stop_trace()?;
+        let _ = host_log::append_log(&app_handle, "Protocol trace stopped");
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn protocol_trace_active(transport: tauri::State<'_, TransportHandle>) -> bool {
+    transport.is_tracing()
+}
+
+#[tauri::command]
+fn tcp_poll_status(transport: tauri::State<'_, TransportHandle>) -> (Option<u32>, Option<i32>) {
     (
-        host_transport::take_last_client_codec_mask(),
-        host_transport::take_last_frame_done(),
+        transport.take_last_client_codec_mask(),
+        transport.take_last_frame_done(),
     )
 }
 
+#[tauri::command]
+fn encoder_capabilities() -> Vec<encoder::EncoderCapability> {
+    encoder::capabilities().entries().to_vec()
+}
+
 #[tauri::command]
 fn session_state_snapshot() -> (Option<u8>, Option<String>) {
     let snapshot = session_state::snapshot();
@@ -597,9 +964,12 @@ fn set_session_input_permissions(
 }
 
 #[tauri::command]
-fn stop_session(app_handle: tauri::AppHandle) -> Result<(), String> {
-    stream_loop::stop_streaming();
-    let lifecycle = if host_transport::is_connected() {
+fn stop_session(
+    app_handle: tauri::AppHandle,
+    transport: tauri::State<'_, TransportHandle>,
+) -> Result<(), String> {
+    transport.stop_streaming();
+    let lifecycle = if transport.is_connected() {
         app_state::SessionLifecycle::Configured
     } else {
         app_state::SessionLifecycle::Idle
@@ -610,7 +980,27 @@ fn stop_session(app_handle: tauri::AppHandle) -> Result<(), String> {
 }
 
 fn main() {
+    let transport = TransportHandle::default();
     tauri::Builder::default()
+        .manage(transport.clone())
+        .setup(move |app| {
+            screenshot::init(&app.handle());
+            let settings = settings_registry::load_settings(&app.handle());
+            udp_transport::apply(&settings);
+            if let Err(err) = quic_transport::apply(&app.handle(), &settings) {
+                let _ = host_log::append_log(&app.handle(), err);
+            }
+            if let Err(err) = host_listener::apply(&app.handle(), &transport, &settings) {
+                let _ = host_log::append_log(&app.handle(), err);
+            }
+            if let Err(err) = aoap_transport::apply(&app.handle(), &transport, &settings) {
+                let _ = host_log::append_log(&app.handle(), err);
+            }
+            if let Err(err) = discovery::apply(&app.handle(), &settings) {
+                let _ = host_log::append_log(&app.handle(), err);
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             app_status,
             platform_name,
@@ -620,6 +1010,16 @@ fn main() {
             connect_device,
             update_settings,
             reset_settings,
+            generate_psk,
+            host_identity_fingerprint,
+            list_pending_approvals,
+            adb_candidates,
+            adb_prepare,
+            discovered_devices,
+            approve_pending_connection,
+            reject_pending_connection,
+            host_pairing_qr,
+            pair_from_qr_payload,
             negotiate_codec,
             list_logs,
             export_logs,
@@ -644,11 +1044,22 @@ fn main() {
             create_virtual_display,
             remove_virtual_display,
             set_session_display_target,
+            set_session_capture_target,
+            list_capture_windows,
             start_session,
             prepare_session,
             tcp_connect_and_configure,
             tcp_disconnect,
             tcp_poll_status,
+            take_screenshot,
+            request_client_screenshot,
+            start_recording,
+            stop_recording,
+            recording_status,
+            start_protocol_trace,
+            stop_protocol_trace,
+            protocol_trace_active,
+            encoder_capabilities,
             session_state_snapshot,
             session_stats_snapshot,
             add_virtual_display,
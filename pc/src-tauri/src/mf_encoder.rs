@@ -1,31 +1,45 @@
+#[cfg(windows)]
+use crate::capture::{
+    self, CaptureHandle, CaptureTarget, DamageTracker, DxgiSource, FrameSource, InputMapping,
+};
 use crate::codec::CodecId;
 #[cfg(windows)]
-use crate::capture;
+use crate::codec_profile::CODEC_FLAG_LOW_DELAY;
+#[cfg(windows)]
+use crate::color::{ColorMatrix, ColorRange, ColorSpace, PixelFormat};
+use crate::encoder::{estimate_timestamp_100ns, EncoderConfig, VideoEncoder};
 
+#[cfg(windows)]
+use std::mem::ManuallyDrop;
+#[cfg(windows)]
+use windows::core::Interface;
 #[cfg(windows)]
 use windows::core::GUID;
 #[cfg(windows)]
+use windows::Win32::Graphics::Direct3D11::ID3D11Texture2D;
+#[cfg(windows)]
 use windows::Win32::Media::MediaFoundation::{
-    IMFActivate, IMFMediaBuffer, IMFMediaType, IMFTransform, MFCreateMediaType, MFCreateMemoryBuffer,
-    MFCreateSample, MFCreateDXGISurfaceBuffer, MFShutdown, MFStartup, MFTEnumEx,
-    MFT_OUTPUT_DATA_BUFFER, MFT_ENUM_FLAG_LOCALMFT, MFT_ENUM_FLAG_SYNCMFT,
-    MFT_MESSAGE_COMMAND_FLUSH, MFT_MESSAGE_COMMAND_DRAIN, MFT_MESSAGE_NOTIFY_BEGIN_STREAMING,
-    MFT_MESSAGE_NOTIFY_START_OF_STREAM, MFT_REGISTER_TYPE_INFO, MFT_CATEGORY_VIDEO_ENCODER,
-    MFMediaType_Video, MFVideoFormat_H264, MFVideoFormat_HEVC, MFVideoFormat_NV12, MFVideoFormat_ARGB32,
-    MF_E_TRANSFORM_NEED_MORE_INPUT, MF_MT_AVG_BITRATE, MF_MT_FRAME_RATE, MF_MT_FRAME_SIZE,
-    MF_MT_INTERLACE_MODE, MF_MT_MAJOR_TYPE, MF_MT_PIXEL_ASPECT_RATIO, MF_MT_SUBTYPE,
-    MFVideoInterlace_Progressive, MF_VERSION,
+    CODECAPI_AVEncCommonMeanBitRate, CODECAPI_AVEncMPVDefaultBPictureCount,
+    CODECAPI_AVEncVideoForceKeyFrame, ICodecAPI, IMFActivate, IMFMediaBuffer, IMFMediaType,
+    IMFTransform, MFCreateDXGISurfaceBuffer, MFCreateMediaType, MFCreateMemoryBuffer,
+    MFCreateSample, MFMediaType_Video, MFNominalRange_0_255, MFNominalRange_16_235, MFShutdown,
+    MFStartup, MFTEnumEx, MFVideoFormat_ARGB32, MFVideoFormat_H264, MFVideoFormat_HEVC,
+    MFVideoFormat_NV12, MFVideoInterlace_Progressive, MFVideoPrimaries_BT2020,
+    MFVideoPrimaries_BT470_2_SysBG, MFVideoPrimaries_BT709, MFVideoTransFunc_2020,
+    MFVideoTransFunc_709, MFVideoTransferMatrix_BT2020_10, MFVideoTransferMatrix_BT601,
+    MFVideoTransferMatrix_BT709, MFT_CATEGORY_VIDEO_ENCODER, MFT_ENUM_FLAG_LOCALMFT,
+    MFT_ENUM_FLAG_SYNCMFT, MFT_MESSAGE_COMMAND_DRAIN, MFT_MESSAGE_COMMAND_FLUSH,
+    MFT_MESSAGE_NOTIFY_BEGIN_STREAMING, MFT_MESSAGE_NOTIFY_START_OF_STREAM, MFT_OUTPUT_DATA_BUFFER,
+    MFT_REGISTER_TYPE_INFO, MF_E_TRANSFORM_NEED_MORE_INPUT, MF_LOW_LATENCY, MF_MT_AVG_BITRATE,
+    MF_MT_DEFAULT_STRIDE, MF_MT_FRAME_RATE, MF_MT_FRAME_SIZE, MF_MT_INTERLACE_MODE,
+    MF_MT_MAJOR_TYPE, MF_MT_MPEG2_LEVEL, MF_MT_MPEG2_PROFILE, MF_MT_PIXEL_ASPECT_RATIO,
+    MF_MT_SUBTYPE, MF_MT_TRANSFER_FUNCTION, MF_MT_VIDEO_NOMINAL_RANGE, MF_MT_VIDEO_PRIMARIES,
+    MF_MT_YUV_MATRIX, MF_VERSION,
 };
 #[cfg(windows)]
 use windows::Win32::System::Com::{
     CoInitializeEx, CoTaskMemFree, CoUninitialize, COINIT_MULTITHREADED,
 };
-#[cfg(windows)]
-use windows::core::Interface;
-#[cfg(windows)]
-use windows::Win32::Graphics::Direct3D11::ID3D11Texture2D;
-#[cfg(windows)]
-use std::mem::ManuallyDrop;
 
 pub struct MfEncoder {
     pub codec_id: CodecId,
@@ -35,6 +49,8 @@ pub struct MfEncoder {
     pub fps: u32,
     pub keyframe_interval: u32,
     frame_index: u64,
+    /// A keyframe was asked for and the encoder could not be told directly.
+    keyframe_requested: bool,
     #[cfg(windows)]
     com_initialized: bool,
     #[cfg(windows)]
@@ -49,39 +65,44 @@ pub struct MfEncoder {
     last_error: Option<String>,
     #[cfg(windows)]
     use_dxgi_surface: bool,
-    pub display_target_id: Option<String>,
+    #[cfg(windows)]
+    capture: CaptureHandle,
+    #[cfg(windows)]
+    surface_source: Option<DxgiSource>,
+    /// Change tracking for the zero-copy path, which bypasses `capture`.
+    #[cfg(windows)]
+    surface_damage: DamageTracker,
+    /// The last frame was skipped because the desktop did not change.
+    #[cfg(windows)]
+    idle: bool,
+    /// The desktop is not at the encode size or the target is cropped, so ARGB
+    /// frames come from the CPU scaler.
+    #[cfg(windows)]
+    scale_surface: bool,
+    #[cfg(windows)]
+    capture_target: CaptureTarget,
 }
 
 impl MfEncoder {
-    pub fn new(
-        codec_id: CodecId,
-        width: i32,
-        height: i32,
-        bitrate_kbps: u32,
-        fps: u32,
-        keyframe_interval: u32,
-        display_target_id: Option<String>,
-    ) -> Result<Self, String> {
-        let aligned_width = (width.max(2)) & !1;
-        let aligned_height = (height.max(2)) & !1;
-        match codec_id {
+    pub fn new(config: EncoderConfig) -> Result<Self, String> {
+        let config = EncoderConfig {
+            width: (config.width.max(2)) & !1,
+            height: (config.height.max(2)) & !1,
+            ..config
+        };
+        match config.codec_id {
             CodecId::H264 | CodecId::H265 => {
                 #[cfg(windows)]
-                let init = init_media_foundation(
-                    codec_id,
-                    aligned_width,
-                    aligned_height,
-                    bitrate_kbps,
-                    fps,
-                )?;
+                let init = init_media_foundation(&config)?;
                 Ok(Self {
-                    codec_id,
-                    width: aligned_width,
-                    height: aligned_height,
-                    bitrate_kbps,
-                    fps,
-                    keyframe_interval,
+                    codec_id: config.codec_id,
+                    width: config.width,
+                    height: config.height,
+                    bitrate_kbps: config.bitrate_kbps,
+                    fps: config.fps,
+                    keyframe_interval: config.keyframe_interval,
                     frame_index: 0,
+                    keyframe_requested: false,
                     #[cfg(windows)]
                     com_initialized: init.com_initialized,
                     #[cfg(windows)]
@@ -96,18 +117,42 @@ impl MfEncoder {
                     last_error: None,
                     #[cfg(windows)]
                     use_dxgi_surface: init.use_dxgi_surface,
-                    display_target_id,
+                    #[cfg(windows)]
+                    capture: CaptureHandle::new(
+                        config.capture_target.clone(),
+                        config.width,
+                        config.height,
+                        config.color,
+                        config.scale,
+                    ),
+                    #[cfg(windows)]
+                    surface_source: None,
+                    #[cfg(windows)]
+                    surface_damage: DamageTracker::default(),
+                    #[cfg(windows)]
+                    idle: false,
+                    #[cfg(windows)]
+                    scale_surface: !config.capture_target.is_whole_display(),
+                    #[cfg(windows)]
+                    capture_target: config.capture_target,
                 })
             }
             _ => Err("Media Foundation encoder supports H.264/H.265 only".to_string()),
         }
     }
+}
 
-    pub fn encode_frame(&mut self) -> (Vec<u8>, Option<u64>) {
+impl VideoEncoder for MfEncoder {
+    fn encode_frame(&mut self) -> (Vec<u8>, Option<u64>) {
         #[cfg(windows)]
-        if let Some((payload, timestamp)) = self.encode_mf_frame() {
-            if !payload.is_empty() {
-                return (payload, Some(timestamp));
+        {
+            if let Some((payload, timestamp)) = self.encode_mf_frame() {
+                if !payload.is_empty() {
+                    return (payload, Some(timestamp));
+                }
+            }
+            if std::mem::take(&mut self.idle) {
+                return (Vec::new(), None);
             }
         }
 
@@ -127,14 +172,61 @@ impl MfEncoder {
             bytes_per_frame = bytes_per_frame.saturating_mul(9) / 10;
         }
         self.frame_index = self.frame_index.wrapping_add(1);
-        if self.keyframe_interval > 0
-            && self.frame_index % self.keyframe_interval as u64 == 0
+        if std::mem::take(&mut self.keyframe_requested)
+            || (self.keyframe_interval > 0
+                && self
+                    .frame_index
+                    .is_multiple_of(self.keyframe_interval as u64))
         {
             bytes_per_frame = bytes_per_frame.saturating_mul(2).min(768 * 1024);
         }
         let timestamp = estimate_timestamp_100ns(self.frame_index, self.fps);
         (vec![0u8; bytes_per_frame as usize], Some(timestamp))
     }
+
+    fn take_last_error(&mut self) -> Option<String> {
+        #[cfg(windows)]
+        {
+            self.last_error.take()
+        }
+        #[cfg(not(windows))]
+        {
+            None
+        }
+    }
+
+    fn request_keyframe(&mut self) {
+        #[cfg(windows)]
+        if let Some(transform) = self.transform.as_ref() {
+            match set_codec_value(transform, &CODECAPI_AVEncVideoForceKeyFrame, 1) {
+                Ok(()) => return,
+                Err(err) => self.last_error = Some(err),
+            }
+        }
+        self.keyframe_requested = true;
+    }
+
+    fn set_bitrate(&mut self, bitrate_kbps: u32) {
+        self.bitrate_kbps = bitrate_kbps;
+        #[cfg(windows)]
+        if let Some(transform) = self.transform.as_ref() {
+            let bps = bitrate_kbps.saturating_mul(1000);
+            if let Err(err) = set_codec_value(transform, &CODECAPI_AVEncCommonMeanBitRate, bps) {
+                self.last_error = Some(err);
+            }
+        }
+    }
+}
+
+/// Changes an encoder property mid-stream through `ICodecAPI`.
+#[cfg(windows)]
+fn set_codec_value(transform: &IMFTransform, api: &GUID, value: u32) -> Result<(), String> {
+    let codec_api: ICodecAPI = transform
+        .cast()
+        .map_err(|err| format!("ICodecAPI unavailable: 0x{:08x}", err.code().0))?;
+    let value = windows::core::VARIANT::from(value);
+    unsafe { codec_api.SetValue(api, &value) }
+        .map_err(|err| format!("ICodecAPI SetValue failed: 0x{:08x}", err.code().0))
 }
 
 #[cfg(windows)]
@@ -164,13 +256,7 @@ struct MfInit {
 }
 
 #[cfg(windows)]
-fn init_media_foundation(
-    codec_id: CodecId,
-    width: i32,
-    height: i32,
-    bitrate_kbps: u32,
-    fps: u32,
-) -> Result<MfInit, String> {
+fn init_media_foundation(config: &EncoderConfig) -> Result<MfInit, String> {
     let com_result = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) };
     let com_initialized = com_result.is_ok();
     if !com_initialized {
@@ -186,7 +272,7 @@ fn init_media_foundation(
     let mf_started = true;
 
     let (transform, output_buffer_len, use_dxgi_surface) =
-        init_transform(codec_id, width, height, bitrate_kbps, fps).unwrap_or((None, 0, false));
+        init_transform(config).unwrap_or((None, 0, false));
     let encoder_available = transform.is_some();
     Ok(MfInit {
         com_initialized,
@@ -199,14 +285,8 @@ fn init_media_foundation(
 }
 
 #[cfg(windows)]
-fn init_transform(
-    codec_id: CodecId,
-    width: i32,
-    height: i32,
-    bitrate_kbps: u32,
-    fps: u32,
-) -> Result<(Option<IMFTransform>, u32, bool), String> {
-    let output_guid: GUID = match codec_id {
+fn init_transform(config: &EncoderConfig) -> Result<(Option<IMFTransform>, u32, bool), String> {
+    let output_guid: GUID = match config.codec_id {
         CodecId::H264 => MFVideoFormat_H264,
         CodecId::H265 => MFVideoFormat_HEVC,
         _ => return Ok((None, 0, false)),
@@ -255,20 +335,29 @@ fn init_transform(
         return Ok((None, 0, false));
     };
 
+    if config.params.flags & CODEC_FLAG_LOW_DELAY != 0 {
+        // Best effort: encoders without these keep their default GOP structure.
+        if let Ok(attributes) = unsafe { transform.GetAttributes() } {
+            let _ = unsafe { attributes.SetUINT32(&MF_LOW_LATENCY, 1) };
+        }
+        let _ = set_codec_value(&transform, &CODECAPI_AVEncMPVDefaultBPictureCount, 0);
+    }
+
     let mut use_dxgi_surface = false;
-    let input_type = build_input_type(MFVideoFormat_ARGB32, width, height, fps)?;
+    let (width, height, fps, color) = (config.width, config.height, config.fps, config.color);
+    let input_type = build_input_type(MFVideoFormat_ARGB32, width, height, fps, color)?;
     let input_result = unsafe { transform.SetInputType(0, &input_type, 0) };
     if input_result.is_ok() {
         use_dxgi_surface = true;
     } else {
-        let nv12_input = build_input_type(MFVideoFormat_NV12, width, height, fps)?;
+        let nv12_input = build_input_type(MFVideoFormat_NV12, width, height, fps, color)?;
         unsafe {
             transform
                 .SetInputType(0, &nv12_input, 0)
                 .map_err(|err| format!("MF SetInputType failed: 0x{:08x}", err.code().0))?;
         }
     }
-    let output_type = build_output_type(codec_id, width, height, fps, bitrate_kbps)?;
+    let output_type = build_output_type(config)?;
     unsafe {
         transform
             .SetOutputType(0, &output_type, 0)
@@ -284,7 +373,13 @@ fn init_transform(
 }
 
 #[cfg(windows)]
-fn build_input_type(subtype: GUID, width: i32, height: i32, fps: u32) -> Result<IMFMediaType, String> {
+fn build_input_type(
+    subtype: GUID,
+    width: i32,
+    height: i32,
+    fps: u32,
+    color: ColorSpace,
+) -> Result<IMFMediaType, String> {
     let media_type = unsafe { MFCreateMediaType() }
         .map_err(|err| format!("MFCreateMediaType failed: 0x{:08x}", err.code().0))?;
     unsafe {
@@ -294,6 +389,12 @@ fn build_input_type(subtype: GUID, width: i32, height: i32, fps: u32) -> Result<
         media_type
             .SetGUID(&MF_MT_SUBTYPE, &subtype)
             .map_err(|err| format!("MF Set subtype failed: 0x{:08x}", err.code().0))?;
+        if subtype == MFVideoFormat_ARGB32 {
+            // Scaled CPU frames are top-down; DXGI surface buffers carry their own pitch.
+            media_type
+                .SetUINT32(&MF_MT_DEFAULT_STRIDE, (width * 4) as u32)
+                .map_err(|err| format!("MF Set stride failed: 0x{:08x}", err.code().0))?;
+        }
         set_attribute_size(&media_type, &MF_MT_FRAME_SIZE, width as u32, height as u32)?;
         set_attribute_ratio(&media_type, &MF_MT_FRAME_RATE, fps, 1)?;
         set_attribute_ratio(&media_type, &MF_MT_PIXEL_ASPECT_RATIO, 1, 1)?;
@@ -301,18 +402,13 @@ fn build_input_type(subtype: GUID, width: i32, height: i32, fps: u32) -> Result<
             .SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)
             .map_err(|err| format!("MF Set interlace failed: 0x{:08x}", err.code().0))?;
     }
+    set_color_attributes(&media_type, color);
     Ok(media_type)
 }
 
 #[cfg(windows)]
-fn build_output_type(
-    codec_id: CodecId,
-    width: i32,
-    height: i32,
-    fps: u32,
-    bitrate_kbps: u32,
-) -> Result<IMFMediaType, String> {
-    let output_guid = match codec_id {
+fn build_output_type(config: &EncoderConfig) -> Result<IMFMediaType, String> {
+    let output_guid = match config.codec_id {
         CodecId::H264 => MFVideoFormat_H264,
         CodecId::H265 => MFVideoFormat_HEVC,
         _ => return Err("Unsupported output codec".to_string()),
@@ -326,19 +422,70 @@ fn build_output_type(
         media_type
             .SetGUID(&MF_MT_SUBTYPE, &output_guid)
             .map_err(|err| format!("MF Set subtype failed: 0x{:08x}", err.code().0))?;
-        set_attribute_size(&media_type, &MF_MT_FRAME_SIZE, width as u32, height as u32)?;
-        set_attribute_ratio(&media_type, &MF_MT_FRAME_RATE, fps, 1)?;
+        set_attribute_size(
+            &media_type,
+            &MF_MT_FRAME_SIZE,
+            config.width as u32,
+            config.height as u32,
+        )?;
+        set_attribute_ratio(&media_type, &MF_MT_FRAME_RATE, config.fps, 1)?;
         set_attribute_ratio(&media_type, &MF_MT_PIXEL_ASPECT_RATIO, 1, 1)?;
         media_type
             .SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)
             .map_err(|err| format!("MF Set interlace failed: 0x{:08x}", err.code().0))?;
         media_type
-            .SetUINT32(&MF_MT_AVG_BITRATE, bitrate_kbps.saturating_mul(1000))
+            .SetUINT32(&MF_MT_AVG_BITRATE, config.bitrate_kbps.saturating_mul(1000))
             .map_err(|err| format!("MF Set bitrate failed: 0x{:08x}", err.code().0))?;
+        // eAVEncH264VProfile/eAVEncH265VProfile and the level enums use the
+        // wire ids, so the negotiated values go in as they are.
+        if config.params.profile != 0 {
+            media_type
+                .SetUINT32(&MF_MT_MPEG2_PROFILE, config.params.profile as u32)
+                .map_err(|err| format!("MF Set profile failed: 0x{:08x}", err.code().0))?;
+        }
+        if config.params.level != 0 {
+            media_type
+                .SetUINT32(&MF_MT_MPEG2_LEVEL, config.params.level as u32)
+                .map_err(|err| format!("MF Set level failed: 0x{:08x}", err.code().0))?;
+        }
     }
+    set_color_attributes(&media_type, config.color);
     Ok(media_type)
 }
 
+/// Tags the media type with the session colour space so the encoder converts with
+/// the same matrix and writes matching VUI. Encoders that ignore these keep working.
+#[cfg(windows)]
+fn set_color_attributes(media_type: &IMFMediaType, color: ColorSpace) {
+    let (matrix, primaries, transfer) = match color.matrix {
+        ColorMatrix::Bt601 => (
+            MFVideoTransferMatrix_BT601.0,
+            MFVideoPrimaries_BT470_2_SysBG.0,
+            MFVideoTransFunc_709.0,
+        ),
+        ColorMatrix::Bt709 => (
+            MFVideoTransferMatrix_BT709.0,
+            MFVideoPrimaries_BT709.0,
+            MFVideoTransFunc_709.0,
+        ),
+        ColorMatrix::Bt2020 => (
+            MFVideoTransferMatrix_BT2020_10.0,
+            MFVideoPrimaries_BT2020.0,
+            MFVideoTransFunc_2020.0,
+        ),
+    };
+    let range = match color.range {
+        ColorRange::Full => MFNominalRange_0_255.0,
+        ColorRange::Limited => MFNominalRange_16_235.0,
+    };
+    unsafe {
+        let _ = media_type.SetUINT32(&MF_MT_YUV_MATRIX, matrix as u32);
+        let _ = media_type.SetUINT32(&MF_MT_VIDEO_PRIMARIES, primaries as u32);
+        let _ = media_type.SetUINT32(&MF_MT_TRANSFER_FUNCTION, transfer as u32);
+        let _ = media_type.SetUINT32(&MF_MT_VIDEO_NOMINAL_RANGE, range as u32);
+    }
+}
+
 #[cfg(windows)]
 fn get_output_buffer_len(transform: &IMFTransform) -> u32 {
     if let Ok(info) = unsafe { transform.GetOutputStreamInfo(0) } {
@@ -408,14 +555,48 @@ fn drain_output(transform: &IMFTransform, output_buffer_len: u32) -> Result<Vec<
 
 #[cfg(windows)]
 impl MfEncoder {
+    /// Leaves an unchanged frame unencoded; timestamps keep advancing so the next
+    /// encoded frame is placed at the right time.
+    fn skip_frame(&mut self) {
+        self.idle = true;
+        self.frame_index = self.frame_index.wrapping_add(1);
+    }
+
     fn encode_mf_frame(&mut self) -> Option<(Vec<u8>, u64)> {
         let transform = self.transform.as_ref()?;
-        let buffer = if self.use_dxgi_surface {
-            match capture::capture_dxgi_surface(
-                self.width,
-                self.height,
-                self.display_target_id.as_deref(),
-            ) {
+        if self.use_dxgi_surface && !self.scale_surface && self.surface_source.is_none() {
+            match DxgiSource::open(&self.capture_target, self.width, self.height) {
+                // Textures go to the encoder as-is, so a desktop at another
+                // resolution has to take the CPU scaler instead.
+                Ok(source) if source.size() != Some((self.width, self.height)) => {
+                    self.scale_surface = true;
+                }
+                Ok(source) => self.surface_source = Some(source),
+                Err(err) => {
+                    self.last_error = Some(err);
+                    return None;
+                }
+            }
+        }
+        let buffer = if self.use_dxgi_surface && !self.scale_surface {
+            let source = self.surface_source.as_mut()?;
+            let frame = source.acquire_surface(capture::ACQUIRE_TIMEOUT);
+            let encode = frame.is_ok()
+                && self.surface_damage.observe(
+                    source.damage(),
+                    self.width,
+                    self.height,
+                    std::time::Instant::now(),
+                );
+            let mut stats = source.stats();
+            self.surface_damage.annotate(&mut stats);
+            capture::publish_stats(stats);
+            capture::publish_input_mapping(InputMapping::direct(source.bounds()));
+            match frame {
+                Ok(_) if !encode => {
+                    self.skip_frame();
+                    return None;
+                }
                 Ok(frame) => {
                     let buffer = unsafe {
                         MFCreateDXGISurfaceBuffer(
@@ -439,14 +620,28 @@ impl MfEncoder {
                     return None;
                 }
             }
+        } else if self.use_dxgi_surface {
+            let bgra = self.capture.acquire_bgra().ok();
+            if self.capture.unchanged() {
+                self.skip_frame();
+                return None;
+            }
+            let frame_len = self.width as usize * self.height as usize * 4;
+            match create_sample_buffer(frame_len, bgra.as_deref()) {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    self.last_error = Some(err);
+                    return None;
+                }
+            }
         } else {
-            let nv12 = capture::capture_nv12_with_target(
-                self.width,
-                self.height,
-                self.display_target_id.as_deref(),
-            )
-            .ok();
-            match create_nv12_sample_with_data(self.width, self.height, nv12.as_deref()) {
+            let nv12 = self.capture.acquire(PixelFormat::Nv12).ok();
+            if self.capture.unchanged() {
+                self.skip_frame();
+                return None;
+            }
+            let frame_len = PixelFormat::Nv12.frame_len(self.width as usize, self.height as usize);
+            match create_sample_buffer(frame_len, nv12.as_deref()) {
                 Ok(buffer) => buffer,
                 Err(err) => {
                     self.last_error = Some(err);
@@ -487,27 +682,10 @@ impl MfEncoder {
             }
         }
     }
-
-    pub fn take_last_error(&mut self) -> Option<String> {
-        self.last_error.take()
-    }
-}
-
-#[cfg(not(windows))]
-impl MfEncoder {
-    pub fn take_last_error(&mut self) -> Option<String> {
-        None
-    }
 }
 
 #[cfg(windows)]
-fn create_nv12_sample_with_data(
-    width: i32,
-    height: i32,
-    data: Option<&[u8]>,
-) -> Result<IMFMediaBuffer, String> {
-    let frame_size = (width.max(1) as usize) * (height.max(1) as usize);
-    let buffer_len = frame_size + (frame_size / 2);
+fn create_sample_buffer(buffer_len: usize, data: Option<&[u8]>) -> Result<IMFMediaBuffer, String> {
     let buffer = unsafe { MFCreateMemoryBuffer(buffer_len as u32) }
         .map_err(|err| format!("MFCreateMemoryBuffer failed: 0x{:08x}", err.code().0))?;
     unsafe {
@@ -567,8 +745,3 @@ fn set_attribute_ratio(
             .map_err(|err| format!("MF Set ratio failed: 0x{:08x}", err.code().0))
     }
 }
-
-fn estimate_timestamp_100ns(frame_index: u64, fps: u32) -> u64 {
-    let fps = fps.max(1) as u64;
-    frame_index.saturating_mul(10_000_000 / fps)
-}
@@ -0,0 +1,468 @@
+use std::fs;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use qrcode::{Color, QrCode};
+use serde::{Deserialize, Serialize};
+
+use crate::app_state::{HostSettings, PairedDevice};
+use crate::authorization::AuthMode;
+use crate::device_registry;
+use crate::protocol::handshake::PROTOCOL_VERSION;
+use crate::protocol::key_exchange::{
+    fingerprint, format_fingerprint, parse_fingerprint, Identity, KEY_LEN,
+};
+use crate::protocol::qr_payload::{
+    self, Bootstrap, PairingPayload, PreferredTransport, PAIRING_NONCE_LEN,
+};
+use crate::protocol::secure::{random_nonce, ChannelSecurity, Cipher, CipherChoice, PeerTrust};
+use crate::session_state;
+use crate::settings_registry;
+
+const IDENTITY_FILE: &str = "host_identity.json";
+pub const PUBLIC_KEY_MODE: &str = "Public key";
+const QR_LIFETIME_SECS: u64 = 5 * 60;
+const QR_MODULE_PIXELS: usize = 8;
+const QR_QUIET_ZONE: usize = 4;
+
+/// Nonces of scanned codes with their expiry, so a code pairs only once.
+type UsedNonces = Vec<([u8; PAIRING_NONCE_LEN], u64)>;
+
+static USED_QR_NONCES: OnceLock<Mutex<UsedNonces>> = OnceLock::new();
+
+fn used_qr_nonces_store() -> &'static Mutex<UsedNonces> {
+    USED_QR_NONCES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// The host's own pairing code, ready to display.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingQr {
+    pub payload: String,
+    /// SVG markup, or a `data:image/png;base64,` URL.
+    pub image: String,
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredIdentity {
+    seed: String,
+}
+
+/// The host's long-term identity, created on first use.
+pub fn load_identity(app_handle: &tauri::AppHandle) -> Result<Identity, String> {
+    let path = identity_path(app_handle);
+    if let Ok(contents) = fs::read_to_string(&path) {
+        let stored: StoredIdentity =
+            serde_json::from_str(&contents).map_err(|err| format!("{}: {err}", path.display()))?;
+        let seed = parse_fingerprint(&stored.seed)
+            .ok_or_else(|| format!("{}: invalid identity seed", path.display()))?;
+        return Ok(Identity::from_seed(&seed));
+    }
+
+    let identity = Identity::generate()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let stored = StoredIdentity {
+        seed: format_fingerprint(&identity.seed()),
+    };
+    let payload = serde_json::to_string_pretty(&stored).map_err(|err| err.to_string())?;
+    fs::write(&path, payload).map_err(|err| err.to_string())?;
+    Ok(identity)
+}
+
+pub fn host_fingerprint(app_handle: &tauri::AppHandle) -> Result<String, String> {
+    Ok(format_fingerprint(
+        &load_identity(app_handle)?.fingerprint(),
+    ))
+}
+
+/// Channel security for the configured mode. In public-key mode every pinned device
+/// is trusted, and the active device must present exactly its pinned key.
+pub fn channel_security(
+    app_handle: &tauri::AppHandle,
+    settings: &HostSettings,
+) -> Result<ChannelSecurity, String> {
+    if settings.crypto_mode != PUBLIC_KEY_MODE {
+        return Ok(settings.channel_security());
+    }
+    let devices = device_registry::load_devices(app_handle);
+    let active = session_state::snapshot().active_device_id;
+    let expected = active
+        .and_then(|id| devices.iter().find(|device| device.id == id))
+        .and_then(pinned_fingerprint);
+    Ok(ChannelSecurity::PublicKey {
+        identity: Box::new(load_identity(app_handle)?),
+        cipher: Cipher::from_name(&settings.cipher)
+            .map(CipherChoice::Fixed)
+            .unwrap_or(CipherChoice::Auto),
+        trust: PeerTrust {
+            trusted: devices.iter().filter_map(pinned_fingerprint).collect(),
+            expected,
+            allow_pairing: AuthMode::from_name(&settings.auth_mode) != AuthMode::PairedOnly,
+        },
+    })
+}
+
+/// Pins `fingerprint` on the device already carrying it, else on a new device,
+/// recording `address` when known.
+pub fn pin_fingerprint(
+    app_handle: &tauri::AppHandle,
+    fingerprint: &str,
+    transport: &str,
+    address: Option<(&str, u16)>,
+) -> Result<Vec<PairedDevice>, String> {
+    let mut devices = device_registry::load_devices(app_handle);
+    pin_device(&mut devices, fingerprint, transport, address)?;
+    device_registry::save_devices(app_handle, &devices)?;
+    Ok(devices)
+}
+
+/// The registry side of `pin_fingerprint`. Only an identical pin is reused: the
+/// active session's device belongs to whoever is streaming, not to the client
+/// being approved.
+fn pin_device(
+    devices: &mut Vec<PairedDevice>,
+    fingerprint: &str,
+    transport: &str,
+    address: Option<(&str, u16)>,
+) -> Result<(), String> {
+    let fingerprint = parse_fingerprint(fingerprint)
+        .map(|bytes| format_fingerprint(&bytes))
+        .ok_or_else(|| "Invalid fingerprint".to_string())?;
+    let index = devices
+        .iter()
+        .position(|device| device.peer_fingerprint.as_deref() == Some(&fingerprint))
+        .unwrap_or_else(|| {
+            devices.push(PairedDevice {
+                id: format!("key-{}", &fingerprint[..12]),
+                name: format!("Device {}", &fingerprint[..8]),
+                transport: transport.to_string(),
+                status: "Paired".to_string(),
+                last_seen: None,
+                input_permissions: Default::default(),
+                peer_fingerprint: None,
+                address: None,
+                port: None,
+            });
+            devices.len() - 1
+        });
+    let device = &mut devices[index];
+    device.peer_fingerprint = Some(fingerprint);
+    if let Some((host, port)) = address {
+        set_address(device, host, port);
+    }
+    Ok(())
+}
+
+/// Records where the device pinned to `fingerprint` was just reached. Called only
+/// after a handshake proved the pinned key, so a spoofed announcement cannot
+/// redirect a paired device.
+pub fn record_address(
+    app_handle: &tauri::AppHandle,
+    fingerprint: &[u8; 32],
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let mut devices = device_registry::load_devices(app_handle);
+    if move_device(&mut devices, fingerprint, host, port) {
+        device_registry::save_devices(app_handle, &devices)?;
+    }
+    Ok(())
+}
+
+/// The registry side of `record_address`; whether anything changed.
+fn move_device(
+    devices: &mut [PairedDevice],
+    fingerprint: &[u8; 32],
+    host: &str,
+    port: u16,
+) -> bool {
+    let fingerprint = format_fingerprint(fingerprint);
+    let Some(device) = devices.iter_mut().find(|device| {
+        device
+            .peer_fingerprint
+            .as_deref()
+            .is_some_and(|pinned| pinned.eq_ignore_ascii_case(&fingerprint))
+    }) else {
+        return false;
+    };
+    let before = (device.address.clone(), device.port);
+    set_address(device, host, port);
+    before != (device.address.clone(), device.port)
+}
+
+fn set_address(device: &mut PairedDevice, host: &str, port: u16) {
+    device.address = Some(host.to_string());
+    // Clients that dialed in have no known port; keep the saved one.
+    if port != 0 {
+        device.port = Some(port);
+    }
+}
+
+/// Signs a short-lived pairing code for this host and renders it as `svg` or `png`.
+/// Public-key mode (or no PSK set) pins the host's fingerprint; otherwise the code
+/// carries the PSK.
+pub fn host_pairing_qr(
+    app_handle: &tauri::AppHandle,
+    settings: &HostSettings,
+    format: &str,
+) -> Result<PairingQr, String> {
+    let identity = load_identity(app_handle)?;
+    let psk = settings.psk.trim();
+    let bootstrap = if settings.crypto_mode == PUBLIC_KEY_MODE || psk.is_empty() {
+        Bootstrap::Fingerprint(identity.fingerprint())
+    } else {
+        Bootstrap::Psk(psk.to_string())
+    };
+    let expires_at = unix_now() + QR_LIFETIME_SECS;
+    let payload = PairingPayload {
+        address: local_address().ok_or_else(|| "No network address found".to_string())?,
+        hostname: host_name(),
+        tcp_port: settings.listen_port(),
+        quic_port: settings.quic_enabled.then(|| settings.quic_port()),
+        transport: PreferredTransport::Tcp,
+        min_protocol_version: PROTOCOL_VERSION,
+        bootstrap,
+        nonce: random_nonce()?,
+        expires_at,
+    };
+    let text = qr_payload::encode(&payload, &identity);
+    let code = QrCode::new(text.as_bytes()).map_err(|err| err.to_string())?;
+    let image = match format {
+        "svg" => code
+            .render::<qrcode::render::svg::Color>()
+            .min_dimensions(256, 256)
+            .build(),
+        "png" => format!("data:image/png;base64,{}", STANDARD.encode(qr_png(&code)?)),
+        other => return Err(format!("Unsupported QR format {other}")),
+    };
+    Ok(PairingQr {
+        payload: text,
+        image,
+        expires_at,
+    })
+}
+
+/// Pairs with a device from its scanned code: pins the key that signed it, stores
+/// where to reach it and, for PSK codes, adopts the key.
+pub fn pair_from_qr_payload(
+    app_handle: &tauri::AppHandle,
+    text: &str,
+) -> Result<Vec<PairedDevice>, String> {
+    let now = unix_now();
+    let verified = qr_payload::decode(text, now).map_err(|err| err.to_string())?;
+    let payload = verified.payload;
+    {
+        let mut used = used_qr_nonces_store()
+            .lock()
+            .map_err(|_| "Lock poisoned".to_string())?;
+        used.retain(|(_, expires_at)| *expires_at > now);
+        if used.iter().any(|(nonce, _)| *nonce == payload.nonce) {
+            return Err("This pairing code was already used".to_string());
+        }
+        used.push((payload.nonce, payload.expires_at));
+    }
+
+    if let Bootstrap::Psk(psk) = &payload.bootstrap {
+        let mut settings = settings_registry::load_settings(app_handle);
+        settings.psk = psk.clone();
+        settings_registry::save_settings(app_handle, &settings)?;
+    }
+    let fingerprint = format_fingerprint(&fingerprint(&verified.signer));
+    let mut devices = device_registry::load_devices(app_handle);
+    let index = devices
+        .iter()
+        .position(|device| device.peer_fingerprint.as_deref() == Some(fingerprint.as_str()))
+        .unwrap_or_else(|| {
+            devices.push(PairedDevice {
+                id: format!("key-{}", &fingerprint[..12]),
+                name: String::new(),
+                transport: "Wi-Fi".to_string(),
+                status: "Paired".to_string(),
+                last_seen: None,
+                input_permissions: Default::default(),
+                peer_fingerprint: Some(fingerprint.clone()),
+                address: None,
+                port: None,
+            });
+            devices.len() - 1
+        });
+    let device = &mut devices[index];
+    device.name = payload
+        .hostname
+        .clone()
+        .unwrap_or_else(|| payload.address.clone());
+    device.address = Some(payload.address.clone());
+    device.port = Some(payload.tcp_port);
+    device_registry::save_devices(app_handle, &devices)?;
+    Ok(devices)
+}
+
+fn qr_png(code: &QrCode) -> Result<Vec<u8>, String> {
+    let modules = code.width();
+    let size = (modules + 2 * QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+    let colors = code.to_colors();
+    let mut pixels = vec![0xffu8; size * size];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x = (index % modules + QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+        let y = (index / modules + QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+        for row in y..y + QR_MODULE_PIXELS {
+            pixels[row * size + x..row * size + x + QR_MODULE_PIXELS].fill(0);
+        }
+    }
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer
+        .write_image_data(&pixels)
+        .map_err(|err| err.to_string())?;
+    drop(writer);
+    Ok(output)
+}
+
+/// Address of the interface that routes outward; nothing is sent.
+fn local_address() -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    let address = socket.local_addr().ok()?.ip();
+    (!address.is_unspecified()).then(|| address.to_string())
+}
+
+fn host_name() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn pinned_fingerprint(device: &PairedDevice) -> Option<[u8; KEY_LEN]> {
+    device
+        .peer_fingerprint
+        .as_deref()
+        .and_then(parse_fingerprint)
+}
+
+fn identity_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    app_handle
+        .path_resolver()
+        .app_data_dir()
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+        .join(IDENTITY_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_qr_png_with_quiet_zone() {
+        let code = QrCode::new(b"uberdisplay:test").unwrap();
+        let png = qr_png(&code).unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        let size = (code.width() + 2 * QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+        assert_eq!((info.width as usize, info.height as usize), (size, size));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+
+        let margin = QR_QUIET_ZONE * QR_MODULE_PIXELS;
+        assert!(pixels[..margin * size].iter().all(|pixel| *pixel == 0xff));
+        // Every code starts with a dark finder pattern in its top-left module.
+        assert_eq!(pixels[margin * size + margin], 0);
+    }
+
+    #[test]
+    fn approving_a_client_leaves_the_active_device_pin_alone() {
+        let first = format_fingerprint(&[0x11; KEY_LEN]);
+        let second = format_fingerprint(&[0x22; KEY_LEN]);
+        let mut devices = vec![PairedDevice {
+            id: "tablet".to_string(),
+            name: "Tablet".to_string(),
+            transport: "Wi-Fi".to_string(),
+            status: "Paired".to_string(),
+            last_seen: None,
+            input_permissions: Default::default(),
+            peer_fingerprint: Some(first.clone()),
+            address: Some("192.168.1.20".to_string()),
+            port: Some(7000),
+        }];
+        session_state::update_active_device(Some("tablet".to_string()), Default::default());
+
+        pin_device(&mut devices, &second, "Wi-Fi", Some(("192.168.1.30", 0))).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].peer_fingerprint.as_deref(), Some(first.as_str()));
+        assert_eq!(devices[0].address.as_deref(), Some("192.168.1.20"));
+        assert_eq!(
+            devices[1].peer_fingerprint.as_deref(),
+            Some(second.as_str())
+        );
+        assert_eq!(devices[1].address.as_deref(), Some("192.168.1.30"));
+
+        // Approving the same key again updates its entry rather than adding one.
+        pin_device(&mut devices, &second, "Wi-Fi", Some(("192.168.1.31", 0))).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].address.as_deref(), Some("192.168.1.31"));
+        session_state::update_active_device(None, Default::default());
+    }
+
+    #[test]
+    fn authenticated_sessions_move_only_the_pinned_device() {
+        let mut devices = vec![PairedDevice {
+            id: "tablet".to_string(),
+            name: "Tablet".to_string(),
+            transport: "Wi-Fi".to_string(),
+            status: "Paired".to_string(),
+            last_seen: None,
+            input_permissions: Default::default(),
+            peer_fingerprint: Some(format_fingerprint(&[0x11; KEY_LEN]).to_uppercase()),
+            address: Some("192.168.1.20".to_string()),
+            port: Some(7000),
+        }];
+
+        assert!(!move_device(
+            &mut devices,
+            &[0x22; KEY_LEN],
+            "192.168.1.99",
+            7001
+        ));
+        assert_eq!(devices[0].address.as_deref(), Some("192.168.1.20"));
+
+        // A client that dialed in keeps its saved port.
+        assert!(move_device(
+            &mut devices,
+            &[0x11; KEY_LEN],
+            "192.168.1.21",
+            0
+        ));
+        assert_eq!(devices[0].address.as_deref(), Some("192.168.1.21"));
+        assert_eq!(devices[0].port, Some(7000));
+        assert!(!move_device(
+            &mut devices,
+            &[0x11; KEY_LEN],
+            "192.168.1.21",
+            0
+        ));
+    }
+}
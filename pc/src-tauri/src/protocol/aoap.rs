@@ -0,0 +1,653 @@
+//! Host side of the Android Open Accessory protocol. A phone on USB is asked
+//! for its accessory protocol version, sent the identification strings the
+//! client's accessory filter matches, and told to start; it drops off the bus
+//! and comes back under Google's accessory ids with a bulk IN/OUT pair. The
+//! session then runs over that pair like any other byte stream.
+//!
+//! USB access goes through `UsbBackend` and `UsbDevice`, so the sequence and the
+//! transport run against a mock in tests.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::secure::{Opener, Sealer};
+use super::transport::{
+    seal_packet, Counters, HandshakeStream, PacketReader, Transport, TransportError, TransportStats,
+};
+
+pub const GOOGLE_VID: u16 = 0x18d1;
+/// Accessory, accessory + ADB, and the audio variants of both.
+pub const ACCESSORY_PIDS: [u16; 6] = [0x2d00, 0x2d01, 0x2d02, 0x2d03, 0x2d04, 0x2d05];
+
+/// Vendor control requests (device recipient).
+pub const REQUEST_GET_PROTOCOL: u8 = 51;
+pub const REQUEST_SEND_STRING: u8 = 52;
+pub const REQUEST_START: u8 = 53;
+
+pub const STRING_MANUFACTURER: u16 = 0;
+pub const STRING_MODEL: u16 = 1;
+pub const STRING_DESCRIPTION: u16 = 2;
+pub const STRING_VERSION: u16 = 3;
+pub const STRING_URI: u16 = 4;
+pub const STRING_SERIAL: u16 = 5;
+
+const USB_CLASS_HUB: u8 = 9;
+
+pub const CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a phone may take to come back in accessory mode.
+pub const REENUMERATION_TIMEOUT: Duration = Duration::from_secs(5);
+const REENUMERATION_POLL: Duration = Duration::from_millis(100);
+/// Bulk reads always ask for this much; a shorter buffer than the transfer the
+/// phone sends overflows.
+pub const BULK_BUFFER: usize = 16 * 1024;
+/// How often blocked bulk transfers check whether the session was closed.
+const BULK_POLL: Duration = Duration::from_millis(100);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Writes queued ahead of the OUT endpoint, so senders do not wait for each
+/// transfer to complete.
+const WRITE_QUEUE: usize = 64;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AoapError {
+    #[error("USB transfer timed out")]
+    Timeout,
+    #[error("USB device disconnected")]
+    Disconnected,
+    #[error("the device does not support accessory mode")]
+    NotSupported,
+    #[error("the device did not come back in accessory mode")]
+    NoAccessory,
+    #[error("USB error: {0}")]
+    Usb(String),
+}
+
+/// A device on the bus, as enumerated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbDeviceInfo {
+    pub bus: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub class: u8,
+}
+
+impl UsbDeviceInfo {
+    pub fn is_accessory(&self) -> bool {
+        self.vendor_id == GOOGLE_VID && ACCESSORY_PIDS.contains(&self.product_id)
+    }
+}
+
+/// An opened device. Control transfers are vendor requests to the device; bulk
+/// transfers use the accessory interface, which only accessories have.
+pub trait UsbDevice: Send + Sync + 'static {
+    fn control_in(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, AoapError>;
+    fn control_out(
+        &self,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<usize, AoapError>;
+    fn bulk_read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, AoapError>;
+    fn bulk_write(&self, data: &[u8], timeout: Duration) -> Result<usize, AoapError>;
+}
+
+pub trait UsbBackend {
+    type Device: UsbDevice;
+    fn devices(&self) -> Result<Vec<UsbDeviceInfo>, AoapError>;
+    /// Opens a device; accessories come back with their bulk interface claimed.
+    fn open(&self, info: &UsbDeviceInfo) -> Result<Self::Device, AoapError>;
+}
+
+/// What the host identifies itself as. The client only answers to the
+/// manufacturer, model and version in its accessory filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessoryStrings {
+    pub manufacturer: String,
+    pub model: String,
+    pub description: String,
+    pub version: String,
+    pub uri: String,
+    pub serial: String,
+}
+
+impl Default for AccessoryStrings {
+    fn default() -> Self {
+        Self {
+            manufacturer: "UberDisplay".to_string(),
+            model: "AOAP".to_string(),
+            description: "UberDisplay host".to_string(),
+            version: "1".to_string(),
+            uri: "https://github.com/supermarsx/uberdisplay".to_string(),
+            serial: String::new(),
+        }
+    }
+}
+
+impl AccessoryStrings {
+    fn indexed(&self) -> [(u16, &str); 6] {
+        [
+            (STRING_MANUFACTURER, &self.manufacturer),
+            (STRING_MODEL, &self.model),
+            (STRING_DESCRIPTION, &self.description),
+            (STRING_VERSION, &self.version),
+            (STRING_URI, &self.uri),
+            (STRING_SERIAL, &self.serial),
+        ]
+    }
+}
+
+/// The device's accessory protocol version; 0 means it has none.
+pub fn protocol_version<D: UsbDevice>(device: &D) -> Result<u16, AoapError> {
+    let mut version = [0u8; 2];
+    let read = device.control_in(REQUEST_GET_PROTOCOL, 0, 0, &mut version)?;
+    if read < version.len() {
+        return Err(AoapError::NotSupported);
+    }
+    Ok(u16::from_le_bytes(version))
+}
+
+/// Sends the identification strings and switches the device to accessory mode.
+pub fn start_accessory<D: UsbDevice>(
+    device: &D,
+    strings: &AccessoryStrings,
+) -> Result<(), AoapError> {
+    if protocol_version(device)? == 0 {
+        return Err(AoapError::NotSupported);
+    }
+    for (index, value) in strings.indexed() {
+        let mut data = Vec::with_capacity(value.len() + 1);
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+        device.control_out(REQUEST_SEND_STRING, 0, index, &data)?;
+    }
+    device.control_out(REQUEST_START, 0, 0, &[])?;
+    Ok(())
+}
+
+/// Opens an accessory on the bus, switching a phone to accessory mode first if
+/// there is none. Devices that turn the switch down are added to `refused` and
+/// left alone while they stay plugged in. `None` when no device could be used.
+pub fn connect<B: UsbBackend>(
+    backend: &B,
+    strings: &AccessoryStrings,
+    refused: &mut Vec<UsbDeviceInfo>,
+) -> Result<Option<B::Device>, AoapError> {
+    let devices = backend.devices()?;
+    refused.retain(|info| devices.contains(info));
+    if let Some(accessory) = devices.iter().find(|info| info.is_accessory()) {
+        return backend.open(accessory).map(Some);
+    }
+
+    let mut switched = false;
+    for info in &devices {
+        if info.class == USB_CLASS_HUB || refused.contains(info) {
+            continue;
+        }
+        let started = backend
+            .open(info)
+            .and_then(|device| start_accessory(&device, strings));
+        if started.is_ok() {
+            switched = true;
+            break;
+        }
+        refused.push(*info);
+    }
+    if !switched {
+        return Ok(None);
+    }
+
+    let deadline = Instant::now() + REENUMERATION_TIMEOUT;
+    loop {
+        if let Some(accessory) = backend.devices()?.iter().find(|info| info.is_accessory()) {
+            return backend.open(accessory).map(Some);
+        }
+        if Instant::now() >= deadline {
+            return Err(AoapError::NoAccessory);
+        }
+        thread::sleep(REENUMERATION_POLL);
+    }
+}
+
+/// An accessory's bulk pair as a byte stream, for the handshake.
+pub struct UsbStream<D: UsbDevice> {
+    device: Arc<D>,
+    read_timeout: Option<Duration>,
+    /// Received past what the handshake has read so far.
+    pending: VecDeque<u8>,
+}
+
+impl<D: UsbDevice> UsbStream<D> {
+    pub fn new(device: D) -> Self {
+        Self {
+            device: Arc::new(device),
+            read_timeout: None,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<D: UsbDevice> Read for UsbStream<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        let mut transfer = vec![0u8; BULK_BUFFER];
+        while self.pending.is_empty() {
+            match self.device.bulk_read(&mut transfer, BULK_POLL) {
+                Ok(read) => self.pending.extend(&transfer[..read]),
+                Err(AoapError::Timeout) => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                }
+                Err(AoapError::Disconnected) => return Ok(0),
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+        let count = buf.len().min(self.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(self.pending.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl<D: UsbDevice> Write for UsbStream<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = &buf[..buf.len().min(BULK_BUFFER)];
+        self.device
+            .bulk_write(chunk, WRITE_TIMEOUT)
+            .map_err(io::Error::other)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<D: UsbDevice> HandshakeStream for UsbStream<D> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn into_transport(
+        self,
+        sealer: Option<Sealer>,
+        opener: Option<Opener>,
+    ) -> Result<Arc<dyn Transport>, TransportError> {
+        Ok(Arc::new(AoapTransport::start(self, sealer, opener)))
+    }
+}
+
+/// A session over an accessory's bulk pair. A reader thread keeps a bulk read
+/// outstanding and a writer thread drains queued writes, so neither direction
+/// waits on the other's transfers.
+pub struct AoapTransport {
+    outgoing: Mutex<Outgoing>,
+    incoming: Mutex<Incoming>,
+    open: Arc<AtomicBool>,
+    counters: Counters,
+}
+
+struct Outgoing {
+    sealer: Option<Sealer>,
+    queue: SyncSender<Vec<u8>>,
+}
+
+struct Incoming {
+    queue: Receiver<Vec<u8>>,
+    packets: PacketReader,
+}
+
+impl AoapTransport {
+    fn start<D: UsbDevice>(
+        stream: UsbStream<D>,
+        sealer: Option<Sealer>,
+        opener: Option<Opener>,
+    ) -> Self {
+        let open = Arc::new(AtomicBool::new(true));
+        let (received, incoming) = mpsc::channel();
+        let (outgoing, to_write) = mpsc::sync_channel(WRITE_QUEUE);
+        // Whatever arrived behind the handshake belongs to the session.
+        if !stream.pending.is_empty() {
+            let _ = received.send(Vec::from(stream.pending));
+        }
+        {
+            let device = stream.device.clone();
+            let open = open.clone();
+            thread::spawn(move || read_bulk(device.as_ref(), &open, &received));
+        }
+        {
+            let device = stream.device;
+            let open = open.clone();
+            thread::spawn(move || write_bulk(device.as_ref(), &open, &to_write));
+        }
+        Self {
+            outgoing: Mutex::new(Outgoing {
+                sealer,
+                queue: outgoing,
+            }),
+            incoming: Mutex::new(Incoming {
+                queue: incoming,
+                packets: PacketReader::new(opener),
+            }),
+            open,
+            counters: Counters::default(),
+        }
+    }
+}
+
+fn read_bulk<D: UsbDevice>(device: &D, open: &AtomicBool, received: &mpsc::Sender<Vec<u8>>) {
+    let mut transfer = vec![0u8; BULK_BUFFER];
+    while open.load(Ordering::SeqCst) {
+        match device.bulk_read(&mut transfer, BULK_POLL) {
+            Ok(read) => {
+                if received.send(transfer[..read].to_vec()).is_err() {
+                    break;
+                }
+            }
+            Err(AoapError::Timeout) => {}
+            Err(_) => break,
+        }
+    }
+    open.store(false, Ordering::SeqCst);
+}
+
+fn write_bulk<D: UsbDevice>(device: &D, open: &AtomicBool, to_write: &Receiver<Vec<u8>>) {
+    while open.load(Ordering::SeqCst) {
+        let bytes = match to_write.recv_timeout(BULK_POLL) {
+            Ok(bytes) => bytes,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let mut written = 0;
+        while written < bytes.len() {
+            let end = bytes.len().min(written + BULK_BUFFER);
+            match device.bulk_write(&bytes[written..end], WRITE_TIMEOUT) {
+                Ok(count) => written += count,
+                Err(_) => {
+                    open.store(false, Ordering::SeqCst);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Transport for AoapTransport {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn send(&self, stream_id: u8, packet: &[u8]) -> Result<(), TransportError> {
+        if !self.open.load(Ordering::SeqCst) {
+            return Err(TransportError::Closed);
+        }
+        let mut outgoing = self.outgoing.lock().map_err(|_| TransportError::Poisoned)?;
+        let outgoing = &mut *outgoing;
+        let bytes = seal_packet(outgoing.sealer.as_mut(), stream_id, packet);
+        outgoing
+            .queue
+            .send(bytes)
+            .map_err(|_| TransportError::Closed)?;
+        self.counters.sent(packet.len());
+        Ok(())
+    }
+
+    fn receive(&self) -> Result<Option<(u8, Vec<u8>)>, TransportError> {
+        let mut incoming = self.incoming.lock().map_err(|_| TransportError::Poisoned)?;
+        let incoming = &mut *incoming;
+        loop {
+            if let Some((stream_id, packet)) = incoming.packets.next() {
+                self.counters.received(packet.len());
+                return Ok(Some((stream_id, packet)));
+            }
+            // The reader thread drops its end when the device goes away or the
+            // session is closed.
+            match incoming.queue.recv() {
+                Ok(bytes) => incoming.packets.push(&bytes)?,
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.open.store(false, Ordering::SeqCst);
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::transport::chunk_packet;
+
+    const PHONE: UsbDeviceInfo = UsbDeviceInfo {
+        bus: 1,
+        address: 4,
+        vendor_id: 0x04e8,
+        product_id: 0x6860,
+        class: 0,
+    };
+    const HUB: UsbDeviceInfo = UsbDeviceInfo {
+        bus: 1,
+        address: 1,
+        vendor_id: 0x1d6b,
+        product_id: 0x0002,
+        class: USB_CLASS_HUB,
+    };
+    const ACCESSORY: UsbDeviceInfo = UsbDeviceInfo {
+        bus: 1,
+        address: 5,
+        vendor_id: GOOGLE_VID,
+        product_id: 0x2d01,
+        class: 0,
+    };
+
+    /// A phone that re-enumerates as an accessory after `START`, with its bulk
+    /// pair backed by channels.
+    struct MockBus {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        devices: Vec<UsbDeviceInfo>,
+        strings: Vec<(u16, Vec<u8>)>,
+        started: bool,
+    }
+
+    struct MockDevice {
+        info: UsbDeviceInfo,
+        state: Arc<Mutex<MockState>>,
+        to_host: Mutex<Receiver<Vec<u8>>>,
+        from_host: mpsc::Sender<Vec<u8>>,
+    }
+
+    impl UsbBackend for MockBus {
+        type Device = MockDevice;
+
+        fn devices(&self) -> Result<Vec<UsbDeviceInfo>, AoapError> {
+            Ok(self.state.lock().unwrap().devices.clone())
+        }
+
+        fn open(&self, info: &UsbDeviceInfo) -> Result<MockDevice, AoapError> {
+            let (_, to_host) = mpsc::channel();
+            let (from_host, _) = mpsc::channel();
+            Ok(MockDevice {
+                info: *info,
+                state: self.state.clone(),
+                to_host: Mutex::new(to_host),
+                from_host,
+            })
+        }
+    }
+
+    impl UsbDevice for MockDevice {
+        fn control_in(
+            &self,
+            request: u8,
+            _value: u16,
+            _index: u16,
+            buf: &mut [u8],
+        ) -> Result<usize, AoapError> {
+            if self.info != PHONE || request != REQUEST_GET_PROTOCOL {
+                return Err(AoapError::Usb("stall".to_string()));
+            }
+            buf[..2].copy_from_slice(&2u16.to_le_bytes());
+            Ok(2)
+        }
+
+        fn control_out(
+            &self,
+            request: u8,
+            _value: u16,
+            index: u16,
+            data: &[u8],
+        ) -> Result<usize, AoapError> {
+            let mut state = self.state.lock().unwrap();
+            match request {
+                REQUEST_SEND_STRING => state.strings.push((index, data.to_vec())),
+                REQUEST_START => {
+                    state.started = true;
+                    state.devices.retain(|info| *info != PHONE);
+                    state.devices.push(ACCESSORY);
+                }
+                _ => return Err(AoapError::Usb("stall".to_string())),
+            }
+            Ok(data.len())
+        }
+
+        fn bulk_read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, AoapError> {
+            match self.to_host.lock().unwrap().recv_timeout(timeout) {
+                Ok(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                }
+                Err(RecvTimeoutError::Timeout) => Err(AoapError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => Err(AoapError::Disconnected),
+            }
+        }
+
+        fn bulk_write(&self, data: &[u8], _timeout: Duration) -> Result<usize, AoapError> {
+            self.from_host
+                .send(data.to_vec())
+                .map_err(|_| AoapError::Disconnected)?;
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn switches_the_phone_and_reopens_it_as_an_accessory() {
+        let bus = MockBus {
+            state: Arc::new(Mutex::new(MockState {
+                devices: vec![HUB, PHONE],
+                ..MockState::default()
+            })),
+        };
+        let mut refused = Vec::new();
+        let device = connect(&bus, &AccessoryStrings::default(), &mut refused)
+            .unwrap()
+            .unwrap();
+        assert_eq!(device.info, ACCESSORY);
+        assert!(refused.is_empty());
+
+        let state = bus.state.lock().unwrap();
+        assert!(state.started);
+        let strings: Vec<_> = state.strings.iter().map(|(index, _)| *index).collect();
+        assert_eq!(strings, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(state.strings[0].1, b"UberDisplay\0");
+        assert_eq!(state.strings[1].1, b"AOAP\0");
+        assert_eq!(state.strings[3].1, b"1\0");
+    }
+
+    #[test]
+    fn devices_without_accessory_mode_are_left_alone() {
+        let other = UsbDeviceInfo {
+            address: 7,
+            ..PHONE
+        };
+        let bus = MockBus {
+            state: Arc::new(Mutex::new(MockState {
+                devices: vec![HUB, other],
+                ..MockState::default()
+            })),
+        };
+        let mut refused = Vec::new();
+        let strings = AccessoryStrings::default();
+        assert!(connect(&bus, &strings, &mut refused).unwrap().is_none());
+        assert_eq!(refused, vec![other]);
+
+        // Unplugged, it is forgotten.
+        bus.state.lock().unwrap().devices = vec![HUB];
+        assert!(connect(&bus, &strings, &mut refused).unwrap().is_none());
+        assert!(refused.is_empty());
+    }
+
+    #[test]
+    fn carries_packets_over_the_bulk_pair() {
+        let (phone_sends, to_host) = mpsc::channel();
+        let (from_host, phone_receives) = mpsc::channel();
+        let device = MockDevice {
+            info: ACCESSORY,
+            state: Arc::default(),
+            to_host: Mutex::new(to_host),
+            from_host,
+        };
+        let mut stream = UsbStream::new(device);
+
+        // The handshake reads one packet; the next one, sent in the same transfer,
+        // waits for the session.
+        let mut transfer = chunk_packet(0, b"caps");
+        transfer.extend(chunk_packet(0, b"first"));
+        phone_sends.send(transfer).unwrap();
+        let mut header = [0u8; 3];
+        stream.read_exact(&mut header).unwrap();
+        let mut rest = vec![0u8; u16::from_le_bytes([header[1], header[2]]) as usize];
+        stream.read_exact(&mut rest).unwrap();
+        assert_eq!(&rest[4..], b"caps");
+        stream.write_all(&chunk_packet(0, b"host caps")).unwrap();
+        assert_eq!(
+            phone_receives.recv().unwrap(),
+            chunk_packet(0, b"host caps")
+        );
+
+        let transport = stream.into_transport(None, None).unwrap();
+        assert_eq!(transport.receive().unwrap(), Some((0, b"first".to_vec())));
+        phone_sends.send(chunk_packet(2, b"second")).unwrap();
+        assert_eq!(transport.receive().unwrap(), Some((2, b"second".to_vec())));
+
+        let frame = vec![9u8; 40_000];
+        transport.send(0, &frame).unwrap();
+        let mut received = Vec::new();
+        let expected = chunk_packet(0, &frame);
+        while received.len() < expected.len() {
+            let transfer = phone_receives.recv().unwrap();
+            assert!(transfer.len() <= BULK_BUFFER);
+            received.extend(transfer);
+        }
+        assert_eq!(received, expected);
+
+        // Unplugging ends the session.
+        drop(phone_sends);
+        assert_eq!(transport.receive().unwrap(), None);
+        assert!(transport.send(0, b"late").is_err());
+    }
+}
@@ -0,0 +1,540 @@
+//! Finding clients on the LAN (§7.13). Clients announce themselves two ways: as
+//! a DNS-SD service of type `_uberdisplay._tcp` answering mDNS queries, and with
+//! a UDP broadcast beacon for networks that filter multicast. Both carry the
+//! same facts, decoded here into an `Advertisement`.
+//!
+//! The beacon (little endian):
+//!
+//! ```text
+//! "UDBC" | version u8 | port u16 | protocolVersion u16 | hasFingerprint u8 | [fingerprint 32]
+//!        | idLen u8 | id | nameLen u8 | name
+//! ```
+//!
+//! The mDNS side only needs the subset of DNS used by DNS-SD: a `PTR` question,
+//! and `PTR`, `SRV`, `TXT`, `A` and `AAAA` answers with compressed names. TXT
+//! keys are `id`, `pv` (protocol version) and `fp` (hex fingerprint); the
+//! instance name is the device name.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use thiserror::Error;
+
+use super::key_exchange::{format_fingerprint, parse_fingerprint};
+
+pub const SERVICE_TYPE: &str = "_uberdisplay._tcp.local";
+pub const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+/// UDP port clients broadcast beacons to.
+pub const BEACON_PORT: u16 = 1447;
+
+const BEACON_MAGIC: &[u8; 4] = b"UDBC";
+pub const BEACON_VERSION: u8 = 1;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// mDNS puts the cache-flush flag in the top bit of the class.
+const CLASS_MASK: u16 = 0x7fff;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const POINTER: u8 = 0xc0;
+/// Compression pointers followed before a name is considered a loop.
+const MAX_POINTERS: usize = 32;
+const RESPONSE_TTL: u32 = 120;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DiscoveryError {
+    #[error("message is too short")]
+    Truncated,
+    #[error("not an UberDisplay beacon")]
+    BadMagic,
+    #[error("beacon version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error("message is malformed: {0}")]
+    Malformed(&'static str),
+}
+
+/// A client announcing itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advertisement {
+    pub id: String,
+    pub name: String,
+    /// TCP port the client listens on.
+    pub port: u16,
+    pub protocol_version: u16,
+    pub fingerprint: Option<[u8; 32]>,
+    /// From an `A`/`AAAA` record; beacons leave it to the datagram's source.
+    pub address: Option<IpAddr>,
+}
+
+pub fn encode_beacon(advertisement: &Advertisement) -> Vec<u8> {
+    let id = truncated(&advertisement.id);
+    let name = truncated(&advertisement.name);
+    let mut out = Vec::with_capacity(48 + id.len() + name.len());
+    out.extend_from_slice(BEACON_MAGIC);
+    out.push(BEACON_VERSION);
+    out.extend_from_slice(&advertisement.port.to_le_bytes());
+    out.extend_from_slice(&advertisement.protocol_version.to_le_bytes());
+    match &advertisement.fingerprint {
+        Some(fingerprint) => {
+            out.push(1);
+            out.extend_from_slice(fingerprint);
+        }
+        None => out.push(0),
+    }
+    for text in [id, name] {
+        out.push(text.len() as u8);
+        out.extend_from_slice(text);
+    }
+    out
+}
+
+pub fn decode_beacon(bytes: &[u8]) -> Result<Advertisement, DiscoveryError> {
+    let mut reader = Reader::new(bytes);
+    if reader.take(4)? != BEACON_MAGIC {
+        return Err(DiscoveryError::BadMagic);
+    }
+    let version = reader.u8()?;
+    if version != BEACON_VERSION {
+        return Err(DiscoveryError::UnsupportedVersion(version));
+    }
+    let port = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+    let protocol_version = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+    let fingerprint = match reader.u8()? {
+        0 => None,
+        1 => Some(
+            reader
+                .take(32)?
+                .try_into()
+                .map_err(|_| DiscoveryError::Truncated)?,
+        ),
+        _ => return Err(DiscoveryError::Malformed("fingerprint flag")),
+    };
+    let id = reader.short_text()?;
+    let name = reader.short_text()?;
+    if id.is_empty() {
+        return Err(DiscoveryError::Malformed("empty id"));
+    }
+    Ok(Advertisement {
+        id,
+        name,
+        port,
+        protocol_version,
+        fingerprint,
+        address: None,
+    })
+}
+
+/// An mDNS question for every `_uberdisplay._tcp` instance. Sent from a port
+/// other than 5353, it gets answers unicast straight back (RFC 6762 §6.7).
+pub fn build_query(id: u16) -> Vec<u8> {
+    let mut out = header(id, 0, 1, 0);
+    write_name(&mut out, SERVICE_TYPE);
+    out.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out
+}
+
+/// Whether `message` is a query for the service type; responders answer those.
+pub fn is_service_query(message: &[u8]) -> bool {
+    let Ok(flags) = read_u16(message, 2) else {
+        return false;
+    };
+    if flags & FLAG_RESPONSE != 0 {
+        return false;
+    }
+    let Ok(questions) = read_u16(message, 4) else {
+        return false;
+    };
+    let mut offset = 12;
+    for _ in 0..questions {
+        let Ok((name, next)) = read_name(message, offset) else {
+            return false;
+        };
+        let Ok(kind) = read_u16(message, next) else {
+            return false;
+        };
+        if kind == TYPE_PTR && name.eq_ignore_ascii_case(SERVICE_TYPE) {
+            return true;
+        }
+        offset = next + 4;
+    }
+    false
+}
+
+/// A complete DNS-SD answer for one instance: `PTR`, `SRV`, `TXT` and the
+/// address record when `advertisement.address` is set.
+pub fn build_response(id: u16, advertisement: &Advertisement) -> Vec<u8> {
+    let instance = format!("{}.{SERVICE_TYPE}", advertisement.name);
+    let target = format!("{}.local", advertisement.id);
+    let address = advertisement.address;
+    let mut out = header(
+        id,
+        FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+        0,
+        3 + address.is_some() as u16,
+    );
+
+    let mut rdata = Vec::new();
+    write_name(&mut rdata, &instance);
+    write_record(&mut out, SERVICE_TYPE, TYPE_PTR, &rdata);
+
+    let mut rdata = Vec::new();
+    rdata.extend_from_slice(&0u16.to_be_bytes());
+    rdata.extend_from_slice(&0u16.to_be_bytes());
+    rdata.extend_from_slice(&advertisement.port.to_be_bytes());
+    write_name(&mut rdata, &target);
+    write_record(&mut out, &instance, TYPE_SRV, &rdata);
+
+    let mut entries = vec![
+        format!("id={}", advertisement.id),
+        format!("pv={}", advertisement.protocol_version),
+    ];
+    if let Some(fingerprint) = &advertisement.fingerprint {
+        entries.push(format!("fp={}", format_fingerprint(fingerprint)));
+    }
+    let mut rdata = Vec::new();
+    for entry in &entries {
+        let entry = truncated(entry);
+        rdata.push(entry.len() as u8);
+        rdata.extend_from_slice(entry);
+    }
+    write_record(&mut out, &instance, TYPE_TXT, &rdata);
+
+    match address {
+        Some(IpAddr::V4(ip)) => write_record(&mut out, &target, TYPE_A, &ip.octets()),
+        Some(IpAddr::V6(ip)) => write_record(&mut out, &target, TYPE_AAAA, &ip.octets()),
+        None => {}
+    }
+    out
+}
+
+/// The instances described by an mDNS response. Instances missing their `SRV`
+/// record are left out; a missing address is left for the caller to fill in.
+pub fn parse_response(message: &[u8]) -> Result<Vec<Advertisement>, DiscoveryError> {
+    if read_u16(message, 2)? & FLAG_RESPONSE == 0 {
+        return Ok(Vec::new());
+    }
+    let questions = read_u16(message, 4)?;
+    let records = read_u16(message, 6)? as usize
+        + read_u16(message, 8)? as usize
+        + read_u16(message, 10)? as usize;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        let (_, next) = read_name(message, offset)?;
+        offset = next + 4;
+    }
+
+    let mut instances = Vec::new();
+    let mut services: HashMap<String, (u16, String)> = HashMap::new();
+    let mut texts: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    let mut addresses: HashMap<String, IpAddr> = HashMap::new();
+    for _ in 0..records {
+        let (name, next) = read_name(message, offset)?;
+        let kind = read_u16(message, next)?;
+        let class = read_u16(message, next + 2)? & CLASS_MASK;
+        let length = read_u16(message, next + 8)? as usize;
+        let start = next + 10;
+        let rdata = message
+            .get(start..start + length)
+            .ok_or(DiscoveryError::Truncated)?;
+        offset = start + length;
+        if class != CLASS_IN {
+            continue;
+        }
+        let key = name.to_ascii_lowercase();
+        match kind {
+            TYPE_PTR if key == SERVICE_TYPE.to_ascii_lowercase() => {
+                let (instance, _) = read_name(message, start)?;
+                instances.push(instance);
+            }
+            TYPE_SRV => {
+                let port = read_u16(message, start + 4)?;
+                let (target, _) = read_name(message, start + 6)?;
+                services.insert(key, (port, target.to_ascii_lowercase()));
+            }
+            TYPE_TXT => {
+                texts.insert(key, parse_txt(rdata));
+            }
+            TYPE_A if length == 4 => {
+                let octets: [u8; 4] = rdata.try_into().map_err(|_| DiscoveryError::Truncated)?;
+                addresses.insert(key, IpAddr::V4(Ipv4Addr::from(octets)));
+            }
+            TYPE_AAAA if length == 16 => {
+                let octets: [u8; 16] = rdata.try_into().map_err(|_| DiscoveryError::Truncated)?;
+                // An IPv4 answer wins; it is what the host dials first.
+                addresses
+                    .entry(key)
+                    .or_insert(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+    }
+
+    let suffix = format!(".{}", SERVICE_TYPE.to_ascii_lowercase());
+    Ok(instances
+        .into_iter()
+        .filter_map(|instance| {
+            let key = instance.to_ascii_lowercase();
+            let (port, target) = services.get(&key)?;
+            let name = instance[..key.strip_suffix(&suffix)?.len()].to_string();
+            let text = texts.get(&key).map(Vec::as_slice).unwrap_or_default();
+            let value = |wanted: &str| {
+                text.iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(wanted))
+                    .map(|(_, value)| value.as_str())
+            };
+            Some(Advertisement {
+                id: value("id").unwrap_or(&name).to_string(),
+                protocol_version: value("pv").and_then(|pv| pv.parse().ok()).unwrap_or(0),
+                fingerprint: value("fp").and_then(parse_fingerprint),
+                port: *port,
+                address: addresses.get(target).copied(),
+                name,
+            })
+        })
+        .collect())
+}
+
+fn parse_txt(rdata: &[u8]) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while let Some(&length) = rdata.get(offset) {
+        let Some(entry) = rdata.get(offset + 1..offset + 1 + length as usize) else {
+            break;
+        };
+        let entry = String::from_utf8_lossy(entry);
+        if let Some((key, value)) = entry.split_once('=') {
+            entries.push((key.to_string(), value.to_string()));
+        }
+        offset += 1 + length as usize;
+    }
+    entries
+}
+
+fn header(id: u16, flags: u16, questions: u16, answers: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(512);
+    for field in [id, flags, questions, answers, 0, 0] {
+        out.extend_from_slice(&field.to_be_bytes());
+    }
+    out
+}
+
+fn write_record(out: &mut Vec<u8>, name: &str, kind: u16, rdata: &[u8]) {
+    write_name(out, name);
+    out.extend_from_slice(&kind.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out.extend_from_slice(&RESPONSE_TTL.to_be_bytes());
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend_from_slice(rdata);
+}
+
+/// Writes `name` uncompressed. A DNS-SD instance name's first label may hold
+/// dots, so everything before the service type is kept as one label.
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    let (instance, rest) = match name.len().checked_sub(SERVICE_TYPE.len() + 1) {
+        Some(split)
+            if name.is_char_boundary(split)
+                && name[split + 1..].eq_ignore_ascii_case(SERVICE_TYPE) =>
+        {
+            (Some(&name[..split]), &name[split + 1..])
+        }
+        _ => (None, name),
+    };
+    for label in instance.into_iter().chain(rest.split('.')) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+/// Reads the name at `offset`, following compression pointers, and returns it
+/// with the offset just past it.
+fn read_name(message: &[u8], offset: usize) -> Result<(String, usize), DiscoveryError> {
+    let mut labels = Vec::new();
+    let mut position = offset;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(position).ok_or(DiscoveryError::Truncated)?;
+        if length & POINTER == POINTER {
+            let target = (read_u16(message, position)? & 0x3fff) as usize;
+            end.get_or_insert(position + 2);
+            pointers += 1;
+            if pointers > MAX_POINTERS || target >= message.len() {
+                return Err(DiscoveryError::Malformed("name pointer"));
+            }
+            position = target;
+            continue;
+        }
+        if length == 0 {
+            let end = end.unwrap_or(position + 1);
+            return Ok((labels.join("."), end));
+        }
+        let label = message
+            .get(position + 1..position + 1 + length as usize)
+            .ok_or(DiscoveryError::Truncated)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        position += 1 + length as usize;
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Result<u16, DiscoveryError> {
+    message
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or(DiscoveryError::Truncated)
+}
+
+/// At most 255 bytes, cut at a character boundary.
+fn truncated(text: &str) -> &[u8] {
+    let mut end = text.len().min(255);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text.as_bytes()[..end]
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DiscoveryError> {
+        let taken = self
+            .bytes
+            .get(self.offset..self.offset + count)
+            .ok_or(DiscoveryError::Truncated)?;
+        self.offset += count;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DiscoveryError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn short_text(&mut self) -> Result<String, DiscoveryError> {
+        let length = self.u8()? as usize;
+        let text = self.take(length)?;
+        String::from_utf8(text.to_vec()).map_err(|_| DiscoveryError::Malformed("utf-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tablet() -> Advertisement {
+        Advertisement {
+            id: "a1b2c3".to_string(),
+            name: "Living room tablet".to_string(),
+            port: 1445,
+            protocol_version: 4,
+            fingerprint: Some([7; 32]),
+            address: Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 42))),
+        }
+    }
+
+    #[test]
+    fn beacon_round_trips() {
+        let advertisement = Advertisement {
+            address: None,
+            ..tablet()
+        };
+        let beacon = encode_beacon(&advertisement);
+        assert_eq!(decode_beacon(&beacon).unwrap(), advertisement);
+
+        let anonymous = Advertisement {
+            fingerprint: None,
+            ..advertisement
+        };
+        assert_eq!(
+            decode_beacon(&encode_beacon(&anonymous)).unwrap(),
+            anonymous
+        );
+
+        assert_eq!(decode_beacon(b"UDBX\x01"), Err(DiscoveryError::BadMagic));
+        let mut future = beacon.clone();
+        future[4] = 9;
+        assert_eq!(
+            decode_beacon(&future),
+            Err(DiscoveryError::UnsupportedVersion(9))
+        );
+        assert_eq!(
+            decode_beacon(&beacon[..beacon.len() - 1]),
+            Err(DiscoveryError::Truncated)
+        );
+    }
+
+    #[test]
+    fn responses_answer_the_service_query() {
+        assert!(is_service_query(&build_query(7)));
+        let response = build_response(7, &tablet());
+        assert!(!is_service_query(&response));
+        assert_eq!(parse_response(&response).unwrap(), vec![tablet()]);
+        // Queries carry no answers.
+        assert!(parse_response(&build_query(7)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn follows_compressed_names() {
+        // PTR to "Desk._uberdisplay._tcp.local" and SRV for it, with the service
+        // type and the instance name written once and pointed to afterwards.
+        let mut message = header(0, FLAG_RESPONSE, 0, 3);
+        let service = message.len();
+        write_name(&mut message, SERVICE_TYPE);
+        message.extend_from_slice(&TYPE_PTR.to_be_bytes());
+        message.extend_from_slice(&(CLASS_IN | 0x8000).to_be_bytes());
+        message.extend_from_slice(&RESPONSE_TTL.to_be_bytes());
+        message.extend_from_slice(&7u16.to_be_bytes());
+        let instance = message.len();
+        message.extend_from_slice(b"\x04Desk");
+        message.extend_from_slice(&[POINTER, service as u8]);
+
+        message.extend_from_slice(&[POINTER, instance as u8]);
+        message.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&RESPONSE_TTL.to_be_bytes());
+        message.extend_from_slice(&13u16.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0, 0x05, 0xa5]);
+        let target = message.len();
+        message.extend_from_slice(b"\x04desk");
+        // "local", the last label of the service type.
+        message.extend_from_slice(&[POINTER, (service + 18) as u8]);
+
+        message.extend_from_slice(&[POINTER, target as u8]);
+        message.extend_from_slice(&TYPE_A.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&RESPONSE_TTL.to_be_bytes());
+        message.extend_from_slice(&4u16.to_be_bytes());
+        message.extend_from_slice(&[10, 0, 0, 5]);
+
+        let found = parse_response(&message).unwrap();
+        assert_eq!(
+            found,
+            vec![Advertisement {
+                id: "Desk".to_string(),
+                name: "Desk".to_string(),
+                port: 1445,
+                protocol_version: 0,
+                fingerprint: None,
+                address: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))),
+            }]
+        );
+
+        // A pointer to itself is a loop, not a hang.
+        let mut looped = header(0, FLAG_RESPONSE, 0, 1);
+        looped.extend_from_slice(&[POINTER, 12]);
+        assert!(parse_response(&looped).is_err());
+    }
+}
@@ -21,6 +21,48 @@ pub fn write_stream_chunks(stream_id: u8, packet: &[u8], out: &mut Vec<u8>) {
     }
 }
 
+/// Reassembles framed packets from the raw chunk stream. Stream ids above 1
+/// share the last reassembly buffer.
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    pending: Vec<u8>,
+    streams: [Vec<u8>; 2],
+}
+
+impl StreamDecoder {
+    /// Feeds bytes read from the transport and returns the packets completed by
+    /// them, each with the stream id of its last chunk.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        self.pending.extend_from_slice(bytes);
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= 3 {
+            let stream_id = self.pending[offset];
+            let chunk_len =
+                u16::from_le_bytes([self.pending[offset + 1], self.pending[offset + 2]]) as usize;
+            if self.pending.len() - offset < 3 + chunk_len {
+                break;
+            }
+            let chunk = &self.pending[offset + 3..offset + 3 + chunk_len];
+            let buffer = &mut self.streams[(stream_id as usize).min(1)];
+            buffer.extend_from_slice(chunk);
+            offset += 3 + chunk_len;
+
+            while buffer.len() >= 4 {
+                let packet_len =
+                    u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+                if buffer.len() < 4 + packet_len {
+                    break;
+                }
+                packets.push((stream_id, buffer[4..4 + packet_len].to_vec()));
+                buffer.drain(..4 + packet_len);
+            }
+        }
+        self.pending.drain(..offset);
+        packets
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,4 +89,25 @@ mod tests {
         let second_len = u16::from_le_bytes([out[second_offset + 1], out[second_offset + 2]]) as usize;
         assert_eq!(second_len, 10);
     }
+
+    #[test]
+    fn decodes_packets_split_across_chunks_and_reads() {
+        let first = encode_stream_packet(17, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let second = encode_stream_packet(4, &[9, 0, 0, 0]);
+        let mut wire = Vec::new();
+        write_stream_chunks(0, &first[..5], &mut wire);
+        write_stream_chunks(1, &second, &mut wire);
+        write_stream_chunks(0, &first[5..], &mut wire);
+
+        let mut decoder = StreamDecoder::default();
+        let mut packets = Vec::new();
+        for byte in wire.chunks(2) {
+            packets.extend(decoder.push(byte));
+        }
+        assert_eq!(
+            packets,
+            vec![(1, second[4..].to_vec()), (0, first[4..].to_vec())]
+        );
+        assert!(decoder.pending.is_empty());
+    }
 }
@@ -4,6 +4,9 @@ pub enum HandshakeError {
     VersionOutOfRange,
 }
 
+/// Protocol version the host speaks and sends in its handshake.
+pub const PROTOCOL_VERSION: u16 = 4;
+
 const HANDSHAKE_BASE: &str = "KELOCUBE_MIRR_";
 const HANDSHAKE_VERSION_LEN: usize = 3;
 
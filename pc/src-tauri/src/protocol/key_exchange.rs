@@ -0,0 +1,326 @@
+//! Identity keys and the ephemeral key exchange behind public-key sessions.
+//!
+//! Each device has a long-term Ed25519 identity; its X25519 form takes part in the
+//! exchange, so a peer without the matching private key cannot derive the session
+//! keys. The session secret is three X25519 results:
+//!
+//! ```text
+//! DH(host ephemeral, client ephemeral) | DH(host identity, client ephemeral)
+//!     | DH(host ephemeral, client identity)
+//! ```
+//!
+//! The client commits to its ephemeral key (SHA-256) in `Capabilities` and only
+//! reveals it in `KeyExchange` after the host has sent its own, so neither side can
+//! choose keys to steer the short authentication string.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const KEY_LEN: usize = 32;
+/// Length of the session secret fed to HKDF.
+pub const SHARED_SECRET_LEN: usize = 3 * KEY_LEN;
+pub const SIGNATURE_LEN: usize = 64;
+
+const SAS_LABEL: &[u8] = b"uberdisplay sas v1";
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum KeyExchangeError {
+    #[error("identity key is not a valid Ed25519 public key")]
+    InvalidIdentity,
+    #[error("key exchange produced a weak shared secret")]
+    WeakKey,
+    #[error("ephemeral key does not match its commitment")]
+    CommitmentMismatch,
+    #[error("signature does not match the identity key")]
+    InvalidSignature,
+}
+
+/// Long-term identity, stored as its 32-byte Ed25519 seed.
+#[derive(Clone)]
+pub struct Identity {
+    signing: SigningKey,
+}
+
+impl Identity {
+    pub fn generate() -> Result<Self, String> {
+        let mut seed = [0u8; KEY_LEN];
+        getrandom::getrandom(&mut seed).map_err(|err| err.to_string())?;
+        Ok(Self::from_seed(&seed))
+    }
+
+    pub fn from_seed(seed: &[u8; KEY_LEN]) -> Self {
+        Self {
+            signing: SigningKey::from_bytes(seed),
+        }
+    }
+
+    pub fn seed(&self) -> [u8; KEY_LEN] {
+        self.signing.to_bytes()
+    }
+
+    /// The Ed25519 public key sent to peers.
+    pub fn public_key(&self) -> [u8; KEY_LEN] {
+        self.signing.verifying_key().to_bytes()
+    }
+
+    pub fn fingerprint(&self) -> [u8; 32] {
+        fingerprint(&self.public_key())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        self.signing.sign(message).to_bytes()
+    }
+
+    fn x25519_secret(&self) -> StaticSecret {
+        StaticSecret::from(self.signing.to_scalar_bytes())
+    }
+}
+
+impl PartialEq for Identity {
+    fn eq(&self, other: &Self) -> bool {
+        self.public_key() == other.public_key()
+    }
+}
+
+impl Eq for Identity {}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("fingerprint", &format_fingerprint(&self.fingerprint()))
+            .finish()
+    }
+}
+
+/// One-off key for a single connection.
+pub struct EphemeralKey {
+    secret: StaticSecret,
+}
+
+impl EphemeralKey {
+    pub fn generate() -> Result<Self, String> {
+        let mut bytes = [0u8; KEY_LEN];
+        getrandom::getrandom(&mut bytes).map_err(|err| err.to_string())?;
+        Ok(Self {
+            secret: StaticSecret::from(bytes),
+        })
+    }
+
+    pub fn public_key(&self) -> [u8; KEY_LEN] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    pub fn commitment(&self) -> [u8; 32] {
+        commitment(&self.public_key())
+    }
+}
+
+/// Checks an Ed25519 signature made with `Identity::sign`.
+pub fn verify(
+    identity_key: &[u8; KEY_LEN],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<(), KeyExchangeError> {
+    VerifyingKey::from_bytes(identity_key)
+        .map_err(|_| KeyExchangeError::InvalidIdentity)?
+        .verify_strict(message, &Signature::from_bytes(signature))
+        .map_err(|_| KeyExchangeError::InvalidSignature)
+}
+
+pub fn commitment(ephemeral_key: &[u8; KEY_LEN]) -> [u8; 32] {
+    Sha256::digest(ephemeral_key).into()
+}
+
+pub fn check_commitment(
+    ephemeral_key: &[u8; KEY_LEN],
+    expected: &[u8; 32],
+) -> Result<(), KeyExchangeError> {
+    if &commitment(ephemeral_key) == expected {
+        Ok(())
+    } else {
+        Err(KeyExchangeError::CommitmentMismatch)
+    }
+}
+
+/// SHA-256 of an Ed25519 identity key; what devices pin.
+pub fn fingerprint(identity_key: &[u8; KEY_LEN]) -> [u8; 32] {
+    Sha256::digest(identity_key).into()
+}
+
+pub fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    fingerprint
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+pub fn parse_fingerprint(text: &str) -> Option<[u8; 32]> {
+    let text = text.trim();
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut fingerprint = [0u8; 32];
+    for (index, byte) in fingerprint.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(fingerprint)
+}
+
+/// Session secret for `role`, given both identities and ephemeral keys.
+pub fn shared_secret(
+    role: super::secure::Role,
+    identity: &Identity,
+    ephemeral: &EphemeralKey,
+    peer_identity: &[u8; KEY_LEN],
+    peer_ephemeral: &[u8; KEY_LEN],
+) -> Result<[u8; SHARED_SECRET_LEN], KeyExchangeError> {
+    use super::secure::Role;
+
+    let peer_static = PublicKey::from(
+        VerifyingKey::from_bytes(peer_identity)
+            .map_err(|_| KeyExchangeError::InvalidIdentity)?
+            .to_montgomery()
+            .to_bytes(),
+    );
+    let peer_ephemeral = PublicKey::from(*peer_ephemeral);
+    let identity_secret = identity.x25519_secret();
+
+    let both_ephemeral = ephemeral.secret.diffie_hellman(&peer_ephemeral);
+    let (host_identity, client_identity) = match role {
+        Role::Host => (
+            identity_secret.diffie_hellman(&peer_ephemeral),
+            ephemeral.secret.diffie_hellman(&peer_static),
+        ),
+        Role::Client => (
+            ephemeral.secret.diffie_hellman(&peer_static),
+            identity_secret.diffie_hellman(&peer_ephemeral),
+        ),
+    };
+    let parts = [both_ephemeral, host_identity, client_identity];
+    if parts.iter().any(|part| !part.was_contributory()) {
+        return Err(KeyExchangeError::WeakKey);
+    }
+    let mut secret = [0u8; SHARED_SECRET_LEN];
+    for (chunk, part) in secret.chunks_exact_mut(KEY_LEN).zip(parts.iter()) {
+        chunk.copy_from_slice(part.as_bytes());
+    }
+    Ok(secret)
+}
+
+/// Six-digit code both devices show for the first pairing; it matches only when
+/// both saw the same negotiation.
+pub fn sas_code(transcript: &[u8; 32]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(SAS_LABEL);
+    hasher.update(transcript);
+    let digest = hasher.finalize();
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000;
+    format!("{:03} {:03}", value / 1000, value % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::secure::Role;
+
+    #[test]
+    fn both_sides_derive_the_same_secret() {
+        let host = Identity::generate().unwrap();
+        let client = Identity::generate().unwrap();
+        let host_ephemeral = EphemeralKey::generate().unwrap();
+        let client_ephemeral = EphemeralKey::generate().unwrap();
+
+        let on_host = shared_secret(
+            Role::Host,
+            &host,
+            &host_ephemeral,
+            &client.public_key(),
+            &client_ephemeral.public_key(),
+        )
+        .unwrap();
+        let on_client = shared_secret(
+            Role::Client,
+            &client,
+            &client_ephemeral,
+            &host.public_key(),
+            &host_ephemeral.public_key(),
+        )
+        .unwrap();
+        assert_eq!(on_host, on_client);
+
+        // Claiming someone else's identity without its key gives a different secret.
+        let impostor = Identity::generate().unwrap();
+        let on_impostor = shared_secret(
+            Role::Client,
+            &impostor,
+            &client_ephemeral,
+            &host.public_key(),
+            &host_ephemeral.public_key(),
+        )
+        .unwrap();
+        assert_ne!(on_host, on_impostor);
+    }
+
+    #[test]
+    fn rejects_invalid_and_low_order_keys() {
+        let identity = Identity::generate().unwrap();
+        let ephemeral = EphemeralKey::generate().unwrap();
+        let peer = Identity::generate().unwrap();
+        assert_eq!(
+            shared_secret(
+                Role::Host,
+                &identity,
+                &ephemeral,
+                &peer.public_key(),
+                &[0u8; KEY_LEN]
+            ),
+            Err(KeyExchangeError::WeakKey)
+        );
+        // y = 2 is not on the Ed25519 curve.
+        let mut not_a_point = [0u8; KEY_LEN];
+        not_a_point[0] = 2;
+        assert!(shared_secret(
+            Role::Host,
+            &identity,
+            &ephemeral,
+            &not_a_point,
+            &EphemeralKey::generate().unwrap().public_key()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn checks_commitments_and_formats_codes() {
+        let ephemeral = EphemeralKey::generate().unwrap();
+        let commitment = ephemeral.commitment();
+        assert_eq!(
+            check_commitment(&ephemeral.public_key(), &commitment),
+            Ok(())
+        );
+        assert_eq!(
+            check_commitment(&EphemeralKey::generate().unwrap().public_key(), &commitment),
+            Err(KeyExchangeError::CommitmentMismatch)
+        );
+
+        let code = sas_code(&[7; 32]);
+        assert_eq!(code, sas_code(&[7; 32]));
+        assert_ne!(code, sas_code(&[8; 32]));
+        assert_eq!(code.len(), 7);
+        assert!(code[..3]
+            .chars()
+            .chain(code[4..].chars())
+            .all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn round_trips_identities_and_fingerprints() {
+        let identity = Identity::generate().unwrap();
+        assert_eq!(Identity::from_seed(&identity.seed()), identity);
+        let text = format_fingerprint(&identity.fingerprint());
+        assert_eq!(text.len(), 64);
+        assert_eq!(parse_fingerprint(&text), Some(identity.fingerprint()));
+        assert_eq!(parse_fingerprint("abc"), None);
+        assert!(!format!("{identity:?}").contains(&format!("{:?}", identity.seed())));
+    }
+}
@@ -1,5 +1,15 @@
 #![allow(dead_code)]
 
+pub mod aoap;
+pub mod discovery;
 pub mod framing;
 pub mod handshake;
+pub mod key_exchange;
+pub mod netem;
 pub mod packets;
+pub mod qr_payload;
+pub mod quic;
+pub mod secure;
+pub mod trace;
+pub mod transport;
+pub mod udp;
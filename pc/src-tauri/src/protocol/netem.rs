@@ -0,0 +1,117 @@
+//! A lossy link for tests of the datagram transport, after Linux `netem`: random
+//! loss, a fixed delay and random jitter (which reorders datagrams). Runs on
+//! caller-supplied instants with a seeded generator, so results are repeatable.
+
+use std::time::{Duration, Instant};
+
+pub struct Netem {
+    loss: f64,
+    delay: Duration,
+    jitter: Duration,
+    state: u64,
+    in_flight: Vec<(Instant, u64, Vec<u8>)>,
+    next_order: u64,
+    dropped: u64,
+}
+
+impl Netem {
+    /// A perfect link; add impairments with the builder methods.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            loss: 0.0,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            // xorshift cannot leave an all-zero state.
+            state: seed | 1,
+            in_flight: Vec::new(),
+            next_order: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Share of datagrams dropped, from 0.0 to 1.0.
+    pub fn loss(mut self, fraction: f64) -> Self {
+        self.loss = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Extra delay, uniform between zero and `jitter`, per datagram.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn send(&mut self, datagram: Vec<u8>, now: Instant) {
+        if self.random() < self.loss {
+            self.dropped += 1;
+            return;
+        }
+        let jitter = self.jitter.mul_f64(self.random());
+        self.in_flight
+            .push((now + self.delay + jitter, self.next_order, datagram));
+        self.next_order += 1;
+    }
+
+    /// Datagrams due by `now`, in arrival order.
+    pub fn deliver(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let (mut due, waiting): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|(arrival, _, _)| *arrival <= now);
+        self.in_flight = waiting;
+        due.sort_by_key(|(arrival, order, _)| (*arrival, *order));
+        due.into_iter().map(|(_, _, datagram)| datagram).collect()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// xorshift64*, mapped to [0, 1).
+    fn random(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_delays_and_reorders_repeatably() {
+        let start = Instant::now();
+        let run = || {
+            let mut link = Netem::new(42)
+                .loss(0.25)
+                .delay(Duration::from_millis(10))
+                .jitter(Duration::from_millis(5));
+            for index in 0..400u16 {
+                link.send(index.to_le_bytes().to_vec(), start);
+            }
+            assert!(link.deliver(start + Duration::from_millis(9)).is_empty());
+            let arrived = link.deliver(start + Duration::from_millis(15));
+            assert_eq!(link.in_flight(), 0);
+            assert_eq!(arrived.len() as u64 + link.dropped(), 400);
+            arrived
+        };
+        let arrived = run();
+        assert_eq!(arrived, run());
+        assert!((250..350).contains(&arrived.len()), "{}", arrived.len());
+        let mut sorted = arrived.clone();
+        sorted.sort_by_key(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+        assert_ne!(sorted, arrived);
+    }
+}
@@ -1,5 +1,8 @@
 use thiserror::Error;
 
+use super::key_exchange::KEY_LEN;
+use super::secure::{CAP_FLAG_CIPHERS, CAP_FLAG_PUBLIC_KEY, CHANNEL_NONCE_LEN};
+
 #[derive(Debug, Error)]
 pub enum PacketError {
     #[error("packet payload too short")]
@@ -21,8 +24,24 @@ pub struct ConfigurePacket {
     pub codec_profile: u8,
     pub codec_level: u8,
     pub codec_flags: u8,
+    /// Sent after the codec extension; ignored when `codec_id` is `None`.
+    pub color: Option<ColorDescription>,
+}
+
+/// ITU-T H.273 colour description carried in the `Configure` v3 extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorDescription {
+    pub primaries: u8,
+    pub transfer: u8,
+    pub matrix: u8,
+    pub full_range: bool,
 }
 
+/// `frame_meta` bits on `Frame` packets.
+pub const FRAME_META_KEYFRAME: u8 = 0x01;
+pub const FRAME_META_PARAMETER_SETS: u8 = 0x02;
+pub const FRAME_META_TIMESTAMP: u8 = 0x80;
+
 #[derive(Debug, PartialEq)]
 pub struct FramePacket<'a> {
     pub frame_meta: u8,
@@ -73,9 +92,78 @@ pub struct FrameDonePacket {
 }
 
 #[derive(Debug, PartialEq)]
+pub struct CommandPacket {
+    pub command_id: i32,
+}
+
+/// Host command that saves a screenshot of the capture target. Accepted both as a
+/// `Command` id and as the `action` of an action-menu `InputKey` press.
+pub const COMMAND_SCREENSHOT: i32 = 2001;
+
+/// `Error` codes sent by UberDisplay hosts; older clients only read the code.
+pub const ERROR_HOST_BUSY: u8 = 32;
+pub const ERROR_NOT_AUTHORIZED: u8 = 33;
+pub const ERROR_CODEC_MISMATCH: u8 = 34;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct CapabilitiesPacket {
     pub codec_mask: u32,
     pub flags: u32,
+    pub decoder_limits: Option<DecoderLimits>,
+    /// Secure channel nonce, sent after the decoder limits when a cipher flag is set.
+    pub channel_nonce: Option<[u8; CHANNEL_NONCE_LEN]>,
+    /// Follows the nonce when `CAP_FLAG_PUBLIC_KEY` is set.
+    pub public_key: Option<PublicKeyShare>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PublicKeyShare {
+    /// Ed25519 identity key.
+    pub identity_key: [u8; KEY_LEN],
+    /// The client's SHA-256 commitment to its ephemeral key, or the host's
+    /// ephemeral X25519 key.
+    pub key_share: [u8; KEY_LEN],
+}
+
+/// The client's ephemeral key, revealed after the host's `Capabilities`.
+#[derive(Debug, PartialEq)]
+pub struct KeyExchangePacket {
+    pub ephemeral_key: [u8; KEY_LEN],
+}
+
+/// Moves video to a datagram path (`udp`) on the host's `port`; the client starts
+/// sending feedback there and the host replies with frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaPathPacket {
+    pub port: u16,
+    /// Data fragments per parity fragment; 0 means no FEC.
+    pub fec_group: u8,
+    pub max_datagram: u16,
+}
+
+/// Moves the session to QUIC (`quic`) on the host's `port`. The client connects
+/// with its identity certificate and opens the control stream with `token`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuicPathPacket {
+    pub port: u16,
+    pub token: [u8; 16],
+}
+
+/// Optional decoder limits appended to `Capabilities` by newer clients.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecoderLimits {
+    pub max_width: u16,
+    pub max_height: u16,
+    pub max_fps: u16,
+    pub codecs: Vec<CodecLimit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodecLimit {
+    pub codec_id: u8,
+    pub max_profile: u8,
+    pub max_level: u8,
+    pub codec_flags: u8,
 }
 
 #[derive(Debug, PartialEq)]
@@ -84,8 +172,10 @@ pub enum ClientPacket {
     Pen(PenPacket),
     Keyboard(KeyboardPacket),
     InputKey(InputKeyPacket),
+    Command(CommandPacket),
     FrameDone(FrameDonePacket),
     Capabilities(CapabilitiesPacket),
+    KeyExchange(KeyExchangePacket),
 }
 
 pub fn build_state_packet(payload: &[u8]) -> Vec<u8> {
@@ -96,7 +186,7 @@ pub fn build_state_packet(payload: &[u8]) -> Vec<u8> {
 }
 
 pub fn build_configure_packet(packet: ConfigurePacket) -> Vec<u8> {
-    let mut buffer = Vec::with_capacity(1 + 5 * 4 + 4);
+    let mut buffer = Vec::with_capacity(1 + 5 * 4 + 8);
     buffer.push(1);
     buffer.extend_from_slice(&packet.width.to_le_bytes());
     buffer.extend_from_slice(&packet.height.to_le_bytes());
@@ -108,16 +198,36 @@ pub fn build_configure_packet(packet: ConfigurePacket) -> Vec<u8> {
         buffer.push(packet.codec_profile);
         buffer.push(packet.codec_level);
         buffer.push(packet.codec_flags);
+        if let Some(color) = packet.color {
+            buffer.push(color.primaries);
+            buffer.push(color.transfer);
+            buffer.push(color.matrix);
+            buffer.push(color.full_range as u8);
+        }
     }
     buffer
 }
 
+/// Asks the client to save its next rendered frame; the payload is empty.
+pub fn build_take_screenshot_packet() -> Vec<u8> {
+    vec![7]
+}
+
+/// An error code followed by a UTF-8 explanation for the user.
+pub fn build_error_packet(code: u8, message: &str) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(2 + message.len());
+    buffer.push(14);
+    buffer.push(code);
+    buffer.extend_from_slice(message.as_bytes());
+    buffer
+}
+
 pub fn build_frame_packet(packet: FramePacket<'_>) -> Vec<u8> {
     let mut buffer = Vec::with_capacity(2 + packet.h264_bytes.len() + 8);
     buffer.push(3);
     let mut frame_meta = packet.frame_meta;
     if packet.timestamp_100ns.is_some() {
-        frame_meta |= 0x80;
+        frame_meta |= FRAME_META_TIMESTAMP;
     }
     buffer.push(frame_meta);
     if let Some(timestamp) = packet.timestamp_100ns {
@@ -132,6 +242,54 @@ pub fn build_capabilities_packet(packet: CapabilitiesPacket) -> Vec<u8> {
     buffer.push(17);
     buffer.extend_from_slice(&packet.codec_mask.to_le_bytes());
     buffer.extend_from_slice(&packet.flags.to_le_bytes());
+    // The nonce follows the limits block, so it needs one even when there are no limits.
+    let limits = match (packet.decoder_limits, packet.channel_nonce) {
+        (None, Some(_)) => Some(DecoderLimits::default()),
+        (limits, _) => limits,
+    };
+    if let Some(limits) = limits {
+        buffer.extend_from_slice(&limits.max_width.to_le_bytes());
+        buffer.extend_from_slice(&limits.max_height.to_le_bytes());
+        buffer.extend_from_slice(&limits.max_fps.to_le_bytes());
+        buffer.push(limits.codecs.len().min(u8::MAX as usize) as u8);
+        for limit in limits.codecs.iter().take(u8::MAX as usize) {
+            buffer.push(limit.codec_id);
+            buffer.push(limit.max_profile);
+            buffer.push(limit.max_level);
+            buffer.push(limit.codec_flags);
+        }
+    }
+    if let Some(nonce) = packet.channel_nonce {
+        buffer.extend_from_slice(&nonce);
+        if let Some(share) = packet.public_key {
+            buffer.extend_from_slice(&share.identity_key);
+            buffer.extend_from_slice(&share.key_share);
+        }
+    }
+    buffer
+}
+
+pub fn build_media_path_packet(packet: MediaPathPacket) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(6);
+    buffer.push(19);
+    buffer.extend_from_slice(&packet.port.to_le_bytes());
+    buffer.push(packet.fec_group);
+    buffer.extend_from_slice(&packet.max_datagram.to_le_bytes());
+    buffer
+}
+
+pub fn build_quic_path_packet(packet: QuicPathPacket) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(19);
+    buffer.push(20);
+    buffer.extend_from_slice(&packet.port.to_le_bytes());
+    buffer.extend_from_slice(&packet.token);
+    buffer
+}
+
+pub fn build_key_exchange_packet(packet: KeyExchangePacket) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(1 + KEY_LEN);
+    buffer.push(18);
+    buffer.extend_from_slice(&packet.ephemeral_key);
     buffer
 }
 
@@ -143,8 +301,10 @@ pub fn parse_client_packet(bytes: &[u8]) -> Result<ClientPacket, PacketError> {
         9 => parse_pen_packet(payload).map(ClientPacket::Pen),
         13 => parse_input_key_packet(payload).map(ClientPacket::InputKey),
         15 => parse_keyboard_packet(payload).map(ClientPacket::Keyboard),
+        16 => parse_command_packet(payload).map(ClientPacket::Command),
         4 => parse_frame_done_packet(payload).map(ClientPacket::FrameDone),
         17 => parse_capabilities_packet(payload).map(ClientPacket::Capabilities),
+        18 => parse_key_exchange_packet(payload).map(ClientPacket::KeyExchange),
         other => Err(PacketError::UnsupportedDataType(*other)),
     }
 }
@@ -208,6 +368,16 @@ fn parse_input_key_packet(payload: &[u8]) -> Result<InputKeyPacket, PacketError>
     })
 }
 
+fn parse_command_packet(payload: &[u8]) -> Result<CommandPacket, PacketError> {
+    if payload.len() != 4 {
+        return Err(PacketError::PayloadTooShort);
+    }
+
+    Ok(CommandPacket {
+        command_id: i32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+    })
+}
+
 fn parse_frame_done_packet(payload: &[u8]) -> Result<FrameDonePacket, PacketError> {
     if payload.len() != 4 {
         return Err(PacketError::PayloadTooShort);
@@ -219,20 +389,109 @@ fn parse_frame_done_packet(payload: &[u8]) -> Result<FrameDonePacket, PacketErro
 }
 
 fn parse_capabilities_packet(payload: &[u8]) -> Result<CapabilitiesPacket, PacketError> {
-    if payload.len() != 8 {
+    if payload.len() < 8 {
         return Err(PacketError::PayloadTooShort);
     }
+    let flags = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+
+    let (decoder_limits, rest) = if payload.len() > 8 {
+        let (limits, rest) = parse_decoder_limits(&payload[8..])?;
+        // An empty block only pads the way to the channel nonce.
+        ((limits != DecoderLimits::default()).then_some(limits), rest)
+    } else {
+        (None, &[][..])
+    };
+    let channel_nonce = if flags & CAP_FLAG_CIPHERS != 0 && rest.len() >= CHANNEL_NONCE_LEN {
+        Some(rest[..CHANNEL_NONCE_LEN].try_into().unwrap())
+    } else {
+        None
+    };
+    let share = rest.get(CHANNEL_NONCE_LEN..CHANNEL_NONCE_LEN + 2 * KEY_LEN);
+    let public_key = match (channel_nonce, share) {
+        (Some(_), Some(share)) if flags & CAP_FLAG_PUBLIC_KEY != 0 => Some(PublicKeyShare {
+            identity_key: share[..KEY_LEN].try_into().unwrap(),
+            key_share: share[KEY_LEN..].try_into().unwrap(),
+        }),
+        _ => None,
+    };
 
     Ok(CapabilitiesPacket {
         codec_mask: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
-        flags: u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]),
+        flags,
+        decoder_limits,
+        channel_nonce,
+        public_key,
     })
 }
 
+fn parse_key_exchange_packet(payload: &[u8]) -> Result<KeyExchangePacket, PacketError> {
+    let ephemeral_key = payload
+        .get(..KEY_LEN)
+        .ok_or(PacketError::PayloadTooShort)?
+        .try_into()
+        .unwrap();
+    Ok(KeyExchangePacket { ephemeral_key })
+}
+
+/// The limits block and whatever follows it.
+fn parse_decoder_limits(payload: &[u8]) -> Result<(DecoderLimits, &[u8]), PacketError> {
+    if payload.len() < 7 {
+        return Err(PacketError::PayloadTooShort);
+    }
+    let count = payload[6] as usize;
+    let entries = &payload[7..];
+    if entries.len() < count * 4 {
+        return Err(PacketError::PayloadTooShort);
+    }
+
+    let limits = DecoderLimits {
+        max_width: u16::from_le_bytes([payload[0], payload[1]]),
+        max_height: u16::from_le_bytes([payload[2], payload[3]]),
+        max_fps: u16::from_le_bytes([payload[4], payload[5]]),
+        codecs: entries
+            .chunks_exact(4)
+            .take(count)
+            .map(|chunk| CodecLimit {
+                codec_id: chunk[0],
+                max_profile: chunk[1],
+                max_level: chunk[2],
+                codec_flags: chunk[3],
+            })
+            .collect(),
+    };
+    Ok((limits, &entries[count * 4..]))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn builds_error_packet() {
+        let packet = build_error_packet(ERROR_HOST_BUSY, "busy");
+        assert_eq!(packet, [14, ERROR_HOST_BUSY, b'b', b'u', b's', b'y']);
+    }
+
+    #[test]
+    fn builds_media_path_packet() {
+        let packet = build_media_path_packet(MediaPathPacket {
+            port: 0x1234,
+            fec_group: 4,
+            max_datagram: 1200,
+        });
+        assert_eq!(packet, [19, 0x34, 0x12, 4, 0xb0, 0x04]);
+    }
+
+    #[test]
+    fn builds_quic_path_packet() {
+        let packet = build_quic_path_packet(QuicPathPacket {
+            port: 1446,
+            token: [7; 16],
+        });
+        assert_eq!(packet[..3], [20, 0xa6, 0x05]);
+        assert_eq!(packet[3..], [7; 16]);
+    }
+
     #[test]
     fn builds_configure_packet() {
         let packet = build_configure_packet(ConfigurePacket {
@@ -245,6 +504,7 @@ mod tests {
             codec_profile: 0,
             codec_level: 0,
             codec_flags: 0,
+            color: None,
         });
 
         assert_eq!(packet[0], 1);
@@ -267,6 +527,7 @@ mod tests {
             codec_profile: 1,
             codec_level: 2,
             codec_flags: 0,
+            color: None,
         });
 
         assert_eq!(packet[0], 1);
@@ -274,6 +535,30 @@ mod tests {
         assert_eq!(packet[21], 2);
     }
 
+    #[test]
+    fn builds_configure_packet_with_color_description() {
+        let packet = build_configure_packet(ConfigurePacket {
+            width: 1920,
+            height: 1080,
+            host_width: 1920,
+            host_height: 1080,
+            encoder_id: 1,
+            codec_id: Some(1),
+            codec_profile: 100,
+            codec_level: 40,
+            codec_flags: 0x04,
+            color: Some(ColorDescription {
+                primaries: 1,
+                transfer: 1,
+                matrix: 1,
+                full_range: true,
+            }),
+        });
+
+        assert_eq!(packet.len(), 1 + 20 + 4 + 4);
+        assert_eq!(&packet[21..], &[1, 100, 40, 0x04, 1, 1, 1, 1]);
+    }
+
     #[test]
     fn builds_frame_packet_with_meta() {
         let packet = build_frame_packet(FramePacket {
@@ -358,6 +643,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_command_packet() {
+        let payload = COMMAND_SCREENSHOT.to_le_bytes();
+        let packet = parse_client_packet(
+            &[16u8]
+                .iter()
+                .chain(payload.iter())
+                .copied()
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            packet,
+            ClientPacket::Command(CommandPacket {
+                command_id: COMMAND_SCREENSHOT,
+            })
+        );
+        assert!(parse_client_packet(&[16u8, 1, 0]).is_err());
+        assert_eq!(build_take_screenshot_packet(), vec![7]);
+    }
+
     #[test]
     fn parses_frame_done_packet() {
         let payload = [7u8, 0, 0, 0];
@@ -379,7 +686,93 @@ mod tests {
             ClientPacket::Capabilities(CapabilitiesPacket {
                 codec_mask: 1,
                 flags: 2,
+                decoder_limits: None,
+                channel_nonce: None,
+                public_key: None,
             })
         );
     }
+
+    #[test]
+    fn round_trips_capabilities_decoder_limits() {
+        let limits = DecoderLimits {
+            max_width: 2560,
+            max_height: 1600,
+            max_fps: 120,
+            codecs: vec![CodecLimit {
+                codec_id: 1,
+                max_profile: 100,
+                max_level: 42,
+                codec_flags: 0x04,
+            }],
+        };
+        let packet = build_capabilities_packet(CapabilitiesPacket {
+            codec_mask: 3,
+            flags: 0,
+            decoder_limits: Some(limits.clone()),
+            channel_nonce: None,
+            public_key: None,
+        });
+
+        assert_eq!(packet.len(), 1 + 8 + 7 + 4);
+        match parse_client_packet(&packet).unwrap() {
+            ClientPacket::Capabilities(caps) => assert_eq!(caps.decoder_limits, Some(limits)),
+            _ => panic!("unexpected packet"),
+        }
+    }
+
+    #[test]
+    fn round_trips_capabilities_channel_nonce() {
+        let caps = CapabilitiesPacket {
+            codec_mask: 1,
+            flags: CAP_FLAG_CIPHERS,
+            decoder_limits: None,
+            channel_nonce: Some([0xa5; CHANNEL_NONCE_LEN]),
+            public_key: None,
+        };
+        let packet = build_capabilities_packet(caps.clone());
+
+        assert_eq!(packet.len(), 1 + 8 + 7 + CHANNEL_NONCE_LEN);
+        assert_eq!(
+            parse_client_packet(&packet).unwrap(),
+            ClientPacket::Capabilities(caps)
+        );
+    }
+
+    #[test]
+    fn round_trips_public_key_share_and_key_exchange() {
+        let caps = CapabilitiesPacket {
+            codec_mask: 1,
+            flags: CAP_FLAG_CIPHERS | CAP_FLAG_PUBLIC_KEY,
+            decoder_limits: None,
+            channel_nonce: Some([1; CHANNEL_NONCE_LEN]),
+            public_key: Some(PublicKeyShare {
+                identity_key: [2; KEY_LEN],
+                key_share: [3; KEY_LEN],
+            }),
+        };
+        let packet = build_capabilities_packet(caps.clone());
+        assert_eq!(packet.len(), 1 + 8 + 7 + CHANNEL_NONCE_LEN + 2 * KEY_LEN);
+        assert_eq!(
+            parse_client_packet(&packet).unwrap(),
+            ClientPacket::Capabilities(caps)
+        );
+
+        let packet = build_key_exchange_packet(KeyExchangePacket {
+            ephemeral_key: [4; KEY_LEN],
+        });
+        assert_eq!(
+            parse_client_packet(&packet).unwrap(),
+            ClientPacket::KeyExchange(KeyExchangePacket {
+                ephemeral_key: [4; KEY_LEN]
+            })
+        );
+        assert!(parse_client_packet(&packet[..KEY_LEN]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_decoder_limits() {
+        let payload = [17u8, 1, 0, 0, 0, 0, 0, 0, 0, 10, 0, 10, 0, 30, 0, 1, 1];
+        assert!(parse_client_packet(&payload).is_err());
+    }
 }
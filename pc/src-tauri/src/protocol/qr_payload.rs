@@ -0,0 +1,353 @@
+//! The pairing payload shown as a QR code.
+//!
+//! ```text
+//! uberdisplay:<base64url, unpadded, of CBOR [version, body, signature]>
+//! ```
+//!
+//! `body` is a CBOR byte string holding a map with small integer keys (see
+//! `Key`), so the code stays small enough to scan from a phone screen. The
+//! signature is Ed25519 by the identity key carried in the body, over the body
+//! bytes. A payload pinning a fingerprint must be signed by the key it names, so
+//! scanning it pins a key whose holder produced the code.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::value::Value;
+
+use super::key_exchange::{fingerprint, verify, Identity, KEY_LEN, SIGNATURE_LEN};
+
+pub const QR_PREFIX: &str = "uberdisplay:";
+pub const QR_PAYLOAD_VERSION: u64 = 1;
+pub const PAIRING_NONCE_LEN: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum QrPayloadError {
+    #[error("not an UberDisplay pairing code")]
+    NotPairingCode,
+    #[error("pairing code is not valid base64url")]
+    Encoding,
+    #[error("pairing code is malformed: {0}")]
+    Malformed(&'static str),
+    #[error("pairing code version {0} is not supported")]
+    UnsupportedVersion(u64),
+    #[error("pairing code signature is invalid")]
+    InvalidSignature,
+    #[error("pairing code fingerprint does not match its signer")]
+    FingerprintMismatch,
+    #[error("pairing code expired")]
+    Expired,
+}
+
+/// Body map keys.
+#[derive(Clone, Copy)]
+enum Key {
+    Address = 1,
+    Hostname = 2,
+    TcpPort = 3,
+    QuicPort = 4,
+    Transport = 5,
+    MinProtocolVersion = 6,
+    Psk = 7,
+    Fingerprint = 8,
+    Nonce = 9,
+    ExpiresAt = 10,
+    Signer = 11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreferredTransport {
+    Tcp = 0,
+    Quic = 1,
+}
+
+impl PreferredTransport {
+    pub fn name(self) -> &'static str {
+        match self {
+            PreferredTransport::Tcp => "tcp",
+            PreferredTransport::Quic => "quic",
+        }
+    }
+}
+
+/// What secures the first connection: a shared key, or the fingerprint to pin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bootstrap {
+    Psk(String),
+    Fingerprint([u8; 32]),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingPayload {
+    pub address: String,
+    pub hostname: Option<String>,
+    pub tcp_port: u16,
+    pub quic_port: Option<u16>,
+    pub transport: PreferredTransport,
+    pub min_protocol_version: u16,
+    pub bootstrap: Bootstrap,
+    /// Random per code; a scanner refuses a nonce it has already used.
+    pub nonce: [u8; PAIRING_NONCE_LEN],
+    /// Unix seconds after which the code is refused.
+    pub expires_at: u64,
+}
+
+/// A decoded payload whose signature and expiry have been checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedPayload {
+    pub payload: PairingPayload,
+    /// Identity key that signed the code.
+    pub signer: [u8; KEY_LEN],
+}
+
+/// Signs `payload` with `identity` and renders it as QR text.
+pub fn encode(payload: &PairingPayload, identity: &Identity) -> String {
+    let mut entries = vec![
+        (Key::Address, Value::Text(payload.address.clone())),
+        (Key::TcpPort, Value::from(payload.tcp_port)),
+        (Key::Transport, Value::from(payload.transport as u8)),
+        (
+            Key::MinProtocolVersion,
+            Value::from(payload.min_protocol_version),
+        ),
+        (Key::Nonce, Value::Bytes(payload.nonce.to_vec())),
+        (Key::ExpiresAt, Value::from(payload.expires_at)),
+        (Key::Signer, Value::Bytes(identity.public_key().to_vec())),
+    ];
+    if let Some(hostname) = &payload.hostname {
+        entries.push((Key::Hostname, Value::Text(hostname.clone())));
+    }
+    if let Some(port) = payload.quic_port {
+        entries.push((Key::QuicPort, Value::from(port)));
+    }
+    entries.push(match &payload.bootstrap {
+        Bootstrap::Psk(psk) => (Key::Psk, Value::Text(psk.clone())),
+        Bootstrap::Fingerprint(fingerprint) => {
+            (Key::Fingerprint, Value::Bytes(fingerprint.to_vec()))
+        }
+    });
+    entries.sort_by_key(|(key, _)| *key as u8);
+    let body = to_cbor(&Value::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::from(key as u8), value))
+            .collect(),
+    ));
+
+    let signature = identity.sign(&body);
+    let outer = to_cbor(&Value::Array(vec![
+        Value::from(QR_PAYLOAD_VERSION),
+        Value::Bytes(body),
+        Value::Bytes(signature.to_vec()),
+    ]));
+    format!("{QR_PREFIX}{}", URL_SAFE_NO_PAD.encode(outer))
+}
+
+/// Parses scanned QR text, checking the signature and that it has not expired at
+/// `now` (Unix seconds).
+pub fn decode(text: &str, now: u64) -> Result<VerifiedPayload, QrPayloadError> {
+    let text = text.trim();
+    let encoded = text
+        .get(..QR_PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(QR_PREFIX))
+        .map(|_| &text[QR_PREFIX.len()..])
+        .ok_or(QrPayloadError::NotPairingCode)?;
+    let outer = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|_| QrPayloadError::Encoding)?;
+    let outer: Value = ciborium::de::from_reader(outer.as_slice())
+        .map_err(|_| QrPayloadError::Malformed("cbor"))?;
+    let [version, body, signature] = outer
+        .into_array()
+        .ok()
+        .and_then(|items| <[Value; 3]>::try_from(items).ok())
+        .ok_or(QrPayloadError::Malformed("envelope"))?;
+    let version = integer(&version).ok_or(QrPayloadError::Malformed("version"))?;
+    if version != QR_PAYLOAD_VERSION {
+        return Err(QrPayloadError::UnsupportedVersion(version));
+    }
+    let body = body
+        .into_bytes()
+        .map_err(|_| QrPayloadError::Malformed("body"))?;
+    let signature: [u8; SIGNATURE_LEN] = signature
+        .into_bytes()
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(QrPayloadError::Malformed("signature"))?;
+
+    let map: Value = ciborium::de::from_reader(body.as_slice())
+        .map_err(|_| QrPayloadError::Malformed("cbor"))?;
+    let map = map
+        .into_map()
+        .map_err(|_| QrPayloadError::Malformed("body"))?;
+    let field = |key: Key| {
+        map.iter()
+            .find(|(entry, _)| integer(entry) == Some(key as u64))
+            .map(|(_, value)| value)
+    };
+
+    let signer: [u8; KEY_LEN] =
+        fixed_bytes(field(Key::Signer)).ok_or(QrPayloadError::Malformed("signer"))?;
+    verify(&signer, &body, &signature).map_err(|_| QrPayloadError::InvalidSignature)?;
+
+    let bootstrap = match (field(Key::Psk), field(Key::Fingerprint)) {
+        (Some(psk), None) => Bootstrap::Psk(
+            psk.as_text()
+                .ok_or(QrPayloadError::Malformed("psk"))?
+                .to_string(),
+        ),
+        (None, Some(_)) => {
+            let pinned = fixed_bytes(field(Key::Fingerprint))
+                .ok_or(QrPayloadError::Malformed("fingerprint"))?;
+            if pinned != fingerprint(&signer) {
+                return Err(QrPayloadError::FingerprintMismatch);
+            }
+            Bootstrap::Fingerprint(pinned)
+        }
+        _ => return Err(QrPayloadError::Malformed("bootstrap")),
+    };
+    let payload = PairingPayload {
+        address: field(Key::Address)
+            .and_then(Value::as_text)
+            .ok_or(QrPayloadError::Malformed("address"))?
+            .to_string(),
+        hostname: match field(Key::Hostname) {
+            Some(value) => Some(
+                value
+                    .as_text()
+                    .ok_or(QrPayloadError::Malformed("hostname"))?
+                    .to_string(),
+            ),
+            None => None,
+        },
+        tcp_port: port(field(Key::TcpPort)).ok_or(QrPayloadError::Malformed("tcp port"))?,
+        quic_port: match field(Key::QuicPort) {
+            Some(value) => Some(port(Some(value)).ok_or(QrPayloadError::Malformed("quic port"))?),
+            None => None,
+        },
+        transport: match field(Key::Transport).and_then(integer) {
+            Some(0) => PreferredTransport::Tcp,
+            Some(1) => PreferredTransport::Quic,
+            _ => return Err(QrPayloadError::Malformed("transport")),
+        },
+        min_protocol_version: field(Key::MinProtocolVersion)
+            .and_then(integer)
+            .and_then(|value| u16::try_from(value).ok())
+            .ok_or(QrPayloadError::Malformed("protocol version"))?,
+        bootstrap,
+        nonce: fixed_bytes(field(Key::Nonce)).ok_or(QrPayloadError::Malformed("nonce"))?,
+        expires_at: field(Key::ExpiresAt)
+            .and_then(integer)
+            .ok_or(QrPayloadError::Malformed("expiry"))?,
+    };
+    if now >= payload.expires_at {
+        return Err(QrPayloadError::Expired);
+    }
+    Ok(VerifiedPayload { payload, signer })
+}
+
+fn to_cbor(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    ciborium::ser::into_writer(value, &mut out).expect("writing CBOR to a Vec cannot fail");
+    out
+}
+
+fn integer(value: &Value) -> Option<u64> {
+    value
+        .as_integer()
+        .and_then(|integer| u64::try_from(integer).ok())
+}
+
+fn port(value: Option<&Value>) -> Option<u16> {
+    value
+        .and_then(integer)
+        .and_then(|value| u16::try_from(value).ok())
+        .filter(|port| *port != 0)
+}
+
+fn fixed_bytes<const N: usize>(value: Option<&Value>) -> Option<[u8; N]> {
+    value
+        .and_then(Value::as_bytes)
+        .and_then(|bytes| bytes.as_slice().try_into().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_800_000_000;
+
+    fn payload(bootstrap: Bootstrap) -> PairingPayload {
+        PairingPayload {
+            address: "192.168.1.20".to_string(),
+            hostname: Some("studio-pc".to_string()),
+            tcp_port: 1445,
+            quic_port: None,
+            transport: PreferredTransport::Tcp,
+            min_protocol_version: 4,
+            bootstrap,
+            nonce: [9; PAIRING_NONCE_LEN],
+            expires_at: NOW + 300,
+        }
+    }
+
+    #[test]
+    fn round_trips_signed_payloads() {
+        let identity = Identity::generate().unwrap();
+        for bootstrap in [
+            Bootstrap::Fingerprint(identity.fingerprint()),
+            Bootstrap::Psk("00ff".repeat(16)),
+        ] {
+            let original = payload(bootstrap);
+            let text = encode(&original, &identity);
+            assert!(text.starts_with(QR_PREFIX));
+            assert!(text.len() < 400, "{} characters", text.len());
+            let decoded = decode(&text, NOW).unwrap();
+            assert_eq!(decoded.payload, original);
+            assert_eq!(decoded.signer, identity.public_key());
+        }
+
+        let quic = PairingPayload {
+            hostname: None,
+            quic_port: Some(1446),
+            transport: PreferredTransport::Quic,
+            ..payload(Bootstrap::Fingerprint(identity.fingerprint()))
+        };
+        // Some scanners upper-case the scheme.
+        let text = encode(&quic, &identity).replacen(QR_PREFIX, "UBERDISPLAY:", 1);
+        assert_eq!(decode(&text, NOW).unwrap().payload, quic);
+    }
+
+    #[test]
+    fn rejects_expired_tampered_and_foreign_codes() {
+        let identity = Identity::generate().unwrap();
+        let original = payload(Bootstrap::Fingerprint(identity.fingerprint()));
+        let text = encode(&original, &identity);
+        assert_eq!(decode(&text, NOW + 300), Err(QrPayloadError::Expired));
+
+        let mut raw = URL_SAFE_NO_PAD.decode(&text[QR_PREFIX.len()..]).unwrap();
+        let index = raw.windows(4).position(|window| window == b"192.").unwrap();
+        raw[index + 3] = b'9';
+        let tampered = format!("{QR_PREFIX}{}", URL_SAFE_NO_PAD.encode(raw));
+        assert_eq!(
+            decode(&tampered, NOW),
+            Err(QrPayloadError::InvalidSignature)
+        );
+
+        // Pinning someone else's fingerprint is refused even with a valid signature.
+        let other = Identity::generate().unwrap();
+        let foreign = encode(&original, &other);
+        assert_eq!(
+            decode(&foreign, NOW),
+            Err(QrPayloadError::FingerprintMismatch)
+        );
+
+        assert_eq!(
+            decode("https://example.com", NOW),
+            Err(QrPayloadError::NotPairingCode)
+        );
+        assert_eq!(
+            decode("uberdisplay:***", NOW),
+            Err(QrPayloadError::Encoding)
+        );
+    }
+}
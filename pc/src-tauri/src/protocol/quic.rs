@@ -0,0 +1,378 @@
+//! QUIC transport (§7.11): TLS identities made from the pairing keys, and the
+//! stream layout shared by both ends.
+//!
+//! Each side presents a self-signed certificate over its Ed25519 identity key, so
+//! the TLS handshake proves possession of the key pinned at pairing. Certificates
+//! are never checked against a CA or a name: the client compares the host's key
+//! with the fingerprint it pinned, and the host matches the client's key to the
+//! TCP session that offered the path.
+//!
+//! The client opens one bidirectional stream for control and input: the token from
+//! `QuicPath`, then chunk framing as on TCP (§7.2, §7.3). Every `Frame` travels on
+//! its own unidirectional stream holding just that packet, so a late frame can be
+//! abandoned without holding up input or the frames after it.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use thiserror::Error;
+
+use super::key_exchange::{fingerprint, Identity, KEY_LEN};
+
+/// Client and host capability bit: the peer can move the session to QUIC.
+pub const CAP_FLAG_QUIC: u32 = 1 << 4;
+pub const ALPN: &[u8] = b"uberdisplay/1";
+/// Certificates carry no meaningful name; clients ask for this one.
+pub const SERVER_NAME: &str = "uberdisplay";
+pub const TOKEN_LEN: usize = 16;
+pub const DEFAULT_PORT: u16 = 1446;
+/// Keeps NAT bindings open between frames.
+pub const KEEP_ALIVE: Duration = Duration::from_secs(2);
+/// A path silent this long is closed, and the session falls back to TCP.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// PKCS#8 v1 wrapping of a raw Ed25519 seed (RFC 8410).
+const PKCS8_ED25519_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+/// `AlgorithmIdentifier` of an Ed25519 `subjectPublicKeyInfo`.
+const ED25519_ALGORITHM: [u8; 7] = [0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70];
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_VERSION: u8 = 0xa0;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QuicError {
+    #[error("certificate is malformed")]
+    MalformedCertificate,
+    #[error("certificate key is not Ed25519")]
+    NotEd25519,
+    #[error("TLS setup failed: {0}")]
+    Tls(String),
+}
+
+/// A self-signed certificate over `identity`, with its private key.
+pub fn certificate(
+    identity: &Identity,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>), QuicError> {
+    let mut pkcs8 = PKCS8_ED25519_PREFIX.to_vec();
+    pkcs8.extend_from_slice(&identity.seed());
+    let key = PrivatePkcs8KeyDer::from(pkcs8);
+    let key_pair = rcgen::KeyPair::from_pkcs8_der_and_sign_algo(&key, &rcgen::PKCS_ED25519)
+        .map_err(tls_error)?;
+    let certificate = rcgen::CertificateParams::new(vec![SERVER_NAME.to_string()])
+        .and_then(|params| params.self_signed(&key_pair))
+        .map_err(tls_error)?;
+    Ok((certificate.der().clone(), PrivateKeyDer::Pkcs8(key)))
+}
+
+/// The Ed25519 identity key a certificate was issued for.
+pub fn certificate_key(der: &[u8]) -> Result<[u8; KEY_LEN], QuicError> {
+    let certificate = expect(der, TAG_SEQUENCE)?.0;
+    let mut fields = expect(certificate, TAG_SEQUENCE)?.0;
+    if fields.first() == Some(&TAG_VERSION) {
+        fields = element(fields)?.2;
+    }
+    // Serial number, signature algorithm, issuer, validity and subject.
+    for _ in 0..5 {
+        fields = element(fields)?.2;
+    }
+    let info = expect(fields, TAG_SEQUENCE)?.0;
+    let (_, _, key) = element(info)?;
+    if info[..info.len() - key.len()] != ED25519_ALGORITHM {
+        return Err(QuicError::NotEd25519);
+    }
+    match expect(key, TAG_BIT_STRING)?.0 {
+        [0, key @ ..] if key.len() == KEY_LEN => Ok(key.try_into().unwrap()),
+        _ => Err(QuicError::NotEd25519),
+    }
+}
+
+/// Splits one DER element off `input`: its tag, contents and what follows.
+fn element(input: &[u8]) -> Result<(u8, &[u8], &[u8]), QuicError> {
+    let malformed = QuicError::MalformedCertificate;
+    let (&tag, rest) = input.split_first().ok_or(QuicError::MalformedCertificate)?;
+    let (&first, rest) = rest.split_first().ok_or(QuicError::MalformedCertificate)?;
+    let (len, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 || rest.len() < count {
+            return Err(malformed);
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |len, byte| len << 8 | usize::from(*byte));
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return Err(malformed);
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+fn expect(input: &[u8], tag: u8) -> Result<(&[u8], &[u8]), QuicError> {
+    match element(input)? {
+        (found, contents, rest) if found == tag => Ok((contents, rest)),
+        _ => Err(QuicError::MalformedCertificate),
+    }
+}
+
+fn tls_error(err: impl std::fmt::Display) -> QuicError {
+    QuicError::Tls(err.to_string())
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn transport() -> quinn::TransportConfig {
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    transport.max_idle_timeout(IDLE_TIMEOUT.try_into().ok());
+    transport
+}
+
+/// Host configuration: presents `identity` and requires a client identity.
+pub fn server_config(identity: &Identity) -> Result<quinn::ServerConfig, QuicError> {
+    let provider = provider();
+    let (certificate, key) = certificate(identity)?;
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .with_client_cert_verifier(Arc::new(AnyIdentity { provider }))
+        .with_single_cert(vec![certificate], key)
+        .map_err(tls_error)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(tls_error)?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(Arc::new(transport()));
+    Ok(config)
+}
+
+/// Client configuration: presents `identity` and accepts only the host whose
+/// identity fingerprint is `host`.
+pub fn client_config(
+    identity: &Identity,
+    host: [u8; 32],
+) -> Result<quinn::ClientConfig, QuicError> {
+    let provider = provider();
+    let (certificate, key) = certificate(identity)?;
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedHost {
+            fingerprint: host,
+            provider,
+        }))
+        .with_client_auth_cert(vec![certificate], key)
+        .map_err(tls_error)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(tls).map_err(tls_error)?;
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport()));
+    Ok(config)
+}
+
+/// The identity key the peer proved during the handshake.
+pub fn peer_key(connection: &quinn::Connection) -> Option<[u8; KEY_LEN]> {
+    let chain = connection
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    certificate_key(chain.first()?).ok()
+}
+
+fn bad_certificate() -> rustls::Error {
+    rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure)
+}
+
+/// Accepts only the host key the client pinned.
+#[derive(Debug)]
+struct PinnedHost {
+    fingerprint: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedHost {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match certificate_key(end_entity) {
+            Ok(key) if fingerprint(&key) == self.fingerprint => Ok(ServerCertVerified::assertion()),
+            _ => Err(bad_certificate()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+/// Accepts any client holding an Ed25519 identity; the host then decides whether
+/// that identity owns the session it claims.
+#[derive(Debug)]
+struct AnyIdentity {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ClientCertVerifier for AnyIdentity {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        certificate_key(end_entity)
+            .map(|_| ClientCertVerified::assertion())
+            .map_err(|_| bad_certificate())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    #[test]
+    fn certificates_carry_the_identity_key() {
+        let identity = Identity::from_seed(&[9; KEY_LEN]);
+        let (certificate, _) = certificate(&identity).unwrap();
+        assert_eq!(
+            certificate_key(&certificate).unwrap(),
+            identity.public_key()
+        );
+
+        let other = rcgen::KeyPair::generate().unwrap();
+        let ecdsa = rcgen::CertificateParams::new(vec![SERVER_NAME.to_string()])
+            .unwrap()
+            .self_signed(&other)
+            .unwrap();
+        assert_eq!(certificate_key(ecdsa.der()), Err(QuicError::NotEd25519));
+        assert_eq!(
+            certificate_key(&certificate[..certificate.len() - 1]),
+            Err(QuicError::MalformedCertificate)
+        );
+    }
+
+    #[test]
+    fn handshake_proves_both_identities() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let host = Identity::from_seed(&[1; KEY_LEN]);
+        let client = Identity::from_seed(&[2; KEY_LEN]);
+        runtime.block_on(async {
+            let server = quinn::Endpoint::server(
+                server_config(&host).unwrap(),
+                SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            )
+            .unwrap();
+            let address = server.local_addr().unwrap();
+            let (keys, mut accepted) = tokio::sync::mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                while let Some(incoming) = server.accept().await {
+                    if let Ok(connection) = incoming.await {
+                        let _ = keys.send(peer_key(&connection));
+                    }
+                }
+            });
+            let dialer =
+                quinn::Endpoint::client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+
+            let pinned = client_config(&client, host.fingerprint()).unwrap();
+            let connection = dialer
+                .connect_with(pinned, address, SERVER_NAME)
+                .unwrap()
+                .await
+                .unwrap();
+            assert_eq!(peer_key(&connection), Some(host.public_key()));
+            assert_eq!(accepted.recv().await, Some(Some(client.public_key())));
+
+            let wrong_pin = client_config(&client, client.fingerprint()).unwrap();
+            assert!(dialer
+                .connect_with(wrong_pin, address, SERVER_NAME)
+                .unwrap()
+                .await
+                .is_err());
+        });
+    }
+}
@@ -0,0 +1,916 @@
+//! Authenticated encryption below the chunk framing.
+//!
+//! Once both `Capabilities` packets have been exchanged in the clear, each
+//! direction switches to a sequence of records carrying the chunk byte stream:
+//!
+//! ```text
+//! record: length u32 | sequence u64 | ciphertext + 16-byte tag
+//! ```
+//!
+//! `length` counts the sequence number and the ciphertext. The sequence number
+//! starts at zero, grows by one per record and doubles as the AEAD nonce; the
+//! record header is authenticated as associated data. Each direction has its own
+//! key, derived with HKDF-SHA256 from the session secret (the pre-shared key, or
+//! the key exchange in `key_exchange`) and salted with a hash of the negotiation
+//! packets, so every session gets fresh keys and a tampered negotiation fails on
+//! the first record.
+//!
+//! Long sessions rotate keys. A sender due for a new key (see `RekeyPolicy`) first
+//! sends a control record, marked by the top bit of `length`, announcing the next
+//! key epoch; everything after it uses a key derived from the current one. The
+//! receiver keeps the previous key for `REKEY_GRACE` so records already sealed
+//! under it still open.
+//!
+//! The datagram media path (`udp`) cannot use records, since datagrams get lost
+//! and reordered. Each datagram is sealed on its own by a `DatagramCipher`, keyed
+//! from the direction's channel key of the current epoch, with the datagram's
+//! sequence number as the nonce. Datagrams carry their epoch, so the receiver
+//! picks the matching key even when one arrives before or after the rekey record.
+
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+use super::key_exchange::Identity;
+
+pub const CAP_FLAG_CHACHA20_POLY1305: u32 = 1 << 0;
+pub const CAP_FLAG_AES_256_GCM: u32 = 1 << 1;
+pub const CAP_FLAG_CIPHERS: u32 = CAP_FLAG_CHACHA20_POLY1305 | CAP_FLAG_AES_256_GCM;
+/// Keys come from an identity key exchange instead of a pre-shared key.
+pub const CAP_FLAG_PUBLIC_KEY: u32 = 1 << 2;
+
+/// Random value each side appends to its `Capabilities` when offering encryption.
+pub const CHANNEL_NONCE_LEN: usize = 16;
+/// Plaintext carried by one record; longer writes are split.
+pub const MAX_RECORD_PLAINTEXT: usize = 1 << 20;
+
+const RECORD_HEADER_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LABEL_HOST_TO_CLIENT: &[u8] = b"uberdisplay channel v1 host->client";
+const KEY_LABEL_CLIENT_TO_HOST: &[u8] = b"uberdisplay channel v1 client->host";
+const KEY_LABEL_REKEY: &[u8] = b"uberdisplay rekey v1";
+const KEY_LABEL_DATAGRAM: &[u8] = b"uberdisplay datagram v1";
+/// Set in a record's `length` when it carries a control message.
+const RECORD_CONTROL: u32 = 1 << 31;
+const CONTROL_REKEY: u8 = 1;
+/// How long a receiver still accepts records under the key it just replaced.
+pub const REKEY_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl Cipher {
+    pub fn flag(self) -> u32 {
+        match self {
+            Cipher::ChaCha20Poly1305 => CAP_FLAG_CHACHA20_POLY1305,
+            Cipher::Aes256Gcm => CAP_FLAG_AES_256_GCM,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::ChaCha20Poly1305 => "ChaCha20-Poly1305",
+            Cipher::Aes256Gcm => "AES-256-GCM",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm]
+            .into_iter()
+            .find(|cipher| cipher.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The cipher selected by `flags`, which must name exactly one.
+    pub fn from_flags(flags: u32) -> Option<Self> {
+        match flags & CAP_FLAG_CIPHERS {
+            CAP_FLAG_CHACHA20_POLY1305 => Some(Cipher::ChaCha20Poly1305),
+            CAP_FLAG_AES_256_GCM => Some(Cipher::Aes256Gcm),
+            _ => None,
+        }
+    }
+}
+
+/// Which cipher the host is willing to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherChoice {
+    /// AES-256-GCM when this machine accelerates AES, ChaCha20-Poly1305 otherwise.
+    Auto,
+    Fixed(Cipher),
+}
+
+impl CipherChoice {
+    /// Picks the session cipher among those the client advertised. Clients without
+    /// fast AES should not advertise it.
+    pub fn select(self, client_flags: u32, hardware_aes: bool) -> Option<Cipher> {
+        let offered = |cipher: Cipher| client_flags & cipher.flag() != 0;
+        match self {
+            CipherChoice::Fixed(cipher) => offered(cipher).then_some(cipher),
+            CipherChoice::Auto => {
+                let order = if hardware_aes {
+                    [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305]
+                } else {
+                    [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm]
+                };
+                order.into_iter().find(|cipher| offered(*cipher))
+            }
+        }
+    }
+}
+
+/// How a session protects its traffic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelSecurity {
+    /// Debug only: nothing is encrypted or authenticated.
+    Plaintext,
+    Psk {
+        key: Vec<u8>,
+        cipher: CipherChoice,
+    },
+    /// Ephemeral X25519 exchange authenticated by long-term identity keys.
+    PublicKey {
+        identity: Box<Identity>,
+        cipher: CipherChoice,
+        trust: PeerTrust,
+    },
+}
+
+/// Which client identities the host accepts without a new pairing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerTrust {
+    /// Fingerprints pinned on paired devices.
+    pub trusted: Vec<[u8; 32]>,
+    /// Pinned fingerprint of the device being connected to, if one is selected. Any
+    /// other key is refused outright rather than offered for pairing.
+    pub expected: Option<[u8; 32]>,
+    /// Whether an unknown key may be offered for pairing instead of refused.
+    pub allow_pairing: bool,
+}
+
+/// When a sender switches to a fresh key: after `max_bytes` of plaintext or
+/// `max_age` under one key, whichever comes first. `None` disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl RekeyPolicy {
+    pub const NEVER: RekeyPolicy = RekeyPolicy {
+        max_bytes: None,
+        max_age: None,
+    };
+
+    fn due(&self, sealed_bytes: u64, age: Duration) -> bool {
+        self.max_bytes.is_some_and(|limit| sealed_bytes >= limit)
+            || self.max_age.is_some_and(|limit| age >= limit)
+    }
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(1 << 30),
+            max_age: Some(Duration::from_secs(30 * 60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    Client,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ChannelError {
+    #[error("record failed authentication")]
+    Authentication,
+    #[error("record {sequence} was replayed (expected {expected})")]
+    Replayed { sequence: u64, expected: u64 },
+    #[error("record {sequence} arrived out of order (expected {expected})")]
+    OutOfOrder { sequence: u64, expected: u64 },
+    #[error("record length {0} is invalid")]
+    InvalidLength(usize),
+    #[error("invalid control record")]
+    InvalidControl,
+}
+
+pub fn hardware_aes() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::arch::is_x86_feature_detected!("aes")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("aes")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+pub fn random_nonce() -> Result<[u8; CHANNEL_NONCE_LEN], String> {
+    let mut nonce = [0u8; CHANNEL_NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|err| err.to_string())?;
+    Ok(nonce)
+}
+
+/// A random 256-bit pre-shared key, hex encoded for entry on the other device.
+pub fn generate_psk() -> Result<String, String> {
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).map_err(|err| err.to_string())?;
+    Ok(key.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Key derivation salt: the negotiation packets as sent, in order, each prefixed
+/// with its length.
+pub fn transcript_hash(packets: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for packet in packets {
+        hasher.update((packet.len() as u32).to_le_bytes());
+        hasher.update(packet);
+    }
+    hasher.finalize().into()
+}
+
+/// Both halves of the channel for `role`, keyed from the session `secret`.
+pub fn establish(
+    secret: &[u8],
+    cipher: Cipher,
+    transcript: &[u8; 32],
+    role: Role,
+) -> (Sealer, Opener) {
+    let host_to_client = EpochKey::new(
+        cipher,
+        0,
+        derive_key(secret, cipher, transcript, KEY_LABEL_HOST_TO_CLIENT),
+    );
+    let client_to_host = EpochKey::new(
+        cipher,
+        0,
+        derive_key(secret, cipher, transcript, KEY_LABEL_CLIENT_TO_HOST),
+    );
+    let (outgoing, incoming) = match role {
+        Role::Host => (host_to_client, client_to_host),
+        Role::Client => (client_to_host, host_to_client),
+    };
+    (
+        Sealer {
+            datagram: DatagramCipher::for_channel(&outgoing),
+            key: outgoing,
+            sequence: 0,
+            policy: RekeyPolicy::default(),
+            sealed_bytes: 0,
+            keyed_at: Instant::now(),
+        },
+        Opener {
+            datagram: DatagramCipher::for_channel(&incoming),
+            key: incoming,
+            previous: None,
+            next_sequence: 0,
+            pending: Vec::new(),
+        },
+    )
+}
+
+fn derive_key(secret: &[u8], cipher: Cipher, transcript: &[u8; 32], label: &[u8]) -> [u8; 32] {
+    let mut info = label.to_vec();
+    info.push(cipher.flag() as u8);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(transcript), secret)
+        .expand(&info, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+/// One direction's key for one epoch. Epoch `n + 1` is derived from epoch `n`, so
+/// both sides follow a rekey without another exchange.
+struct EpochKey {
+    cipher: Cipher,
+    epoch: u32,
+    key: [u8; 32],
+    record: RecordCipher,
+}
+
+impl EpochKey {
+    fn new(cipher: Cipher, epoch: u32, key: [u8; 32]) -> Self {
+        Self {
+            cipher,
+            epoch,
+            record: RecordCipher::new(cipher, &key),
+            key,
+        }
+    }
+
+    fn next(&self) -> Self {
+        let epoch = self.epoch + 1;
+        let mut info = KEY_LABEL_REKEY.to_vec();
+        info.push(self.cipher.flag() as u8);
+        info.extend_from_slice(&epoch.to_le_bytes());
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &self.key)
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self::new(self.cipher, epoch, key)
+    }
+}
+
+enum RecordCipher {
+    ChaCha20Poly1305(Box<ChaCha20Poly1305>),
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl RecordCipher {
+    fn new(cipher: Cipher, key: &[u8; 32]) -> Self {
+        match cipher {
+            Cipher::ChaCha20Poly1305 => {
+                RecordCipher::ChaCha20Poly1305(Box::new(ChaCha20Poly1305::new(key.into())))
+            }
+            Cipher::Aes256Gcm => RecordCipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+        }
+    }
+
+    fn encrypt(&self, sequence: u64, aad: &[u8], msg: &[u8]) -> Vec<u8> {
+        let nonce = record_nonce(sequence);
+        let payload = Payload { msg, aad };
+        match self {
+            RecordCipher::ChaCha20Poly1305(cipher) => cipher.encrypt((&nonce).into(), payload),
+            RecordCipher::Aes256Gcm(cipher) => cipher.encrypt((&nonce).into(), payload),
+        }
+        .expect("record plaintext is within AEAD limits")
+    }
+
+    fn decrypt(&self, sequence: u64, aad: &[u8], msg: &[u8]) -> Result<Vec<u8>, ChannelError> {
+        let nonce = record_nonce(sequence);
+        let payload = Payload { msg, aad };
+        match self {
+            RecordCipher::ChaCha20Poly1305(cipher) => cipher.decrypt((&nonce).into(), payload),
+            RecordCipher::Aes256Gcm(cipher) => cipher.decrypt((&nonce).into(), payload),
+        }
+        .map_err(|_| ChannelError::Authentication)
+    }
+}
+
+/// One epoch's key for one direction's datagrams.
+pub struct DatagramKey {
+    epoch: u32,
+    record: RecordCipher,
+}
+
+impl DatagramKey {
+    fn derive(channel_key: &EpochKey) -> Self {
+        let mut info = KEY_LABEL_DATAGRAM.to_vec();
+        info.push(channel_key.cipher.flag() as u8);
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &channel_key.key)
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self {
+            epoch: channel_key.epoch,
+            record: RecordCipher::new(channel_key.cipher, &key),
+        }
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Ciphertext and tag for `plaintext`, with `header` authenticated alongside.
+    pub fn seal(&self, sequence: u64, header: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        self.record.encrypt(sequence, header, plaintext)
+    }
+
+    pub fn open(
+        &self,
+        sequence: u64,
+        header: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, ChannelError> {
+        self.record.decrypt(sequence, header, ciphertext)
+    }
+}
+
+/// Seals or opens single datagrams for one direction. Clones share their keys
+/// with the `Sealer` or `Opener` that handed them out, which rotates them with
+/// the record channel; datagrams name the epoch they were sealed under.
+#[derive(Clone)]
+pub struct DatagramCipher {
+    keys: Arc<Mutex<DatagramKeys>>,
+}
+
+struct DatagramKeys {
+    current: Arc<DatagramKey>,
+    /// The following epoch, for datagrams that overtake the rekey record.
+    next: Option<Arc<DatagramKey>>,
+    /// The key replaced by the last rekey, and until when it is still accepted.
+    previous: Option<(Arc<DatagramKey>, Instant)>,
+}
+
+impl DatagramCipher {
+    /// A fixed key that stays at epoch 0.
+    pub fn new(cipher: Cipher, key: [u8; 32]) -> Self {
+        Self::with_keys(DatagramKeys {
+            current: Arc::new(DatagramKey {
+                epoch: 0,
+                record: RecordCipher::new(cipher, &key),
+            }),
+            next: None,
+            previous: None,
+        })
+    }
+
+    fn for_channel(channel_key: &EpochKey) -> Self {
+        Self::with_keys(DatagramKeys {
+            current: Arc::new(DatagramKey::derive(channel_key)),
+            next: Some(Arc::new(DatagramKey::derive(&channel_key.next()))),
+            previous: None,
+        })
+    }
+
+    fn with_keys(keys: DatagramKeys) -> Self {
+        Self {
+            keys: Arc::new(Mutex::new(keys)),
+        }
+    }
+
+    fn keys(&self) -> MutexGuard<'_, DatagramKeys> {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Follows the record channel to `channel_key`'s epoch.
+    fn rotate(&self, channel_key: &EpochKey) {
+        let mut keys = self.keys();
+        let current = match keys.next.take() {
+            Some(next) if next.epoch == channel_key.epoch => next,
+            _ => Arc::new(DatagramKey::derive(channel_key)),
+        };
+        let previous = std::mem::replace(&mut keys.current, current);
+        keys.previous = Some((previous, Instant::now() + REKEY_GRACE));
+        keys.next = Some(Arc::new(DatagramKey::derive(&channel_key.next())));
+    }
+
+    /// The key new datagrams are sealed under.
+    pub fn current(&self) -> Arc<DatagramKey> {
+        self.keys().current.clone()
+    }
+
+    /// The key for a datagram sealed under `epoch`: the current one, the next,
+    /// or the previous one during its grace period.
+    pub fn key(&self, epoch: u32) -> Option<Arc<DatagramKey>> {
+        let keys = self.keys();
+        let previous = keys
+            .previous
+            .as_ref()
+            .filter(|(_, until)| Instant::now() < *until)
+            .map(|(key, _)| key);
+        let key = [Some(&keys.current), keys.next.as_ref(), previous]
+            .into_iter()
+            .flatten()
+            .find(|key| key.epoch == epoch)
+            .cloned();
+        key
+    }
+}
+
+fn record_nonce(sequence: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+    nonce
+}
+
+/// Encrypts outgoing bytes into records.
+pub struct Sealer {
+    key: EpochKey,
+    datagram: DatagramCipher,
+    sequence: u64,
+    policy: RekeyPolicy,
+    sealed_bytes: u64,
+    keyed_at: Instant,
+}
+
+impl Sealer {
+    pub fn set_rekey_policy(&mut self, policy: RekeyPolicy) {
+        self.policy = policy;
+    }
+
+    /// Current key epoch; 0 until the first rekey.
+    pub fn epoch(&self) -> u32 {
+        self.key.epoch
+    }
+
+    /// Seals this direction's datagrams, following its rekeys.
+    pub fn datagram_cipher(&self) -> DatagramCipher {
+        self.datagram.clone()
+    }
+
+    /// Appends the records carrying `plaintext` to `out`, rotating the key first if
+    /// the policy says so. A single call never spans two keys.
+    pub fn seal(&mut self, plaintext: &[u8], out: &mut Vec<u8>) {
+        if self.policy.due(self.sealed_bytes, self.keyed_at.elapsed()) {
+            self.rekey(out);
+        }
+        for part in plaintext.chunks(MAX_RECORD_PLAINTEXT) {
+            self.write_record(0, part, out);
+        }
+        self.sealed_bytes += plaintext.len() as u64;
+    }
+
+    /// Announces the next epoch under the current key, then switches to it.
+    pub fn rekey(&mut self, out: &mut Vec<u8>) {
+        let next = self.key.next();
+        let mut control = vec![CONTROL_REKEY];
+        control.extend_from_slice(&next.epoch.to_le_bytes());
+        self.write_record(RECORD_CONTROL, &control, out);
+        self.datagram.rotate(&next);
+        self.key = next;
+        self.sealed_bytes = 0;
+        self.keyed_at = Instant::now();
+    }
+
+    fn write_record(&mut self, marker: u32, part: &[u8], out: &mut Vec<u8>) {
+        let length = 8 + part.len() + TAG_LEN;
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        header[..4].copy_from_slice(&(length as u32 | marker).to_le_bytes());
+        header[4..].copy_from_slice(&self.sequence.to_le_bytes());
+        let ciphertext = self.key.record.encrypt(self.sequence, &header, part);
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&ciphertext);
+        self.sequence += 1;
+    }
+}
+
+/// Decrypts incoming records, rejecting anything replayed, reordered or altered.
+pub struct Opener {
+    key: EpochKey,
+    datagram: DatagramCipher,
+    /// The key replaced by the last rekey, and until when it is still accepted.
+    previous: Option<(EpochKey, Instant)>,
+    next_sequence: u64,
+    pending: Vec<u8>,
+}
+
+impl Opener {
+    /// Current key epoch of the peer's sealer.
+    pub fn epoch(&self) -> u32 {
+        self.key.epoch
+    }
+
+    /// Opens the peer's datagrams, following its rekeys.
+    pub fn datagram_cipher(&self) -> DatagramCipher {
+        self.datagram.clone()
+    }
+
+    /// Feeds bytes read from the transport and returns the plaintext of the records
+    /// they complete. Any error is fatal for the connection.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<u8>, ChannelError> {
+        self.pending.extend_from_slice(bytes);
+        let mut plaintext = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= 4 {
+            let raw = u32::from_le_bytes(self.pending[offset..offset + 4].try_into().unwrap());
+            let length = (raw & !RECORD_CONTROL) as usize;
+            if !(8 + TAG_LEN..=8 + MAX_RECORD_PLAINTEXT + TAG_LEN).contains(&length) {
+                return Err(ChannelError::InvalidLength(raw as usize));
+            }
+            if self.pending.len() - offset < 4 + length {
+                break;
+            }
+            let record = &self.pending[offset..offset + 4 + length];
+            let opened = open_record(
+                &self.key,
+                self.previous.as_ref(),
+                &mut self.next_sequence,
+                record,
+            )?;
+            if raw & RECORD_CONTROL != 0 {
+                self.apply_control(&opened)?;
+            } else {
+                plaintext.extend_from_slice(&opened);
+            }
+            offset += 4 + length;
+        }
+        self.pending.drain(..offset);
+        Ok(plaintext)
+    }
+
+    fn apply_control(&mut self, control: &[u8]) -> Result<(), ChannelError> {
+        let next = self.key.next();
+        match control {
+            [CONTROL_REKEY, epoch @ ..] if epoch == next.epoch.to_le_bytes() => {
+                self.datagram.rotate(&next);
+                let previous = std::mem::replace(&mut self.key, next);
+                self.previous = Some((previous, Instant::now() + REKEY_GRACE));
+                Ok(())
+            }
+            _ => Err(ChannelError::InvalidControl),
+        }
+    }
+}
+
+fn open_record(
+    key: &EpochKey,
+    previous: Option<&(EpochKey, Instant)>,
+    next_sequence: &mut u64,
+    record: &[u8],
+) -> Result<Vec<u8>, ChannelError> {
+    let (header, ciphertext) = record.split_at(RECORD_HEADER_LEN);
+    let sequence = u64::from_le_bytes(header[4..].try_into().unwrap());
+    let expected = *next_sequence;
+    if sequence < expected {
+        return Err(ChannelError::Replayed { sequence, expected });
+    }
+    if sequence > expected {
+        return Err(ChannelError::OutOfOrder { sequence, expected });
+    }
+    let plaintext = match (key.record.decrypt(sequence, header, ciphertext), previous) {
+        (Err(ChannelError::Authentication), Some((previous, until))) if Instant::now() < *until => {
+            previous.record.decrypt(sequence, header, ciphertext)?
+        }
+        (result, _) => result?,
+    };
+    *next_sequence += 1;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSK: &[u8] = b"correct horse battery staple";
+
+    fn pair(cipher: Cipher) -> ((Sealer, Opener), (Sealer, Opener)) {
+        let transcript = transcript_hash(&[b"client caps", b"host caps"]);
+        (
+            establish(PSK, cipher, &transcript, Role::Host),
+            establish(PSK, cipher, &transcript, Role::Client),
+        )
+    }
+
+    fn seal(sealer: &mut Sealer, plaintext: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        sealer.seal(plaintext, &mut out);
+        out
+    }
+
+    #[test]
+    fn round_trips_both_directions_with_either_cipher() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let ((mut host_sealer, mut host_opener), (mut client_sealer, mut client_opener)) =
+                pair(cipher);
+
+            let large = vec![0x5a; MAX_RECORD_PLAINTEXT + 10];
+            let mut wire = seal(&mut host_sealer, b"configure");
+            wire.extend(seal(&mut host_sealer, &large));
+            assert_ne!(
+                &wire[RECORD_HEADER_LEN..RECORD_HEADER_LEN + 9],
+                b"configure"
+            );
+            let mut received = Vec::new();
+            for piece in wire.chunks(7_000) {
+                received.extend(client_opener.push(piece).unwrap());
+            }
+            assert_eq!(received.len(), 9 + large.len());
+            assert_eq!(&received[..9], b"configure");
+            assert_eq!(client_opener.next_sequence, 3);
+
+            let reply = seal(&mut client_sealer, b"frame done");
+            assert_eq!(host_opener.push(&reply).unwrap(), b"frame done");
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_records() {
+        let ((mut sealer, _), (_, _)) = pair(Cipher::ChaCha20Poly1305);
+        let record = seal(&mut sealer, b"keyboard down");
+        for index in [RECORD_HEADER_LEN, record.len() - 1] {
+            let ((_, _), (_, mut opener)) = pair(Cipher::ChaCha20Poly1305);
+            let mut tampered = record.clone();
+            tampered[index] ^= 0x01;
+            assert_eq!(opener.push(&tampered), Err(ChannelError::Authentication));
+        }
+
+        let ((_, _), (_, mut opener)) = pair(Cipher::ChaCha20Poly1305);
+        let mut oversized = record.clone();
+        oversized[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            opener.push(&oversized),
+            Err(ChannelError::InvalidLength(u32::MAX as usize))
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_keys_and_tampered_negotiation() {
+        let transcript = transcript_hash(&[b"client caps", b"host caps"]);
+        let (mut sealer, _) = establish(PSK, Cipher::Aes256Gcm, &transcript, Role::Host);
+        let record = seal(&mut sealer, b"touch");
+
+        let (_, mut wrong_psk) = establish(b"guess", Cipher::Aes256Gcm, &transcript, Role::Client);
+        assert_eq!(wrong_psk.push(&record), Err(ChannelError::Authentication));
+
+        let downgraded = transcript_hash(&[b"client caps without aes", b"host caps"]);
+        let (_, mut wrong_transcript) =
+            establish(PSK, Cipher::Aes256Gcm, &downgraded, Role::Client);
+        assert_eq!(
+            wrong_transcript.push(&record),
+            Err(ChannelError::Authentication)
+        );
+
+        // Records reflected back at their sender use the other direction's key.
+        let (_, mut reflected) = establish(PSK, Cipher::Aes256Gcm, &transcript, Role::Host);
+        assert_eq!(reflected.push(&record), Err(ChannelError::Authentication));
+    }
+
+    #[test]
+    fn rejects_replayed_and_reordered_records() {
+        let ((mut sealer, _), (_, mut opener)) = pair(Cipher::ChaCha20Poly1305);
+        let first = seal(&mut sealer, b"first");
+        let second = seal(&mut sealer, b"second");
+        assert_eq!(opener.push(&first).unwrap(), b"first");
+        assert_eq!(
+            opener.push(&first),
+            Err(ChannelError::Replayed {
+                sequence: 0,
+                expected: 1
+            })
+        );
+
+        let ((_, _), (_, mut opener)) = pair(Cipher::ChaCha20Poly1305);
+        assert_eq!(
+            opener.push(&second),
+            Err(ChannelError::OutOfOrder {
+                sequence: 1,
+                expected: 0
+            })
+        );
+    }
+
+    #[test]
+    fn datagram_ciphers_pair_up_across_rekeys() {
+        let ((mut host_sealer, host_opener), (client_sealer, mut client_opener)) =
+            pair(Cipher::ChaCha20Poly1305);
+        let outgoing = host_sealer.datagram_cipher();
+        let incoming = client_opener.datagram_cipher();
+        let open = |epoch: u32, sequence: u64, header: &[u8], sealed: &[u8]| {
+            incoming
+                .key(epoch)
+                .ok_or(ChannelError::Authentication)?
+                .open(sequence, header, sealed)
+        };
+
+        let first = outgoing.current();
+        assert_eq!(first.epoch(), 0);
+        let sealed = first.seal(7, b"header", b"fragment");
+        assert_eq!(open(0, 7, b"header", &sealed).unwrap(), b"fragment");
+        assert_eq!(
+            open(0, 8, b"header", &sealed),
+            Err(ChannelError::Authentication)
+        );
+        assert_eq!(
+            open(0, 7, b"HEADER", &sealed),
+            Err(ChannelError::Authentication)
+        );
+
+        // Rekeying the channel rotates the datagram key with it.
+        let mut wire = Vec::new();
+        host_sealer.rekey(&mut wire);
+        let second = outgoing.current();
+        assert_eq!(second.epoch(), 1);
+        let rotated = second.seal(7, b"header", b"fragment");
+        assert_ne!(rotated, sealed);
+        assert_eq!(
+            first.open(7, b"header", &rotated),
+            Err(ChannelError::Authentication)
+        );
+        // A datagram may overtake the rekey record, and one sealed before it may
+        // arrive after; both open by the epoch they carry.
+        assert_eq!(open(1, 7, b"header", &rotated).unwrap(), b"fragment");
+        client_opener.push(&wire).unwrap();
+        assert_eq!(incoming.current().epoch(), 1);
+        assert_eq!(open(1, 7, b"header", &rotated).unwrap(), b"fragment");
+        assert_eq!(open(0, 7, b"header", &sealed).unwrap(), b"fragment");
+        assert!(incoming.key(3).is_none());
+
+        // Each direction has its own key.
+        assert_eq!(
+            host_opener
+                .datagram_cipher()
+                .current()
+                .open(7, b"header", &sealed),
+            Err(ChannelError::Authentication)
+        );
+        let feedback = client_sealer
+            .datagram_cipher()
+            .current()
+            .seal(0, b"", b"feedback");
+        assert_eq!(
+            host_opener
+                .datagram_cipher()
+                .key(0)
+                .unwrap()
+                .open(0, b"", &feedback)
+                .unwrap(),
+            b"feedback"
+        );
+    }
+
+    #[test]
+    fn rotates_keys_mid_stream_by_volume_and_age() {
+        let ((mut sealer, _), (_, mut opener)) = pair(Cipher::Aes256Gcm);
+        sealer.set_rekey_policy(RekeyPolicy {
+            max_bytes: Some(1000),
+            max_age: None,
+        });
+        let mut wire = Vec::new();
+        let mut sent = Vec::new();
+        for index in 0..10u8 {
+            let packet = vec![index; 400];
+            sealer.seal(&packet, &mut wire);
+            sent.extend_from_slice(&packet);
+        }
+        // Every third packet crosses 1000 bytes, so the 4th, 7th and 10th rekey first.
+        assert_eq!(sealer.epoch(), 3);
+        let mut received = Vec::new();
+        for piece in wire.chunks(333) {
+            received.extend(opener.push(piece).unwrap());
+        }
+        assert_eq!(received, sent);
+        assert_eq!(opener.epoch(), 3);
+
+        sealer.set_rekey_policy(RekeyPolicy {
+            max_bytes: None,
+            max_age: Some(Duration::from_secs(60)),
+        });
+        let fresh = seal(&mut sealer, b"fresh");
+        assert_eq!(sealer.epoch(), 3);
+        sealer.keyed_at = Instant::now().checked_sub(Duration::from_secs(61)).unwrap();
+        let aged = seal(&mut sealer, b"aged");
+        assert_eq!(sealer.epoch(), 4);
+        assert_eq!(opener.push(&fresh).unwrap(), b"fresh");
+        assert_eq!(opener.push(&aged).unwrap(), b"aged");
+        assert_eq!(opener.epoch(), 4);
+    }
+
+    #[test]
+    fn keeps_the_previous_key_only_briefly() {
+        let ((mut sealer, _), (_, mut opener)) = pair(Cipher::ChaCha20Poly1305);
+        // An identical sealer that never rekeys stands in for records sealed before
+        // the switch.
+        let ((mut stale, _), _) = pair(Cipher::ChaCha20Poly1305);
+        let mut announce = Vec::new();
+        sealer.rekey(&mut announce);
+        seal(&mut stale, b"skipped");
+        let late = seal(&mut stale, b"late");
+        let too_late = seal(&mut stale, b"too late");
+
+        assert!(opener.push(&announce).unwrap().is_empty());
+        assert_eq!(opener.epoch(), 1);
+        assert_eq!(opener.push(&late).unwrap(), b"late");
+        opener.previous.as_mut().unwrap().1 = Instant::now();
+        assert_eq!(opener.push(&too_late), Err(ChannelError::Authentication));
+
+        let ((mut sealer, _), (_, mut opener)) = pair(Cipher::ChaCha20Poly1305);
+        let mut skipped_epoch = Vec::new();
+        sealer.write_record(
+            RECORD_CONTROL,
+            &[CONTROL_REKEY, 5, 0, 0, 0],
+            &mut skipped_epoch,
+        );
+        assert_eq!(
+            opener.push(&skipped_epoch),
+            Err(ChannelError::InvalidControl)
+        );
+    }
+
+    #[test]
+    fn selects_ciphers_from_client_flags() {
+        let both = CAP_FLAG_CIPHERS;
+        assert_eq!(
+            CipherChoice::Auto.select(both, true),
+            Some(Cipher::Aes256Gcm)
+        );
+        assert_eq!(
+            CipherChoice::Auto.select(both, false),
+            Some(Cipher::ChaCha20Poly1305)
+        );
+        assert_eq!(
+            CipherChoice::Auto.select(CAP_FLAG_AES_256_GCM, false),
+            Some(Cipher::Aes256Gcm)
+        );
+        assert_eq!(CipherChoice::Auto.select(0, true), None);
+        assert_eq!(
+            CipherChoice::Fixed(Cipher::Aes256Gcm).select(CAP_FLAG_CHACHA20_POLY1305, true),
+            None
+        );
+        assert_eq!(Cipher::from_flags(both), None);
+        assert_eq!(Cipher::from_name("aes-256-gcm"), Some(Cipher::Aes256Gcm));
+    }
+}
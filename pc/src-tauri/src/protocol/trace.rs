@@ -0,0 +1,346 @@
+//! Binary protocol trace: a header followed by one record per framed packet.
+//!
+//! ```text
+//! header:  "UDTR" | version u8 | flags u8 (bit 0: payloads) | reserved u16
+//! record:  timestamp_us u64 | direction u8 | stream_id u8 | data_type u8
+//!          | length u32 | payload_len u32 | payload
+//! ```
+//!
+//! All integers are little endian. `length` is the packet length including the
+//! data type byte; `payload_len` is zero unless the trace was started with payloads.
+
+use std::io::{self, Read, Write};
+use std::time::Instant;
+
+pub const TRACE_MAGIC: &[u8; 4] = b"UDTR";
+pub const TRACE_VERSION: u8 = 1;
+pub const TRACE_EXTENSION: &str = "udtrace";
+
+const FLAG_PAYLOADS: u8 = 0x01;
+const RECORD_HEADER_LEN: usize = 19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    HostToClient = 0,
+    ClientToHost = 1,
+}
+
+impl Direction {
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Direction::HostToClient => "H->C",
+            Direction::ClientToHost => "C->H",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Microseconds since the trace started.
+    pub timestamp_us: u64,
+    pub direction: Direction,
+    pub stream_id: u8,
+    pub data_type: u8,
+    pub length: u32,
+    /// The whole packet, type byte included, when payloads are recorded.
+    pub payload: Option<Vec<u8>>,
+}
+
+/// Writes trace records as packets go by.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    started: Instant,
+    include_payloads: bool,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W, include_payloads: bool) -> io::Result<Self> {
+        out.write_all(TRACE_MAGIC)?;
+        out.write_all(&[
+            TRACE_VERSION,
+            if include_payloads { FLAG_PAYLOADS } else { 0 },
+            0,
+            0,
+        ])?;
+        Ok(Self {
+            out,
+            started: Instant::now(),
+            include_payloads,
+        })
+    }
+
+    /// Records one framed packet (type byte first, no length prefix).
+    pub fn record(&mut self, direction: Direction, stream_id: u8, packet: &[u8]) -> io::Result<()> {
+        let record = TraceRecord {
+            timestamp_us: self.started.elapsed().as_micros() as u64,
+            direction,
+            stream_id,
+            data_type: packet.first().copied().unwrap_or(u8::MAX),
+            length: packet.len() as u32,
+            payload: self.include_payloads.then(|| packet.to_vec()),
+        };
+        self.write_record(&record)
+    }
+
+    pub fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let payload = record.payload.as_deref().unwrap_or_default();
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        header[..8].copy_from_slice(&record.timestamp_us.to_le_bytes());
+        header[8] = record.direction as u8;
+        header[9] = record.stream_id;
+        header[10] = record.data_type;
+        header[11..15].copy_from_slice(&record.length.to_le_bytes());
+        header[15..19].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(payload)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Reads a trace written by `TraceWriter`.
+pub struct TraceReader<R: Read> {
+    input: R,
+    pub include_payloads: bool,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header)?;
+        if &header[..4] != TRACE_MAGIC {
+            return Err(invalid("not a protocol trace"));
+        }
+        if header[4] != TRACE_VERSION {
+            return Err(invalid(&format!("unsupported trace version {}", header[4])));
+        }
+        Ok(Self {
+            input,
+            include_payloads: header[5] & FLAG_PAYLOADS != 0,
+        })
+    }
+
+    /// The next record, or `None` at a clean end of file.
+    pub fn next_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.input.read(&mut header[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                read => filled += read,
+            }
+        }
+        let direction = match header[8] {
+            0 => Direction::HostToClient,
+            1 => Direction::ClientToHost,
+            other => return Err(invalid(&format!("invalid direction {other}"))),
+        };
+        let payload_len = u32::from_le_bytes(header[15..19].try_into().unwrap()) as usize;
+        let payload = if payload_len > 0 {
+            let mut payload = vec![0u8; payload_len];
+            self.input.read_exact(&mut payload)?;
+            Some(payload)
+        } else {
+            None
+        };
+        Ok(Some(TraceRecord {
+            timestamp_us: u64::from_le_bytes(header[..8].try_into().unwrap()),
+            direction,
+            stream_id: header[9],
+            data_type: header[10],
+            length: u32::from_le_bytes(header[11..15].try_into().unwrap()),
+            payload,
+        }))
+    }
+
+    pub fn records(mut self) -> io::Result<Vec<TraceRecord>> {
+        let mut records = Vec::new();
+        while let Some(record) = self.next_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Packet names from spec §7.4.
+pub fn data_type_name(data_type: u8) -> &'static str {
+    match data_type {
+        0 => "State",
+        1 => "Configure",
+        2 => "Stop",
+        3 => "Frame",
+        4 => "FrameDone",
+        5 => "PointerMove",
+        6 => "PointerShape",
+        7 => "TakeScreenshot",
+        8 => "Touch",
+        9 => "Pen",
+        10 => "Unlock",
+        11 => "Scale",
+        12 => "InputConfig",
+        13 => "InputKey",
+        14 => "Error",
+        15 => "Keyboard",
+        16 => "Command",
+        17 => "Capabilities",
+        18 => "KeyExchange",
+        _ => "Unknown",
+    }
+}
+
+/// One line per record: time, direction, stream, type and length.
+pub fn format_record(record: &TraceRecord) -> String {
+    format!(
+        "{:>12.3} ms  {}  s{}  {:<14} ({:>2})  {} bytes",
+        record.timestamp_us as f64 / 1000.0,
+        record.direction.arrow(),
+        record.stream_id,
+        data_type_name(record.data_type),
+        record.data_type,
+        record.length
+    )
+}
+
+/// Difference between the host packets of a recorded trace and a replay.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub expected_frames: usize,
+    pub actual_frames: usize,
+    /// First control packet that differs: index among control packets, and the
+    /// expected and actual data types (`None` past the end of either side).
+    pub divergence: Option<(usize, Option<u8>, Option<u8>)>,
+}
+
+impl ReplayReport {
+    /// Compares the host-to-client packets of both traces. Control packets must
+    /// match in order; `Frame` packets depend on timing and are only counted.
+    pub fn compare(expected: &[TraceRecord], actual: &[TraceRecord]) -> Self {
+        let split = |records: &[TraceRecord]| {
+            let host: Vec<u8> = records
+                .iter()
+                .filter(|record| record.direction == Direction::HostToClient)
+                .map(|record| record.data_type)
+                .collect();
+            let frames = host.iter().filter(|data_type| **data_type == 3).count();
+            let control: Vec<u8> = host
+                .into_iter()
+                .filter(|data_type| *data_type != 3)
+                .collect();
+            (control, frames)
+        };
+        let (expected_control, expected_frames) = split(expected);
+        let (actual_control, actual_frames) = split(actual);
+        let divergence = (0..expected_control.len().max(actual_control.len()))
+            .map(|index| {
+                (
+                    index,
+                    expected_control.get(index).copied(),
+                    actual_control.get(index).copied(),
+                )
+            })
+            .find(|(_, expected, actual)| expected != actual);
+        Self {
+            expected_frames,
+            actual_frames,
+            divergence,
+        }
+    }
+
+    pub fn matches(&self) -> bool {
+        self.divergence.is_none() && (self.expected_frames == 0) == (self.actual_frames == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(direction: Direction, data_type: u8) -> TraceRecord {
+        TraceRecord {
+            timestamp_us: 0,
+            direction,
+            stream_id: 0,
+            data_type,
+            length: 1,
+            payload: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_records_with_and_without_payloads() {
+        for include_payloads in [false, true] {
+            let mut writer = TraceWriter::new(Vec::new(), include_payloads).unwrap();
+            writer
+                .record(Direction::ClientToHost, 0, &[17, 1, 0, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            writer
+                .record(Direction::HostToClient, 1, &[3, 0x80])
+                .unwrap();
+            let bytes = writer.finish().unwrap();
+
+            let reader = TraceReader::new(bytes.as_slice()).unwrap();
+            assert_eq!(reader.include_payloads, include_payloads);
+            let records = reader.records().unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].direction, Direction::ClientToHost);
+            assert_eq!((records[0].data_type, records[0].length), (17, 9));
+            assert_eq!((records[1].stream_id, records[1].data_type), (1, 3));
+            assert_eq!(records[1].payload, include_payloads.then(|| vec![3, 0x80]));
+            assert!(records[0].timestamp_us <= records[1].timestamp_us);
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_and_truncated_files() {
+        assert!(TraceReader::new(&b"RIFF\x01\0\0\0"[..]).is_err());
+        let mut writer = TraceWriter::new(Vec::new(), true).unwrap();
+        writer
+            .record(Direction::HostToClient, 0, &[1, 2, 3])
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+        let mut reader = TraceReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(reader.next_record().is_err());
+    }
+
+    #[test]
+    fn formats_records() {
+        let mut line = record(Direction::ClientToHost, 17);
+        line.timestamp_us = 1_500;
+        line.length = 9;
+        assert_eq!(
+            format_record(&line),
+            "       1.500 ms  C->H  s0  Capabilities   (17)  9 bytes"
+        );
+    }
+
+    #[test]
+    fn compares_control_packets_and_counts_frames() {
+        let expected = [
+            record(Direction::HostToClient, 17),
+            record(Direction::ClientToHost, 17),
+            record(Direction::HostToClient, 1),
+            record(Direction::HostToClient, 3),
+            record(Direction::HostToClient, 3),
+        ];
+        let replayed = [
+            record(Direction::HostToClient, 17),
+            record(Direction::HostToClient, 1),
+            record(Direction::HostToClient, 3),
+        ];
+        let report = ReplayReport::compare(&expected, &replayed);
+        assert!(report.matches());
+        assert_eq!((report.expected_frames, report.actual_frames), (2, 1));
+
+        let report = ReplayReport::compare(&expected, &replayed[..1]);
+        assert_eq!(report.divergence, Some((1, Some(1), None)));
+        assert!(!report.matches());
+    }
+}
@@ -0,0 +1,392 @@
+//! What a session's packets travel over once its handshake is done. The host
+//! keeps sessions as `Transport` objects, so the same session code runs over TCP,
+//! USB accessories, and in tests over an in-memory loopback pair. The handshake
+//! itself runs on a `HandshakeStream`, which then turns into the transport.
+//!
+//! Packets are framed as in §7.2: a `u32` length, then the packet, cut into
+//! stream chunks.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use super::framing::{write_stream_chunks, StreamDecoder};
+use super::secure::{ChannelError, Opener, Sealer};
+
+/// How often a blocked loopback `receive` checks whether its pair was closed.
+const LOOPBACK_POLL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("transport closed")]
+    Closed,
+    #[error("transport lock poisoned")]
+    Poisoned,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Channel(#[from] ChannelError),
+}
+
+/// Traffic on one transport, in packets and packet bytes (framing and
+/// encryption not counted).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportStats {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+}
+
+impl TransportStats {
+    pub fn add(&mut self, other: &TransportStats) {
+        self.packets_sent += other.packets_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.packets_received += other.packets_received;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+/// One established session. Sending and receiving may happen on different
+/// threads at once; `receive` is meant for a single reader.
+pub trait Transport: Send + Sync {
+    /// Where the peer is, for transports that have an address.
+    fn peer_addr(&self) -> Option<SocketAddr>;
+    fn send(&self, stream_id: u8, packet: &[u8]) -> Result<(), TransportError>;
+    /// Blocks for the next packet. `None` once the transport is closed, from
+    /// either end.
+    fn receive(&self) -> Result<Option<(u8, Vec<u8>)>, TransportError>;
+    /// Ends the session; a blocked `receive` returns.
+    fn close(&self);
+    fn stats(&self) -> TransportStats;
+}
+
+/// A byte stream a session's handshake runs on before it is keyed.
+pub trait HandshakeStream: Read + Write + Sized {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+    /// Hands the stream to the session with the keys the handshake produced; both
+    /// are absent only in debug plaintext mode.
+    fn into_transport(
+        self,
+        sealer: Option<Sealer>,
+        opener: Option<Opener>,
+    ) -> Result<Arc<dyn Transport>, TransportError>;
+}
+
+impl HandshakeStream for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn into_transport(
+        self,
+        sealer: Option<Sealer>,
+        opener: Option<Opener>,
+    ) -> Result<Arc<dyn Transport>, TransportError> {
+        Ok(Arc::new(TcpTransport::new(self, sealer, opener)?))
+    }
+}
+
+/// Frames one packet as stream chunks for `stream_id`.
+pub fn chunk_packet(stream_id: u8, packet: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(4 + packet.len());
+    framed.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    framed.extend_from_slice(packet);
+
+    let mut chunked = Vec::with_capacity(framed.len() + 3);
+    write_stream_chunks(stream_id, &framed, &mut chunked);
+    chunked
+}
+
+/// `chunk_packet`, sealed when the session is encrypted.
+pub(crate) fn seal_packet(sealer: Option<&mut Sealer>, stream_id: u8, packet: &[u8]) -> Vec<u8> {
+    let chunked = chunk_packet(stream_id, packet);
+    match sealer {
+        Some(sealer) => {
+            let mut sealed = Vec::with_capacity(chunked.len() + 32);
+            sealer.seal(&chunked, &mut sealed);
+            sealed
+        }
+        None => chunked,
+    }
+}
+
+/// Packets out of received bytes: records are opened when the session is
+/// encrypted, then chunks reassembled. A record that fails to open is an error.
+pub(crate) struct PacketReader {
+    opener: Option<Opener>,
+    decoder: StreamDecoder,
+    pending: VecDeque<(u8, Vec<u8>)>,
+}
+
+impl PacketReader {
+    pub(crate) fn new(opener: Option<Opener>) -> Self {
+        Self {
+            opener,
+            decoder: StreamDecoder::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Result<(), TransportError> {
+        let plaintext = match self.opener.as_mut() {
+            Some(opener) => opener.push(bytes)?,
+            None => bytes.to_vec(),
+        };
+        self.pending.extend(self.decoder.push(&plaintext));
+        Ok(())
+    }
+
+    pub(crate) fn next(&mut self) -> Option<(u8, Vec<u8>)> {
+        self.pending.pop_front()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> TransportStats {
+        TransportStats {
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A TCP session after its handshake, encrypted unless it is in debug plaintext
+/// mode.
+pub struct TcpTransport {
+    peer: Option<SocketAddr>,
+    /// Kept apart from the reader and writer so `close` never waits on them.
+    control: TcpStream,
+    writer: Mutex<TcpWriter>,
+    reader: Mutex<TcpReader>,
+    counters: Counters,
+}
+
+struct TcpWriter {
+    stream: TcpStream,
+    sealer: Option<Sealer>,
+}
+
+struct TcpReader {
+    stream: TcpStream,
+    packets: PacketReader,
+}
+
+impl TcpTransport {
+    /// Takes over `stream` with the channel keys its handshake produced; both are
+    /// absent only in debug plaintext mode.
+    pub fn new(
+        stream: TcpStream,
+        sealer: Option<Sealer>,
+        opener: Option<Opener>,
+    ) -> Result<Self, TransportError> {
+        Ok(Self {
+            peer: stream.peer_addr().ok(),
+            control: stream.try_clone()?,
+            reader: Mutex::new(TcpReader {
+                stream: stream.try_clone()?,
+                packets: PacketReader::new(opener),
+            }),
+            writer: Mutex::new(TcpWriter { stream, sealer }),
+            counters: Counters::default(),
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    fn send(&self, stream_id: u8, packet: &[u8]) -> Result<(), TransportError> {
+        let mut writer = self.writer.lock().map_err(|_| TransportError::Poisoned)?;
+        let writer = &mut *writer;
+        let bytes = seal_packet(writer.sealer.as_mut(), stream_id, packet);
+        writer.stream.write_all(&bytes)?;
+        self.counters.sent(packet.len());
+        Ok(())
+    }
+
+    fn receive(&self) -> Result<Option<(u8, Vec<u8>)>, TransportError> {
+        let mut reader = self.reader.lock().map_err(|_| TransportError::Poisoned)?;
+        let reader = &mut *reader;
+        let mut buffer = [0u8; 4096];
+        loop {
+            if let Some((stream_id, packet)) = reader.packets.next() {
+                self.counters.received(packet.len());
+                return Ok(Some((stream_id, packet)));
+            }
+            let read = match reader.stream.read(&mut buffer)? {
+                0 => return Ok(None),
+                read => read,
+            };
+            reader.packets.push(&buffer[..read])?;
+        }
+    }
+
+    fn close(&self) {
+        let _ = self.control.shutdown(Shutdown::Both);
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+}
+
+/// One end of an in-memory session; see `loopback_pair`.
+pub struct LoopbackTransport {
+    outgoing: Sender<(u8, Vec<u8>)>,
+    incoming: Mutex<Receiver<(u8, Vec<u8>)>>,
+    /// Shared by both ends; closing either closes the pair.
+    open: Arc<AtomicBool>,
+    counters: Counters,
+}
+
+/// Two connected transports: what one sends, the other receives.
+pub fn loopback_pair() -> (LoopbackTransport, LoopbackTransport) {
+    let (to_second, from_first) = mpsc::channel();
+    let (to_first, from_second) = mpsc::channel();
+    let open = Arc::new(AtomicBool::new(true));
+    let first = LoopbackTransport {
+        outgoing: to_second,
+        incoming: Mutex::new(from_second),
+        open: open.clone(),
+        counters: Counters::default(),
+    };
+    let second = LoopbackTransport {
+        outgoing: to_first,
+        incoming: Mutex::new(from_first),
+        open,
+        counters: Counters::default(),
+    };
+    (first, second)
+}
+
+impl Transport for LoopbackTransport {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn send(&self, stream_id: u8, packet: &[u8]) -> Result<(), TransportError> {
+        if !self.open.load(Ordering::SeqCst) {
+            return Err(TransportError::Closed);
+        }
+        self.outgoing
+            .send((stream_id, packet.to_vec()))
+            .map_err(|_| TransportError::Closed)?;
+        self.counters.sent(packet.len());
+        Ok(())
+    }
+
+    fn receive(&self) -> Result<Option<(u8, Vec<u8>)>, TransportError> {
+        let incoming = self.incoming.lock().map_err(|_| TransportError::Poisoned)?;
+        while self.open.load(Ordering::SeqCst) {
+            match incoming.recv_timeout(LOOPBACK_POLL) {
+                Ok((stream_id, packet)) => {
+                    self.counters.received(packet.len());
+                    return Ok(Some((stream_id, packet)));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&self) {
+        self.open.store(false, Ordering::SeqCst);
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::secure::{establish, Cipher, Role};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn tcp_carries_sealed_packets_both_ways() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dialed = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let transcript = [9u8; 32];
+        let (host_sealer, host_opener) =
+            establish(b"psk", Cipher::ChaCha20Poly1305, &transcript, Role::Host);
+        let (client_sealer, client_opener) =
+            establish(b"psk", Cipher::ChaCha20Poly1305, &transcript, Role::Client);
+        let host = TcpTransport::new(accepted, Some(host_sealer), Some(host_opener)).unwrap();
+        let client = TcpTransport::new(dialed, Some(client_sealer), Some(client_opener)).unwrap();
+
+        host.send(0, b"configure").unwrap();
+        host.send(2, &[7u8; 70_000]).unwrap();
+        assert_eq!(client.receive().unwrap(), Some((0, b"configure".to_vec())));
+        assert_eq!(client.receive().unwrap(), Some((2, vec![7u8; 70_000])));
+        client.send(0, b"done").unwrap();
+        assert_eq!(host.receive().unwrap(), Some((0, b"done".to_vec())));
+
+        assert_eq!(
+            host.stats(),
+            TransportStats {
+                packets_sent: 2,
+                bytes_sent: 70_009,
+                packets_received: 1,
+                bytes_received: 4,
+            }
+        );
+        host.close();
+        assert_eq!(client.receive().unwrap(), None);
+    }
+
+    #[test]
+    fn closing_a_loopback_end_wakes_the_other() {
+        let (first, second) = loopback_pair();
+        first.send(0, b"ping").unwrap();
+        assert_eq!(second.receive().unwrap(), Some((0, b"ping".to_vec())));
+
+        let second = Arc::new(second);
+        let reader = {
+            let second = second.clone();
+            thread::spawn(move || second.receive().unwrap())
+        };
+        first.close();
+        assert_eq!(reader.join().unwrap(), None);
+        assert!(matches!(
+            second.send(0, b"late"),
+            Err(TransportError::Closed)
+        ));
+    }
+}
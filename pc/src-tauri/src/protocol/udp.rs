@@ -0,0 +1,1176 @@
+//! Datagram media path for Wi-Fi: video frames travel over UDP so one lost
+//! segment does not stall every frame behind it, while control and input stay on
+//! the TCP session that negotiated the path (`MediaPath`, §7.10).
+//!
+//! Every datagram starts with the same header (little endian):
+//!
+//! ```text
+//! kind u8 | fecGroup u8 | fragments u16 | sequence u64 | frame u32 | index u16 | sendTimeUs u32 | epoch u32
+//! ```
+//!
+//! `sequence` counts datagrams per direction and is the AEAD nonce when the path is
+//! encrypted; the header is then authenticated and the payload sealed under the
+//! key of the channel's `epoch`. A frame is
+//! cut into `fragments` data datagrams. With a non-zero `fecGroup`, each run of
+//! that many fragments is followed by one parity datagram, the XOR of the run's
+//! fragments (each prefixed with its length), which rebuilds any single missing
+//! fragment of the run.
+//!
+//! The receiver delivers frames in order and gives up on a frame once a later one
+//! completes or it times out. It reports loss and echoes send times in feedback
+//! datagrams, and asks for a keyframe whenever it gives up on a frame. The sender
+//! paces datagrams and lets a congestion controller choose the encoder bitrate.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::secure::DatagramCipher;
+
+/// Client and host capability bit: the peer can take video over a datagram path.
+pub const CAP_FLAG_UDP_MEDIA: u32 = 1 << 3;
+
+pub const HEADER_LEN: usize = 26;
+/// Fits common path MTUs, VPN and tunnel overhead included.
+pub const DEFAULT_MAX_DATAGRAM: usize = 1200;
+/// Room for the AEAD tag, kept whether or not the path is encrypted.
+const SEAL_OVERHEAD: usize = 16;
+/// Smallest datagram that leaves room for a useful fragment.
+const MIN_DATAGRAM: usize = 128;
+
+pub const KIND_DATA: u8 = 0;
+pub const KIND_PARITY: u8 = 1;
+pub const KIND_FEEDBACK: u8 = 2;
+pub const KIND_KEYFRAME_REQUEST: u8 = 3;
+
+/// How long a partly received frame waits for its missing fragments.
+pub const FRAME_TIMEOUT: Duration = Duration::from_millis(200);
+pub const FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+/// Keyframe requests are repeated no more often than this while frames keep failing.
+pub const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+/// Datagrams go out this much faster than the target bitrate, so a frame leaves
+/// well within its frame interval without bursting into the access point.
+pub const PACING_FACTOR: f64 = 2.5;
+
+/// Loss above this cuts the bitrate in proportion.
+const LOSS_HIGH: f64 = 0.10;
+/// Loss below this lets the bitrate grow.
+const LOSS_LOW: f64 = 0.02;
+/// Round trip time above the lowest seen that counts as a queue building up.
+const QUEUE_DELAY_LIMIT: Duration = Duration::from_millis(100);
+const INCREASE_FACTOR: f64 = 1.05;
+const DELAY_DECREASE_FACTOR: f64 = 0.85;
+/// Samples longer than this are wrap-around or clock noise.
+const MAX_RTT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DatagramError {
+    #[error("datagram is too short")]
+    Truncated,
+    #[error("unknown datagram kind {0}")]
+    UnknownKind(u8),
+    #[error("datagram kind {0} is not expected here")]
+    UnexpectedKind(u8),
+    #[error("fragment {index} of {fragments} is invalid")]
+    InvalidFragment { index: u16, fragments: u16 },
+    #[error("a {0}-byte packet needs too many fragments")]
+    TooLarge(usize),
+    #[error("datagram failed authentication")]
+    Authentication,
+    #[error("no key for epoch {0}")]
+    UnknownEpoch(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Header {
+    pub kind: u8,
+    pub fec_group: u8,
+    pub fragments: u16,
+    pub sequence: u64,
+    pub frame: u32,
+    pub index: u16,
+    pub send_time_us: u32,
+    /// Key epoch the payload is sealed under; 0 on unencrypted paths.
+    pub epoch: u32,
+}
+
+impl Header {
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0] = self.kind;
+        bytes[1] = self.fec_group;
+        bytes[2..4].copy_from_slice(&self.fragments.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.index.to_le_bytes());
+        bytes[18..22].copy_from_slice(&self.send_time_us.to_le_bytes());
+        bytes[22..26].copy_from_slice(&self.epoch.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DatagramError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(DatagramError::Truncated);
+        }
+        let kind = bytes[0];
+        if kind > KIND_KEYFRAME_REQUEST {
+            return Err(DatagramError::UnknownKind(kind));
+        }
+        Ok(Self {
+            kind,
+            fec_group: bytes[1],
+            fragments: u16::from_le_bytes([bytes[2], bytes[3]]),
+            sequence: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            frame: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+            index: u16::from_le_bytes([bytes[16], bytes[17]]),
+            send_time_us: u32::from_le_bytes(bytes[18..22].try_into().unwrap()),
+            epoch: u32::from_le_bytes(bytes[22..26].try_into().unwrap()),
+        })
+    }
+}
+
+/// Header followed by the payload, sealed under the cipher's current key when
+/// there is one; the header's epoch is set to match.
+pub fn seal(header: &Header, payload: &[u8], cipher: Option<&DatagramCipher>) -> Vec<u8> {
+    let key = cipher.map(DatagramCipher::current);
+    let header = Header {
+        epoch: key.as_ref().map_or(0, |key| key.epoch()),
+        ..*header
+    };
+    let head = header.encode();
+    let mut datagram = Vec::with_capacity(HEADER_LEN + payload.len() + SEAL_OVERHEAD);
+    datagram.extend_from_slice(&head);
+    match key {
+        Some(key) => datagram.extend(key.seal(header.sequence, &head, payload)),
+        None => datagram.extend_from_slice(payload),
+    }
+    datagram
+}
+
+pub fn open(
+    datagram: &[u8],
+    cipher: Option<&DatagramCipher>,
+) -> Result<(Header, Vec<u8>), DatagramError> {
+    let header = Header::decode(datagram)?;
+    let (head, body) = datagram.split_at(HEADER_LEN);
+    let payload = match cipher {
+        Some(cipher) => cipher
+            .key(header.epoch)
+            .ok_or(DatagramError::UnknownEpoch(header.epoch))?
+            .open(header.sequence, head, body)
+            .map_err(|_| DatagramError::Authentication)?,
+        None => body.to_vec(),
+    };
+    Ok((header, payload))
+}
+
+/// Receiver report, sent every `FEEDBACK_INTERVAL`. Counters are cumulative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Feedback {
+    pub highest_sequence: u64,
+    pub received: u64,
+    pub lost: u64,
+    pub frames_dropped: u64,
+    /// `sendTimeUs` of the latest datagram received, and how long ago it arrived.
+    pub echo_send_time_us: u32,
+    pub echo_hold_us: u32,
+}
+
+impl Feedback {
+    const LEN: usize = 40;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(&self.highest_sequence.to_le_bytes());
+        bytes.extend_from_slice(&self.received.to_le_bytes());
+        bytes.extend_from_slice(&self.lost.to_le_bytes());
+        bytes.extend_from_slice(&self.frames_dropped.to_le_bytes());
+        bytes.extend_from_slice(&self.echo_send_time_us.to_le_bytes());
+        bytes.extend_from_slice(&self.echo_hold_us.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DatagramError> {
+        if bytes.len() < Self::LEN {
+            return Err(DatagramError::Truncated);
+        }
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        Ok(Self {
+            highest_sequence: u64_at(0),
+            received: u64_at(8),
+            lost: u64_at(16),
+            frames_dropped: u64_at(24),
+            echo_send_time_us: u32_at(32),
+            echo_hold_us: u32_at(36),
+        })
+    }
+}
+
+/// Cuts `packet` into data fragments of at most `fragment_len` bytes, each run of
+/// `fec_group` followed by its parity fragment. Headers carry everything but the
+/// sequence number and send time.
+pub fn fragment(
+    frame: u32,
+    packet: &[u8],
+    fragment_len: usize,
+    fec_group: u8,
+) -> Result<Vec<(Header, Vec<u8>)>, DatagramError> {
+    let count = packet.len().div_ceil(fragment_len).max(1);
+    let fragments = u16::try_from(count).map_err(|_| DatagramError::TooLarge(packet.len()))?;
+    let header = |kind, index| Header {
+        kind,
+        fec_group,
+        fragments,
+        frame,
+        index,
+        ..Header::default()
+    };
+    let pieces: Vec<&[u8]> = if packet.is_empty() {
+        vec![&[]]
+    } else {
+        packet.chunks(fragment_len).collect()
+    };
+    let group_len = if fec_group == 0 {
+        pieces.len()
+    } else {
+        usize::from(fec_group)
+    };
+    let mut out = Vec::with_capacity(pieces.len() + pieces.len() / group_len + 1);
+    for (group, run) in pieces.chunks(group_len).enumerate() {
+        let first = group * group_len;
+        for (offset, piece) in run.iter().enumerate() {
+            out.push((header(KIND_DATA, (first + offset) as u16), piece.to_vec()));
+        }
+        if fec_group > 0 {
+            let mut parity = Vec::new();
+            for piece in run {
+                xor_fragment(&mut parity, piece);
+            }
+            out.push((header(KIND_PARITY, group as u16), parity));
+        }
+    }
+    Ok(out)
+}
+
+/// XORs `len u16 | fragment` into `parity`, growing it as needed.
+fn xor_fragment(parity: &mut Vec<u8>, fragment: &[u8]) {
+    if parity.len() < 2 + fragment.len() {
+        parity.resize(2 + fragment.len(), 0);
+    }
+    let len = (fragment.len() as u16).to_le_bytes();
+    for (target, byte) in parity.iter_mut().zip(len.iter().chain(fragment)) {
+        *target ^= byte;
+    }
+}
+
+struct PartialFrame {
+    fragments: u16,
+    fec_group: u8,
+    data: Vec<Option<Vec<u8>>>,
+    received: u16,
+    parity: BTreeMap<u16, Vec<u8>>,
+    first_seen: Instant,
+    recovered: bool,
+}
+
+impl PartialFrame {
+    fn new(header: &Header, now: Instant) -> Self {
+        Self {
+            fragments: header.fragments,
+            fec_group: header.fec_group,
+            data: vec![None; usize::from(header.fragments)],
+            received: 0,
+            parity: BTreeMap::new(),
+            first_seen: now,
+            recovered: false,
+        }
+    }
+
+    fn group_len(&self) -> usize {
+        usize::from(self.fec_group)
+    }
+
+    fn insert(&mut self, header: &Header, payload: Vec<u8>) -> Result<(), DatagramError> {
+        let invalid = DatagramError::InvalidFragment {
+            index: header.index,
+            fragments: header.fragments,
+        };
+        if header.fragments != self.fragments || header.fec_group != self.fec_group {
+            return Err(invalid);
+        }
+        let index = usize::from(header.index);
+        if header.kind == KIND_PARITY {
+            if self.fec_group == 0 || index * self.group_len() >= self.data.len() {
+                return Err(invalid);
+            }
+            self.parity.insert(header.index, payload);
+        } else {
+            let slot = self.data.get_mut(index).ok_or(invalid)?;
+            if slot.is_none() {
+                *slot = Some(payload);
+                self.received += 1;
+            }
+        }
+        self.recover();
+        Ok(())
+    }
+
+    /// Rebuilds the one missing fragment of any run whose parity has arrived.
+    fn recover(&mut self) {
+        if self.fec_group == 0 || self.complete() {
+            return;
+        }
+        let group_len = self.group_len();
+        for (group, parity) in &self.parity {
+            let start = usize::from(*group) * group_len;
+            let end = (start + group_len).min(self.data.len());
+            let mut missing = (start..end).filter(|index| self.data[*index].is_none());
+            let (Some(lost), None) = (missing.next(), missing.next()) else {
+                continue;
+            };
+            let mut rebuilt = parity.clone();
+            for fragment in self.data[start..end].iter().flatten() {
+                xor_fragment(&mut rebuilt, fragment);
+            }
+            let len = usize::from(u16::from_le_bytes([rebuilt[0], rebuilt[1]]));
+            if rebuilt.len() < 2 + len {
+                continue;
+            }
+            rebuilt.truncate(2 + len);
+            rebuilt.drain(..2);
+            self.data[lost] = Some(rebuilt);
+            self.received += 1;
+            self.recovered = true;
+        }
+    }
+
+    fn complete(&self) -> bool {
+        self.received == self.fragments
+    }
+
+    fn assemble(self) -> Vec<u8> {
+        self.data.into_iter().flatten().flatten().collect()
+    }
+}
+
+/// Receiver counters; all cumulative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LinkStats {
+    pub received: u64,
+    pub highest_sequence: u64,
+    pub lost: u64,
+    pub frames_delivered: u64,
+    /// Delivered frames that needed a parity fragment.
+    pub frames_recovered: u64,
+    /// Frames given up on, timed out or overtaken by a later frame.
+    pub frames_dropped: u64,
+}
+
+/// Puts frames back together from data and parity datagrams.
+pub struct Reassembler {
+    frames: BTreeMap<u32, PartialFrame>,
+    /// Lowest frame number not yet delivered or given up on.
+    next_frame: Option<u32>,
+    timeout: Duration,
+    stats: LinkStats,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            frames: BTreeMap::new(),
+            next_frame: None,
+            timeout,
+            stats: LinkStats::default(),
+        }
+    }
+
+    /// Adds one data or parity datagram and returns the frame it completes, if any.
+    pub fn push(
+        &mut self,
+        header: &Header,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, DatagramError> {
+        if header.kind != KIND_DATA && header.kind != KIND_PARITY {
+            return Err(DatagramError::UnexpectedKind(header.kind));
+        }
+        if header.fragments == 0 {
+            return Err(DatagramError::InvalidFragment {
+                index: header.index,
+                fragments: 0,
+            });
+        }
+        self.stats.received += 1;
+        // Sequence numbers start at zero on every path.
+        self.stats.highest_sequence = self.stats.highest_sequence.max(header.sequence);
+        self.stats.lost = (self.stats.highest_sequence + 1).saturating_sub(self.stats.received);
+
+        let next = *self.next_frame.get_or_insert(header.frame);
+        if header.frame < next {
+            // Late fragment of a frame already delivered or given up on.
+            return Ok(None);
+        }
+        let partial = self
+            .frames
+            .entry(header.frame)
+            .or_insert_with(|| PartialFrame::new(header, now));
+        partial.insert(header, payload)?;
+        if !partial.complete() {
+            return Ok(None);
+        }
+        let partial = self
+            .frames
+            .remove(&header.frame)
+            .expect("frame was just completed");
+        self.settle(header.frame, true);
+        self.stats.frames_delivered += 1;
+        if partial.recovered {
+            self.stats.frames_recovered += 1;
+        }
+        Ok(Some(partial.assemble()))
+    }
+
+    /// Gives up on frames that have waited longer than the timeout.
+    pub fn expire(&mut self, now: Instant) {
+        let expired = self
+            .frames
+            .iter()
+            .filter(|(_, partial)| now.duration_since(partial.first_seen) >= self.timeout)
+            .map(|(frame, _)| *frame)
+            .max();
+        if let Some(frame) = expired {
+            self.settle(frame, false);
+        }
+    }
+
+    /// Closes every frame up to `frame`: the ones before it are dropped, and
+    /// `frame` itself too unless it was delivered.
+    fn settle(&mut self, frame: u32, delivered: bool) {
+        let next = self.next_frame.unwrap_or(frame);
+        self.stats.frames_dropped += u64::from(frame - next) + u64::from(!delivered);
+        self.next_frame = Some(frame.wrapping_add(1));
+        self.frames = self.frames.split_off(&frame.wrapping_add(1));
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+}
+
+/// Spaces datagrams at a multiple of the target bitrate.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    bytes_per_second: f64,
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn new(target_kbps: u32) -> Self {
+        let mut pacer = Self {
+            bytes_per_second: 0.0,
+            next: None,
+        };
+        pacer.set_target_kbps(target_kbps);
+        pacer
+    }
+
+    pub fn set_target_kbps(&mut self, kbps: u32) {
+        self.bytes_per_second = f64::from(kbps.max(1)) * 1000.0 / 8.0 * PACING_FACTOR;
+    }
+
+    /// When a datagram of `bytes` may leave, given it is ready at `now`.
+    pub fn schedule(&mut self, bytes: usize, now: Instant) -> Instant {
+        let at = self.next.map_or(now, |next| next.max(now));
+        self.next = Some(at + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second));
+        at
+    }
+}
+
+/// Loss- and delay-based bitrate control, driven by receiver feedback. Heavy loss
+/// cuts the rate in proportion to the loss, a growing round trip (a queue filling
+/// at the access point) cuts it by a fixed step, and a clean link lets it grow
+/// back towards the maximum.
+#[derive(Debug, Clone)]
+pub struct CongestionController {
+    target_kbps: u32,
+    min_kbps: u32,
+    max_kbps: u32,
+    last_report: Option<(u64, u64)>,
+    min_rtt: Option<Duration>,
+}
+
+impl CongestionController {
+    pub fn new(start_kbps: u32, min_kbps: u32, max_kbps: u32) -> Self {
+        let max_kbps = max_kbps.max(min_kbps);
+        Self {
+            target_kbps: start_kbps.clamp(min_kbps, max_kbps),
+            min_kbps,
+            max_kbps,
+            last_report: None,
+            min_rtt: None,
+        }
+    }
+
+    pub fn target_kbps(&self) -> u32 {
+        self.target_kbps
+    }
+
+    /// Takes the cumulative counters of one report and returns the new target.
+    pub fn on_feedback(&mut self, received: u64, lost: u64, rtt: Option<Duration>) -> u32 {
+        let (previous_received, previous_lost) = self.last_report.unwrap_or((0, 0));
+        self.last_report = Some((received, lost));
+        let received = received.saturating_sub(previous_received);
+        let lost = lost.saturating_sub(previous_lost);
+        if received + lost == 0 {
+            return self.target_kbps;
+        }
+        let loss = lost as f64 / (received + lost) as f64;
+        let queuing = rtt.map(|rtt| {
+            let min_rtt = *self
+                .min_rtt
+                .insert(self.min_rtt.map_or(rtt, |min| min.min(rtt)));
+            rtt - min_rtt
+        });
+        let factor = if loss > LOSS_HIGH {
+            1.0 - 0.5 * loss
+        } else if queuing.is_some_and(|queuing| queuing > QUEUE_DELAY_LIMIT) {
+            DELAY_DECREASE_FACTOR
+        } else if loss < LOSS_LOW {
+            INCREASE_FACTOR
+        } else {
+            1.0
+        };
+        self.target_kbps = ((f64::from(self.target_kbps) * factor).round() as u32)
+            .clamp(self.min_kbps, self.max_kbps);
+        self.target_kbps
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaConfig {
+    pub max_datagram: usize,
+    /// Data fragments per parity fragment; 0 turns FEC off.
+    pub fec_group: u8,
+    pub start_kbps: u32,
+    pub min_kbps: u32,
+    pub max_kbps: u32,
+}
+
+impl MediaConfig {
+    /// Starts at the configured bitrate and may go down to an eighth of it.
+    pub fn for_bitrate(kbps: u32, fec_group: u8) -> Self {
+        Self {
+            max_datagram: DEFAULT_MAX_DATAGRAM,
+            fec_group,
+            start_kbps: kbps,
+            min_kbps: (kbps / 8).max(100),
+            max_kbps: kbps,
+        }
+    }
+
+    fn fragment_len(&self) -> usize {
+        // Parity fragments carry a two-byte length on top of the longest fragment.
+        self.max_datagram.max(MIN_DATAGRAM) - HEADER_LEN - SEAL_OVERHEAD - 2
+    }
+}
+
+/// Host half of a media path: packets in, paced datagrams out, feedback in.
+pub struct MediaSender {
+    config: MediaConfig,
+    sealer: Option<DatagramCipher>,
+    opener: Option<DatagramCipher>,
+    epoch: Instant,
+    sequence: u64,
+    frame: u32,
+    pacer: Pacer,
+    controller: CongestionController,
+    peer_sequence: Option<u64>,
+    handled_drops: u64,
+    keyframe_requested: bool,
+    rtt: Option<Duration>,
+    feedback: Option<Feedback>,
+}
+
+impl MediaSender {
+    /// `sealer` protects outgoing datagrams and `opener` checks the receiver's;
+    /// both or neither.
+    pub fn new(
+        config: MediaConfig,
+        sealer: Option<DatagramCipher>,
+        opener: Option<DatagramCipher>,
+        now: Instant,
+    ) -> Self {
+        let controller =
+            CongestionController::new(config.start_kbps, config.min_kbps, config.max_kbps);
+        Self {
+            pacer: Pacer::new(controller.target_kbps()),
+            controller,
+            config,
+            sealer,
+            opener,
+            epoch: now,
+            sequence: 0,
+            frame: 0,
+            peer_sequence: None,
+            handled_drops: 0,
+            keyframe_requested: false,
+            rtt: None,
+            feedback: None,
+        }
+    }
+
+    /// The datagrams carrying `packet`, each with the time it should be sent.
+    pub fn send(
+        &mut self,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Vec<(Instant, Vec<u8>)>, DatagramError> {
+        let pieces = fragment(
+            self.frame,
+            packet,
+            self.config.fragment_len(),
+            self.config.fec_group,
+        )?;
+        self.frame = self.frame.wrapping_add(1);
+        let mut datagrams = Vec::with_capacity(pieces.len());
+        for (mut header, payload) in pieces {
+            let at = self
+                .pacer
+                .schedule(HEADER_LEN + payload.len() + SEAL_OVERHEAD, now);
+            header.sequence = self.sequence;
+            header.send_time_us = self.micros(at);
+            self.sequence += 1;
+            datagrams.push((at, seal(&header, &payload, self.sealer.as_ref())));
+        }
+        Ok(datagrams)
+    }
+
+    /// Takes a feedback or keyframe request datagram from the receiver.
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> Result<(), DatagramError> {
+        let (header, payload) = open(datagram, self.opener.as_ref())?;
+        if self
+            .peer_sequence
+            .is_some_and(|sequence| header.sequence <= sequence)
+        {
+            // Replayed, or overtaken by a newer report.
+            return Ok(());
+        }
+        self.peer_sequence = Some(header.sequence);
+        match header.kind {
+            KIND_FEEDBACK => {
+                let feedback = Feedback::decode(&payload)?;
+                if feedback.received > 0 {
+                    let elapsed = self
+                        .micros(now)
+                        .wrapping_sub(feedback.echo_send_time_us)
+                        .wrapping_sub(feedback.echo_hold_us);
+                    let rtt = Duration::from_micros(u64::from(elapsed));
+                    if rtt < MAX_RTT {
+                        self.rtt = Some(rtt);
+                    }
+                }
+                let target =
+                    self.controller
+                        .on_feedback(feedback.received, feedback.lost, self.rtt);
+                self.pacer.set_target_kbps(target);
+                self.note_drops(feedback.frames_dropped);
+                self.feedback = Some(feedback);
+                Ok(())
+            }
+            KIND_KEYFRAME_REQUEST => {
+                let drops = payload
+                    .get(..8)
+                    .ok_or(DatagramError::Truncated)?
+                    .try_into()
+                    .map(u64::from_le_bytes)
+                    .unwrap();
+                self.note_drops(drops);
+                Ok(())
+            }
+            kind => Err(DatagramError::UnexpectedKind(kind)),
+        }
+    }
+
+    /// Requests and feedback both carry the drop count, so either one arriving is
+    /// enough and a repeat of the same count asks for nothing new.
+    fn note_drops(&mut self, frames_dropped: u64) {
+        if frames_dropped > self.handled_drops {
+            self.handled_drops = frames_dropped;
+            self.keyframe_requested = true;
+        }
+    }
+
+    pub fn take_keyframe_request(&mut self) -> bool {
+        std::mem::take(&mut self.keyframe_requested)
+    }
+
+    pub fn target_kbps(&self) -> u32 {
+        self.controller.target_kbps()
+    }
+
+    /// Caps the controller at a new bitrate, for example after the user changes
+    /// quality; the target restarts there.
+    pub fn set_max_kbps(&mut self, kbps: u32) {
+        self.config = MediaConfig {
+            max_datagram: self.config.max_datagram,
+            ..MediaConfig::for_bitrate(kbps, self.config.fec_group)
+        };
+        self.controller = CongestionController::new(kbps, self.config.min_kbps, kbps);
+        self.pacer.set_target_kbps(kbps);
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// The receiver's latest report.
+    pub fn last_feedback(&self) -> Option<Feedback> {
+        self.feedback
+    }
+
+    fn micros(&self, at: Instant) -> u32 {
+        at.saturating_duration_since(self.epoch).as_micros() as u32
+    }
+}
+
+/// Client half of a media path: datagrams in, frames out, feedback and keyframe
+/// requests back.
+pub struct MediaReceiver {
+    reassembler: Reassembler,
+    opener: Option<DatagramCipher>,
+    sealer: Option<DatagramCipher>,
+    sequence: u64,
+    latest: Option<(u32, Instant)>,
+    next_feedback: Instant,
+    last_keyframe_request: Option<Instant>,
+    requested_drops: u64,
+}
+
+impl MediaReceiver {
+    pub fn new(
+        opener: Option<DatagramCipher>,
+        sealer: Option<DatagramCipher>,
+        now: Instant,
+    ) -> Self {
+        Self {
+            reassembler: Reassembler::new(FRAME_TIMEOUT),
+            opener,
+            sealer,
+            sequence: 0,
+            latest: None,
+            next_feedback: now,
+            last_keyframe_request: None,
+            requested_drops: 0,
+        }
+    }
+
+    /// Takes one datagram from the host and returns the packet it completes.
+    pub fn receive(
+        &mut self,
+        datagram: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, DatagramError> {
+        let (header, payload) = open(datagram, self.opener.as_ref())?;
+        let packet = self.reassembler.push(&header, payload, now)?;
+        self.latest = Some((header.send_time_us, now));
+        Ok(packet)
+    }
+
+    /// Expires stale frames and returns the datagrams to send back now: a keyframe
+    /// request when frames were lost since the last one, and feedback when due.
+    pub fn poll(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        self.reassembler.expire(now);
+        let stats = self.reassembler.stats();
+        let mut out = Vec::new();
+        let request_due = self
+            .last_keyframe_request
+            .is_none_or(|last| now.duration_since(last) >= KEYFRAME_REQUEST_INTERVAL);
+        if stats.frames_dropped > self.requested_drops && request_due {
+            self.requested_drops = stats.frames_dropped;
+            self.last_keyframe_request = Some(now);
+            let payload = stats.frames_dropped.to_le_bytes();
+            out.push(self.control(KIND_KEYFRAME_REQUEST, &payload));
+        }
+        if now >= self.next_feedback {
+            self.next_feedback = now + FEEDBACK_INTERVAL;
+            let (echo_send_time_us, echo_hold_us) = self
+                .latest
+                .map(|(sent, at)| (sent, now.duration_since(at).as_micros() as u32))
+                .unwrap_or_default();
+            let feedback = Feedback {
+                highest_sequence: stats.highest_sequence,
+                received: stats.received,
+                lost: stats.lost,
+                frames_dropped: stats.frames_dropped,
+                echo_send_time_us,
+                echo_hold_us,
+            };
+            out.push(self.control(KIND_FEEDBACK, &feedback.encode()));
+        }
+        out
+    }
+
+    fn control(&mut self, kind: u8, payload: &[u8]) -> Vec<u8> {
+        let header = Header {
+            kind,
+            sequence: self.sequence,
+            ..Header::default()
+        };
+        self.sequence += 1;
+        seal(&header, payload, self.sealer.as_ref())
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.reassembler.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::netem::Netem;
+    use crate::protocol::secure::Cipher;
+
+    fn packet(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|index| (index as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    fn sequenced(pieces: Vec<(Header, Vec<u8>)>) -> Vec<(Header, Vec<u8>)> {
+        pieces
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, (header, payload))| {
+                (
+                    Header {
+                        sequence: sequence as u64,
+                        ..header
+                    },
+                    payload,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_headers_and_feedback() {
+        let header = Header {
+            kind: KIND_PARITY,
+            fec_group: 4,
+            fragments: 9,
+            sequence: u64::MAX - 1,
+            frame: 77,
+            index: 2,
+            send_time_us: 123_456,
+            epoch: 3,
+        };
+        assert_eq!(Header::decode(&header.encode()), Ok(header));
+        let mut unknown = header.encode();
+        unknown[0] = 9;
+        assert_eq!(Header::decode(&unknown), Err(DatagramError::UnknownKind(9)));
+        assert_eq!(Header::decode(&unknown[..5]), Err(DatagramError::Truncated));
+
+        let feedback = Feedback {
+            highest_sequence: 10,
+            received: 9,
+            lost: 1,
+            frames_dropped: 2,
+            echo_send_time_us: 5,
+            echo_hold_us: 6,
+        };
+        assert_eq!(Feedback::decode(&feedback.encode()), Ok(feedback));
+    }
+
+    #[test]
+    fn reassembles_fragments_in_any_order() {
+        let data = packet(5000, 1);
+        let mut pieces = sequenced(fragment(3, &data, 1000, 0).unwrap());
+        assert_eq!(pieces.len(), 5);
+        pieces.reverse();
+        let mut reassembler = Reassembler::new(FRAME_TIMEOUT);
+        let now = Instant::now();
+        let delivered: Vec<Vec<u8>> = pieces
+            .into_iter()
+            .filter_map(|(header, payload)| reassembler.push(&header, payload, now).unwrap())
+            .collect();
+        assert_eq!(delivered, vec![data]);
+        let stats = reassembler.stats();
+        assert_eq!(
+            (stats.frames_delivered, stats.frames_dropped, stats.lost),
+            (1, 0, 0)
+        );
+
+        let empty = sequenced(fragment(4, &[], 1000, 2).unwrap());
+        assert_eq!(empty.len(), 2);
+        let (header, payload) = empty[0].clone();
+        assert_eq!(
+            reassembler.push(&header, payload, now),
+            Ok(Some(Vec::new()))
+        );
+        assert!(fragment(0, &vec![0; 70_000], 1, 0).is_err());
+    }
+
+    #[test]
+    fn parity_rebuilds_one_missing_fragment_per_group() {
+        let now = Instant::now();
+        let data = packet(4500, 2);
+        let pieces = sequenced(fragment(0, &data, 1000, 2).unwrap());
+        // Five fragments in runs of two: three parity fragments.
+        assert_eq!(pieces.len(), 8);
+
+        // One fragment lost from each run, including the short last one.
+        let mut reassembler = Reassembler::new(FRAME_TIMEOUT);
+        let mut delivered = None;
+        for (header, payload) in pieces.iter().cloned() {
+            if header.kind == KIND_DATA && header.index % 2 == 0 {
+                continue;
+            }
+            if let Some(frame) = reassembler.push(&header, payload, now).unwrap() {
+                delivered = Some(frame);
+            }
+        }
+        assert_eq!(delivered, Some(data));
+        assert_eq!(reassembler.stats().frames_recovered, 1);
+        assert_eq!(reassembler.stats().lost, 3);
+
+        // Two from one run is beyond repair; the frame times out as dropped.
+        let mut reassembler = Reassembler::new(FRAME_TIMEOUT);
+        for (header, payload) in pieces {
+            if header.kind == KIND_DATA && header.index < 2 {
+                continue;
+            }
+            assert_eq!(reassembler.push(&header, payload, now), Ok(None));
+        }
+        reassembler.expire(now + FRAME_TIMEOUT);
+        assert_eq!(reassembler.stats().frames_dropped, 1);
+    }
+
+    #[test]
+    fn a_completed_frame_overtakes_older_incomplete_ones() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(FRAME_TIMEOUT);
+        let first = sequenced(fragment(0, &packet(3000, 0), 1000, 0).unwrap());
+        for (header, payload) in first.iter().take(2).cloned() {
+            reassembler.push(&header, payload, now).unwrap();
+        }
+        let third = fragment(2, &packet(10, 3), 1000, 0).unwrap();
+        let (header, payload) = third[0].clone();
+        assert!(reassembler.push(&header, payload, now).unwrap().is_some());
+        // Frames 0 and 1 are given up; the rest of frame 0 arriving late is ignored.
+        let (header, payload) = first[2].clone();
+        assert_eq!(reassembler.push(&header, payload, now), Ok(None));
+        let stats = reassembler.stats();
+        assert_eq!((stats.frames_delivered, stats.frames_dropped), (1, 2));
+    }
+
+    #[test]
+    fn sealed_datagrams_reject_tampering() {
+        let cipher = DatagramCipher::new(Cipher::Aes256Gcm, [7; 32]);
+        let header = Header {
+            sequence: 5,
+            fragments: 1,
+            ..Header::default()
+        };
+        let datagram = seal(&header, b"slice", Some(&cipher));
+        assert_eq!(
+            open(&datagram, Some(&cipher)),
+            Ok((header, b"slice".to_vec()))
+        );
+        for index in [4, datagram.len() - 1] {
+            let mut tampered = datagram.clone();
+            tampered[index] ^= 1;
+            assert_eq!(
+                open(&tampered, Some(&cipher)),
+                Err(DatagramError::Authentication)
+            );
+        }
+    }
+
+    #[test]
+    fn paces_datagrams_at_the_target_rate() {
+        let now = Instant::now();
+        // 8000 kbps paced at 2.5x is 2.5 MB/s: 1000 bytes every 400 us.
+        let mut pacer = Pacer::new(8000);
+        assert_eq!(pacer.schedule(1000, now), now);
+        assert_eq!(pacer.schedule(1000, now), now + Duration::from_micros(400));
+        assert_eq!(pacer.schedule(1000, now), now + Duration::from_micros(800));
+        // An idle pacer does not save up credit.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(pacer.schedule(1000, later), later);
+    }
+
+    #[test]
+    fn congestion_controller_follows_loss_and_delay() {
+        let mut controller = CongestionController::new(10_000, 1_000, 10_000);
+        let rtt = Some(Duration::from_millis(20));
+        // A clean link stays at the maximum.
+        assert_eq!(controller.on_feedback(100, 0, rtt), 10_000);
+        // 30% loss in the interval cuts by 15%.
+        assert_eq!(controller.on_feedback(170, 30, rtt), 8_500);
+        // Moderate loss holds.
+        assert_eq!(controller.on_feedback(266, 34, rtt), 8_500);
+        // A queue building up cuts even without loss.
+        assert_eq!(
+            controller.on_feedback(366, 34, Some(Duration::from_millis(150))),
+            7_225
+        );
+        // Clean reports grow it back, and an idle interval changes nothing.
+        assert_eq!(controller.on_feedback(466, 34, rtt), 7_586);
+        assert_eq!(controller.on_feedback(466, 34, rtt), 7_586);
+        // Never below the floor.
+        for step in 1..40u64 {
+            controller.on_feedback(466 + step * 10, 34 + step * 90, rtt);
+        }
+        assert_eq!(controller.target_kbps(), 1_000);
+    }
+
+    #[test]
+    fn keyframe_requests_are_rate_limited_and_deduplicated() {
+        let start = Instant::now();
+        let mut sender = MediaSender::new(MediaConfig::for_bitrate(8000, 0), None, None, start);
+        let mut receiver = MediaReceiver::new(None, None, start);
+        // Frame 0 loses its last fragment, frame 1 arrives whole.
+        let lossy = sender.send(&packet(3000, 0), start).unwrap();
+        for (_, datagram) in &lossy[..lossy.len() - 1] {
+            receiver.receive(datagram, start).unwrap();
+        }
+        for (_, datagram) in sender.send(&packet(100, 1), start).unwrap() {
+            assert!(receiver.receive(&datagram, start).unwrap().is_some());
+        }
+        let replies = receiver.poll(start);
+        assert_eq!(replies.len(), 2);
+        for reply in &replies {
+            sender.receive(reply, start).unwrap();
+        }
+        assert!(sender.take_keyframe_request());
+        assert!(!sender.take_keyframe_request());
+        // The same report replayed asks for nothing.
+        sender.receive(&replies[0], start).unwrap();
+        assert!(!sender.take_keyframe_request());
+
+        // Another drop soon after waits for the request interval.
+        let kinds = |replies: Vec<Vec<u8>>| -> Vec<u8> {
+            replies
+                .iter()
+                .map(|reply| Header::decode(reply).unwrap().kind)
+                .collect()
+        };
+        for (_, datagram) in &sender.send(&packet(3000, 2), start).unwrap()[..1] {
+            receiver.receive(datagram, start).unwrap();
+        }
+        let timed_out = start + FRAME_TIMEOUT;
+        assert_eq!(kinds(receiver.poll(timed_out)), vec![KIND_FEEDBACK]);
+        let due = start + KEYFRAME_REQUEST_INTERVAL;
+        assert_eq!(kinds(receiver.poll(due)), vec![KIND_KEYFRAME_REQUEST]);
+        assert_eq!(receiver.stats().frames_dropped, 2);
+    }
+
+    /// Streams 60 fps frames through a lossy link in simulated time. Returns the
+    /// sender, the receiver's counters and the keyframes sent on request.
+    fn stream_over(
+        link: Netem,
+        fec_group: u8,
+        frames: u32,
+        encrypted: bool,
+    ) -> (MediaSender, LinkStats, u32) {
+        let start = Instant::now();
+        let (host, client) = encrypted
+            .then(|| {
+                (
+                    DatagramCipher::new(Cipher::ChaCha20Poly1305, [1; 32]),
+                    DatagramCipher::new(Cipher::ChaCha20Poly1305, [2; 32]),
+                )
+            })
+            .unzip();
+        let mut sender = MediaSender::new(
+            MediaConfig::for_bitrate(20_000, fec_group),
+            host.clone(),
+            client.clone(),
+            start,
+        );
+        let mut receiver = MediaReceiver::new(host, client, start);
+        let mut downlink = link;
+        let mut uplink = Netem::new(99).delay(Duration::from_millis(10));
+        let mut keyframes = 0;
+        let mut pending: Vec<(Instant, Vec<u8>)> = Vec::new();
+        let step = Duration::from_millis(1);
+        let mut now = start;
+        let end = start + Duration::from_millis(u64::from(frames) * 16 + 500);
+        let mut frame = 0u32;
+        while now < end {
+            if frame < frames && now >= start + Duration::from_millis(u64::from(frame) * 16) {
+                let keyframe = sender.take_keyframe_request();
+                keyframes += u32::from(keyframe);
+                let len = if keyframe { 40_000 } else { 8_000 };
+                pending.extend(sender.send(&packet(len, frame as u8), now).unwrap());
+                frame += 1;
+            }
+            let (due, later): (Vec<_>, Vec<_>) = pending.drain(..).partition(|(at, _)| *at <= now);
+            pending = later;
+            for (_, datagram) in due {
+                downlink.send(datagram, now);
+            }
+            for datagram in downlink.deliver(now) {
+                if let Some(frame) = receiver.receive(&datagram, now).unwrap() {
+                    assert_eq!(frame.len() % 8_000, 0);
+                }
+            }
+            for reply in receiver.poll(now) {
+                uplink.send(reply, now);
+            }
+            for reply in uplink.deliver(now) {
+                sender.receive(&reply, now).unwrap();
+            }
+            now += step;
+        }
+        (sender, receiver.stats(), keyframes)
+    }
+
+    #[test]
+    fn clean_loopback_delivers_every_frame_at_full_rate() {
+        let link = Netem::new(1).delay(Duration::from_millis(10));
+        let (sender, stats, keyframes) = stream_over(link, 0, 120, true);
+        assert_eq!(stats.frames_delivered, 120);
+        assert_eq!((stats.lost, stats.frames_dropped, keyframes), (0, 0, 0));
+        assert_eq!(sender.target_kbps(), 20_000);
+        let rtt = sender.rtt().unwrap();
+        assert!(
+            rtt >= Duration::from_millis(20) && rtt < Duration::from_millis(25),
+            "{rtt:?}"
+        );
+    }
+
+    #[test]
+    fn lossy_link_recovers_with_fec_and_backs_off() {
+        let lossy = || {
+            Netem::new(7)
+                .loss(0.03)
+                .delay(Duration::from_millis(10))
+                .jitter(Duration::from_millis(4))
+        };
+        let (_, without_fec, _) = stream_over(lossy(), 0, 200, false);
+        let (sender, with_fec, keyframes) = stream_over(lossy(), 4, 200, false);
+        assert!(with_fec.frames_recovered > 0);
+        assert!(
+            with_fec.frames_delivered > without_fec.frames_delivered,
+            "{with_fec:?} vs {without_fec:?}"
+        );
+        // Whatever could not be repaired was answered with a keyframe.
+        assert!(with_fec.frames_dropped > 0 && keyframes > 0);
+        assert!(sender.last_feedback().unwrap().lost > 0);
+
+        let heavy = Netem::new(3).loss(0.25).delay(Duration::from_millis(10));
+        let (sender, _, _) = stream_over(heavy, 4, 200, false);
+        assert!(
+            sender.target_kbps() < 20_000 / 2,
+            "{}",
+            sender.target_kbps()
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! Host end of QUIC sessions (§7.11). A session starts on TCP as usual; when both
+//! sides offer QUIC in public-key mode, the host sends `QuicPath` with a one-off
+//! token and the client connects here with its identity certificate. Once the
+//! client's control stream presents the token, control and input run on that
+//! stream and every frame on its own stream. If the QUIC connection drops, the
+//! session carries on over TCP.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use quinn::{Connection, Endpoint, Incoming, RecvStream, SendStream, VarInt};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::timeout;
+
+use crate::app_state::HostSettings;
+use crate::host_log;
+use crate::host_transport::TransportHandle;
+use crate::pairing;
+use crate::protocol::framing::StreamDecoder;
+use crate::protocol::key_exchange::{fingerprint, Identity};
+use crate::protocol::packets::QuicPathPacket;
+use crate::protocol::quic::{self, TOKEN_LEN};
+
+/// How long a `QuicPath` offer waits for the client to connect.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a connected client has to open its control stream and send the token.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+/// A frame not acknowledged by then is abandoned; the next one is a keyframe.
+const FRAME_DEADLINE: Duration = Duration::from_millis(500);
+
+static RUNTIME: OnceLock<Result<Runtime, String>> = OnceLock::new();
+static SERVER: OnceLock<Mutex<Option<Server>>> = OnceLock::new();
+static OFFERS: OnceLock<Mutex<Vec<Offer>>> = OnceLock::new();
+static PATHS: OnceLock<Mutex<Vec<QuicPath>>> = OnceLock::new();
+static KEYFRAME_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn runtime() -> Result<&'static Runtime, String> {
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(2)
+                .thread_name("quic")
+                .enable_all()
+                .build()
+                .map_err(|err| err.to_string())
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+fn server_store() -> &'static Mutex<Option<Server>> {
+    SERVER.get_or_init(|| Mutex::new(None))
+}
+
+fn offer_store() -> &'static Mutex<Vec<Offer>> {
+    OFFERS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn path_store() -> &'static Mutex<Vec<QuicPath>> {
+    PATHS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+struct Server {
+    endpoint: Endpoint,
+    address: SocketAddr,
+    /// Identity the certificate was made from; a new one restarts the endpoint.
+    fingerprint: [u8; 32],
+}
+
+struct Offer {
+    hub: TransportHandle,
+    session: u64,
+    token: [u8; TOKEN_LEN],
+    peer: [u8; 32],
+    expires: Instant,
+}
+
+struct QuicPath {
+    session: u64,
+    connection: Connection,
+    control: UnboundedSender<Vec<u8>>,
+    frames: UnboundedSender<Vec<u8>>,
+}
+
+/// Starts, restarts or stops the QUIC endpoint to match `settings`. Sessions on
+/// a stopped endpoint fall back to TCP.
+pub fn apply(app_handle: &tauri::AppHandle, settings: &HostSettings) -> Result<(), String> {
+    let wanted = settings
+        .quic_enabled
+        .then(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, settings.quic_port())));
+    let identity = match wanted {
+        Some(_) => Some(pairing::load_identity(app_handle)?),
+        None => None,
+    };
+    let running = status();
+    if let Some(address) = running {
+        let unchanged = match (wanted, &identity) {
+            (Some(wanted), Some(identity)) => wanted.port() == address.port() && serving(identity),
+            _ => false,
+        };
+        if unchanged {
+            return Ok(());
+        }
+        stop();
+        let _ = host_log::append_log(app_handle, format!("Stopped QUIC on {address}"));
+    }
+    let (Some(bind), Some(identity)) = (wanted, identity) else {
+        return Ok(());
+    };
+    let address = serve(&identity, bind)?;
+    let _ = host_log::append_log(app_handle, format!("Offering QUIC on {address}"));
+    Ok(())
+}
+
+fn serving(identity: &Identity) -> bool {
+    server_store()
+        .lock()
+        .ok()
+        .and_then(|guard| guard.as_ref().map(|server| server.fingerprint))
+        == Some(identity.fingerprint())
+}
+
+/// Opens the endpoint on `bind` and returns the address it got.
+fn serve(identity: &Identity, bind: SocketAddr) -> Result<SocketAddr, String> {
+    let config = quic::server_config(identity).map_err(|err| err.to_string())?;
+    let runtime = runtime()?;
+    let endpoint = {
+        let _context = runtime.enter();
+        Endpoint::server(config, bind)
+            .map_err(|err| format!("Cannot listen for QUIC on {bind}: {err}"))?
+    };
+    let address = endpoint.local_addr().map_err(|err| err.to_string())?;
+    runtime.spawn(accept_loop(endpoint.clone()));
+    let mut lock = server_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    *lock = Some(Server {
+        endpoint,
+        address,
+        fingerprint: identity.fingerprint(),
+    });
+    Ok(address)
+}
+
+fn stop() {
+    if let Ok(mut lock) = server_store().lock() {
+        if let Some(server) = lock.take() {
+            server.endpoint.close(VarInt::from_u32(0), b"stopped");
+        }
+    }
+    close_all();
+}
+
+/// The address the endpoint is bound to, if it is running.
+pub fn status() -> Option<SocketAddr> {
+    server_store()
+        .lock()
+        .ok()
+        .and_then(|guard| guard.as_ref().map(|server| server.address))
+}
+
+pub fn enabled() -> bool {
+    status().is_some()
+}
+
+/// Lets the client with identity fingerprint `peer` move `session` of `hub` to
+/// QUIC, and returns the `QuicPath` packet telling it how.
+pub fn offer(
+    hub: &TransportHandle,
+    session: u64,
+    peer: [u8; 32],
+) -> Result<QuicPathPacket, String> {
+    let port = status()
+        .ok_or_else(|| "QUIC is turned off".to_string())?
+        .port();
+    let mut token = [0u8; TOKEN_LEN];
+    getrandom::getrandom(&mut token).map_err(|err| err.to_string())?;
+    let mut lock = offer_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    let now = Instant::now();
+    lock.retain(|offer| offer.expires > now);
+    lock.push(Offer {
+        hub: hub.clone(),
+        session,
+        token,
+        peer,
+        expires: now + OFFER_TIMEOUT,
+    });
+    Ok(QuicPathPacket { port, token })
+}
+
+/// The session an offer was made for and the handle it belongs to, used up by
+/// this call.
+fn redeem(token: &[u8; TOKEN_LEN], peer: &[u8; 32]) -> Option<(TransportHandle, u64)> {
+    let mut lock = offer_store().lock().ok()?;
+    let now = Instant::now();
+    lock.retain(|offer| offer.expires > now);
+    let index = lock
+        .iter()
+        .position(|offer| offer.token == *token && offer.peer == *peer)?;
+    let offer = lock.remove(index);
+    Some((offer.hub, offer.session))
+}
+
+async fn accept_loop(endpoint: Endpoint) {
+    while let Some(incoming) = endpoint.accept().await {
+        tokio::spawn(attach(incoming));
+    }
+}
+
+/// Runs one client connection until it closes.
+async fn attach(incoming: Incoming) {
+    let Ok(connection) = incoming.await else {
+        return;
+    };
+    let Some(session) = claim(&connection).await else {
+        connection.close(VarInt::from_u32(1), b"unknown session");
+        return;
+    };
+    connection.close(VarInt::from_u32(0), b"closed");
+    detach(session, &connection);
+}
+
+/// Waits for the control stream and its token, then routes the session here
+/// until the stream ends.
+async fn claim(connection: &Connection) -> Option<u64> {
+    let peer = fingerprint(&quic::peer_key(connection)?);
+    let (send, mut recv) = timeout(OPEN_TIMEOUT, connection.accept_bi())
+        .await
+        .ok()?
+        .ok()?;
+    let mut token = [0u8; TOKEN_LEN];
+    timeout(OPEN_TIMEOUT, recv.read_exact(&mut token))
+        .await
+        .ok()?
+        .ok()?;
+    let (hub, session) = redeem(&token, &peer)?;
+
+    let (control, control_queue) = mpsc::unbounded_channel();
+    let (frames, frame_queue) = mpsc::unbounded_channel();
+    tokio::spawn(write_control(send, control_queue));
+    tokio::spawn(write_frames(connection.clone(), frame_queue));
+    if let Ok(mut lock) = path_store().lock() {
+        lock.retain(|path| path.session != session);
+        lock.push(QuicPath {
+            session,
+            connection: connection.clone(),
+            control,
+            frames,
+        });
+    }
+    read_control(&hub, recv).await;
+    Some(session)
+}
+
+async fn read_control(hub: &TransportHandle, mut recv: RecvStream) {
+    let mut decoder = StreamDecoder::default();
+    let mut buffer = [0u8; 4096];
+    while let Ok(Some(read)) = recv.read(&mut buffer).await {
+        for (stream_id, packet) in decoder.push(&buffer[..read]) {
+            hub.receive_client_packet(stream_id, &packet);
+        }
+    }
+}
+
+async fn write_control(mut send: SendStream, mut queue: UnboundedReceiver<Vec<u8>>) {
+    while let Some(chunked) = queue.recv().await {
+        if send.write_all(&chunked).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Streams are opened in frame order, so clients can decode in stream order.
+async fn write_frames(connection: Connection, mut queue: UnboundedReceiver<Vec<u8>>) {
+    while let Some(packet) = queue.recv().await {
+        let Ok(stream) = connection.open_uni().await else {
+            break;
+        };
+        tokio::spawn(write_frame(stream, packet));
+    }
+}
+
+async fn write_frame(mut stream: SendStream, packet: Vec<u8>) {
+    let delivered = timeout(FRAME_DEADLINE, deliver(&mut stream, &packet)).await;
+    if !matches!(delivered, Ok(true)) {
+        let _ = stream.reset(VarInt::from_u32(0));
+        KEYFRAME_REQUESTED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Whether the client acknowledged the whole frame.
+async fn deliver(stream: &mut SendStream, packet: &[u8]) -> bool {
+    stream.write_all(packet).await.is_ok()
+        && stream.finish().is_ok()
+        && matches!(stream.stopped().await, Ok(None))
+}
+
+/// Drops the path if it still belongs to `connection`; a newer one is kept.
+fn detach(session: u64, connection: &Connection) {
+    if let Ok(mut lock) = path_store().lock() {
+        lock.retain(|path| {
+            path.session != session || path.connection.stable_id() != connection.stable_id()
+        });
+    }
+}
+
+/// Queues chunked control bytes on the session's QUIC control stream. `false`
+/// when the session has no QUIC path, so the caller writes to TCP.
+pub fn send_control(session: u64, chunked: &[u8]) -> bool {
+    let Ok(lock) = path_store().lock() else {
+        return false;
+    };
+    lock.iter()
+        .find(|path| path.session == session)
+        .is_some_and(|path| path.control.send(chunked.to_vec()).is_ok())
+}
+
+/// Queues a `Frame` packet on every QUIC path and returns the sessions it reached.
+pub fn send_frame(packet: &[u8]) -> Vec<u64> {
+    let Ok(lock) = path_store().lock() else {
+        return Vec::new();
+    };
+    lock.iter()
+        .filter(|path| path.frames.send(packet.to_vec()).is_ok())
+        .map(|path| path.session)
+        .collect()
+}
+
+/// Whether a frame was abandoned since the last call.
+pub fn take_keyframe_request() -> bool {
+    KEYFRAME_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+pub fn close(session: u64) {
+    if let Ok(mut lock) = offer_store().lock() {
+        lock.retain(|offer| offer.session != session);
+    }
+    if let Ok(mut lock) = path_store().lock() {
+        lock.retain(|path| {
+            let keep = path.session != session;
+            if !keep {
+                path.connection.close(VarInt::from_u32(0), b"closed");
+            }
+            keep
+        });
+    }
+}
+
+pub fn close_all() {
+    if let Ok(mut lock) = offer_store().lock() {
+        lock.clear();
+    }
+    if let Ok(mut lock) = path_store().lock() {
+        for path in lock.drain(..) {
+            path.connection.close(VarInt::from_u32(0), b"closed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::framing::write_stream_chunks;
+    use crate::protocol::key_exchange::KEY_LEN;
+    use crate::protocol::quic::SERVER_NAME;
+
+    #[test]
+    fn carries_control_and_frames_for_the_offered_session() {
+        let host = Identity::from_seed(&[5; KEY_LEN]);
+        let client = Identity::from_seed(&[6; KEY_LEN]);
+        let stranger = Identity::from_seed(&[7; KEY_LEN]);
+        let address = serve(&host, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let session = u64::MAX - 1;
+        let hub = TransportHandle::default();
+        let path = offer(&hub, session, client.fingerprint()).unwrap();
+        assert_eq!(path.port, address.port());
+
+        runtime().unwrap().block_on(async {
+            let dialer = Endpoint::client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+
+            // The token only works for the identity it was offered to.
+            let config = quic::client_config(&stranger, host.fingerprint()).unwrap();
+            let intruder = dialer
+                .connect_with(config, address, SERVER_NAME)
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut send, _recv) = intruder.open_bi().await.unwrap();
+            send.write_all(&path.token).await.unwrap();
+            intruder.closed().await;
+            assert!(send_frame(b"frame")
+                .iter()
+                .all(|reached| *reached != session));
+
+            let config = quic::client_config(&client, host.fingerprint()).unwrap();
+            let connection = dialer
+                .connect_with(config, address, SERVER_NAME)
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut send, mut recv) = connection.open_bi().await.unwrap();
+            send.write_all(&path.token).await.unwrap();
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while !send_control(session, b"") {
+                assert!(Instant::now() < deadline, "the host never took the path");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            let mut chunked = Vec::new();
+            write_stream_chunks(0, b"\x05\x00\x00\x00hello", &mut chunked);
+            assert!(send_control(session, &chunked));
+            let mut received = vec![0u8; chunked.len()];
+            recv.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, chunked);
+
+            assert!(send_frame(b"first").contains(&session));
+            assert!(send_frame(b"second").contains(&session));
+            for expected in [&b"first"[..], b"second"] {
+                let mut stream = connection.accept_uni().await.unwrap();
+                assert_eq!(stream.read_to_end(1024).await.unwrap(), expected);
+            }
+            assert!(!take_keyframe_request());
+
+            close(session);
+            connection.closed().await;
+        });
+        assert!(!send_control(session, b""));
+        stop();
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bitstream::FrameInspection;
+use crate::codec::{self, CodecId};
+
+mod mp4;
+
+use mp4::Mp4Writer;
+
+/// Container for a session recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordingFormat {
+    /// Annex-B elementary stream (`.h264`/`.h265`): the frame bytes exactly as sent.
+    Raw,
+    /// Fragmented MP4 carrying each frame's `timestamp_100ns`.
+    Mp4,
+}
+
+/// Caps after which a recording stops by itself; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingLimits {
+    pub max_bytes: Option<u64>,
+    pub max_duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingStatus {
+    pub active: bool,
+    pub path: Option<String>,
+    pub format: Option<RecordingFormat>,
+    pub bytes: u64,
+    pub frames: u64,
+    pub duration_ms: u64,
+    /// Why the recording ended when it was not stopped from the UI.
+    pub stop_reason: Option<String>,
+}
+
+enum Sink {
+    Raw { out: BufWriter<File>, written: u64 },
+    Mp4(Mp4Writer<BufWriter<File>>),
+}
+
+impl Sink {
+    fn write(&mut self, payload: &[u8], decode_time: u64, keyframe: bool) -> io::Result<()> {
+        match self {
+            Sink::Raw { out, written } => {
+                out.write_all(payload)?;
+                *written += payload.len() as u64;
+                Ok(())
+            }
+            Sink::Mp4(writer) => writer.write_frame(payload, decode_time, keyframe),
+        }
+    }
+
+    fn written(&self) -> u64 {
+        match self {
+            Sink::Raw { written, .. } => *written,
+            Sink::Mp4(writer) => writer.written(),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::Raw { mut out, .. } => out.flush(),
+            Sink::Mp4(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+/// A recording in progress. Frames are the encoder output the client received;
+/// nothing is encoded a second time.
+pub struct Recording {
+    path: PathBuf,
+    format: RecordingFormat,
+    codec_id: CodecId,
+    limits: RecordingLimits,
+    sink: Sink,
+    clock: Instant,
+    first_timestamp: Option<u64>,
+    last_decode_time: u64,
+    frames: u64,
+}
+
+impl Recording {
+    /// Creates `recording-<timestamp>.<ext>` in `dir`.
+    pub fn create(
+        dir: &Path,
+        format: RecordingFormat,
+        codec_id: CodecId,
+        limits: RecordingLimits,
+        timestamp: u64,
+    ) -> Result<Self, String> {
+        let extension = match (format, codec_id) {
+            (RecordingFormat::Mp4, _) => "mp4",
+            (RecordingFormat::Raw, CodecId::H264) => "h264",
+            (RecordingFormat::Raw, CodecId::H265) => "h265",
+            (RecordingFormat::Raw, other) => {
+                return Err(format!(
+                    "Raw recording supports H.264 and H.265, not {}",
+                    codec::codec_name(other)
+                ))
+            }
+        };
+        fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        let path = dir.join(format!("recording-{timestamp}.{extension}"));
+        let sink = match format {
+            RecordingFormat::Raw => Sink::Raw {
+                out: BufWriter::new(File::create(&path).map_err(|err| err.to_string())?),
+                written: 0,
+            },
+            RecordingFormat::Mp4 => {
+                // Validate the codec before creating the file.
+                Mp4Writer::new(io::sink(), codec_id)?;
+                let file = File::create(&path).map_err(|err| err.to_string())?;
+                Sink::Mp4(Mp4Writer::new(BufWriter::new(file), codec_id)?)
+            }
+        };
+        Ok(Self {
+            path,
+            format,
+            codec_id,
+            limits,
+            sink,
+            clock: Instant::now(),
+            first_timestamp: None,
+            last_decode_time: 0,
+            frames: 0,
+        })
+    }
+
+    /// Appends one sent frame. Frames before the first decodable keyframe are
+    /// dropped so the file starts cleanly. Returns why the recording has to end
+    /// instead of writing the frame once a cap is hit.
+    pub fn record(
+        &mut self,
+        codec_id: CodecId,
+        payload: &[u8],
+        timestamp_100ns: Option<u64>,
+        inspection: &FrameInspection,
+    ) -> Result<Option<String>, String> {
+        if codec_id != self.codec_id {
+            return Ok(Some("Codec changed".to_string()));
+        }
+        if self.frames == 0 && !inspection.is_decodable_start(codec_id) {
+            return Ok(None);
+        }
+        let timestamp =
+            timestamp_100ns.unwrap_or_else(|| self.clock.elapsed().as_nanos() as u64 / 100);
+        let first = *self.first_timestamp.get_or_insert(timestamp);
+        let decode_time = timestamp.saturating_sub(first).max(self.last_decode_time);
+        if let Some(max_ms) = self.limits.max_duration_ms {
+            if decode_time >= max_ms.saturating_mul(10_000) {
+                return Ok(Some("Duration limit reached".to_string()));
+            }
+        }
+        if let Some(max_bytes) = self.limits.max_bytes {
+            if self.sink.written() + payload.len() as u64 > max_bytes {
+                return Ok(Some("Size limit reached".to_string()));
+            }
+        }
+        self.sink
+            .write(payload, decode_time, inspection.is_keyframe())
+            .map_err(|err| err.to_string())?;
+        self.last_decode_time = decode_time;
+        self.frames += 1;
+        Ok(None)
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            active: true,
+            path: Some(self.path.to_string_lossy().to_string()),
+            format: Some(self.format),
+            bytes: self.sink.written(),
+            frames: self.frames,
+            duration_ms: self.last_decode_time / 10_000,
+            stop_reason: None,
+        }
+    }
+
+    /// Flushes the file and returns its final status.
+    pub fn finish(self, stop_reason: Option<String>) -> Result<RecordingStatus, String> {
+        let mut status = self.status();
+        self.sink.finish().map_err(|err| err.to_string())?;
+        status.active = false;
+        status.bytes = fs::metadata(&self.path)
+            .map(|meta| meta.len())
+            .unwrap_or(status.bytes);
+        status.stop_reason = stop_reason;
+        Ok(status)
+    }
+}
+
+#[derive(Default)]
+struct RecorderState {
+    recording: Option<Recording>,
+    last: RecordingStatus,
+}
+
+static RECORDER: OnceLock<Mutex<RecorderState>> = OnceLock::new();
+
+fn recorder_store() -> &'static Mutex<RecorderState> {
+    RECORDER.get_or_init(|| Mutex::new(RecorderState::default()))
+}
+
+/// Starts recording the frames sent from now on into `dir`; returns the file path.
+pub fn start(
+    dir: &Path,
+    format: RecordingFormat,
+    codec_id: CodecId,
+    limits: RecordingLimits,
+) -> Result<String, String> {
+    let mut state = recorder_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    if state.recording.is_some() {
+        return Err("A recording is already running".to_string());
+    }
+    let recording = Recording::create(dir, format, codec_id, limits, now_millis())?;
+    state.last = recording.status();
+    let path = state.last.path.clone().unwrap_or_default();
+    state.recording = Some(recording);
+    Ok(path)
+}
+
+/// Stops the running recording, if any, and returns the final status.
+pub fn stop(stop_reason: Option<String>) -> Result<RecordingStatus, String> {
+    let mut state = recorder_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    if let Some(recording) = state.recording.take() {
+        state.last = recording.finish(stop_reason)?;
+    }
+    Ok(state.last.clone())
+}
+
+pub fn status() -> RecordingStatus {
+    recorder_store()
+        .lock()
+        .map(|state| match &state.recording {
+            Some(recording) => recording.status(),
+            None => state.last.clone(),
+        })
+        .unwrap_or_default()
+}
+
+/// Hands a frame that was just sent to the running recording, if any.
+pub fn tap(
+    codec_id: CodecId,
+    payload: &[u8],
+    timestamp_100ns: Option<u64>,
+    inspection: &FrameInspection,
+) {
+    let Ok(mut state) = recorder_store().lock() else {
+        return;
+    };
+    let Some(recording) = state.recording.as_mut() else {
+        return;
+    };
+    let stop_reason = match recording.record(codec_id, payload, timestamp_100ns, inspection) {
+        Ok(None) => return,
+        Ok(Some(reason)) => reason,
+        Err(err) => format!("Write failed: {err}"),
+    };
+    if let Some(recording) = state.recording.take() {
+        state.last = recording
+            .finish(Some(stop_reason.clone()))
+            .unwrap_or_else(|err| RecordingStatus {
+                stop_reason: Some(format!("{stop_reason}; {err}")),
+                ..RecordingStatus::default()
+            });
+    }
+}
+
+pub fn recordings_dir(app_handle: &tauri::AppHandle) -> PathBuf {
+    app_handle
+        .path_resolver()
+        .app_data_dir()
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+        .join("recordings")
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitstream;
+
+    const KEYFRAME: [u8; 29] = [
+        0, 0, 0, 1, 0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x40, 0, 0,
+        1, 0x68, 0xeb, 0xe3, 0xcb, 0, 0, 1, 0x65, 0x88, 0x84,
+    ];
+    const DELTA: [u8; 6] = [0, 0, 0, 1, 0x41, 0x9a];
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("uberdisplay-{name}-{}", now_millis()))
+    }
+
+    fn record(recording: &mut Recording, frame: &[u8], timestamp: u64) -> Option<String> {
+        let inspection = bitstream::inspect(CodecId::H264, frame);
+        recording
+            .record(CodecId::H264, frame, Some(timestamp), &inspection)
+            .unwrap()
+    }
+
+    #[test]
+    fn raw_recording_starts_at_keyframe_and_honours_size_cap() {
+        let dir = temp_dir("recording-raw");
+        let limits = RecordingLimits {
+            max_bytes: Some((KEYFRAME.len() + DELTA.len() * 2) as u64),
+            max_duration_ms: None,
+        };
+        let mut recording =
+            Recording::create(&dir, RecordingFormat::Raw, CodecId::H264, limits, 7).unwrap();
+        assert_eq!(record(&mut recording, &DELTA, 0), None);
+        assert_eq!(record(&mut recording, &KEYFRAME, 10_000), None);
+        assert_eq!(record(&mut recording, &DELTA, 20_000), None);
+        assert_eq!(record(&mut recording, &DELTA, 30_000), None);
+        assert_eq!(
+            record(&mut recording, &DELTA, 40_000).as_deref(),
+            Some("Size limit reached")
+        );
+
+        let status = recording.finish(None).unwrap();
+        assert_eq!(
+            status.path,
+            Some(dir.join("recording-7.h264").to_string_lossy().to_string())
+        );
+        assert_eq!(status.frames, 3);
+        assert_eq!(status.duration_ms, 2);
+        let written = fs::read(dir.join("recording-7.h264")).unwrap();
+        assert_eq!(written, [&KEYFRAME[..], &DELTA, &DELTA].concat());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn mp4_recording_stops_at_duration_cap() {
+        let dir = temp_dir("recording-mp4");
+        let limits = RecordingLimits {
+            max_bytes: None,
+            max_duration_ms: Some(100),
+        };
+        let mut recording =
+            Recording::create(&dir, RecordingFormat::Mp4, CodecId::H264, limits, 8).unwrap();
+        let start = 5_000_000_000u64;
+        assert_eq!(record(&mut recording, &KEYFRAME, start), None);
+        assert_eq!(record(&mut recording, &DELTA, start + 500_000), None);
+        assert_eq!(
+            record(&mut recording, &DELTA, start + 1_000_000).as_deref(),
+            Some("Duration limit reached")
+        );
+
+        let status = recording
+            .finish(Some("Duration limit reached".to_string()))
+            .unwrap();
+        assert_eq!((status.frames, status.duration_ms), (2, 50));
+        let file = fs::read(dir.join("recording-8.mp4")).unwrap();
+        assert_eq!(status.bytes, file.len() as u64);
+        assert_eq!(&file[4..8], b"ftyp");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_codecs_without_a_container_mapping() {
+        let dir = temp_dir("recording-av1");
+        for format in [RecordingFormat::Raw, RecordingFormat::Mp4] {
+            assert!(
+                Recording::create(&dir, format, CodecId::Av1, RecordingLimits::default(), 9)
+                    .is_err()
+            );
+        }
+        assert!(!dir.join("recording-9.mp4").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
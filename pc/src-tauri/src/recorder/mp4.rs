@@ -0,0 +1,509 @@
+use std::io::{self, Write};
+
+use crate::bitstream::{self, NalKind};
+use crate::codec::CodecId;
+
+/// Media timescale: sample times are the stream's 100 ns timestamps as-is.
+pub const TIMESCALE: u32 = 10_000_000;
+
+/// Duration given to the last sample, and to every sample until two timestamps
+/// have been seen.
+const DEFAULT_SAMPLE_DURATION: u32 = TIMESCALE / 60;
+
+const TRACK_ID: u32 = 1;
+
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+
+/// Fragmented MP4 muxer for H.264/HEVC access units. Writes the init segment on the
+/// first keyframe, then one `moof`+`mdat` per frame, so a file cut short by a crash
+/// still plays up to the last complete fragment.
+///
+/// Sample entries are `avc3`/`hev1`: parameter sets stay in-band, which keeps
+/// encoder reconfigurations within a recording playable.
+pub struct Mp4Writer<W: Write> {
+    out: W,
+    codec_id: CodecId,
+    initialized: bool,
+    sequence: u32,
+    pending: Option<Sample>,
+    last_duration: u32,
+    written: u64,
+}
+
+struct Sample {
+    data: Vec<u8>,
+    decode_time: u64,
+    keyframe: bool,
+}
+
+impl<W: Write> Mp4Writer<W> {
+    pub fn new(out: W, codec_id: CodecId) -> Result<Self, String> {
+        if !matches!(codec_id, CodecId::H264 | CodecId::H265) {
+            return Err(format!(
+                "MP4 recording supports H.264 and H.265, not {}",
+                crate::codec::codec_name(codec_id)
+            ));
+        }
+        Ok(Self {
+            out,
+            codec_id,
+            initialized: false,
+            sequence: 0,
+            pending: None,
+            last_duration: DEFAULT_SAMPLE_DURATION,
+            written: 0,
+        })
+    }
+
+    /// Bytes written to the output so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Queues an Annex-B access unit decoded at `decode_time` (100 ns units from
+    /// the start of the recording). The first frame must be a keyframe carrying
+    /// parameter sets. Each frame is written once the next one gives its duration.
+    pub fn write_frame(
+        &mut self,
+        annexb: &[u8],
+        decode_time: u64,
+        keyframe: bool,
+    ) -> io::Result<()> {
+        if !self.initialized {
+            let init = self.init_segment(annexb).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "first recorded frame lacks parameter sets",
+                )
+            })?;
+            self.emit(&init)?;
+            self.initialized = true;
+        }
+        let sample = Sample {
+            data: length_prefixed(annexb),
+            decode_time,
+            keyframe,
+        };
+        if let Some(previous) = self.pending.take() {
+            let duration = decode_time
+                .saturating_sub(previous.decode_time)
+                .clamp(1, u32::MAX as u64) as u32;
+            self.last_duration = duration;
+            self.write_fragment(&previous, duration)?;
+        }
+        self.pending = Some(sample);
+        Ok(())
+    }
+
+    /// Writes the held-back frame and returns the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(last) = self.pending.take() {
+            self.write_fragment(&last, self.last_duration)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_fragment(&mut self, sample: &Sample, duration: u32) -> io::Result<()> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut fragment = Vec::with_capacity(sample.data.len() + 128);
+        let mut data_offset_at = 0;
+        write_box(&mut fragment, b"moof", |moof| {
+            write_full_box(moof, b"mfhd", 0, 0, |mfhd| {
+                put_u32(mfhd, self.sequence);
+            });
+            write_box(moof, b"traf", |traf| {
+                // default-base-is-moof: data offsets are relative to this moof.
+                write_full_box(traf, b"tfhd", 0, 0x02_0000, |tfhd| {
+                    put_u32(tfhd, TRACK_ID);
+                });
+                write_full_box(traf, b"tfdt", 1, 0, |tfdt| {
+                    tfdt.extend_from_slice(&sample.decode_time.to_be_bytes());
+                });
+                // data-offset, sample-duration, sample-size and sample-flags present.
+                write_full_box(traf, b"trun", 0, 0x0701, |trun| {
+                    put_u32(trun, 1);
+                    data_offset_at = trun.len();
+                    put_u32(trun, 0);
+                    put_u32(trun, duration);
+                    put_u32(trun, sample.data.len() as u32);
+                    put_u32(
+                        trun,
+                        if sample.keyframe {
+                            SAMPLE_FLAGS_SYNC
+                        } else {
+                            SAMPLE_FLAGS_NON_SYNC
+                        },
+                    );
+                });
+            });
+        });
+        let data_offset = (fragment.len() + 8) as u32;
+        fragment[data_offset_at..data_offset_at + 4].copy_from_slice(&data_offset.to_be_bytes());
+        put_u32(&mut fragment, (sample.data.len() + 8) as u32);
+        fragment.extend_from_slice(b"mdat");
+        self.emit(&fragment)?;
+        self.emit(&sample.data)
+    }
+
+    fn init_segment(&self, annexb: &[u8]) -> Option<Vec<u8>> {
+        let units = bitstream::nal_units(self.codec_id, annexb);
+        let find = |kind: NalKind| {
+            units
+                .iter()
+                .find(|unit| unit.kind == kind)
+                .map(|unit| unit.payload)
+        };
+        let sps = find(NalKind::Sps)?;
+        let pps = find(NalKind::Pps)?;
+        let info = bitstream::parse_sps(self.codec_id, sps)?;
+        let (entry_kind, config) = match self.codec_id {
+            CodecId::H265 => (*b"hev1", hvcc(find(NalKind::Vps)?, sps, pps)?),
+            _ => (*b"avc3", avcc(sps, pps)?),
+        };
+        let (width, height) = (
+            info.width.min(u16::MAX as u32),
+            info.height.min(u16::MAX as u32),
+        );
+
+        let mut init = Vec::with_capacity(1024);
+        write_box(&mut init, b"ftyp", |ftyp| {
+            ftyp.extend_from_slice(b"isom");
+            put_u32(ftyp, 0x200);
+            ftyp.extend_from_slice(b"isomiso6mp41");
+            ftyp.extend_from_slice(if self.codec_id == CodecId::H265 {
+                b"hev1"
+            } else {
+                b"avc1"
+            });
+        });
+        write_box(&mut init, b"moov", |moov| {
+            write_full_box(moov, b"mvhd", 0, 0, |mvhd| {
+                put_u32(mvhd, 0);
+                put_u32(mvhd, 0);
+                put_u32(mvhd, TIMESCALE);
+                put_u32(mvhd, 0);
+                put_u32(mvhd, 0x0001_0000);
+                put_u16(mvhd, 0x0100);
+                mvhd.extend_from_slice(&[0; 10]);
+                put_matrix(mvhd);
+                mvhd.extend_from_slice(&[0; 24]);
+                put_u32(mvhd, TRACK_ID + 1);
+            });
+            write_box(moov, b"trak", |trak| {
+                // Track enabled and in movie.
+                write_full_box(trak, b"tkhd", 0, 0x03, |tkhd| {
+                    put_u32(tkhd, 0);
+                    put_u32(tkhd, 0);
+                    put_u32(tkhd, TRACK_ID);
+                    put_u32(tkhd, 0);
+                    put_u32(tkhd, 0);
+                    tkhd.extend_from_slice(&[0; 8]);
+                    put_u16(tkhd, 0);
+                    put_u16(tkhd, 0);
+                    put_u16(tkhd, 0);
+                    put_u16(tkhd, 0);
+                    put_matrix(tkhd);
+                    put_u32(tkhd, width << 16);
+                    put_u32(tkhd, height << 16);
+                });
+                write_box(trak, b"mdia", |mdia| {
+                    write_full_box(mdia, b"mdhd", 0, 0, |mdhd| {
+                        put_u32(mdhd, 0);
+                        put_u32(mdhd, 0);
+                        put_u32(mdhd, TIMESCALE);
+                        put_u32(mdhd, 0);
+                        // Packed ISO-639 "und".
+                        put_u16(mdhd, 0x55c4);
+                        put_u16(mdhd, 0);
+                    });
+                    write_full_box(mdia, b"hdlr", 0, 0, |hdlr| {
+                        put_u32(hdlr, 0);
+                        hdlr.extend_from_slice(b"vide");
+                        hdlr.extend_from_slice(&[0; 12]);
+                        hdlr.extend_from_slice(b"UberDisplay\0");
+                    });
+                    write_box(mdia, b"minf", |minf| {
+                        write_full_box(minf, b"vmhd", 0, 0x01, |vmhd| {
+                            vmhd.extend_from_slice(&[0; 8]);
+                        });
+                        write_box(minf, b"dinf", |dinf| {
+                            write_full_box(dinf, b"dref", 0, 0, |dref| {
+                                put_u32(dref, 1);
+                                // Self-contained: media data is in this file.
+                                write_full_box(dref, b"url ", 0, 0x01, |_| {});
+                            });
+                        });
+                        write_box(minf, b"stbl", |stbl| {
+                            write_full_box(stbl, b"stsd", 0, 0, |stsd| {
+                                put_u32(stsd, 1);
+                                write_box(stsd, &entry_kind, |entry| {
+                                    entry.extend_from_slice(&[0; 6]);
+                                    put_u16(entry, 1);
+                                    entry.extend_from_slice(&[0; 16]);
+                                    put_u16(entry, width as u16);
+                                    put_u16(entry, height as u16);
+                                    put_u32(entry, 0x0048_0000);
+                                    put_u32(entry, 0x0048_0000);
+                                    put_u32(entry, 0);
+                                    put_u16(entry, 1);
+                                    entry.extend_from_slice(&[0; 32]);
+                                    put_u16(entry, 0x0018);
+                                    put_u16(entry, 0xffff);
+                                    entry.extend_from_slice(&config);
+                                });
+                            });
+                            // Samples live in the fragments; the tables stay empty.
+                            write_full_box(stbl, b"stts", 0, 0, |stts| put_u32(stts, 0));
+                            write_full_box(stbl, b"stsc", 0, 0, |stsc| put_u32(stsc, 0));
+                            write_full_box(stbl, b"stsz", 0, 0, |stsz| {
+                                put_u32(stsz, 0);
+                                put_u32(stsz, 0);
+                            });
+                            write_full_box(stbl, b"stco", 0, 0, |stco| put_u32(stco, 0));
+                        });
+                    });
+                });
+            });
+            write_box(moov, b"mvex", |mvex| {
+                write_full_box(mvex, b"trex", 0, 0, |trex| {
+                    put_u32(trex, TRACK_ID);
+                    put_u32(trex, 1);
+                    put_u32(trex, 0);
+                    put_u32(trex, 0);
+                    put_u32(trex, 0);
+                });
+            });
+        });
+        Some(init)
+    }
+}
+
+/// `AVCDecoderConfigurationRecord` with one SPS and one PPS and 4-byte lengths.
+fn avcc(sps: &[u8], pps: &[u8]) -> Option<Vec<u8>> {
+    let mut config = Vec::new();
+    write_box(&mut config, b"avcC", |avcc| {
+        avcc.push(1);
+        avcc.extend_from_slice(&sps[1..4]);
+        avcc.push(0xff);
+        avcc.push(0xe1);
+        put_u16(avcc, sps.len() as u16);
+        avcc.extend_from_slice(sps);
+        avcc.push(1);
+        put_u16(avcc, pps.len() as u16);
+        avcc.extend_from_slice(pps);
+    });
+    (sps.len() >= 4).then_some(config)
+}
+
+/// `HEVCDecoderConfigurationRecord` with one VPS, SPS and PPS and 4-byte lengths.
+/// Chroma format and bit depth are the 8-bit 4:2:0 every host encoder produces.
+fn hvcc(vps: &[u8], sps: &[u8], pps: &[u8]) -> Option<Vec<u8>> {
+    let ptl = bitstream::hevc_general_ptl(sps)?;
+    let mut config = Vec::new();
+    write_box(&mut config, b"hvcC", |hvcc| {
+        hvcc.push(1);
+        hvcc.extend_from_slice(&ptl);
+        put_u16(hvcc, 0xf000);
+        hvcc.push(0xfc);
+        hvcc.push(0xfd);
+        hvcc.push(0xf8);
+        hvcc.push(0xf8);
+        put_u16(hvcc, 0);
+        // One temporal layer, 4-byte NAL lengths.
+        hvcc.push(0x0b);
+        hvcc.push(3);
+        for (nal_type, unit) in [(32u8, vps), (33, sps), (34, pps)] {
+            hvcc.push(0x80 | nal_type);
+            put_u16(hvcc, 1);
+            put_u16(hvcc, unit.len() as u16);
+            hvcc.extend_from_slice(unit);
+        }
+    });
+    Some(config)
+}
+
+/// Annex-B to the 4-byte length-prefixed form MP4 samples use.
+fn length_prefixed(annexb: &[u8]) -> Vec<u8> {
+    let units = bitstream::split_annexb(annexb);
+    let mut sample = Vec::with_capacity(annexb.len() + units.len() * 4);
+    for (_, payload) in units {
+        put_u32(&mut sample, payload.len() as u32);
+        sample.extend_from_slice(payload);
+    }
+    sample
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(kind);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |inner| {
+        put_u32(inner, (version as u32) << 24 | (flags & 0x00ff_ffff));
+        body(inner);
+    });
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Identity transformation matrix.
+fn put_matrix(out: &mut Vec<u8>) {
+    for value in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        put_u32(out, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// H.264 High@4.0 1920x1080 SPS and a PPS.
+    const SPS: [u8; 12] = [
+        0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x40,
+    ];
+    const PPS: [u8; 4] = [0x68, 0xeb, 0xe3, 0xcb];
+
+    fn keyframe() -> Vec<u8> {
+        let mut frame = vec![0, 0, 0, 1];
+        frame.extend_from_slice(&SPS);
+        frame.extend_from_slice(&[0, 0, 0, 1]);
+        frame.extend_from_slice(&PPS);
+        frame.extend_from_slice(&[0, 0, 1, 0x65, 0x88, 0x84, 0x21]);
+        frame
+    }
+
+    /// Top-level boxes as (type, body).
+    fn boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut found = Vec::new();
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            found.push((data[4..8].try_into().unwrap(), &data[8..size]));
+            data = &data[size..];
+        }
+        assert!(data.is_empty(), "trailing bytes after last box");
+        found
+    }
+
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        let (first, rest) = path.split_first().unwrap();
+        let body = boxes(data)
+            .into_iter()
+            .find(|(kind, _)| kind == *first)
+            .map(|(_, body)| body)
+            .unwrap_or_else(|| panic!("missing {}", String::from_utf8_lossy(*first)));
+        if rest.is_empty() {
+            body
+        } else {
+            find(body, rest)
+        }
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn muxes_h264_frames_into_fragments() {
+        let mut writer = Mp4Writer::new(Vec::new(), CodecId::H264).unwrap();
+        writer.write_frame(&keyframe(), 0, true).unwrap();
+        writer
+            .write_frame(&[0, 0, 0, 1, 0x41, 0x9a, 0x02], 333_333, false)
+            .unwrap();
+        let written = writer.written();
+        let file = writer.finish().unwrap();
+        assert!(
+            written < file.len() as u64,
+            "last frame is held until finish"
+        );
+
+        let kinds: Vec<[u8; 4]> = boxes(&file).into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(
+            kinds,
+            [*b"ftyp", *b"moov", *b"moof", *b"mdat", *b"moof", *b"mdat"]
+        );
+
+        let tkhd = find(&file, &[b"moov", b"trak", b"tkhd"]);
+        assert_eq!(u32_at(tkhd, 76) >> 16, 1920);
+        assert_eq!(u32_at(tkhd, 80) >> 16, 1080);
+        let stsd = find(
+            &file,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+        );
+        let avc3 = find(&stsd[8..], &[b"avc3"]);
+        let avcc = find(&avc3[78..], &[b"avcC"]);
+        assert_eq!(&avcc[..5], &[1, 0x64, 0x00, 0x28, 0xff]);
+
+        let fragments: Vec<&[u8]> = boxes(&file)
+            .into_iter()
+            .filter(|(kind, _)| kind == b"moof")
+            .map(|(_, body)| body)
+            .collect();
+        let trun = find(fragments[0], &[b"traf", b"trun"]);
+        // Duration is the gap to the next frame; the last frame reuses it.
+        assert_eq!(u32_at(trun, 12), 333_333);
+        assert_eq!(u32_at(trun, 20), SAMPLE_FLAGS_SYNC);
+        let trun = find(fragments[1], &[b"traf", b"trun"]);
+        assert_eq!(u32_at(trun, 12), 333_333);
+        assert_eq!(u32_at(trun, 20), SAMPLE_FLAGS_NON_SYNC);
+        let tfdt = find(fragments[1], &[b"traf", b"tfdt"]);
+        assert_eq!(&tfdt[4..], &333_333u64.to_be_bytes());
+
+        let mdat = boxes(&file)
+            .into_iter()
+            .filter(|(kind, _)| kind == b"mdat")
+            .nth(1)
+            .unwrap()
+            .1;
+        assert_eq!(mdat, &[0, 0, 0, 3, 0x41, 0x9a, 0x02]);
+    }
+
+    #[test]
+    fn data_offset_points_at_mdat_payload() {
+        let mut writer = Mp4Writer::new(Vec::new(), CodecId::H264).unwrap();
+        writer.write_frame(&keyframe(), 0, true).unwrap();
+        let file = writer.finish().unwrap();
+        let moof_start = boxes(&file)[..2]
+            .iter()
+            .map(|(_, body)| body.len() + 8)
+            .sum::<usize>();
+        let trun = find(&file, &[b"moof", b"traf", b"trun"]);
+        let data_offset = u32_at(trun, 8) as usize;
+        let sample = &file[moof_start + data_offset..];
+        assert_eq!(u32_at(sample, 0) as usize, SPS.len());
+        assert_eq!(&sample[4..4 + SPS.len()], &SPS);
+    }
+
+    #[test]
+    fn rejects_streams_without_parameter_sets() {
+        let mut writer = Mp4Writer::new(Vec::new(), CodecId::H264).unwrap();
+        assert!(writer
+            .write_frame(&[0, 0, 0, 1, 0x41, 0x9a], 0, false)
+            .is_err());
+        assert!(Mp4Writer::new(Vec::new(), CodecId::Av1).is_err());
+    }
+}
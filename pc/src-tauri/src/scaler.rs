@@ -0,0 +1,566 @@
+use std::f64::consts::PI;
+use std::thread;
+
+/// Resampling kernel used when the captured frame and the encode size differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFilter {
+    Bilinear,
+    /// Keys cubic with `a = -0.5` (Catmull-Rom).
+    Bicubic,
+    /// Three-lobe Lanczos; sharpest, keeps small text readable when downscaling.
+    Lanczos,
+}
+
+/// How the source aspect ratio is fitted into the encode size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFit {
+    /// Fill the whole frame, distorting the aspect ratio if it differs.
+    Stretch,
+    /// Keep the aspect ratio and pad with black bars.
+    Letterbox,
+    /// Keep the aspect ratio and cut the overhanging edges of the source.
+    Crop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaleMode {
+    pub filter: ScaleFilter,
+    pub fit: ScaleFit,
+}
+
+impl Default for ScaleMode {
+    fn default() -> Self {
+        Self {
+            filter: ScaleFilter::Bicubic,
+            fit: ScaleFit::Stretch,
+        }
+    }
+}
+
+impl ScaleMode {
+    /// Unknown names fall back to bicubic and stretch.
+    pub fn from_settings(filter: &str, fit: &str) -> Self {
+        let filter = match filter.to_ascii_lowercase().as_str() {
+            "bilinear" => ScaleFilter::Bilinear,
+            "lanczos" => ScaleFilter::Lanczos,
+            _ => ScaleFilter::Bicubic,
+        };
+        let fit = match fit.to_ascii_lowercase().as_str() {
+            "letterbox" => ScaleFit::Letterbox,
+            "crop" => ScaleFit::Crop,
+            _ => ScaleFit::Stretch,
+        };
+        Self { filter, fit }
+    }
+}
+
+impl ScaleFilter {
+    pub fn label(self) -> &'static str {
+        match self {
+            ScaleFilter::Bilinear => "Bilinear",
+            ScaleFilter::Bicubic => "Bicubic",
+            ScaleFilter::Lanczos => "Lanczos",
+        }
+    }
+
+    /// Kernel radius in source pixels at 1:1.
+    fn support(self) -> f64 {
+        match self {
+            ScaleFilter::Bilinear => 1.0,
+            ScaleFilter::Bicubic => 2.0,
+            ScaleFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            ScaleFilter::Bilinear => (1.0 - x).max(0.0),
+            ScaleFilter::Bicubic => {
+                const A: f64 = -0.5;
+                if x < 1.0 {
+                    ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((A * x - 5.0 * A) * x + 8.0 * A) * x - 4.0 * A
+                } else {
+                    0.0
+                }
+            }
+            ScaleFilter::Lanczos => {
+                if x == 0.0 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// A rectangle in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Which part of the source is scaled into which part of the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaleLayout {
+    pub source: Region,
+    pub target: Region,
+}
+
+/// Fits a `src_w`x`src_h` frame into `dst_w`x`dst_h` according to `fit`.
+pub fn layout(src_w: i32, src_h: i32, dst_w: i32, dst_h: i32, fit: ScaleFit) -> ScaleLayout {
+    let full_source = Region {
+        x: 0,
+        y: 0,
+        width: src_w,
+        height: src_h,
+    };
+    let full_target = Region {
+        x: 0,
+        y: 0,
+        width: dst_w,
+        height: dst_h,
+    };
+    let (sw, sh, dw, dh) = (src_w as i64, src_h as i64, dst_w as i64, dst_h as i64);
+    if sw <= 0 || sh <= 0 || dw <= 0 || dh <= 0 || sw * dh == sh * dw {
+        return ScaleLayout {
+            source: full_source,
+            target: full_target,
+        };
+    }
+    let source_wider = sw * dh > sh * dw;
+    match fit {
+        ScaleFit::Stretch => ScaleLayout {
+            source: full_source,
+            target: full_target,
+        },
+        ScaleFit::Letterbox => {
+            let (width, height) = if source_wider {
+                (dw, (sh * dw + sw / 2) / sw)
+            } else {
+                ((sw * dh + sh / 2) / sh, dh)
+            };
+            ScaleLayout {
+                source: full_source,
+                target: Region {
+                    x: ((dw - width) / 2) as i32,
+                    y: ((dh - height) / 2) as i32,
+                    width: width.max(1) as i32,
+                    height: height.max(1) as i32,
+                },
+            }
+        }
+        ScaleFit::Crop => {
+            let (width, height) = if source_wider {
+                ((dw * sh + dh / 2) / dh, sh)
+            } else {
+                (sw, (dh * sw + dw / 2) / dw)
+            };
+            ScaleLayout {
+                source: Region {
+                    x: ((sw - width) / 2) as i32,
+                    y: ((sh - height) / 2) as i32,
+                    width: width.max(1) as i32,
+                    height: height.max(1) as i32,
+                },
+                target: full_target,
+            }
+        }
+    }
+}
+
+const WEIGHT_BITS: u32 = 14;
+const WEIGHT_ONE: i32 = 1 << WEIGHT_BITS;
+const MIN_BAND_ROWS: usize = 32;
+
+/// Q14 filter taps for every output sample along one axis.
+#[derive(Debug, Clone)]
+struct Taps {
+    /// First source index read by each output sample.
+    start: Vec<usize>,
+    /// `taps` weights per output sample, zero padded, each set summing to `WEIGHT_ONE`.
+    weights: Vec<i32>,
+    taps: usize,
+}
+
+impl Taps {
+    fn new(filter: ScaleFilter, offset: usize, src_len: usize, out_len: usize) -> Self {
+        let ratio = src_len as f64 / out_len as f64;
+        // Widen the kernel when downscaling so it also low-pass filters.
+        let stretch = ratio.max(1.0);
+        let support = filter.support() * stretch;
+        let taps = (support.ceil() as usize * 2 + 1).min(src_len);
+        let mut start = Vec::with_capacity(out_len);
+        let mut weights = vec![0i32; out_len * taps];
+        let mut real = vec![0f64; taps];
+        for out in 0..out_len {
+            let center = (out as f64 + 0.5) * ratio;
+            let first = ((center - support).floor().max(0.0) as usize).min(src_len - taps);
+            let mut sum = 0.0;
+            for (tap, value) in real.iter_mut().enumerate() {
+                let distance = (first + tap) as f64 + 0.5 - center;
+                *value = filter.kernel(distance / stretch);
+                sum += *value;
+            }
+            let row = &mut weights[out * taps..(out + 1) * taps];
+            let mut total = 0;
+            for (weight, value) in row.iter_mut().zip(&real) {
+                *weight = (value / sum * WEIGHT_ONE as f64).round() as i32;
+                total += *weight;
+            }
+            // Put the rounding error on the heaviest tap so flat areas stay exact.
+            if let Some(heaviest) = row.iter_mut().max_by_key(|weight| weight.abs()) {
+                *heaviest += WEIGHT_ONE - total;
+            }
+            start.push(offset + first);
+        }
+        Self {
+            start,
+            weights,
+            taps,
+        }
+    }
+
+    fn sample(&self, out: usize) -> (usize, &[i32]) {
+        (
+            self.start[out],
+            &self.weights[out * self.taps..(out + 1) * self.taps],
+        )
+    }
+}
+
+fn round_clamp(acc: i32) -> u8 {
+    ((acc + (WEIGHT_ONE >> 1)) >> WEIGHT_BITS).clamp(0, 255) as u8
+}
+
+/// Separable BGRA resampler for one source/destination size pair. Build once and
+/// reuse for every frame of that size.
+#[derive(Debug, Clone)]
+pub struct Scaler {
+    src_w: usize,
+    src_h: usize,
+    dst_w: usize,
+    dst_h: usize,
+    mode: ScaleMode,
+    layout: ScaleLayout,
+    horizontal: Taps,
+    vertical: Taps,
+}
+
+impl Scaler {
+    pub fn new(src_w: i32, src_h: i32, dst_w: i32, dst_h: i32, mode: ScaleMode) -> Self {
+        let layout = layout(src_w, src_h, dst_w, dst_h, mode.fit);
+        let (source, target) = (layout.source, layout.target);
+        Self {
+            src_w: src_w.max(0) as usize,
+            src_h: src_h.max(0) as usize,
+            dst_w: dst_w.max(0) as usize,
+            dst_h: dst_h.max(0) as usize,
+            mode,
+            layout,
+            horizontal: Taps::new(
+                mode.filter,
+                source.x as usize,
+                source.width.max(1) as usize,
+                target.width.max(1) as usize,
+            ),
+            vertical: Taps::new(
+                mode.filter,
+                source.y as usize,
+                source.height.max(1) as usize,
+                target.height.max(1) as usize,
+            ),
+        }
+    }
+
+    pub fn source_size(&self) -> (i32, i32) {
+        (self.src_w as i32, self.src_h as i32)
+    }
+
+    pub fn layout(&self) -> ScaleLayout {
+        self.layout
+    }
+
+    /// How far, in source pixels, a source change spreads through the filter taps.
+    pub fn reach(&self) -> (i32, i32) {
+        (
+            (self.horizontal.taps / 2) as i32,
+            (self.vertical.taps / 2) as i32,
+        )
+    }
+
+    /// Short description for the session diagnostics, e.g. `Lanczos 3840x2160 -> 1280x800`.
+    pub fn describe(&self) -> String {
+        let fit = match self.mode.fit {
+            ScaleFit::Stretch => "",
+            ScaleFit::Letterbox => " letterbox",
+            ScaleFit::Crop => " crop",
+        };
+        format!(
+            "{} {}x{} -> {}x{}{}",
+            self.mode.filter.label(),
+            self.src_w,
+            self.src_h,
+            self.dst_w,
+            self.dst_h,
+            fit
+        )
+    }
+
+    /// Scales a `src_w`x`src_h` BGRA frame, splitting the work into row bands.
+    pub fn scale(&self, bgra: &[u8]) -> Vec<u8> {
+        let workers = thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1);
+        self.scale_with_workers(bgra, workers)
+    }
+
+    fn scale_with_workers(&self, bgra: &[u8], workers: usize) -> Vec<u8> {
+        let mut output = [0u8, 0, 0, 0xff].repeat(self.dst_w * self.dst_h);
+        if bgra.len() < self.src_w * self.src_h * 4 || self.dst_w == 0 || self.dst_h == 0 {
+            return output;
+        }
+        let (source, target) = (self.layout.source, self.layout.target);
+        let (out_w, out_h) = (target.width as usize, target.height as usize);
+        let in_h = source.height as usize;
+        let src_stride = self.src_w * 4;
+        let dst_stride = self.dst_w * 4;
+
+        // Horizontal pass over the source rows in use, into a packed intermediate.
+        let mut rows = vec![0u8; in_h * out_w * 4];
+        let in_rows =
+            &bgra[source.y as usize * src_stride..(source.y as usize + in_h) * src_stride];
+        let band = in_h.div_ceil(bands(workers, in_h)).max(1);
+        thread::scope(|scope| {
+            for (input, output) in in_rows
+                .chunks(band * src_stride)
+                .zip(rows.chunks_mut(band * out_w * 4))
+            {
+                let taps = &self.horizontal;
+                scope.spawn(move || {
+                    for (src, dst) in input.chunks(src_stride).zip(output.chunks_mut(out_w * 4)) {
+                        resample_row(src, dst, taps);
+                    }
+                });
+            }
+        });
+
+        // Vertical pass, writing straight into the target region of the frame.
+        let target_rows =
+            &mut output[target.y as usize * dst_stride..(target.y as usize + out_h) * dst_stride];
+        let band = out_h.div_ceil(bands(workers, out_h)).max(1);
+        let rows = &rows;
+        let origin = source.y as usize;
+        thread::scope(|scope| {
+            for (index, output) in target_rows.chunks_mut(band * dst_stride).enumerate() {
+                let taps = &self.vertical;
+                scope.spawn(move || {
+                    for (offset, dst) in output.chunks_mut(dst_stride).enumerate() {
+                        let y = index * band + offset;
+                        let dst = &mut dst[target.x as usize * 4..(target.x as usize + out_w) * 4];
+                        resample_column(rows, out_w * 4, y, origin, taps, dst);
+                    }
+                });
+            }
+        });
+        output
+    }
+}
+
+fn bands(workers: usize, rows: usize) -> usize {
+    workers.min(rows / MIN_BAND_ROWS).max(1)
+}
+
+fn resample_row(src: &[u8], dst: &mut [u8], taps: &Taps) {
+    for (out, pixel) in dst.chunks_exact_mut(4).enumerate() {
+        let (first, weights) = taps.sample(out);
+        let mut acc = [0i32; 4];
+        for (tap, weight) in weights.iter().enumerate() {
+            let index = (first + tap) * 4;
+            for (channel, value) in acc.iter_mut().enumerate() {
+                *value += src[index + channel] as i32 * weight;
+            }
+        }
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            *value = round_clamp(acc[channel]);
+        }
+    }
+}
+
+fn resample_column(
+    rows: &[u8],
+    stride: usize,
+    y: usize,
+    origin: usize,
+    taps: &Taps,
+    dst: &mut [u8],
+) {
+    let (first, weights) = taps.sample(y);
+    let first = first - origin;
+    for (byte, value) in dst.iter_mut().enumerate() {
+        let mut acc = 0i32;
+        for (tap, weight) in weights.iter().enumerate() {
+            acc += rows[(first + tap) * stride + byte] as i32 * weight;
+        }
+        *value = round_clamp(acc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::test_pattern;
+
+    const FILTERS: [ScaleFilter; 3] = [
+        ScaleFilter::Bilinear,
+        ScaleFilter::Bicubic,
+        ScaleFilter::Lanczos,
+    ];
+
+    fn grey(values: &[u8]) -> Vec<u8> {
+        values.iter().flat_map(|&v| [v, v, v, 0xff]).collect()
+    }
+
+    fn stretch(filter: ScaleFilter) -> ScaleMode {
+        ScaleMode {
+            filter,
+            fit: ScaleFit::Stretch,
+        }
+    }
+
+    /// FNV-1a, stable across toolchains unlike `DefaultHasher`.
+    fn fingerprint(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    #[test]
+    fn keeps_same_size_frames_and_flat_areas_exact() {
+        let frame = test_pattern::render(64, 48, 3);
+        for filter in FILTERS {
+            assert_eq!(
+                Scaler::new(64, 48, 64, 48, stretch(filter)).scale(&frame),
+                frame
+            );
+            let flat = grey(&[77; 50 * 30]);
+            let scaled = Scaler::new(50, 30, 21, 13, stretch(filter)).scale(&flat);
+            assert_eq!(scaled, grey(&[77; 21 * 13]));
+        }
+    }
+
+    #[test]
+    fn matches_golden_bilinear_samples() {
+        let up = Scaler::new(2, 1, 4, 1, stretch(ScaleFilter::Bilinear)).scale(&grey(&[0, 255]));
+        assert_eq!(up, grey(&[0, 64, 191, 255]));
+        let down = Scaler::new(4, 1, 2, 1, stretch(ScaleFilter::Bilinear))
+            .scale(&grey(&[0, 100, 200, 255]));
+        assert_eq!(down, grey(&[71, 209]));
+    }
+
+    #[test]
+    fn matches_golden_images() {
+        // Test pattern downscaled 320x240 -> 200x150 and upscaled 320x240 -> 400x300.
+        // Regenerate these only for an intentional change in filter output.
+        let frame = test_pattern::render(320, 240, 42);
+        let golden: [(ScaleFilter, u64, u64); 3] = [
+            (
+                ScaleFilter::Bilinear,
+                0x2840_a0e9_22aa_d0c3,
+                0x1ba6_2908_5c05_cab7,
+            ),
+            (
+                ScaleFilter::Bicubic,
+                0x00f6_9a96_28a6_d7ea,
+                0xb645_803e_5280_36ce,
+            ),
+            (
+                ScaleFilter::Lanczos,
+                0x0599_5fbf_f665_238f,
+                0x8b39_1028_97eb_dd34,
+            ),
+        ];
+        for (filter, down, up) in golden {
+            let small = Scaler::new(320, 240, 200, 150, stretch(filter)).scale(&frame);
+            let large = Scaler::new(320, 240, 400, 300, stretch(filter)).scale(&frame);
+            assert_eq!(fingerprint(&small), down, "{filter:?} downscale");
+            assert_eq!(fingerprint(&large), up, "{filter:?} upscale");
+        }
+    }
+
+    #[test]
+    fn parallel_bands_match_single_thread() {
+        let frame = test_pattern::render(320, 240, 5);
+        for filter in FILTERS {
+            for fit in [ScaleFit::Stretch, ScaleFit::Letterbox, ScaleFit::Crop] {
+                let scaler = Scaler::new(320, 240, 250, 200, ScaleMode { filter, fit });
+                assert_eq!(
+                    scaler.scale_with_workers(&frame, 1),
+                    scaler.scale_with_workers(&frame, 7)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fits_aspect_ratio_with_letterbox_or_crop() {
+        let letterbox = layout(1920, 1080, 1280, 1024, ScaleFit::Letterbox);
+        assert_eq!(
+            letterbox.target,
+            Region {
+                x: 0,
+                y: 152,
+                width: 1280,
+                height: 720
+            }
+        );
+        let crop = layout(1920, 1080, 1280, 1024, ScaleFit::Crop);
+        assert_eq!(
+            crop.source,
+            Region {
+                x: 285,
+                y: 0,
+                width: 1350,
+                height: 1080
+            }
+        );
+        let pillarbox = layout(1080, 1920, 1000, 1000, ScaleFit::Letterbox);
+        assert_eq!(pillarbox.target.x, 218);
+        assert_eq!(pillarbox.target.width, 563);
+
+        let scaled = Scaler::new(
+            16,
+            8,
+            16,
+            16,
+            ScaleMode {
+                filter: ScaleFilter::Bilinear,
+                fit: ScaleFit::Letterbox,
+            },
+        )
+        .scale(&grey(&[200; 16 * 8]));
+        assert_eq!(&scaled[..4], &[0, 0, 0, 0xff]);
+        assert_eq!(&scaled[8 * 16 * 4..8 * 16 * 4 + 4], &[200, 200, 200, 0xff]);
+    }
+
+    #[test]
+    fn parses_settings_names() {
+        assert_eq!(
+            ScaleMode::from_settings("Lanczos", "Letterbox"),
+            ScaleMode {
+                filter: ScaleFilter::Lanczos,
+                fit: ScaleFit::Letterbox
+            }
+        );
+        assert_eq!(ScaleMode::from_settings("", "other"), ScaleMode::default());
+    }
+}
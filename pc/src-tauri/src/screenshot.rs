@@ -0,0 +1,175 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::capture::{self, CaptureTarget, CapturedFrame};
+use crate::session_state;
+
+/// Test-pattern size when no session has been configured yet; desktop sources
+/// always capture at native size.
+const DEFAULT_SIZE: (i32, i32) = (1280, 720);
+
+/// A freshly opened duplication may time out before the first desktop frame.
+const CAPTURE_ATTEMPTS: u32 = 10;
+const CAPTURE_TIMEOUT: Duration = Duration::from_millis(100);
+
+static APP_HANDLE: OnceLock<tauri::AppHandle> = OnceLock::new();
+
+/// Keeps the app handle for screenshots requested by the client, which arrive on
+/// the transport thread.
+pub fn init(app_handle: &tauri::AppHandle) {
+    let _ = APP_HANDLE.set(app_handle.clone());
+}
+
+/// Captures the session's current target and saves it under the app data dir.
+pub fn take_screenshot(app_handle: &tauri::AppHandle) -> Result<String, String> {
+    save_current_target(&screenshots_dir(app_handle))
+}
+
+/// Takes a screenshot on behalf of the client and records the outcome in the
+/// host log.
+pub fn take_client_requested() {
+    let Some(app_handle) = APP_HANDLE.get() else {
+        return;
+    };
+    let message = match take_screenshot(app_handle) {
+        Ok(path) => format!("Client requested screenshot saved to {path}"),
+        Err(err) => format!("Client requested screenshot failed: {err}"),
+    };
+    let _ = crate::host_log::append_log(app_handle, message);
+}
+
+fn save_current_target(dir: &Path) -> Result<String, String> {
+    let target = session_state::snapshot().capture_target;
+    let (width, height) = session_state::config_snapshot()
+        .map(|config| (config.width, config.height))
+        .unwrap_or(DEFAULT_SIZE);
+    let frame = capture_frame(&target, width, height)?;
+    let path = save_png(dir, &frame, now_millis())?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Opens a private source for `target` and returns its first frame, leaving any
+/// running stream's capture untouched.
+pub fn capture_frame(
+    target: &CaptureTarget,
+    width: i32,
+    height: i32,
+) -> Result<CapturedFrame, String> {
+    let mut source = capture::open_source(target, width, height)?;
+    let mut last_error = "No frame captured".to_string();
+    for _ in 0..CAPTURE_ATTEMPTS {
+        match source.acquire(CAPTURE_TIMEOUT) {
+            Ok(frame) if !frame.bgra.is_empty() => return Ok(frame),
+            Ok(_) => {}
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+/// Writes `frame` as `screenshot-<unix ms>.png` in `dir`.
+pub fn save_png(dir: &Path, frame: &CapturedFrame, timestamp: u64) -> Result<PathBuf, String> {
+    let png = encode_png(&frame.bgra, frame.width, frame.height)?;
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    let path = dir.join(format!("screenshot-{timestamp}.png"));
+    fs::write(&path, png).map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+/// Encodes a tightly packed BGRA frame as an 8-bit RGB PNG. Alpha is dropped:
+/// desktop captures leave it undefined.
+pub fn encode_png(bgra: &[u8], width: i32, height: i32) -> Result<Vec<u8>, String> {
+    if width <= 0 || height <= 0 {
+        return Err(format!("Invalid screenshot size {width}x{height}"));
+    }
+    let pixels = width as usize * height as usize;
+    if bgra.len() < pixels * 4 {
+        return Err(format!(
+            "Screenshot buffer holds {} bytes, {width}x{height} needs {}",
+            bgra.len(),
+            pixels * 4
+        ));
+    }
+    let rgb: Vec<u8> = bgra[..pixels * 4]
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+        .collect();
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer
+        .write_image_data(&rgb)
+        .map_err(|err| err.to_string())?;
+    writer.finish().map_err(|err| err.to_string())?;
+    Ok(output)
+}
+
+fn screenshots_dir(app_handle: &tauri::AppHandle) -> PathBuf {
+    app_handle
+        .path_resolver()
+        .app_data_dir()
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+        .join("screenshots")
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::test_pattern;
+    use crate::capture::TEST_PATTERN_TARGET;
+
+    fn decode(bytes: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut reader = png::Decoder::new(bytes).read_info().unwrap();
+        let mut pixels = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        pixels.truncate(info.buffer_size());
+        (info, pixels)
+    }
+
+    #[test]
+    fn encodes_test_pattern_frames_as_rgb_png() {
+        let target = CaptureTarget::display(Some(TEST_PATTERN_TARGET.to_string()));
+        let frame = capture_frame(&target, 96, 54).unwrap();
+        let png = encode_png(&frame.bgra, frame.width, frame.height).unwrap();
+
+        let (info, rgb) = decode(&png);
+        assert_eq!((info.width, info.height), (96, 54));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        let expected: Vec<u8> = test_pattern::render(96, 54, 0)
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+            .collect();
+        assert_eq!(rgb, expected);
+    }
+
+    #[test]
+    fn saves_timestamped_png() {
+        let dir = std::env::temp_dir().join(format!("uberdisplay-screenshot-{}", now_millis()));
+        let target = CaptureTarget::display(Some(TEST_PATTERN_TARGET.to_string()));
+        let frame = capture_frame(&target, 32, 16).unwrap();
+        let path = save_png(&dir, &frame, 1234).unwrap();
+
+        assert_eq!(path, dir.join("screenshot-1234.png"));
+        let (info, _) = decode(&fs::read(&path).unwrap());
+        assert_eq!((info.width, info.height), (32, 16));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        assert!(encode_png(&[0; 15], 2, 2).is_err());
+        assert!(encode_png(&[], 0, 2).is_err());
+    }
+}
@@ -1,8 +1,12 @@
+#[cfg(windows)]
 use std::path::PathBuf;
+#[cfg(windows)]
 use std::process::Command;
 
+#[cfg(windows)]
 const SERVICE_NAME: &str = "UberDisplayVddService";
 
+#[cfg(windows)]
 pub fn install_service() -> Result<(), String> {
     let exe_path = service_binary_path()?;
     let bin_arg = format!("binPath= {}", exe_path.display());
@@ -18,14 +22,17 @@ pub fn install_service() -> Result<(), String> {
     }
 }
 
+#[cfg(windows)]
 pub fn start_service() -> Result<(), String> {
     run_sc(&["start", SERVICE_NAME])
 }
 
+#[cfg(windows)]
 pub fn stop_service() -> Result<(), String> {
     run_sc(&["stop", SERVICE_NAME])
 }
 
+#[cfg(windows)]
 pub fn query_service() -> Result<String, String> {
     let output = Command::new("sc.exe")
         .args(["query", SERVICE_NAME])
@@ -40,6 +47,7 @@ pub fn query_service() -> Result<String, String> {
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
+#[cfg(windows)]
 fn run_sc(args: &[&str]) -> Result<(), String> {
     let status = Command::new("sc.exe")
         .args(args)
@@ -52,6 +60,7 @@ fn run_sc(args: &[&str]) -> Result<(), String> {
     }
 }
 
+#[cfg(windows)]
 fn service_binary_path() -> Result<PathBuf, String> {
     let current = std::env::current_exe().map_err(|err| err.to_string())?;
     if let Some(parent) = current.parent() {
@@ -1,6 +1,10 @@
 use crate::app_state::CodecSelection;
-use crate::codec::{self, CodecId};
-use crate::protocol::packets::{build_configure_packet, ConfigurePacket};
+use crate::codec::{self, CodecId, CodecPolicy};
+use crate::codec_profile::{self, CodecParameters, StreamFormat, CODEC_FLAG_LOW_DELAY};
+use crate::color::ColorSpace;
+use crate::encoder;
+use crate::protocol::packets::{build_configure_packet, ConfigurePacket, DecoderLimits};
+use crate::session_state;
 
 #[derive(Debug, Clone)]
 pub struct SessionConfig {
@@ -11,17 +15,38 @@ pub struct SessionConfig {
     pub encoder_id: i32,
     pub client_codec_mask: u32,
     pub preferred_codec: Option<CodecId>,
+    pub codec_opt_in_mask: u32,
+    pub fps: u32,
+    pub client_limits: Option<DecoderLimits>,
+    pub color: ColorSpace,
 }
 
 pub struct SessionPrepareResult {
     pub selection: CodecSelection,
+    /// What the encoder has to stay within; also in `selection` and `Configure`.
+    pub params: CodecParameters,
     pub configure_bytes: Vec<u8>,
 }
 
 pub fn prepare_session(config: SessionConfig) -> Result<SessionPrepareResult, String> {
     let host_mask = codec::host_codec_mask();
-    let selected = codec::select_codec(host_mask, config.client_codec_mask, config.preferred_codec)
+    let decision = CodecPolicy {
+        host_mask,
+        client_mask: config.client_codec_mask,
+        preferred: config.preferred_codec,
+        opt_in_mask: config.codec_opt_in_mask,
+    }
+    .evaluate();
+    let selected = decision
+        .selected
         .ok_or_else(|| "No compatible codec found".to_string())?;
+    let params = codec_parameters(
+        selected,
+        config.width,
+        config.height,
+        config.fps,
+        config.client_limits.as_ref(),
+    )?;
 
     let configure = ConfigurePacket {
         width: config.width,
@@ -30,9 +55,10 @@ pub fn prepare_session(config: SessionConfig) -> Result<SessionPrepareResult, St
         host_height: config.host_height,
         encoder_id: config.encoder_id,
         codec_id: Some(selected as u8),
-        codec_profile: 0,
-        codec_level: 0,
-        codec_flags: 0,
+        codec_profile: params.profile,
+        codec_level: params.level,
+        codec_flags: params.flags,
+        color: Some(config.color.description()),
     };
 
     Ok(SessionPrepareResult {
@@ -41,7 +67,48 @@ pub fn prepare_session(config: SessionConfig) -> Result<SessionPrepareResult, St
             codec_name: codec::codec_name(selected).to_string(),
             host_mask,
             client_mask: config.client_codec_mask,
+            codec_profile: params.profile,
+            codec_level: params.level,
+            codec_flags: params.flags,
+            candidates: decision.candidates,
         },
         configure_bytes: build_configure_packet(configure),
+        params,
     })
 }
+
+/// Profile, level and flags for streaming `codec_id` at this size and rate to a
+/// client with `client_limits`. The host always asks for low delay.
+pub fn codec_parameters(
+    codec_id: CodecId,
+    width: i32,
+    height: i32,
+    fps: u32,
+    client_limits: Option<&DecoderLimits>,
+) -> Result<CodecParameters, String> {
+    codec_profile::negotiate(
+        codec_id,
+        StreamFormat {
+            width: width.max(0) as u32,
+            height: height.max(0) as u32,
+            fps,
+            flags: CODEC_FLAG_LOW_DELAY,
+        },
+        client_limits,
+    )
+}
+
+/// Records a prepared session as the one being streamed: codec, size and the
+/// encoder backend for that codec.
+pub fn activate(result: &SessionPrepareResult, width: i32, height: i32, encoder_id: i32) {
+    let codec_id = codec::codec_id_from_name(&result.selection.codec_name);
+    if let Some(codec_id) = codec_id {
+        session_state::update_codec(codec_id, result.params);
+    }
+    session_state::update_config(width, height, encoder_id);
+    let backend = match codec_id {
+        Some(codec_id) => encoder::select_backend_for_codec(codec_id, None),
+        None => encoder::select_backend(None),
+    };
+    session_state::update_backend(backend);
+}
@@ -1,7 +1,9 @@
 use std::sync::{Mutex, OnceLock};
 
 use crate::app_state::{SessionLifecycle, SessionStats};
+use crate::capture::CaptureTarget;
 use crate::codec::CodecId;
+use crate::codec_profile::CodecParameters;
 use crate::encoder::EncoderBackend;
 
 #[derive(Debug, Clone)]
@@ -14,9 +16,11 @@ pub struct SessionConfigSnapshot {
 #[derive(Debug, Clone)]
 pub struct SessionState {
     pub codec_id: Option<CodecId>,
+    /// Profile, level and flags negotiated with the client for `codec_id`.
+    pub codec_params: CodecParameters,
     pub encoder_backend: Option<EncoderBackend>,
     pub active_device_id: Option<String>,
-    pub display_target_id: Option<String>,
+    pub capture_target: CaptureTarget,
     pub input_permissions: crate::app_state::InputPermissions,
     pub stats: SessionStats,
     pub lifecycle: SessionLifecycle,
@@ -26,21 +30,25 @@ pub struct SessionState {
 static SESSION_STATE: OnceLock<Mutex<SessionState>> = OnceLock::new();
 
 fn state_store() -> &'static Mutex<SessionState> {
-    SESSION_STATE.get_or_init(|| Mutex::new(SessionState {
-        codec_id: None,
-        encoder_backend: None,
-        active_device_id: None,
-        display_target_id: None,
-        input_permissions: crate::app_state::InputPermissions::default(),
-        stats: SessionStats::default(),
-        lifecycle: SessionLifecycle::Idle,
-        config: None,
-    }))
-}
-
-pub fn update_codec(codec_id: CodecId) {
+    SESSION_STATE.get_or_init(|| {
+        Mutex::new(SessionState {
+            codec_id: None,
+            codec_params: CodecParameters::default(),
+            encoder_backend: None,
+            active_device_id: None,
+            capture_target: CaptureTarget::default(),
+            input_permissions: crate::app_state::InputPermissions::default(),
+            stats: SessionStats::default(),
+            lifecycle: SessionLifecycle::Idle,
+            config: None,
+        })
+    })
+}
+
+pub fn update_codec(codec_id: CodecId, params: CodecParameters) {
     if let Ok(mut state) = state_store().lock() {
         state.codec_id = Some(codec_id);
+        state.codec_params = params;
     }
 }
 
@@ -57,9 +65,9 @@ pub fn update_active_device(device_id: Option<String>, permissions: crate::app_s
     }
 }
 
-pub fn update_display_target(display_id: Option<String>) {
+pub fn update_capture_target(target: CaptureTarget) {
     if let Ok(mut state) = state_store().lock() {
-        state.display_target_id = display_id;
+        state.capture_target = target;
     }
 }
 
@@ -124,9 +132,10 @@ pub fn snapshot() -> SessionState {
         .map(|state| state.clone())
         .unwrap_or(SessionState {
             codec_id: None,
+            codec_params: CodecParameters::default(),
             encoder_backend: None,
             active_device_id: None,
-            display_target_id: None,
+            capture_target: CaptureTarget::default(),
             input_permissions: crate::app_state::InputPermissions::default(),
             stats: SessionStats::default(),
             lifecycle: SessionLifecycle::Idle,
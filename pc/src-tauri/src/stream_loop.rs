@@ -1,49 +1,47 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::OnceLock;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::codec::CodecId;
 use crate::app_state::SessionStats;
-use crate::host_transport;
-use crate::mf_encoder::MfEncoder;
-use crate::protocol::packets::{build_frame_packet, FramePacket};
+use crate::bitstream::{self, NalCounts};
+use crate::codec::CodecId;
+use crate::encoder::{self, EncoderBackend, EncoderConfig};
+use crate::host_transport::TransportHandle;
+use crate::protocol::packets::{
+    build_frame_packet, FramePacket, FRAME_META_KEYFRAME, FRAME_META_PARAMETER_SETS,
+};
+use crate::quic_transport;
+use crate::recorder;
 use crate::session_state;
+use crate::udp_transport;
 
-static STREAM_RUNNING: OnceLock<AtomicBool> = OnceLock::new();
-
-fn running_flag() -> &'static AtomicBool {
-    STREAM_RUNNING.get_or_init(|| AtomicBool::new(false))
-}
+/// Datagram congestion control retargets the encoder at most this often...
+const BITRATE_RETARGET_INTERVAL: Duration = Duration::from_secs(1);
+/// ...and only for changes larger than this share of the current bitrate.
+const BITRATE_RETARGET_MIN_CHANGE: f32 = 0.10;
 
 pub fn start_streaming(
-    codec_id: CodecId,
+    transport: TransportHandle,
+    backend: EncoderBackend,
     encoder_id: i32,
-    width: i32,
-    height: i32,
-    bitrate_kbps: u32,
-    fps: u32,
-    keyframe_interval: u32,
-    display_target_id: Option<String>,
+    config: EncoderConfig,
 ) -> Result<(), String> {
-    if running_flag().swap(true, Ordering::SeqCst) {
+    if !transport.begin_streaming() {
         return Ok(());
     }
 
     thread::spawn(move || {
-        let mut encoder =
-            match MfEncoder::new(
-                codec_id,
-                width,
-                height,
-                bitrate_kbps,
-                fps,
-                keyframe_interval,
-                display_target_id,
-            ) {
-                Ok(encoder) => encoder,
-                Err(_) => return,
-            };
+        let (codec_id, fps, bitrate_kbps) = (config.codec_id, config.fps, config.bitrate_kbps);
+        let mut encoder = match encoder::create_encoder(backend, config) {
+            Ok(encoder) => encoder,
+            Err(_) => {
+                transport.stop_streaming();
+                session_state::update_lifecycle(crate::app_state::SessionLifecycle::Error);
+                return;
+            }
+        };
+        udp_transport::set_max_bitrate(bitrate_kbps);
+        let mut current_bitrate_kbps = bitrate_kbps;
+        let mut last_retarget = Instant::now();
         let mut awaiting_ack = false;
         let mut last_send = Instant::now();
         let mut last_timestamp: Option<u64> = None;
@@ -53,11 +51,20 @@ pub fn start_streaming(
         let mut frames_sent = 0u64;
         let mut frames_acked = 0u64;
         let mut mf_failures = 0u32;
+        let mut nal_counts = NalCounts::default();
+        let mut encoded_format = None;
+        let mut skipped_frames = 0u64;
+        // Clients can only start on an IDR carrying parameter sets; hold frames until
+        // one arrives, and ask for it now rather than at the next scheduled IDR.
+        let mut decodable_start = !matches!(codec_id, CodecId::H264 | CodecId::H265);
+        if !decodable_start {
+            encoder.request_keyframe();
+        }
         let max_wait_ms = (1000 / fps.max(1)).saturating_mul(2).max(8) as u64;
-        while running_flag().load(Ordering::SeqCst) {
+        while transport.is_streaming() {
             if awaiting_ack {
                 let mut ack_received = false;
-                if let Some(done) = host_transport::take_last_frame_done() {
+                if let Some(done) = transport.take_last_frame_done() {
                     if done == encoder_id {
                         ack_received = true;
                     }
@@ -71,6 +78,23 @@ pub fn start_streaming(
                 }
             }
 
+            if transport.take_keyframe_request()
+                | udp_transport::take_keyframe_request()
+                | quic_transport::take_keyframe_request()
+            {
+                encoder.request_keyframe();
+            }
+            if last_retarget.elapsed() >= BITRATE_RETARGET_INTERVAL {
+                if let Some(target) = udp_transport::target_bitrate_kbps() {
+                    let change = target.abs_diff(current_bitrate_kbps) as f32;
+                    if change > current_bitrate_kbps as f32 * BITRATE_RETARGET_MIN_CHANGE {
+                        encoder.set_bitrate(target);
+                        current_bitrate_kbps = target;
+                        last_retarget = Instant::now();
+                    }
+                }
+            }
+
             let (payload, timestamp_100ns) = encoder.encode_frame();
             if let Some(err) = encoder.take_last_error() {
                 mf_failures = mf_failures.saturating_add(1);
@@ -83,28 +107,52 @@ pub fn start_streaming(
                 session_state::update_lifecycle(crate::app_state::SessionLifecycle::Streaming);
             }
             let payload_len = payload.len() as u32;
-            let packet = build_frame_packet(FramePacket {
-                frame_meta: 0,
-                timestamp_100ns,
-                h264_bytes: &payload,
-            });
-            let _ = host_transport::send_framed_packet(&packet);
-            frames_sent = frames_sent.saturating_add(1);
-            window_frames = window_frames.saturating_add(1);
-            window_bytes = window_bytes.saturating_add(payload.len() as u64);
-            awaiting_ack = true;
-            last_send = Instant::now();
+            let inspection = bitstream::inspect(codec_id, &payload);
+            nal_counts.add(&inspection.counts);
+            if inspection.sps.is_some() {
+                encoded_format = inspection.sps;
+            }
+            if !decodable_start && inspection.is_decodable_start(codec_id) {
+                decodable_start = true;
+            }
+            if payload.is_empty() {
+                // Nothing changed (or the encoder buffered the frame); nothing to send.
+            } else if decodable_start {
+                let mut frame_meta = 0;
+                if inspection.is_keyframe() {
+                    frame_meta |= FRAME_META_KEYFRAME;
+                }
+                if inspection.has_parameter_sets(codec_id) {
+                    frame_meta |= FRAME_META_PARAMETER_SETS;
+                }
+                let packet = build_frame_packet(FramePacket {
+                    frame_meta,
+                    timestamp_100ns,
+                    h264_bytes: &payload,
+                });
+                let _ = transport.send_frame_packet(&packet);
+                recorder::tap(codec_id, &payload, timestamp_100ns, &inspection);
+                frames_sent = frames_sent.saturating_add(1);
+                window_frames = window_frames.saturating_add(1);
+                window_bytes = window_bytes.saturating_add(payload.len() as u64);
+                awaiting_ack = true;
+                last_send = Instant::now();
+            } else {
+                skipped_frames = skipped_frames.saturating_add(1);
+            }
 
             if last_stats_at.elapsed() >= Duration::from_millis(1000) {
                 let elapsed = last_stats_at.elapsed().as_secs_f32().max(0.001);
                 let fps_estimate = window_frames as f32 / elapsed;
                 let bitrate_kbps =
                     ((window_bytes as f32 * 8.0) / 1000.0 / elapsed).round() as u32;
-                let (dxgi_timeouts, dxgi_access_lost, dxgi_failures, dxgi_last_bytes) =
-                    crate::capture::dxgi_stats_snapshot().unwrap_or((0, 0, 0, 0));
-                let (capture_path, capture_scale) =
-                    crate::capture::capture_info_snapshot()
-                        .unwrap_or(("Unknown".to_string(), "Unknown".to_string()));
+                let capture_stats = crate::capture::stats_snapshot().unwrap_or_else(|| {
+                    crate::capture::CaptureStats {
+                        capture_path: "Unknown".to_string(),
+                        capture_scale: "Unknown".to_string(),
+                        ..Default::default()
+                    }
+                });
                 session_state::update_stats(SessionStats {
                     fps: (fps_estimate * 10.0).round() / 10.0,
                     bitrate_kbps,
@@ -112,12 +160,20 @@ pub fn start_streaming(
                     frames_acked,
                     last_frame_bytes: payload_len,
                     queue_depth: if awaiting_ack { 1 } else { 0 },
-                    dxgi_timeouts,
-                    dxgi_access_lost,
-                    dxgi_failures,
-                    dxgi_last_bytes,
-                    capture_path,
-                    capture_scale,
+                    dxgi_timeouts: capture_stats.timeouts,
+                    dxgi_access_lost: capture_stats.access_lost,
+                    dxgi_failures: capture_stats.failures,
+                    dxgi_last_bytes: capture_stats.last_frame_bytes,
+                    capture_path: capture_stats.capture_path,
+                    capture_scale: capture_stats.capture_scale,
+                    changed_area_percent: capture_stats.changed_area_percent,
+                    unchanged_frames: capture_stats.unchanged_frames,
+                    nal_counts,
+                    encoded_width: encoded_format.map(|sps| sps.width).unwrap_or(0),
+                    encoded_height: encoded_format.map(|sps| sps.height).unwrap_or(0),
+                    encoded_profile: encoded_format.map(|sps| sps.profile).unwrap_or(0),
+                    encoded_level: encoded_format.map(|sps| sps.level).unwrap_or(0),
+                    undecodable_frames_skipped: skipped_frames,
                 });
                 window_bytes = 0;
                 window_frames = 0;
@@ -142,11 +198,8 @@ pub fn start_streaming(
         }
 
         session_state::reset_stats();
+        let _ = recorder::stop(Some("Stream ended".to_string()));
     });
 
     Ok(())
 }
-
-pub fn stop_streaming() {
-    running_flag().store(false, Ordering::SeqCst);
-}
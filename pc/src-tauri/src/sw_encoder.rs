@@ -0,0 +1,325 @@
+use openh264::encoder::{
+    BitRate, Encoder, EncoderConfig as OpenH264Config, FrameRate, FrameType, Level, Profile,
+    RateControlMode, UsageType,
+};
+use openh264::formats::YUVBuffer;
+use openh264::OpenH264API;
+use openh264_sys2::{SBitrateInfo, ENCODER_OPTION_BITRATE, SPATIAL_LAYER_ALL};
+
+use crate::bitstream::{self, NalKind};
+use crate::capture::CaptureHandle;
+use crate::codec::CodecId;
+use crate::codec_profile::{CodecParameters, CodecProfile};
+use crate::color::{ColorRange, ColorSpace, PixelFormat};
+use crate::encoder::{estimate_timestamp_100ns, EncoderConfig, VideoEncoder};
+
+pub struct SoftwareEncoder {
+    pub width: i32,
+    pub height: i32,
+    pub fps: u32,
+    pub keyframe_interval: u32,
+    capture: CaptureHandle,
+    color: ColorSpace,
+    encoder: Encoder,
+    bitrate_kbps: u32,
+    frame_index: u64,
+    keyframe_requested: bool,
+    parameter_sets: Vec<u8>,
+    last_error: Option<String>,
+}
+
+impl SoftwareEncoder {
+    pub fn new(config: EncoderConfig) -> Result<Self, String> {
+        if config.codec_id != CodecId::H264 {
+            return Err("Software encoder supports H.264 only".to_string());
+        }
+        let aligned_width = (config.width.max(2)) & !1;
+        let aligned_height = (config.height.max(2)) & !1;
+        let encoder = open_encoder(config.bitrate_kbps, config.fps, config.params)?;
+        Ok(Self {
+            width: aligned_width,
+            height: aligned_height,
+            fps: config.fps,
+            keyframe_interval: config.keyframe_interval,
+            capture: CaptureHandle::new(
+                config.capture_target,
+                aligned_width,
+                aligned_height,
+                config.color,
+                config.scale,
+            ),
+            color: config.color,
+            encoder,
+            bitrate_kbps: config.bitrate_kbps,
+            frame_index: 0,
+            keyframe_requested: false,
+            parameter_sets: Vec::new(),
+            last_error: None,
+        })
+    }
+
+    /// The first frame and every `keyframe_interval`th one are IDRs.
+    fn keyframe_due(&self) -> bool {
+        self.frame_index == 0
+            || (self.keyframe_interval > 0
+                && self
+                    .frame_index
+                    .is_multiple_of(self.keyframe_interval as u64))
+    }
+
+    fn capture_i420(&mut self) -> Vec<u8> {
+        match self.capture.acquire(PixelFormat::I420) {
+            Ok(i420) => i420,
+            Err(err) => {
+                self.last_error = Some(err);
+                black_i420(
+                    self.width as usize,
+                    self.height as usize,
+                    self.color.range == ColorRange::Full,
+                )
+            }
+        }
+    }
+}
+
+impl VideoEncoder for SoftwareEncoder {
+    fn encode_frame(&mut self) -> (Vec<u8>, Option<u64>) {
+        let i420 = self.capture_i420();
+        if self.capture.unchanged() {
+            // A keyframe scheduled for the skipped frame goes out with the next
+            // encoded one instead of waiting a whole interval.
+            self.keyframe_requested |= self.keyframe_due();
+            // Keep timestamps on the wall clock across the skipped frame.
+            self.frame_index = self.frame_index.wrapping_add(1);
+            return (Vec::new(), None);
+        }
+        if std::mem::take(&mut self.keyframe_requested) || self.keyframe_due() {
+            self.encoder.force_intra_frame();
+        }
+        let timestamp = estimate_timestamp_100ns(self.frame_index, self.fps);
+        self.frame_index = self.frame_index.wrapping_add(1);
+
+        let yuv = YUVBuffer::from_vec(i420, self.width as usize, self.height as usize);
+        let (payload, frame_type) = match self.encoder.encode(&yuv) {
+            Ok(bitstream) => (bitstream.to_vec(), bitstream.frame_type()),
+            Err(err) => {
+                self.last_error = Some(format!("OpenH264 encode failed: {err}"));
+                return (Vec::new(), Some(timestamp));
+            }
+        };
+        if frame_type == FrameType::Skip || payload.is_empty() {
+            return (Vec::new(), Some(timestamp));
+        }
+
+        let sets = extract_parameter_sets(&payload);
+        if !sets.is_empty() {
+            self.parameter_sets = sets;
+        }
+        (
+            ensure_parameter_sets(payload, &self.parameter_sets),
+            Some(timestamp),
+        )
+    }
+
+    fn take_last_error(&mut self) -> Option<String> {
+        self.last_error.take()
+    }
+
+    fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    /// Retargets rate control in place; the stream carries on without an IDR.
+    fn set_bitrate(&mut self, bitrate_kbps: u32) {
+        if bitrate_kbps == self.bitrate_kbps {
+            return;
+        }
+        let mut info = SBitrateInfo {
+            iLayer: SPATIAL_LAYER_ALL,
+            iBitrate: bitrate_bps(bitrate_kbps) as i32,
+        };
+        // SAFETY: the encoder copies the bitrate out of `info` during the call.
+        let result = unsafe {
+            self.encoder
+                .raw_api()
+                .set_option(ENCODER_OPTION_BITRATE, std::ptr::addr_of_mut!(info).cast())
+        };
+        if result == 0 {
+            self.bitrate_kbps = bitrate_kbps;
+        } else {
+            self.last_error = Some(format!("OpenH264 bitrate change failed ({result})"));
+        }
+    }
+}
+
+fn open_encoder(bitrate_kbps: u32, fps: u32, params: CodecParameters) -> Result<Encoder, String> {
+    let mut config = OpenH264Config::new()
+        .bitrate(BitRate::from_bps(bitrate_bps(bitrate_kbps)))
+        .max_frame_rate(FrameRate::from_hz(fps.max(1) as f32))
+        .usage_type(UsageType::ScreenContentRealTime)
+        .rate_control_mode(RateControlMode::Bitrate)
+        .skip_frames(false);
+    if let Some(profile) = h264_profile(params.profile) {
+        config = config.profile(profile);
+    }
+    if let Some(level) = h264_level(params.level) {
+        config = config.level(level);
+    }
+    // OpenH264 does not expose the VUI colour fields; clients take the colour
+    // description from `Configure` instead. It never emits B-frames, so the
+    // low-delay flag needs nothing here.
+    Encoder::with_api_config(OpenH264API::from_source(), config)
+        .map_err(|err| format!("OpenH264 init failed: {err}"))
+}
+
+fn bitrate_bps(bitrate_kbps: u32) -> u32 {
+    bitrate_kbps.max(100).saturating_mul(1000)
+}
+
+fn h264_profile(profile_idc: u8) -> Option<Profile> {
+    match profile_idc {
+        idc if idc == CodecProfile::H264Baseline as u8 => Some(Profile::Baseline),
+        idc if idc == CodecProfile::H264Main as u8 => Some(Profile::Main),
+        idc if idc == CodecProfile::H264High as u8 => Some(Profile::High),
+        _ => None,
+    }
+}
+
+/// OpenH264 levels by `level_idc`; it stops at 5.2.
+const H264_LEVELS: [(u8, Level); 16] = [
+    (10, Level::Level_1_0),
+    (11, Level::Level_1_1),
+    (12, Level::Level_1_2),
+    (13, Level::Level_1_3),
+    (20, Level::Level_2_0),
+    (21, Level::Level_2_1),
+    (22, Level::Level_2_2),
+    (30, Level::Level_3_0),
+    (31, Level::Level_3_1),
+    (32, Level::Level_3_2),
+    (40, Level::Level_4_0),
+    (41, Level::Level_4_1),
+    (42, Level::Level_4_2),
+    (50, Level::Level_5_0),
+    (51, Level::Level_5_1),
+    (52, Level::Level_5_2),
+];
+
+/// The highest OpenH264 level within the negotiated one; a stream signalled
+/// lower than the client's cap still decodes.
+fn h264_level(level_idc: u8) -> Option<Level> {
+    H264_LEVELS
+        .iter()
+        .rev()
+        .find(|(idc, _)| *idc <= level_idc)
+        .map(|(_, level)| *level)
+}
+
+fn black_i420(width: usize, height: usize, full_range: bool) -> Vec<u8> {
+    let luma_len = width * height;
+    let mut output = vec![128u8; luma_len + luma_len / 2];
+    output[..luma_len].fill(if full_range { 0 } else { 16 });
+    output
+}
+
+fn extract_parameter_sets(data: &[u8]) -> Vec<u8> {
+    let mut sets = Vec::new();
+    for unit in bitstream::nal_units(CodecId::H264, data) {
+        if matches!(unit.kind, NalKind::Sps | NalKind::Pps) {
+            sets.extend_from_slice(unit.raw);
+        }
+    }
+    sets
+}
+
+/// Prepends the cached SPS/PPS to IDR access units that were emitted without them,
+/// so a client can join (or recover) on any keyframe.
+fn ensure_parameter_sets(payload: Vec<u8>, parameter_sets: &[u8]) -> Vec<u8> {
+    let inspection = bitstream::inspect(CodecId::H264, &payload);
+    if !inspection.is_keyframe() || inspection.counts.sps > 0 || parameter_sets.is_empty() {
+        return payload;
+    }
+    let mut output = Vec::with_capacity(parameter_sets.len() + payload.len());
+    output.extend_from_slice(parameter_sets);
+    output.extend_from_slice(&payload);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{CaptureTarget, TEST_PATTERN_TARGET};
+    use crate::scaler::ScaleMode;
+
+    #[test]
+    fn prepends_parameter_sets_to_bare_idr() {
+        let sets = [0u8, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68, 0xce];
+        let idr = vec![0u8, 0, 0, 1, 0x65, 0x88, 0x84];
+        let output = ensure_parameter_sets(idr.clone(), &sets);
+        assert_eq!(&output[..sets.len()], &sets);
+        assert_eq!(&output[sets.len()..], idr.as_slice());
+
+        let delta = vec![0u8, 0, 0, 1, 0x41, 0x9a];
+        assert_eq!(ensure_parameter_sets(delta.clone(), &sets), delta);
+    }
+
+    #[test]
+    fn extracts_only_parameter_sets() {
+        let stream = [
+            0u8, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x68, 0xce, 0, 0, 1, 0x65, 0x88,
+        ];
+        assert_eq!(
+            extract_parameter_sets(&stream),
+            vec![0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x68, 0xce]
+        );
+    }
+
+    #[test]
+    fn caps_levels_at_the_negotiated_one() {
+        assert!(matches!(h264_level(42), Some(Level::Level_4_2)));
+        assert!(matches!(h264_level(62), Some(Level::Level_5_2)));
+        assert!(matches!(h264_level(33), Some(Level::Level_3_2)));
+        assert!(h264_level(0).is_none());
+        assert!(matches!(h264_profile(77), Some(Profile::Main)));
+        assert!(h264_profile(0).is_none());
+    }
+
+    #[test]
+    fn encodes_test_pattern_without_a_display() {
+        let mut encoder = SoftwareEncoder::new(EncoderConfig {
+            codec_id: CodecId::H264,
+            params: CodecParameters {
+                profile: CodecProfile::H264Baseline as u8,
+                level: 12,
+                flags: 0,
+            },
+            width: 160,
+            height: 120,
+            fps: 30,
+            bitrate_kbps: 500,
+            keyframe_interval: 30,
+            capture_target: CaptureTarget::display(Some(TEST_PATTERN_TARGET.to_string())),
+            color: ColorSpace::default(),
+            scale: ScaleMode::default(),
+        })
+        .unwrap();
+        let (first, timestamp) = encoder.encode_frame();
+        assert_eq!(timestamp, Some(0));
+        assert_eq!(encoder.take_last_error(), None);
+        assert!(bitstream::inspect(CodecId::H264, &first).is_decodable_start(CodecId::H264));
+
+        let (second, _) = encoder.encode_frame();
+        assert!(!second.is_empty());
+        assert!(!bitstream::inspect(CodecId::H264, &second).is_keyframe());
+        encoder.request_keyframe();
+        let (third, _) = encoder.encode_frame();
+        assert!(bitstream::inspect(CodecId::H264, &third).is_keyframe());
+
+        encoder.set_bitrate(250);
+        assert_eq!(encoder.take_last_error(), None);
+        assert_eq!(encoder.bitrate_kbps, 250);
+        let (fourth, _) = encoder.encode_frame();
+        assert!(!fourth.is_empty());
+        assert!(!bitstream::inspect(CodecId::H264, &fourth).is_keyframe());
+    }
+}
@@ -1,116 +1,20 @@
+use crate::aoap_transport;
 use crate::app_state::TransportStatus;
-
-const TCP_PORT: u16 = 1445;
-
-pub fn probe_transport_status() -> TransportStatus {
-    #[cfg(windows)]
-    let tcp_probe = probe_tcp_table();
-    #[cfg(not(windows))]
-    let tcp_probe = TcpProbe {
-        listening: probe_tcp_listener(),
-        connections: 0,
-    };
-
+use crate::host_listener;
+use crate::host_transport::HostTransport;
+use crate::quic_transport;
+
+/// TCP state comes from the host's own listener and sessions rather than from
+/// whatever else holds the port.
+pub fn probe_transport_status(transport: &HostTransport) -> TransportStatus {
+    let listener = host_listener::status();
     TransportStatus {
-        tcp_listening: tcp_probe.listening,
-        tcp_connections: tcp_probe.connections,
-        aoap_attached: probe_aoap_attached(),
-    }
-}
-
-#[cfg(not(windows))]
-fn probe_tcp_listener() -> bool {
-    use std::net::{SocketAddr, TcpStream};
-    use std::time::Duration;
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], TCP_PORT));
-    TcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_ok()
-}
-
-#[cfg(windows)]
-struct TcpProbe {
-    listening: bool,
-    connections: u32,
-}
-
-#[cfg(windows)]
-fn probe_tcp_table() -> TcpProbe {
-    use std::mem::size_of;
-
-    use windows::Win32::Foundation::{ERROR_INSUFFICIENT_BUFFER, NO_ERROR};
-    use windows::Win32::NetworkManagement::IpHelper::{
-        GetExtendedTcpTable, MIB_TCP_STATE, MIB_TCP_STATE_ESTAB, MIB_TCP_STATE_LISTEN,
-        MIB_TCPTABLE_OWNER_PID, TCP_TABLE_OWNER_PID_ALL,
-    };
-    use windows::Win32::Networking::WinSock::AF_INET;
-
-    let mut size: u32 = 0;
-    let mut result = unsafe {
-        GetExtendedTcpTable(
-            None,
-            &mut size,
-            false,
-            AF_INET.0 as u32,
-            TCP_TABLE_OWNER_PID_ALL,
-            0,
-        )
-    };
-
-    if result != ERROR_INSUFFICIENT_BUFFER.0 {
-        return TcpProbe {
-            listening: false,
-            connections: 0,
-        };
-    }
-
-    let mut buffer = vec![0u8; size as usize];
-    result = unsafe {
-        GetExtendedTcpTable(
-            Some(buffer.as_mut_ptr() as *mut _),
-            &mut size,
-            false,
-            AF_INET.0 as u32,
-            TCP_TABLE_OWNER_PID_ALL,
-            0,
-        )
-    };
-
-    if result != NO_ERROR.0 {
-        return TcpProbe {
-            listening: false,
-            connections: 0,
-        };
-    }
-
-    if buffer.len() < size_of::<MIB_TCPTABLE_OWNER_PID>() {
-        return TcpProbe {
-            listening: false,
-            connections: 0,
-        };
-    }
-
-    let table = buffer.as_ptr() as *const MIB_TCPTABLE_OWNER_PID;
-    let entries = unsafe { (*table).dwNumEntries as usize };
-    let rows = unsafe { std::slice::from_raw_parts((*table).table.as_ptr(), entries) };
-
-    let mut listening = false;
-    let mut connections = 0u32;
-
-    for row in rows {
-        let port = u16::from_be(row.dwLocalPort as u16);
-        if port != TCP_PORT {
-            continue;
-        }
-        if MIB_TCP_STATE(row.dwState as i32) == MIB_TCP_STATE_LISTEN {
-            listening = true;
-        } else if MIB_TCP_STATE(row.dwState as i32) == MIB_TCP_STATE_ESTAB {
-            connections += 1;
-        }
-    }
-
-    TcpProbe {
-        listening,
-        connections,
+        tcp_listening: listener.is_some(),
+        tcp_connections: transport.session_count() as u32,
+        listen_address: listener.map(|address| address.to_string()),
+        quic_address: quic_transport::status().map(|address| address.to_string()),
+        traffic: transport.stats(),
+        aoap_attached: aoap_transport::attached() || probe_aoap_attached(),
     }
 }
 
@@ -0,0 +1,316 @@
+//! Host end of the datagram media paths (§7.10). A path belongs to one TCP
+//! session: the session offers it during negotiation and announces the port in
+//! `MediaPath`, the client's first authenticated feedback tells the host where to
+//! send, and from then on that session's frames leave here instead of on its
+//! stream. Everything else stays on TCP.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::app_state::HostSettings;
+use crate::protocol::packets::MediaPathPacket;
+use crate::protocol::secure::DatagramCipher;
+use crate::protocol::udp::{MediaConfig, MediaSender, DEFAULT_MAX_DATAGRAM};
+
+/// How often the feedback reader checks whether its path was closed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Controller starting point when no stream has set a bitrate yet.
+const DEFAULT_BITRATE_KBPS: u32 = 8000;
+
+static PATHS: OnceLock<Mutex<Vec<MediaPath>>> = OnceLock::new();
+static OPTIONS: OnceLock<Mutex<Option<PathOptions>>> = OnceLock::new();
+static MAX_BITRATE_KBPS: AtomicU32 = AtomicU32::new(0);
+
+fn path_store() -> &'static Mutex<Vec<MediaPath>> {
+    PATHS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn options_store() -> &'static Mutex<Option<PathOptions>> {
+    OPTIONS.get_or_init(|| Mutex::new(None))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathOptions {
+    pub fec_group: u8,
+}
+
+impl PathOptions {
+    /// `None` when video stays on TCP.
+    pub fn from_settings(settings: &HostSettings) -> Option<Self> {
+        settings.udp_media.then_some(Self {
+            fec_group: settings.fec_group,
+        })
+    }
+}
+
+/// One frame's datagrams with the times the pacer scheduled them for.
+type Batch = (SocketAddr, Vec<(Instant, Vec<u8>)>);
+
+struct MediaPath {
+    session: u64,
+    state: Arc<Mutex<PathState>>,
+    stop: Arc<AtomicBool>,
+    /// Feeds the path's sender thread; dropping it ends the thread.
+    outgoing: Sender<Batch>,
+}
+
+struct PathState {
+    sender: MediaSender,
+    /// Where the client's feedback comes from; nothing is sent before it is known.
+    peer: Option<SocketAddr>,
+}
+
+/// Takes effect for sessions negotiated from now on.
+pub fn apply(settings: &HostSettings) {
+    if let Ok(mut guard) = options_store().lock() {
+        *guard = PathOptions::from_settings(settings);
+    }
+}
+
+pub fn enabled() -> bool {
+    options_store()
+        .lock()
+        .map(|guard| guard.is_some())
+        .unwrap_or(false)
+}
+
+/// Opens a path for `session`, accepting feedback from `peer_ip` only, and
+/// returns the `MediaPath` packet announcing it.
+pub fn open(
+    session: u64,
+    peer_ip: IpAddr,
+    sealer: DatagramCipher,
+    opener: DatagramCipher,
+) -> Result<MediaPathPacket, String> {
+    let options = options_store()
+        .lock()
+        .ok()
+        .and_then(|guard| *guard)
+        .ok_or_else(|| "Datagram media is turned off".to_string())?;
+    let unspecified = match peer_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind((unspecified, 0)).map_err(|err| err.to_string())?;
+    socket
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|err| err.to_string())?;
+    let port = socket.local_addr().map_err(|err| err.to_string())?.port();
+    let config = MediaConfig::for_bitrate(max_bitrate_kbps(), options.fec_group);
+    let socket = Arc::new(socket);
+    let (outgoing, batches) = mpsc::channel();
+    let path = MediaPath {
+        session,
+        state: Arc::new(Mutex::new(PathState {
+            sender: MediaSender::new(config, Some(sealer), Some(opener), Instant::now()),
+            peer: None,
+        })),
+        stop: Arc::new(AtomicBool::new(false)),
+        outgoing,
+    };
+    start_reader(&path, socket.clone(), peer_ip);
+    start_sender(socket, batches);
+    let mut lock = path_store()
+        .lock()
+        .map_err(|_| "Lock poisoned".to_string())?;
+    lock.push(path);
+    Ok(MediaPathPacket {
+        port,
+        fec_group: options.fec_group,
+        max_datagram: DEFAULT_MAX_DATAGRAM as u16,
+    })
+}
+
+fn start_reader(path: &MediaPath, socket: Arc<UdpSocket>, peer_ip: IpAddr) {
+    let state = path.state.clone();
+    let stop = path.stop.clone();
+    thread::spawn(move || {
+        let mut buffer = [0u8; 2048];
+        while !stop.load(Ordering::SeqCst) {
+            let (read, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => continue,
+            };
+            if from.ip().to_canonical() != peer_ip.to_canonical() {
+                continue;
+            }
+            let Ok(mut state) = state.lock() else {
+                break;
+            };
+            // Forged or damaged datagrams are dropped; only authenticated ones
+            // may move the path to another port (NAT rebinding).
+            if state
+                .sender
+                .receive(&buffer[..read], Instant::now())
+                .is_ok()
+            {
+                state.peer = Some(from);
+            }
+        }
+    });
+}
+
+/// Paces one path's datagrams, so a slow client holds up neither the other paths
+/// nor the stream loop.
+fn start_sender(socket: Arc<UdpSocket>, batches: Receiver<Batch>) {
+    thread::spawn(move || {
+        for (peer, datagrams) in batches {
+            for (at, datagram) in datagrams {
+                if let Some(wait) = at.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+                // A full socket buffer is loss like any other; feedback reports it.
+                let _ = socket.send_to(&datagram, peer);
+            }
+        }
+    });
+}
+
+pub fn close(session: u64) {
+    if let Ok(mut lock) = path_store().lock() {
+        lock.retain(|path| {
+            let keep = path.session != session;
+            if !keep {
+                path.stop.store(true, Ordering::SeqCst);
+            }
+            keep
+        });
+    }
+}
+
+pub fn close_all() {
+    if let Ok(mut lock) = path_store().lock() {
+        for path in lock.drain(..) {
+            path.stop.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Hands a `Frame` packet to every path whose client has reported in and returns
+/// the sessions it went to. Each path's sender thread paces the datagrams out.
+pub fn send_frame(packet: &[u8]) -> Vec<u64> {
+    let now = Instant::now();
+    let mut reached = Vec::new();
+    if let Ok(lock) = path_store().lock() {
+        for path in lock.iter() {
+            let Ok(mut state) = path.state.lock() else {
+                continue;
+            };
+            let Some(peer) = state.peer else {
+                continue;
+            };
+            let Ok(datagrams) = state.sender.send(packet, now) else {
+                continue;
+            };
+            if path.outgoing.send((peer, datagrams)).is_ok() {
+                reached.push(path.session);
+            }
+        }
+    }
+    reached
+}
+
+/// Whether any client asked for a keyframe since the last call.
+pub fn take_keyframe_request() -> bool {
+    let Ok(lock) = path_store().lock() else {
+        return false;
+    };
+    let mut requested = false;
+    for path in lock.iter() {
+        if let Ok(mut state) = path.state.lock() {
+            requested |= state.sender.take_keyframe_request();
+        }
+    }
+    requested
+}
+
+/// The bitrate the slowest active path can take, when any path is active.
+pub fn target_bitrate_kbps() -> Option<u32> {
+    let lock = path_store().lock().ok()?;
+    lock.iter()
+        .filter_map(|path| {
+            let state = path.state.lock().ok()?;
+            state.peer.map(|_| state.sender.target_kbps())
+        })
+        .min()
+}
+
+/// The stream's configured bitrate, which the controllers never exceed.
+pub fn set_max_bitrate(kbps: u32) {
+    MAX_BITRATE_KBPS.store(kbps, Ordering::SeqCst);
+    if let Ok(lock) = path_store().lock() {
+        for path in lock.iter() {
+            if let Ok(mut state) = path.state.lock() {
+                state.sender.set_max_kbps(kbps);
+            }
+        }
+    }
+}
+
+fn max_bitrate_kbps() -> u32 {
+    match MAX_BITRATE_KBPS.load(Ordering::SeqCst) {
+        0 => DEFAULT_BITRATE_KBPS,
+        kbps => kbps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::secure::Cipher;
+    use crate::protocol::udp::MediaReceiver;
+
+    #[test]
+    fn streams_frames_over_loopback_once_the_client_reports_in() {
+        apply(&HostSettings {
+            udp_media: true,
+            fec_group: 2,
+            ..HostSettings::default()
+        });
+        let host = DatagramCipher::new(Cipher::Aes256Gcm, [3; 32]);
+        let client = DatagramCipher::new(Cipher::Aes256Gcm, [4; 32]);
+        let session = u64::MAX;
+        let announced = open(
+            session,
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            host.clone(),
+            client.clone(),
+        )
+        .unwrap();
+        assert_eq!(announced.fec_group, 2);
+
+        let frame = vec![0x42u8; 5000];
+        // Nothing goes out before the client's first feedback.
+        assert!(!send_frame(&frame).contains(&session));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let host_address = SocketAddr::from((Ipv4Addr::LOCALHOST, announced.port));
+        let mut receiver = MediaReceiver::new(Some(host), Some(client), Instant::now());
+        for feedback in receiver.poll(Instant::now()) {
+            socket.send_to(&feedback, host_address).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !send_frame(&frame).contains(&session) {
+            assert!(Instant::now() < deadline, "feedback never reached the host");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut buffer = [0u8; 2048];
+        let delivered = loop {
+            let (read, _) = socket.recv_from(&mut buffer).unwrap();
+            if let Some(packet) = receiver.receive(&buffer[..read], Instant::now()).unwrap() {
+                break packet;
+            }
+        };
+        assert_eq!(delivered, frame);
+        close(session);
+        assert!(!send_frame(&frame).contains(&session));
+    }
+}